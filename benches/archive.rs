@@ -0,0 +1,141 @@
+// archive.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Benchmarks for archive access paths.
+//
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::fmt::Write as _;
+use std::fs::{create_dir_all, File};
+use std::io::{Cursor, Write};
+use tempfile::TempDir;
+use trafdat::metro::build_full_json;
+use trafdat::sensor::{build_json, lookup_archived, read_archived};
+use trafdat::state::{AppState, Config};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Number of sensors in benchmark archives
+const N_SENSORS: usize = 1000;
+
+/// Number of samples per day (30 second bins)
+const N_SAMPLES: usize = 2880;
+
+/// Sample file extensions written for each sensor
+const EXTS: &[(&str, usize)] = &[("v30", 1), ("s30", 1), ("c30", 2)];
+
+/// Date in a directory
+const DIR_DATE: &str = "20210601";
+
+/// Date in a zip archive
+const ZIP_DATE: &str = "20210602";
+
+/// Build an archive tree with one date directory and one zip file
+fn build_archive() -> (TempDir, AppState) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        traffic_path: dir.path().join("traffic"),
+        metro_path: dir.path().join("metro_config"),
+        ..Config::default()
+    };
+    let state = AppState::new(config);
+    let date_dir = state.storage.date_path("tms", DIR_DATE);
+    create_dir_all(&date_dir).unwrap();
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for sid in 0..N_SENSORS {
+        for (ext, bytes) in EXTS {
+            let name = format!("{}.{}", sid, ext);
+            let data = vec![(sid % 100) as u8; N_SAMPLES * bytes];
+            File::create(date_dir.join(&name))
+                .unwrap()
+                .write_all(&data)
+                .unwrap();
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(&data).unwrap();
+        }
+    }
+    let mut path = state.storage.date_path("tms", ZIP_DATE);
+    path.set_extension("traffic");
+    let buf = zip.finish().unwrap().into_inner();
+    File::create(path).unwrap().write_all(&buf).unwrap();
+    (dir, state)
+}
+
+/// Build a metro_config document with many corridors
+fn build_metro_config() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n");
+    xml.push_str("<tms_config time_stamp=\"bench\">\n");
+    for c in 0..50 {
+        writeln!(xml, "<corridor route=\"R{}\" dir=\"NB\">", c).unwrap();
+        for n in 0..40 {
+            writeln!(
+                xml,
+                "<r_node name=\"rnd_{}_{}\" lon=\"-93.{}\" lat=\"45.{}\">",
+                c, n, n, c
+            )
+            .unwrap();
+            for lane in 1..=3 {
+                writeln!(
+                    xml,
+                    "<detector name=\"D{}_{}_{}\" lane=\"{}\"/>",
+                    c, n, lane, lane
+                )
+                .unwrap();
+            }
+            xml.push_str("</r_node>\n");
+        }
+        xml.push_str("</corridor>\n");
+    }
+    xml.push_str("</tms_config>\n");
+    xml
+}
+
+/// Benchmark reading sample files
+fn bench_read(c: &mut Criterion) {
+    let (_dir, state) = build_archive();
+    c.bench_function("read_dir_file", |b| {
+        b.iter(|| {
+            read_archived(&state, "tms", DIR_DATE, black_box("500"), "v30")
+                .unwrap()
+                .unwrap()
+        })
+    });
+    c.bench_function("read_zip_entry", |b| {
+        b.iter(|| {
+            read_archived(&state, "tms", ZIP_DATE, black_box("500"), "v30")
+                .unwrap()
+                .unwrap()
+        })
+    });
+}
+
+/// Benchmark listing sensors
+fn bench_list(c: &mut Criterion) {
+    let (_dir, state) = build_archive();
+    c.bench_function("list_dir_sensors", |b| {
+        b.iter(|| lookup_archived(&state, "tms", black_box(DIR_DATE)))
+    });
+    c.bench_function("list_zip_sensors", |b| {
+        b.iter(|| lookup_archived(&state, "tms", black_box(ZIP_DATE)))
+    });
+}
+
+/// Benchmark JSON encoding of sample arrays
+fn bench_json(c: &mut Criterion) {
+    let data: Vec<u8> = (0..N_SAMPLES).map(|i| (i % 256) as u8).collect();
+    c.bench_function("json_2880_samples", |b| {
+        b.iter(|| build_json(black_box(data.clone())).unwrap())
+    });
+}
+
+/// Benchmark metro_config parsing
+fn bench_metro(c: &mut Criterion) {
+    let (_dir, state) = build_archive();
+    let xml = build_metro_config();
+    c.bench_function("metro_config_json", |b| {
+        b.iter(|| build_full_json(&state, DIR_DATE, black_box(&xml)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_read, bench_list, bench_json, bench_metro);
+criterion_main!(benches);
@@ -0,0 +1,308 @@
+// lib.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Derive macro for JSON Schemas of trafdat response types.
+//
+// Schemas follow the `serde` attributes of each type (`rename`,
+// `rename_all`, `tag`, `flatten`, `skip` and `skip_serializing_if`), with
+// descriptions taken from doc comments.  Field schemas can be refined with
+// `#[schema(pattern = "...")]` or replaced with `#[schema(value = "...")]`
+// for constant strings.
+//
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Expr, ExprLit, Fields,
+    Lit, LitStr, Meta,
+};
+
+/// Derive `JsonSchema` for a struct or enum
+#[proc_macro_derive(JsonSchema, attributes(schema))]
+pub fn derive_json_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Container (type) attributes
+#[derive(Default)]
+struct Container {
+    /// Rename rule for fields or variants
+    rename_all: Option<String>,
+    /// Tag field of internally tagged enums
+    tag: Option<String>,
+}
+
+/// Field or variant attributes
+#[derive(Default)]
+struct Member {
+    /// Renamed (serialized) name
+    rename: Option<String>,
+    /// Skipped when serializing
+    skip: bool,
+    /// Left out when empty or `None`
+    optional: bool,
+    /// Properties flattened into the parent
+    flatten: bool,
+    /// Pattern for string values
+    pattern: Option<String>,
+    /// Constant string value
+    value: Option<String>,
+}
+
+/// Get the description from doc comments
+fn doc(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
+
+/// Parse a string value of a nested meta item
+fn string_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<String> {
+    Ok(meta.value()?.parse::<LitStr>()?.value())
+}
+
+/// Skip the value of an unused nested meta item
+fn skip_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|nested| skip_value(&nested))?;
+    }
+    Ok(())
+}
+
+/// Parse container attributes
+fn container(attrs: &[Attribute]) -> syn::Result<Container> {
+    let mut res = Container::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                res.rename_all = Some(string_value(&meta)?);
+            } else if meta.path.is_ident("tag") {
+                res.tag = Some(string_value(&meta)?);
+            } else {
+                skip_value(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(res)
+}
+
+/// Parse field or variant attributes
+fn member(attrs: &[Attribute]) -> syn::Result<Member> {
+    let mut res = Member::default();
+    for attr in attrs {
+        if attr.path().is_ident("serde") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    res.rename = Some(string_value(&meta)?);
+                } else if meta.path.is_ident("skip")
+                    || meta.path.is_ident("skip_serializing")
+                {
+                    res.skip = true;
+                } else if meta.path.is_ident("skip_serializing_if") {
+                    res.optional = true;
+                    skip_value(&meta)?;
+                } else if meta.path.is_ident("flatten") {
+                    res.flatten = true;
+                } else {
+                    skip_value(&meta)?;
+                }
+                Ok(())
+            })?;
+        } else if attr.path().is_ident("schema") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("pattern") {
+                    res.pattern = Some(string_value(&meta)?);
+                    Ok(())
+                } else if meta.path.is_ident("value") {
+                    res.value = Some(string_value(&meta)?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown schema attribute"))
+                }
+            })?;
+        }
+    }
+    Ok(res)
+}
+
+/// Apply a `rename_all` rule to a field or variant name
+fn rename(rule: Option<&str>, name: &str) -> String {
+    let snake = || {
+        let mut res = String::new();
+        for (i, c) in name.chars().enumerate() {
+            if c.is_uppercase() {
+                if i > 0 {
+                    res.push('_');
+                }
+                res.extend(c.to_lowercase());
+            } else {
+                res.push(c);
+            }
+        }
+        res
+    };
+    match rule {
+        Some("lowercase") => name.to_lowercase(),
+        Some("UPPERCASE") => name.to_uppercase(),
+        Some("snake_case") => snake(),
+        Some("kebab-case") => snake().replace('_', "-"),
+        _ => name.to_string(),
+    }
+}
+
+/// Get an optional string as tokens
+fn opt_str(val: Option<String>) -> TokenStream2 {
+    match val {
+        Some(val) => quote! { Some(#val) },
+        None => quote! { None },
+    }
+}
+
+/// Build an object schema expression from named fields
+fn object(
+    description: Option<String>,
+    fields: &Fields,
+    rule: Option<&str>,
+    tag: Option<(&str, &str)>,
+) -> syn::Result<TokenStream2> {
+    let description = opt_str(description);
+    let mut props = vec![];
+    if let Some((tag, name)) = tag {
+        props.push(quote! {
+            .property(#tag, ::serde_json::json!({ "const": #name }), true, None)
+        });
+    }
+    let fields = match fields {
+        Fields::Named(fields) => &fields.named,
+        Fields::Unit => {
+            return Ok(quote! {
+                crate::schema::ObjectSchema::new(#description)
+                    #(#props)*
+                    .build()
+            })
+        }
+        Fields::Unnamed(fields) => {
+            return Err(syn::Error::new_spanned(
+                fields,
+                "tuple fields are not supported",
+            ))
+        }
+    };
+    for field in fields {
+        let attrs = member(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+        let ty = &field.ty;
+        let schema = quote! { <#ty as crate::schema::JsonSchema>::schema() };
+        if attrs.flatten {
+            props.push(quote! { .flatten(#schema) });
+            continue;
+        }
+        let ident = field.ident.as_ref().unwrap().to_string();
+        let name = attrs.rename.unwrap_or_else(|| rename(rule, &ident));
+        let schema = match (attrs.value, attrs.pattern) {
+            (Some(value), _) => quote! {
+                ::serde_json::json!({ "const": #value })
+            },
+            (None, Some(pattern)) => quote! {
+                crate::schema::with_pattern(#schema, #pattern)
+            },
+            (None, None) => schema,
+        };
+        let required = !attrs.optional;
+        let fdoc = opt_str(doc(&field.attrs));
+        props.push(quote! { .property(#name, #schema, #required, #fdoc) });
+    }
+    Ok(quote! {
+        crate::schema::ObjectSchema::new(#description)
+            #(#props)*
+            .build()
+    })
+}
+
+/// Expand the derive macro
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let cont = container(&input.attrs)?;
+    let rule = cont.rename_all.as_deref();
+    let description = doc(&input.attrs);
+    let body = match &input.data {
+        Data::Struct(data) => object(description, &data.fields, rule, None)?,
+        Data::Enum(data) => {
+            let unit = data.variants.iter().all(|v| v.fields.is_empty());
+            let mut names = vec![];
+            let mut variants = vec![];
+            for variant in &data.variants {
+                let attrs = member(&variant.attrs)?;
+                if attrs.skip {
+                    continue;
+                }
+                let ident = variant.ident.to_string();
+                let name = attrs.rename.unwrap_or_else(|| rename(rule, &ident));
+                match (&cont.tag, unit) {
+                    (Some(tag), _) => variants.push(object(
+                        doc(&variant.attrs),
+                        &variant.fields,
+                        None,
+                        Some((tag, &name)),
+                    )?),
+                    (None, true) => names.push(name),
+                    (None, false) => {
+                        return Err(syn::Error::new_spanned(
+                            variant,
+                            "enums with fields need a serde tag",
+                        ))
+                    }
+                }
+            }
+            let description = opt_str(description);
+            if cont.tag.is_some() {
+                quote! { crate::schema::one_of(#description, vec![#(#variants),*]) }
+            } else {
+                quote! { crate::schema::string_enum(#description, &[#(#names),*]) }
+            }
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                "unions are not supported",
+            ))
+        }
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::schema::JsonSchema for #name #ty_generics
+            #where_clause
+        {
+            fn schema() -> ::serde_json::Value {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,539 @@
+// admin.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Admin pages for cache, watcher and breaker status.
+//
+use crate::apikey::Scope;
+use crate::audit;
+use crate::avail::RangeKey;
+use crate::cache::key_path;
+use crate::error::Error;
+use crate::health::BreakerStatus;
+use crate::schema::JsonSchema;
+use crate::state::AppState;
+use crate::stats::is_date_segment;
+use crate::template::escape_html;
+use crate::watch::ArchiveEvent;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{AUTHORIZATION, CACHE_CONTROL, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Local};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Number of recent errors kept
+const RECENT_ERRORS: usize = 50;
+
+/// Server error record
+#[derive(Clone)]
+pub struct ErrorEntry {
+    /// Time of response
+    pub time: SystemTime,
+    /// Request method
+    pub method: String,
+    /// Request path
+    pub path: String,
+    /// Response status code
+    pub status: u16,
+    /// Error message
+    pub error: String,
+}
+
+/// Log of recent server errors
+#[derive(Default)]
+pub struct ErrorLog {
+    /// Recent errors, newest first
+    entries: Mutex<VecDeque<ErrorEntry>>,
+}
+
+impl ErrorLog {
+    /// Add an error to the log
+    pub fn push(&self, entry: ErrorEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(RECENT_ERRORS);
+    }
+
+    /// Get recent errors, newest first
+    pub fn recent(&self) -> Vec<ErrorEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Record a server error response
+pub fn record<B>(res: &ServiceResponse<B>) {
+    if !res.status().is_server_error() {
+        return;
+    }
+    let state = match res.request().app_data::<web::Data<AppState>>() {
+        Some(state) => state,
+        None => return,
+    };
+    let error = match res.response().error() {
+        Some(e) => e.to_string(),
+        None => res.status().to_string(),
+    };
+    state.errors.push(ErrorEntry {
+        time: SystemTime::now(),
+        method: res.request().method().to_string(),
+        path: res.request().path().to_string(),
+        status: res.status().as_u16(),
+        error,
+    });
+}
+
+/// Compare tokens without leaking timing of mismatches
+fn token_eq(a: &str, b: &str) -> bool {
+    let a = Sha256::digest(a.as_bytes());
+    let b = Sha256::digest(b.as_bytes());
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Get the token from an `Authorization` header (bearer or basic password)
+pub fn request_token(req: &HttpRequest) -> Option<String> {
+    let auth = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    if let Some(token) = auth.strip_prefix("Bearer ") {
+        return Some(token.trim().to_string());
+    }
+    let creds = STANDARD.decode(auth.strip_prefix("Basic ")?.trim()).ok()?;
+    let creds = String::from_utf8(creds).ok()?;
+    creds.split_once(':').map(|(_user, pass)| pass.to_string())
+}
+
+/// Check if a request has the admin token
+pub fn has_admin_token(state: &AppState, req: &HttpRequest) -> bool {
+    match (&state.config.admin_token, request_token(req)) {
+        (Some(token), Some(t)) => token_eq(&t, token),
+        _ => false,
+    }
+}
+
+/// Check that a request is authorized for admin pages.
+///
+/// An API key with `admin` scope is accepted instead of the admin token.
+pub fn check_auth(state: &AppState, req: &HttpRequest) -> Result<(), Error> {
+    if let Some(principal) = state.api_keys.principal(req) {
+        if principal.has_scope(Scope::Admin) {
+            return Ok(());
+        }
+        return Err(Error::Forbidden);
+    }
+    if state.config.admin_token.is_none() {
+        return Err(Error::NotFound);
+    }
+    if has_admin_token(state, req) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized)
+    }
+}
+
+/// Format a time for display
+fn local_time(time: SystemTime) -> String {
+    let time: DateTime<Local> = time.into();
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Get the district and date (if any) of a request path
+fn path_scope<'a>(
+    state: &AppState,
+    path: &'a str,
+) -> (&'a str, Option<&'a str>) {
+    let path = path
+        .strip_prefix(state.config.url_prefix.as_str())
+        .unwrap_or(path);
+    let mut segs = path.split('/').filter(|s| !s.is_empty());
+    let district = segs.next().unwrap_or("");
+    let date = segs.find(|s| is_date_segment(s)).map(|d| &d[..8]);
+    (district, date)
+}
+
+/// Cached entries for one district and date
+#[derive(JsonSchema, Serialize)]
+struct ScopeStatus {
+    district: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    entries: usize,
+    bytes: usize,
+}
+
+/// Response cache status
+#[derive(JsonSchema, Serialize)]
+struct CacheStatus {
+    enabled: bool,
+    ttl_secs: u64,
+    entries: usize,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hit_rate: Option<f64>,
+    scopes: Vec<ScopeStatus>,
+}
+
+/// Recent watcher event
+#[derive(JsonSchema, Serialize)]
+struct EventStatus {
+    time: String,
+    #[serde(flatten)]
+    event: ArchiveEvent,
+}
+
+/// Archive watcher status
+#[derive(JsonSchema, Serialize)]
+struct WatcherStatus {
+    running: bool,
+    scans: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_scan: Option<String>,
+    recent: Vec<EventStatus>,
+}
+
+/// Recent server error
+#[derive(JsonSchema, Serialize)]
+struct ErrorStatus {
+    time: String,
+    method: String,
+    path: String,
+    status: u16,
+    error: String,
+}
+
+/// Breaker states
+#[derive(JsonSchema, Serialize)]
+struct BreakersStatus {
+    traffic: BreakerStatus,
+    metro_config: BreakerStatus,
+}
+
+/// Disk cache status (proxy mode)
+#[derive(JsonSchema, Serialize)]
+struct DiskCacheStatus {
+    files: usize,
+    bytes: u64,
+}
+
+/// Admin status (JSON)
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct AdminStatus {
+    cache: CacheStatus,
+    zip_handles: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disk_cache: Option<DiskCacheStatus>,
+    watcher: WatcherStatus,
+    breakers: BreakersStatus,
+    errors: Vec<ErrorStatus>,
+}
+
+impl AdminStatus {
+    /// Gather admin status
+    fn new(state: &AppState) -> Self {
+        let entries = state.cache.entries();
+        let mut scopes = BTreeMap::new();
+        for entry in &entries {
+            let (district, date) = path_scope(state, key_path(&entry.key));
+            let scope = scopes.entry((district, date)).or_insert((0, 0));
+            scope.0 += 1;
+            scope.1 += entry.bytes;
+        }
+        let hits = state.cache.hits();
+        let misses = state.cache.misses();
+        let total = hits + misses;
+        let cache = CacheStatus {
+            enabled: !state.config.cache_ttl.is_zero(),
+            ttl_secs: state.config.cache_ttl.as_secs(),
+            entries: entries.len(),
+            bytes: entries.iter().map(|e| e.bytes).sum(),
+            hits,
+            misses,
+            hit_rate: (total > 0).then(|| hits as f64 / total as f64),
+            scopes: scopes
+                .into_iter()
+                .map(|((district, date), (entries, bytes))| ScopeStatus {
+                    district: district.to_string(),
+                    date: date.map(str::to_string),
+                    entries,
+                    bytes,
+                })
+                .collect(),
+        };
+        let watch = state.watch.status();
+        let watcher = WatcherStatus {
+            running: watch.running,
+            scans: watch.scans,
+            last_scan: watch.last_scan.map(local_time),
+            recent: watch
+                .recent
+                .into_iter()
+                .map(|(time, event)| EventStatus {
+                    time: local_time(time),
+                    event,
+                })
+                .collect(),
+        };
+        let health = state.health.report();
+        let errors = state
+            .errors
+            .recent()
+            .into_iter()
+            .map(|e| ErrorStatus {
+                time: local_time(e.time),
+                method: e.method,
+                path: e.path,
+                status: e.status,
+                error: e.error,
+            })
+            .collect();
+        AdminStatus {
+            cache,
+            zip_handles: state.zips.len(),
+            disk_cache: state.disk_cache.as_ref().map(|disk| DiskCacheStatus {
+                files: disk.len(),
+                bytes: disk.total_bytes(),
+            }),
+            watcher,
+            breakers: BreakersStatus {
+                traffic: health.traffic,
+                metro_config: health.metro_config,
+            },
+            errors,
+        }
+    }
+
+    /// Render status as an HTML page
+    fn to_html(&self, prefix: &str) -> String {
+        let flush = format!("{}/admin/flush", escape_html(prefix));
+        let mut html = String::new();
+        write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>trafdat admin</title>\n\
+             <link rel=\"stylesheet\" href=\"{}/trafdat.css\">\n\
+             </head>\n<body>\n<h1>trafdat admin</h1>\n",
+            escape_html(prefix)
+        )
+        .unwrap();
+        html.push_str("<h2>Storage Breakers</h2>\n<table>\n");
+        html.push_str(
+            "<tr><th>Root</th><th>State</th><th>Failures</th></tr>\n",
+        );
+        for (name, b) in [
+            ("traffic", &self.breakers.traffic),
+            ("metro_config", &self.breakers.metro_config),
+        ] {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                name, b.state, b.failures
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+        let c = &self.cache;
+        let rate = match c.hit_rate {
+            Some(rate) => format!("{:.1}%", rate * 100.0),
+            None => "-".to_string(),
+        };
+        write!(
+            html,
+            "<h2>Response Cache</h2>\n<p>TTL {} s; {} entries, {} bytes; \
+             {} hits, {} misses ({} hit rate); {} open zip archives</p>\n",
+            c.ttl_secs,
+            c.entries,
+            c.bytes,
+            c.hits,
+            c.misses,
+            rate,
+            self.zip_handles
+        )
+        .unwrap();
+        html.push_str("<table>\n<tr><th>District</th><th>Date</th>");
+        html.push_str("<th>Entries</th><th>Bytes</th><th></th></tr>\n");
+        for scope in &c.scopes {
+            let district = escape_html(&scope.district);
+            let date = escape_html(scope.date.as_deref().unwrap_or(""));
+            writeln!(
+                html,
+                "<tr><td>{district}</td><td>{date}</td><td>{}</td>\
+                 <td>{}</td><td><form method=\"post\" action=\"{flush}\">\
+                 <input type=\"hidden\" name=\"district\" value=\"{district}\">\
+                 <input type=\"hidden\" name=\"date\" value=\"{date}\">\
+                 <button>Flush</button></form></td></tr>",
+                scope.entries, scope.bytes,
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+        writeln!(
+            html,
+            "<form method=\"post\" action=\"{flush}\">\
+             <input name=\"district\" placeholder=\"district\">\
+             <input name=\"date\" placeholder=\"yyyyMMdd\">\
+             <button>Flush</button></form>\n\
+             <form method=\"post\" action=\"{flush}\">\
+             <button>Flush all</button></form>"
+        )
+        .unwrap();
+        if let Some(disk) = &self.disk_cache {
+            writeln!(
+                html,
+                "<p>Disk cache: {} files, {} bytes</p>",
+                disk.files, disk.bytes
+            )
+            .unwrap();
+        }
+        let w = &self.watcher;
+        write!(
+            html,
+            "<h2>Archive Watcher</h2>\n<p>{}; {} scans; last scan {}</p>\n",
+            if w.running { "running" } else { "not running" },
+            w.scans,
+            w.last_scan.as_deref().unwrap_or("-"),
+        )
+        .unwrap();
+        html.push_str("<table>\n<tr><th>Time</th><th>Event</th>");
+        html.push_str("<th>Details</th></tr>\n");
+        for ev in &w.recent {
+            let details = serde_json::to_string(&ev.event).unwrap_or_default();
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                ev.time,
+                ev.event.name(),
+                escape_html(&details)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n<h2>Recent Errors</h2>\n<table>\n");
+        html.push_str("<tr><th>Time</th><th>Request</th><th>Status</th>");
+        html.push_str("<th>Error</th></tr>\n");
+        for e in &self.errors {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{} {}</td><td>{}</td><td>{}</td></tr>",
+                e.time,
+                escape_html(&e.method),
+                escape_html(&e.path),
+                e.status,
+                escape_html(&e.error)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// Handle a request for the admin page
+pub async fn handle_page(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    check_auth(&state, &req)?;
+    let html = AdminStatus::new(&state).to_html(&state.config.url_prefix);
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .body(html))
+}
+
+/// Handle a request for admin status
+pub async fn handle_status(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    check_auth(&state, &req)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(AdminStatus::new(&state)))
+}
+
+/// Cache flush parameters
+#[derive(Deserialize)]
+pub struct FlushForm {
+    /// District ID (all districts if empty)
+    district: Option<String>,
+    /// Date (yyyyMMdd; all dates if empty)
+    date: Option<String>,
+}
+
+/// Check if a path is a zip archive within a flush scope
+fn zip_matches(
+    state: &AppState,
+    path: &Path,
+    district: Option<&str>,
+    date: Option<&str>,
+) -> bool {
+    match (district, date) {
+        (Some(district), Some(date)) => {
+            let mut zip = state.storage.date_path(district, date);
+            zip.set_extension("traffic");
+            path == zip
+                || path == state.storage.year_archive_path(district, &date[..4])
+        }
+        (Some(district), None) => {
+            path.starts_with(state.storage.district_path(district))
+        }
+        _ => true,
+    }
+}
+
+/// Handle a request to flush cached responses and open archives
+pub async fn handle_flush(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    form: web::Form<FlushForm>,
+) -> Result<HttpResponse, Error> {
+    check_auth(&state, &req)?;
+    let district = form.district.as_deref().filter(|d| !d.is_empty());
+    let date = form.date.as_deref().filter(|d| !d.is_empty());
+    if district.is_some_and(|d| d.contains('/') || d.starts_with('.')) {
+        return Err(Error::InvalidParam("district".into()));
+    }
+    if date.is_some_and(|d| d.len() != 8 || !is_date_segment(d)) {
+        return Err(Error::InvalidParam("date".into()));
+    }
+    if district.is_none() && date.is_some() {
+        return Err(Error::InvalidParam("district".into()));
+    }
+    let responses = state.cache.flush(|path| {
+        let (d, dt) = path_scope(&state, path);
+        district.is_none_or(|district| district == d)
+            && date.is_none_or(|date| dt == Some(date))
+    });
+    let zips = state
+        .zips
+        .flush(|path| zip_matches(&state, path, district, date));
+    let in_district = |key: &RangeKey| {
+        district.is_none_or(|district| key.district == district)
+    };
+    let memos = state.complete_dates.flush(in_district)
+        + state.aadt.flush(in_district)
+        + state.percentiles.flush(|(key, _)| in_district(key));
+    info!(
+        "admin flush {:?} {:?}: {} responses, {} memos, {} archives",
+        district, date, responses, memos, zips
+    );
+    let target = match (district, date) {
+        (Some(district), Some(date)) => format!("{}/{}", district, date),
+        (Some(district), None) => district.to_string(),
+        _ => "*".to_string(),
+    };
+    audit::record(&state, &req, "admin.flush", &target);
+    Ok(HttpResponse::SeeOther()
+        .insert_header((
+            LOCATION,
+            format!("{}/admin/", state.config.url_prefix),
+        ))
+        .finish())
+}
@@ -0,0 +1,283 @@
+// align.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::route::is_valid_district;
+use crate::sensor::{json_response, read_series};
+use crate::state::AppState;
+use crate::wire::{Aligned, AlignedSeries, Mismatch, SeriesInfo};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// Maximum number of series (sensors times extensions) in one request
+const MAX_SERIES: usize = 4096;
+
+/// Query parameters for alignment requests
+#[derive(Deserialize)]
+struct AlignParams {
+    /// Comma-separated sensor IDs
+    sensors: String,
+    /// Comma-separated sample file extensions
+    ext: String,
+}
+
+/// Header names of the sensor ID column in uploaded sensor lists
+const SID_HEADERS: &[&str] = &["sid", "sensor", "detector", "name", "id"];
+
+/// Query parameters for uploaded sensor lists
+#[derive(Deserialize)]
+struct UploadParams {
+    /// Comma-separated sample file extensions
+    ext: String,
+}
+
+/// Sensor in a batch alignment request
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BatchSensor {
+    /// Sensor ID in the requested district
+    Sid(String),
+    /// Sensor ID in another district
+    Pair { district: String, sid: String },
+}
+
+/// Request body for batch alignment requests
+#[derive(Deserialize)]
+pub struct AlignRequest {
+    /// Sensor IDs or `{district, sid}` pairs
+    sensors: Vec<BatchSensor>,
+    /// Sample file extensions
+    ext: Vec<String>,
+}
+
+/// Split a comma-separated list parameter
+pub fn split_list<'a>(
+    name: &str,
+    list: &'a str,
+) -> Result<Vec<&'a str>, Error> {
+    let items: Vec<&str> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    if items.is_empty() {
+        Err(Error::InvalidParam(name.to_string()))
+    } else {
+        Ok(items)
+    }
+}
+
+/// Handle request to check alignment of sensor data on a date
+pub fn handle_aligned(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<AlignParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let sensors: Vec<(Option<&str>, &str)> =
+        split_list("sensors", &params.sensors)?
+            .into_iter()
+            .map(|sid| (None, sid))
+            .collect();
+    let exts = split_list("ext", &params.ext)?;
+    check_alignment(state, district, date, &sensors, &exts)
+}
+
+impl AlignRequest {
+    /// Parse a batch request body by content type.
+    ///
+    /// JSON bodies have `sensors` and `ext`.  Sensor lists uploaded as
+    /// `text/csv` or `text/plain` (one per line) take `ext` from the query.
+    pub fn parse(
+        content_type: Option<&str>,
+        body: &[u8],
+        query: &str,
+    ) -> Result<Self, Error> {
+        let mime = content_type
+            .and_then(|ct| ct.split(';').next())
+            .map(|ct| ct.trim().to_ascii_lowercase());
+        match mime.as_deref() {
+            None | Some("application/json") => serde_json::from_slice(body)
+                .map_err(|e| Error::InvalidParam(format!("body: {}", e))),
+            Some("text/csv" | "text/plain") => {
+                let params = web::Query::<UploadParams>::from_query(query)
+                    .map_err(|e| Error::InvalidParam(e.to_string()))?;
+                let body = std::str::from_utf8(body)
+                    .map_err(|_| Error::InvalidParam("sensors".into()))?;
+                Ok(AlignRequest {
+                    sensors: parse_sensor_list(body),
+                    ext: split_list("ext", &params.ext)?
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                })
+            }
+            Some(ct) => {
+                Err(Error::InvalidParam(format!("content-type: {}", ct)))
+            }
+        }
+    }
+}
+
+/// Parse an uploaded sensor list.
+///
+/// Each line has a sensor ID in the first column, unless a header row names
+/// the ID column (`sid`, `sensor`, `detector`, `name` or `id`); a `district`
+/// column makes cross-district pairs.  Blank lines and `#` comments are
+/// skipped.
+fn parse_sensor_list(body: &str) -> Vec<BatchSensor> {
+    let mut rows = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split(',')
+                .map(|f| f.trim().trim_matches('"'))
+                .collect::<Vec<_>>()
+        })
+        .peekable();
+    let mut sid_col = 0;
+    let mut district_col = None;
+    if let Some(header) = rows.peek() {
+        let find = |names: &[&str]| {
+            header
+                .iter()
+                .position(|f| names.contains(&f.to_ascii_lowercase().as_str()))
+        };
+        if let Some(col) = find(SID_HEADERS) {
+            sid_col = col;
+            district_col = find(&["district"]);
+            rows.next();
+        }
+    }
+    rows.filter_map(|row| {
+        let sid = row.get(sid_col).filter(|s| !s.is_empty())?.to_string();
+        match district_col
+            .and_then(|c| row.get(c))
+            .filter(|d| !d.is_empty())
+        {
+            Some(district) => Some(BatchSensor::Pair {
+                district: district.to_string(),
+                sid,
+            }),
+            None => Some(BatchSensor::Sid(sid)),
+        }
+    })
+    .collect()
+}
+
+impl BatchSensor {
+    /// Get the district (if not the requested one) and sensor ID
+    fn resolve(&self) -> Result<(Option<&str>, &str), Error> {
+        match self {
+            BatchSensor::Sid(sid) => Ok((None, sid)),
+            BatchSensor::Pair { district, sid } => {
+                if is_valid_district(district) {
+                    Ok((Some(district), sid))
+                } else {
+                    Err(Error::InvalidParam(format!("district: {}", district)))
+                }
+            }
+        }
+    }
+}
+
+/// Handle batch request (POST body) to check alignment of sensor data
+pub fn handle_aligned_batch(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    req: &AlignRequest,
+) -> Result<HttpResponse, Error> {
+    let sensors = req
+        .sensors
+        .iter()
+        .map(BatchSensor::resolve)
+        .collect::<Result<Vec<_>, _>>()?;
+    let exts: Vec<&str> = req.ext.iter().map(String::as_str).collect();
+    if sensors.is_empty() {
+        return Err(Error::InvalidParam("sensors".into()));
+    }
+    if exts.is_empty() {
+        return Err(Error::InvalidParam("ext".into()));
+    }
+    check_alignment(state, district, date, &sensors, &exts)
+}
+
+/// Check alignment of sample data for sensors and extensions.
+///
+/// Sensors with a district are read from that district, others from the
+/// requested one.
+fn check_alignment(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sensors: &[(Option<&str>, &str)],
+    exts: &[&str],
+) -> Result<HttpResponse, Error> {
+    if sensors.len() * exts.len() > MAX_SERIES {
+        return Err(Error::InvalidParam(format!(
+            "too many series (max {})",
+            MAX_SERIES
+        )));
+    }
+    let mut found = vec![];
+    for (did, sid) in sensors {
+        for ext in exts {
+            let dist = did.unwrap_or(district);
+            let series = read_series(state, dist, date, sid, ext)?;
+            found.push((*did, *sid, *ext, series));
+        }
+    }
+    let first = found.first().and_then(|(_, _, _, s)| s.as_ref());
+    let aligned = first.filter(|first| {
+        found.iter().all(|(_, _, _, s)| {
+            s.as_ref().is_some_and(|s| {
+                s.period() == first.period()
+                    && s.values().len() == first.values().len()
+            })
+        })
+    });
+    match aligned {
+        Some(first) => {
+            let aligned = Aligned {
+                period: first.period(),
+                samples: first.values().len(),
+                series: found
+                    .iter()
+                    .filter_map(|(did, sid, ext, s)| {
+                        s.as_ref().map(|s| AlignedSeries {
+                            district: did.map(Cow::Borrowed),
+                            sid: Cow::Borrowed(sid),
+                            ext: Cow::Borrowed(ext),
+                            values: Cow::Borrowed(s.values()),
+                        })
+                    })
+                    .collect(),
+            };
+            Ok(json_response(serde_json::to_string(&aligned)?))
+        }
+        None => {
+            let mismatch = Mismatch {
+                error: Cow::Borrowed("unaligned"),
+                series: found
+                    .iter()
+                    .map(|(did, sid, ext, s)| SeriesInfo {
+                        district: did.map(Cow::Borrowed),
+                        sid: Cow::Borrowed(sid),
+                        ext: Cow::Borrowed(ext),
+                        period: s.as_ref().map(|s| s.period()),
+                        samples: s.as_ref().map(|s| s.values().len()),
+                    })
+                    .collect(),
+            };
+            Ok(HttpResponse::Conflict()
+                .content_type("application/json")
+                .body(serde_json::to_string(&mismatch)?))
+        }
+    }
+}
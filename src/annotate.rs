@@ -0,0 +1,108 @@
+// annotate.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Construction and workzone date annotations.
+//
+use crate::error::Error;
+use crate::route::is_valid_date;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+
+/// Annotation file name (in district directory)
+const ANNOTATION_FILE: &str = "annotations.json";
+
+/// Annotation of a date range when data was impacted
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct Annotation {
+    /// First date (yyyyMMdd)
+    pub start: String,
+    /// Last date (yyyyMMdd), inclusive
+    pub end: String,
+    /// Affected corridors (`route_dir`); empty for whole district
+    #[serde(default)]
+    pub corridors: Vec<String>,
+    pub note: String,
+}
+
+/// Annotations for one district
+#[derive(Debug, Default)]
+pub struct Annotations {
+    annotations: Vec<Annotation>,
+}
+
+impl Annotation {
+    /// Check that dates are valid
+    fn is_valid(&self) -> bool {
+        is_valid_date(&self.start)
+            && is_valid_date(&self.end)
+            && self.start <= self.end
+    }
+
+    /// Check if the annotation covers a date and corridor
+    fn matches(&self, date: &str, corridor: &str) -> bool {
+        self.start.as_str() <= date
+            && date <= self.end.as_str()
+            && (self.corridors.is_empty()
+                || self.corridors.iter().any(|c| c == corridor))
+    }
+}
+
+impl Annotations {
+    /// Load annotations from a district directory.
+    ///
+    /// A missing file results in no annotations.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let path = path.join(ANNOTATION_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Annotations::default())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let annotations: Vec<Annotation> =
+            serde_json::from_reader(BufReader::new(file))
+                .map_err(|_| Error::Config(path.display().to_string()))?;
+        if !annotations.iter().all(Annotation::is_valid) {
+            return Err(Error::Config(path.display().to_string()));
+        }
+        Ok(Annotations { annotations })
+    }
+}
+
+/// Load district annotations for a corridor on a date
+pub fn load_matching(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    corridor: &str,
+) -> Result<Vec<Annotation>, Error> {
+    let path = state.storage.district_path(district);
+    let annotations = Annotations::load(&path)?.annotations;
+    Ok(annotations
+        .into_iter()
+        .filter(|a| a.matches(date, corridor))
+        .collect())
+}
+
+/// Handle request for district annotations
+pub fn handle_annotations(
+    state: &AppState,
+    district: &str,
+) -> Result<HttpResponse, Error> {
+    let path = state.storage.district_path(district);
+    if !path.is_dir() {
+        return Err(Error::NotFound);
+    }
+    let annotations = Annotations::load(&path)?;
+    Ok(json_response(serde_json::to_string(
+        &annotations.annotations,
+    )?))
+}
@@ -0,0 +1,225 @@
+// anomaly.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Simple anomaly detectors for binned sample data.
+//
+use crate::corridor::time_of_day;
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use crate::schema::JsonSchema;
+use crate::sensor::{json_response, read_stored_series, sample_scale};
+use crate::state::AppState;
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// Sample types checked for anomalies
+const TYPES: &[&str] = &["v", "o", "c", "s"];
+
+/// Minimum flow (vehicles per hour) before a drop to zero is flagged
+const DROP_FLOW: f64 = 600.0;
+
+/// Minimum duration (seconds) of zeros after a drop
+const DROP_SECS: u32 = 300;
+
+/// Minimum duration (seconds) of repeated values to be frozen
+const FROZEN_SECS: u32 = 1800;
+
+/// Maximum flow (vehicles per hour per lane)
+const MAX_FLOW: f64 = 3000.0;
+
+/// Maximum speed (mph)
+const MAX_SPEED: f64 = 120.0;
+
+/// Maximum occupancy (percent)
+const MAX_OCCUPANCY: f64 = 100.0;
+
+/// Detector scan rate (Hz)
+const SCAN_HZ: f64 = 60.0;
+
+/// Reason an interval was flagged
+#[derive(Clone, Copy, Debug, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Reason {
+    /// Sudden drop to zero from heavy flow
+    ZeroDrop,
+    /// Same value repeated for a long time
+    Frozen,
+    /// Value beyond physical limits
+    Limit,
+}
+
+/// Flagged interval (JSON)
+#[derive(Debug, JsonSchema, Serialize)]
+struct Anomaly {
+    /// Sample extension
+    ext: String,
+    /// Start time (HH:MM)
+    start: String,
+    /// End time (HH:MM), exclusive
+    end: String,
+    /// Number of flagged samples
+    samples: usize,
+    reason: Reason,
+    /// Offending (decoded) value
+    value: f64,
+}
+
+/// Anomaly report for one sensor on a date (JSON)
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Report<'a> {
+    sid: &'a str,
+    /// Sample extensions checked
+    checked: Vec<String>,
+    anomalies: Vec<Anomaly>,
+}
+
+/// Run of samples flagged for one reason
+struct Run {
+    /// Index of first sample
+    first: usize,
+    /// Number of samples
+    len: usize,
+    reason: Reason,
+    value: f64,
+}
+
+/// Get the physical limit for a sample type and period (seconds)
+fn limit(prefix: &str, period: u32) -> f64 {
+    match prefix {
+        "v" => MAX_FLOW * f64::from(period) / 3600.0,
+        "o" => MAX_OCCUPANCY,
+        "c" => SCAN_HZ * f64::from(period),
+        _ => MAX_SPEED,
+    }
+}
+
+/// Find runs of values exceeding a limit
+fn limit_runs(values: &[Option<f64>], max: f64) -> Vec<Run> {
+    let mut runs: Vec<Run> = vec![];
+    for (i, val) in values.iter().enumerate() {
+        match val {
+            Some(v) if *v > max => match runs.last_mut() {
+                Some(run) if run.first + run.len == i => {
+                    run.len += 1;
+                    run.value = run.value.max(*v);
+                }
+                _ => runs.push(Run {
+                    first: i,
+                    len: 1,
+                    reason: Reason::Limit,
+                    value: *v,
+                }),
+            },
+            _ => (),
+        }
+    }
+    runs
+}
+
+/// Find runs of repeated values (missing samples break a run)
+fn repeated_runs(values: &[Option<f64>]) -> Vec<(usize, usize, f64)> {
+    let mut runs = vec![];
+    let mut i = 0;
+    while i < values.len() {
+        let val = match values[i] {
+            Some(v) => v,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let len = values[i..].iter().take_while(|v| **v == Some(val)).count();
+        runs.push((i, len, val));
+        i += len;
+    }
+    runs
+}
+
+/// Find frozen runs and drops to zero
+fn stuck_runs(values: &[Option<f64>], prefix: &str, period: u32) -> Vec<Run> {
+    let drop_len = (DROP_SECS / period).max(1) as usize;
+    let frozen_len = (FROZEN_SECS / period).max(1) as usize;
+    let drop_min = DROP_FLOW * f64::from(period) / 3600.0;
+    let mut runs = vec![];
+    for (first, len, val) in repeated_runs(values) {
+        if val == 0.0 {
+            let before = first.checked_sub(1).and_then(|i| values[i]);
+            if prefix == "v" && len >= drop_len {
+                if let Some(before) = before.filter(|b| *b >= drop_min) {
+                    runs.push(Run {
+                        first,
+                        len,
+                        reason: Reason::ZeroDrop,
+                        value: before,
+                    });
+                }
+            }
+        } else if len >= frozen_len {
+            runs.push(Run {
+                first,
+                len,
+                reason: Reason::Frozen,
+                value: val,
+            });
+        }
+    }
+    runs
+}
+
+/// Check one sample series for anomalies
+fn check_series(
+    ext: &str,
+    prefix: &str,
+    series: &SampleSeries,
+) -> Vec<Anomaly> {
+    let period = series.period();
+    let scale = sample_scale(prefix);
+    let values: Vec<Option<f64>> = series
+        .values()
+        .iter()
+        .map(|v| v.map(|v| f64::from(v) * scale))
+        .collect();
+    let mut runs = limit_runs(&values, limit(prefix, period));
+    runs.extend(stuck_runs(&values, prefix, period));
+    runs.sort_by_key(|run| run.first);
+    runs.into_iter()
+        .map(|run| Anomaly {
+            ext: ext.to_string(),
+            start: time_of_day(run.first as u32 * period),
+            end: time_of_day((run.first + run.len) as u32 * period),
+            samples: run.len,
+            reason: run.reason,
+            value: run.value,
+        })
+        .collect()
+}
+
+/// Handle request for sample data anomalies
+pub fn handle_anomalies(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+) -> Result<HttpResponse, Error> {
+    let mut report = Report {
+        sid,
+        checked: vec![],
+        anomalies: vec![],
+    };
+    for prefix in TYPES {
+        for period in &state.config.sample_periods {
+            let ext = format!("{}{}", prefix, period);
+            if let Some(series) =
+                read_stored_series(state, district, date, sid, &ext)?
+            {
+                report.anomalies.extend(check_series(&ext, prefix, &series));
+                report.checked.push(ext);
+            }
+        }
+    }
+    if report.checked.is_empty() {
+        return Err(Error::NotFound);
+    }
+    Ok(json_response(serde_json::to_string(&report)?))
+}
@@ -0,0 +1,177 @@
+// apikey.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Per-user API keys with scoped permissions.
+//
+// Keys are loaded from a JSON file (`TRAFDAT_API_KEYS`).  When any keys are
+// configured, every request (except documentation pages and health checks)
+// must present a key with the scope of its route, as an `X-API-Key` header,
+// a bearer token or a basic auth password.  A valid signed URL is accepted
+// in place of a key for data routes.  The key holder's name is recorded in
+// access logs and statistics.
+//
+use crate::admin::{has_admin_token, request_token};
+use crate::error::Error;
+use crate::signing;
+use crate::state::AppState;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// API key header name
+const API_KEY: &str = "x-api-key";
+
+/// Permission scope of an API key
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum Scope {
+    /// Read traffic and weather sample data
+    #[serde(rename = "read:data")]
+    ReadData,
+    /// Read metro_config documents
+    #[serde(rename = "read:config")]
+    ReadConfig,
+    /// Admin pages and metrics
+    #[serde(rename = "admin")]
+    Admin,
+    /// Submit and cancel jobs
+    #[serde(rename = "write")]
+    Write,
+}
+
+/// API key entry (from keys file)
+#[derive(Deserialize)]
+struct KeyEntry {
+    /// Secret key
+    key: String,
+    /// Identity of key holder
+    name: String,
+    /// Granted scopes
+    scopes: Vec<Scope>,
+}
+
+/// Principal identified by an API key
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// Identity of key holder
+    pub name: String,
+    /// Granted scopes
+    pub scopes: Vec<Scope>,
+}
+
+/// Configured API keys
+#[derive(Default)]
+pub struct ApiKeys {
+    /// Principals by SHA-256 digest of key
+    keys: HashMap<Vec<u8>, Principal>,
+}
+
+impl Principal {
+    /// Check if the principal has a scope
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+impl ApiKeys {
+    /// Load API keys from a JSON file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let entries: Vec<KeyEntry> =
+            serde_json::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| Error::Config(format!("{:?}: {}", path, e)))?;
+        let mut keys = HashMap::new();
+        for entry in entries {
+            if entry.key.is_empty() || entry.name.is_empty() {
+                return Err(Error::Config(format!("{:?}: empty key", path)));
+            }
+            let principal = Principal {
+                name: entry.name,
+                scopes: entry.scopes,
+            };
+            keys.insert(
+                Sha256::digest(entry.key.as_bytes()).to_vec(),
+                principal,
+            );
+        }
+        Ok(ApiKeys { keys })
+    }
+
+    /// Check if API keys are required
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Get the principal of a key
+    fn lookup(&self, key: &str) -> Option<&Principal> {
+        self.keys.get(Sha256::digest(key.as_bytes()).as_slice())
+    }
+
+    /// Get the principal of a request's key
+    pub fn principal(&self, req: &HttpRequest) -> Option<&Principal> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let key = req
+            .headers()
+            .get(API_KEY)
+            .and_then(|v| v.to_str().ok())
+            .map(|k| k.trim().to_string())
+            .or_else(|| request_token(req))?;
+        self.lookup(&key)
+    }
+}
+
+/// Get the scope required for a request path (after URL prefix)
+fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    let mut segs = path.split('/').filter(|s| !s.is_empty());
+    match segs.next() {
+        None | Some("index.html" | "browse.html" | "trafdat.css") => None,
+        Some("static" | "healthz" | "schema" | "spec.json") => None,
+        Some("admin" | "metrics") => Some(Scope::Admin),
+        Some("metro_config") => Some(Scope::ReadConfig),
+        Some("jobs") if method != Method::GET => Some(Scope::Write),
+        Some(_) => Some(Scope::ReadData),
+    }
+}
+
+/// Get the identity of a request's key, for logging
+pub fn identity(state: &AppState, req: &HttpRequest) -> Option<String> {
+    state.api_keys.principal(req).map(|p| p.name.clone())
+}
+
+/// Check that a request has an API key with the scope of its route
+pub fn check(req: &ServiceRequest) -> Result<(), Error> {
+    let state = match req.app_data::<web::Data<AppState>>() {
+        Some(state) if state.api_keys.is_enabled() => state,
+        _ => return Ok(()),
+    };
+    let path = req.path();
+    let path = path
+        .strip_prefix(state.config.url_prefix.as_str())
+        .unwrap_or(path);
+    let scope = match required_scope(req.method(), path) {
+        Some(scope) => scope,
+        None => return Ok(()),
+    };
+    match state.api_keys.principal(req.request()) {
+        Some(principal) if principal.has_scope(scope) => Ok(()),
+        Some(_) => Err(Error::Forbidden),
+        // the admin token is still accepted for admin routes
+        None if scope == Scope::Admin
+            && has_admin_token(state, req.request()) =>
+        {
+            Ok(())
+        }
+        None if scope == Scope::ReadData
+            && signing::is_signed(state, req.request()) =>
+        {
+            Ok(())
+        }
+        None => Err(Error::Unauthorized),
+    }
+}
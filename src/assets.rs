@@ -0,0 +1,120 @@
+// assets.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::state::AppState;
+use crate::template;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{web, HttpResponse};
+
+/// Cache control for fingerprinted assets
+const CACHE_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Cache control for assets at unversioned URLs
+const CACHE_SHORT: &str = "public, max-age=3600";
+
+/// Cache control for HTML pages (which link fingerprinted assets)
+const CACHE_PAGE: &str = "no-cache";
+
+/// Static asset compiled into the binary
+struct Asset {
+    /// Base file stem
+    stem: &'static str,
+    /// File extension
+    ext: &'static str,
+    /// Content type
+    content_type: &'static str,
+    /// Asset contents
+    body: &'static str,
+}
+
+/// Index page template
+const INDEX_HTML: &str = include_str!("index.html");
+
+/// Archive browser page template
+const BROWSE_HTML: &str = include_str!("browse.html");
+
+/// Style sheet for pages
+const TRAFDAT_CSS: Asset = Asset {
+    stem: "trafdat",
+    ext: "css",
+    content_type: "text/css",
+    body: include_str!("trafdat.css"),
+};
+
+/// All fingerprinted assets
+const ASSETS: &[Asset] = &[TRAFDAT_CSS];
+
+/// Calculate 64-bit FNV-1a hash
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Asset {
+    /// Get fingerprinted file name (e.g. `trafdat.0123456789abcdef.css`)
+    fn file_name(&self) -> String {
+        format!(
+            "{}.{:016x}.{}",
+            self.stem,
+            fnv1a(self.body.as_bytes()),
+            self.ext
+        )
+    }
+
+    /// Get URL relative to pages
+    fn url(&self) -> String {
+        format!("static/{}", self.file_name())
+    }
+
+    /// Build a response with cache control
+    fn response(&self, cache: &'static str) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(self.content_type)
+            .insert_header((CACHE_CONTROL, cache))
+            .body(self.body)
+    }
+}
+
+/// Render a page template
+fn page(state: &AppState, html: &str) -> HttpResponse {
+    let config = &state.config;
+    let css = TRAFDAT_CSS.url();
+    let vars = [
+        ("css", css.as_str()),
+        ("prefix", config.url_prefix.as_str()),
+        ("district", config.district_default.as_str()),
+    ];
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header((CACHE_CONTROL, CACHE_PAGE))
+        .body(template::render(html, &vars))
+}
+
+/// Handle a request for index page
+pub async fn handle_index(state: web::Data<AppState>) -> HttpResponse {
+    page(&state, INDEX_HTML)
+}
+
+/// Handle a request for the archive browser
+pub async fn handle_browse(state: web::Data<AppState>) -> HttpResponse {
+    page(&state, BROWSE_HTML)
+}
+
+/// Handle a request for CSS at its unversioned URL
+pub async fn handle_css() -> HttpResponse {
+    TRAFDAT_CSS.response(CACHE_SHORT)
+}
+
+/// Handle a request for a fingerprinted asset
+pub async fn handle_static(
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    ASSETS
+        .iter()
+        .find(|asset| asset.file_name() == *path)
+        .map(|asset| asset.response(CACHE_IMMUTABLE))
+        .ok_or(Error::NotFound)
+}
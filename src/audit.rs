@@ -0,0 +1,121 @@
+// audit.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Append-only audit log of admin and write operations.
+//
+// Each operation is appended to `TRAFDAT_AUDIT_PATH` as a JSON line, with
+// its time, principal (API key name, `admin` for the admin token), action
+// and target.
+//
+use crate::admin::{check_auth, has_admin_token};
+use crate::apikey;
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::state::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{SecondsFormat, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+
+/// Default number of entries to tail
+const TAIL_DEFAULT: usize = 100;
+
+/// Maximum number of entries to tail
+const TAIL_MAX: usize = 1000;
+
+/// Audit log entry
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct AuditEntry {
+    /// Time of operation (RFC 3339)
+    pub time: String,
+    /// Principal performing the operation
+    pub principal: String,
+    /// Operation (e.g. `job.submit`)
+    pub action: String,
+    /// Target of the operation
+    pub target: String,
+}
+
+/// Query parameters for tailing the audit log
+#[derive(Deserialize)]
+struct TailParams {
+    /// Number of recent entries
+    limit: Option<usize>,
+}
+
+/// Get the principal of a request
+fn principal(state: &AppState, req: &HttpRequest) -> String {
+    match apikey::identity(state, req) {
+        Some(name) => name,
+        None if has_admin_token(state, req) => "admin".into(),
+        None => "anonymous".into(),
+    }
+}
+
+/// Append an entry to the audit log
+fn append(state: &AppState, entry: &AuditEntry) -> Result<(), Error> {
+    let path = match &state.config.audit_path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // one write per line, so concurrent appends are not interleaved
+    file.write_all(&line)?;
+    Ok(())
+}
+
+/// Record an operation in the audit log
+pub fn record(state: &AppState, req: &HttpRequest, action: &str, target: &str) {
+    let entry = AuditEntry {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        principal: principal(state, req),
+        action: action.into(),
+        target: target.into(),
+    };
+    if let Err(e) = append(state, &entry) {
+        warn!("audit {} {}: {}", action, target, e);
+    }
+}
+
+/// Read the most recent entries of the audit log
+fn tail(state: &AppState, limit: usize) -> Result<Vec<AuditEntry>, Error> {
+    let path = state.config.audit_path.as_ref().ok_or(Error::NotFound)?;
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = VecDeque::with_capacity(limit);
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if entries.len() == limit {
+            entries.pop_front();
+        }
+        entries.push_back(serde_json::from_str(&line)?);
+    }
+    Ok(entries.into())
+}
+
+/// Handle request for recent audit log entries
+pub async fn handle_tail(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    check_auth(&state, &req)?;
+    let params = web::Query::<TailParams>::from_query(req.query_string())
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let limit = params.limit.unwrap_or(TAIL_DEFAULT).clamp(1, TAIL_MAX);
+    let entries = tail(&state, limit)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&entries)?))
+}
@@ -0,0 +1,423 @@
+// avail.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Multi-day summaries for a set of sensors: dates with complete data,
+// average daily traffic and percentiles of sample values.
+//
+use crate::align::split_list;
+use crate::dates::{self, dates_in, DATE_FMT};
+use crate::error::Error;
+use crate::memo::Memo;
+use crate::schema::JsonSchema;
+use crate::sensor::{
+    build_json, json_response, lookup_dates, read_series, sample_scale,
+    sample_type,
+};
+use crate::state::AppState;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, HttpResponse};
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+
+/// Maximum number of sensors in a request
+const MAX_SENSORS: usize = 1000;
+
+/// Default sample extension to check
+const EXT_DEFAULT: &str = "v30";
+
+/// Default percentiles
+const PERCENTILES_DEFAULT: &str = "15,50,85";
+
+/// Maximum number of percentiles in a request
+const MAX_PERCENTILES: usize = 20;
+
+/// Header indicating memoized result `HIT` or `MISS`
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// Query parameters for multi-day requests
+#[derive(Deserialize)]
+struct RangeParams {
+    /// Sensor IDs (comma separated)
+    sensors: String,
+    /// First date (inclusive)
+    start: String,
+    /// Last date (inclusive)
+    end: String,
+    /// Sample file extension
+    ext: Option<String>,
+}
+
+/// Query parameters for percentile requests
+#[derive(Deserialize)]
+struct PercentileParams {
+    /// Percentiles (comma separated)
+    p: Option<String>,
+}
+
+/// Memo key for a multi-day request
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RangeKey {
+    /// District ID
+    pub district: String,
+    /// Sensor IDs
+    pub sensors: Vec<String>,
+    /// First date
+    pub start: NaiveDate,
+    /// Last date (inclusive)
+    pub end: NaiveDate,
+    /// Sample file extension
+    pub ext: String,
+}
+
+/// Average daily traffic of a sensor (`aadt.json`)
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub struct SensorAadt {
+    /// Sensor ID
+    sensor: String,
+    /// Number of complete days averaged
+    days: usize,
+    /// Average daily volume, or `None` without complete days
+    aadt: Option<u32>,
+}
+
+/// Value at one percentile
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub struct Percentile {
+    /// Percentile (0-100)
+    p: u32,
+    /// Sample value, or `None` without valid samples
+    value: Option<f64>,
+}
+
+/// Percentiles of sample values of a sensor (`percentiles.json`)
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub struct SensorPercentiles {
+    /// Sensor ID
+    sensor: String,
+    /// Number of valid samples
+    samples: usize,
+    /// Values at requested percentiles
+    percentiles: Vec<Percentile>,
+}
+
+/// Check if a sensor has complete data (no missing samples) on a date
+fn is_complete(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<bool, Error> {
+    Ok(read_series(state, district, date, sid, ext)?
+        .is_some_and(|series| series.values().iter().all(Option::is_some)))
+}
+
+/// Check if all sensors have complete data on a date
+fn all_complete(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sensors: &[String],
+    ext: &str,
+) -> Result<bool, Error> {
+    for sid in sensors {
+        if !is_complete(state, district, date, sid, ext)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Get the total volume of a sensor on a date, if complete
+fn daily_volume(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<i64>, Error> {
+    Ok(
+        read_series(state, district, date, sid, ext)?.and_then(|series| {
+            series.values().iter().map(|v| v.map(i64::from)).sum()
+        }),
+    )
+}
+
+impl RangeKey {
+    /// Parse a multi-day request
+    fn parse(
+        district: &str,
+        params: &RangeParams,
+        ext: &str,
+    ) -> Result<Self, Error> {
+        let sensors = split_list("sensors", &params.sensors)?;
+        if sensors.len() > MAX_SENSORS {
+            return Err(Error::InvalidParam(format!(
+                "sensors: {} > {}",
+                sensors.len(),
+                MAX_SENSORS
+            )));
+        }
+        let (start, end) = dates::parse_range(&params.start, &params.end)?;
+        Ok(RangeKey {
+            district: district.to_string(),
+            sensors: sensors.into_iter().map(String::from).collect(),
+            start,
+            end,
+            ext: ext.to_string(),
+        })
+    }
+
+    /// Get all dates in the range
+    fn dates(&self) -> Vec<String> {
+        dates_in(self.start, self.end)
+    }
+
+    /// Get dates archived in the range
+    fn archived_dates(&self, state: &AppState) -> Vec<String> {
+        let first = self.start.format(DATE_FMT).to_string();
+        let last = self.end.format(DATE_FMT).to_string();
+        let mut dates = vec![];
+        for year in self.start.year()..=self.end.year() {
+            for date in lookup_dates(state, &self.district, &year.to_string()) {
+                if date >= first && date <= last {
+                    dates.push(date);
+                }
+            }
+        }
+        dates.sort();
+        dates.dedup();
+        dates
+    }
+
+    /// Check if the range is historical (will not change).
+    ///
+    /// It must end before the settle period, or have every date in a
+    /// finalized (zip) archive rather than a date directory still being
+    /// written.
+    fn is_historical(&self, state: &AppState, archived: &[String]) -> bool {
+        let settle = Duration::days(state.config.settle_days.into());
+        if self.end + settle < Local::now().date_naive() {
+            return true;
+        }
+        let dates = self.dates();
+        dates == archived
+            && dates.iter().all(|date| {
+                !state.storage.date_path(&self.district, date).is_dir()
+            })
+    }
+
+    /// Get dates in the range on which all sensors have complete data
+    fn complete_dates(&self, state: &AppState) -> Result<Vec<String>, Error> {
+        let all_dates = self.dates();
+        let complete = state.days.map(&all_dates, |date| {
+            all_complete(state, &self.district, date, &self.sensors, &self.ext)
+        });
+        let mut dates = vec![];
+        for (date, complete) in all_dates.into_iter().zip(complete) {
+            if complete? {
+                dates.push(date);
+            }
+        }
+        Ok(dates)
+    }
+
+    /// Get average daily traffic of each sensor over complete days
+    fn aadt(&self, state: &AppState) -> Result<Vec<SensorAadt>, Error> {
+        let totals = state.days.map(&self.dates(), |date| {
+            self.sensors
+                .iter()
+                .map(|sid| {
+                    daily_volume(state, &self.district, date, sid, &self.ext)
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        });
+        let mut sums = vec![(0, 0); self.sensors.len()];
+        for day in totals {
+            for (sum, total) in sums.iter_mut().zip(day?) {
+                if let Some(total) = total {
+                    sum.0 += total;
+                    sum.1 += 1;
+                }
+            }
+        }
+        Ok(self
+            .sensors
+            .iter()
+            .zip(sums)
+            .map(|(sid, (total, days))| SensorAadt {
+                sensor: sid.clone(),
+                days,
+                aadt: (days > 0)
+                    .then(|| (total as f64 / days as f64).round() as u32),
+            })
+            .collect())
+    }
+
+    /// Get percentiles of valid sample values of each sensor
+    fn percentiles(
+        &self,
+        state: &AppState,
+        pcts: &[u32],
+    ) -> Result<Vec<SensorPercentiles>, Error> {
+        let scale = sample_type(&self.ext)
+            .map_or(1.0, |(prefix, _)| sample_scale(prefix));
+        let dates = self.dates();
+        let mut res = vec![];
+        for sid in &self.sensors {
+            let days = state.days.map(&dates, |date| {
+                read_series(state, &self.district, date, sid, &self.ext)
+            });
+            let mut values = vec![];
+            for series in days {
+                if let Some(series) = series? {
+                    values.extend(series.values().iter().flatten());
+                }
+            }
+            values.sort_unstable();
+            let percentiles = pcts
+                .iter()
+                .map(|p| Percentile {
+                    p: *p,
+                    value: nearest_rank(&values, *p)
+                        .map(|v| f64::from(v) * scale),
+                })
+                .collect();
+            res.push(SensorPercentiles {
+                sensor: sid.clone(),
+                samples: values.len(),
+                percentiles,
+            });
+        }
+        Ok(res)
+    }
+}
+
+/// Get the value at a percentile of sorted values (nearest rank method)
+fn nearest_rank(sorted: &[i32], p: u32) -> Option<i32> {
+    let rank = (f64::from(p) / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+/// Parse a list of percentiles
+fn parse_percentiles(list: &str) -> Result<Vec<u32>, Error> {
+    let mut pcts = vec![];
+    for p in split_list("p", list)? {
+        match p.parse() {
+            Ok(p) if p <= 100 => pcts.push(p),
+            _ => return Err(Error::InvalidParam(format!("p: {}", p))),
+        }
+    }
+    if pcts.len() > MAX_PERCENTILES {
+        return Err(Error::InvalidParam(format!(
+            "p: {} > {}",
+            pcts.len(),
+            MAX_PERCENTILES
+        )));
+    }
+    Ok(pcts)
+}
+
+/// Parse query parameters of a multi-day request
+fn parse_range(district: &str, query: &str) -> Result<RangeKey, Error> {
+    let params = web::Query::<RangeParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let ext = params.ext.as_deref().unwrap_or(EXT_DEFAULT);
+    RangeKey::parse(district, &params, ext)
+}
+
+/// Get a result, memoized if the range is historical.
+///
+/// Returns the result, and whether it was memoized (`None` if the range is
+/// not historical).
+fn memoized<K, T, F>(
+    state: &AppState,
+    memo: &Memo<K, T>,
+    range: &RangeKey,
+    key: K,
+    compute: F,
+) -> Result<(T, Option<bool>), Error>
+where
+    K: Eq + Hash,
+    T: Clone,
+    F: FnOnce() -> Result<T, Error>,
+{
+    let archived = range.archived_dates(state);
+    if range.is_historical(state, &archived) {
+        let lookup = memo.get_or_compute(key, archived, compute)?;
+        Ok((lookup.value, Some(lookup.hit)))
+    } else {
+        Ok((compute()?, None))
+    }
+}
+
+/// Build a JSON response, with a memo `X-Cache` header
+fn memo_response(json: String, hit: Option<bool>) -> HttpResponse {
+    let mut res = json_response(json);
+    if let Some(hit) = hit {
+        let hit = if hit { "HIT" } else { "MISS" };
+        res.headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static(hit));
+    }
+    res
+}
+
+/// Handle request for dates on which all sensors have complete data
+pub fn handle_complete_dates(
+    state: &AppState,
+    district: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let range = parse_range(district, query)?;
+    let (dates, hit) =
+        memoized(state, &state.complete_dates, &range, range.clone(), || {
+            range.complete_dates(state)
+        })?;
+    let json = if dates.is_empty() {
+        "[]".to_string()
+    } else {
+        build_json(dates)?
+    };
+    Ok(memo_response(json, hit))
+}
+
+/// Handle request for average daily traffic over complete days
+pub fn handle_aadt(
+    state: &AppState,
+    district: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let range = parse_range(district, query)?;
+    match sample_type(&range.ext) {
+        Some(("v", _)) => (),
+        _ => return Err(Error::InvalidParam(format!("ext: {}", range.ext))),
+    }
+    let (aadt, hit) =
+        memoized(state, &state.aadt, &range, range.clone(), || {
+            range.aadt(state)
+        })?;
+    Ok(memo_response(serde_json::to_string(&aadt)?, hit))
+}
+
+/// Handle request for percentiles of sample values
+pub fn handle_percentiles(
+    state: &AppState,
+    district: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let range = parse_range(district, query)?;
+    if sample_type(&range.ext).is_none() {
+        return Err(Error::InvalidParam(format!("ext: {}", range.ext)));
+    }
+    let params = web::Query::<PercentileParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let pcts =
+        parse_percentiles(params.p.as_deref().unwrap_or(PERCENTILES_DEFAULT))?;
+    let key = (range.clone(), pcts.clone());
+    let (pcts, hit) = memoized(state, &state.percentiles, &range, key, || {
+        range.percentiles(state, &pcts)
+    })?;
+    Ok(memo_response(serde_json::to_string(&pcts)?, hit))
+}
@@ -0,0 +1,111 @@
+// backfill.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::dates::{parse_date, DATE_FMT};
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use crate::sensor::{lookup_archived, read_archived, sample_type};
+use crate::state::{AppState, Config};
+use crate::vlog::{VehicleLog, VLOG_EXT};
+use log::{info, warn};
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+
+/// Period of backfilled binned files (seconds)
+const PERIOD: u32 = 30;
+
+/// Binned files derived from a vehicle event log
+fn derived_files(vlog: &VehicleLog) -> Vec<(String, SampleSeries)> {
+    vec![
+        (format!("v{}", PERIOD), vlog.bin_volume(PERIOD)),
+        (format!("s{}", PERIOD), vlog.bin_speed(PERIOD)),
+        (format!("o{}", PERIOD), vlog.bin_occupancy(PERIOD)),
+    ]
+}
+
+/// Write a binned sample file
+fn write_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+/// Backfill binned files for one date.
+///
+/// Returns the number of files written.
+fn backfill_date(
+    state: &AppState,
+    district: &str,
+    date: &str,
+) -> Result<usize, Error> {
+    let dir = state.storage.date_path(district, date);
+    let mut sensors = lookup_archived(state, district, date);
+    sensors.sort();
+    sensors.dedup();
+    let mut n_files = 0;
+    for sid in sensors {
+        let data = match read_archived(state, district, date, &sid, VLOG_EXT) {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("{} {}: {}", date, sid, e);
+                continue;
+            }
+        };
+        let vlog = VehicleLog::parse(&String::from_utf8_lossy(&data));
+        for (ext, series) in derived_files(&vlog) {
+            let path = dir.join(format!("{}.{}", sid, ext));
+            let archived = read_archived(state, district, date, &sid, &ext);
+            if path.exists() || !matches!(archived, Ok(None)) {
+                continue;
+            }
+            if let Some((prefix, bytes)) = sample_type(&ext) {
+                let marker = state.config.marker(prefix);
+                create_dir_all(&dir)?;
+                write_file(&path, &series.encode_marked(bytes, marker))?;
+                info!("backfilled {:?}", path);
+                n_files += 1;
+            }
+        }
+    }
+    Ok(n_files)
+}
+
+/// Backfill binned files from vehicle event logs for a range of dates.
+///
+/// * `args` Command arguments: district, start date and end date.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (district, start, end) = match args {
+        [district, start, end] => (
+            district,
+            parse_date("date", start)?,
+            parse_date("date", end)?,
+        ),
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: backfill <district> <start_date> <end_date>".into(),
+            ))
+        }
+    };
+    if start > end {
+        return Err(Error::InvalidParam("start date after end date".into()));
+    }
+    let state = AppState::new(Config::from_env()?);
+    let mut total = 0;
+    let mut day = start;
+    while day <= end {
+        let date = day.format(DATE_FMT).to_string();
+        match backfill_date(&state, district, &date) {
+            Ok(n) => total += n,
+            Err(e) => warn!("{}: {}", date, e),
+        }
+        day = match day.succ_opt() {
+            Some(d) => d,
+            None => break,
+        };
+    }
+    println!("backfilled {} files", total);
+    Ok(())
+}
@@ -0,0 +1,179 @@
+// balance.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::annotate::{load_matching, Annotation};
+use crate::corridor::{
+    load_locations, read_station_volume, read_volume, CorridorParams, Location,
+    Locations,
+};
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+
+/// Volume series (per interval)
+type Volume = Vec<Option<i32>>;
+
+/// Flow balance for a segment between two stations
+#[derive(JsonSchema, Serialize)]
+struct Segment<'a> {
+    /// Upstream station
+    from: &'a str,
+    /// Downstream station
+    to: &'a str,
+    /// Segment length (miles)
+    miles: f64,
+    /// Number of intervals with complete data
+    intervals: usize,
+    /// Upstream mainline volume
+    upstream: i64,
+    /// Entrance ramp volume
+    entrance: i64,
+    /// Exit ramp volume
+    exit: i64,
+    /// Downstream mainline volume
+    downstream: i64,
+    /// Conservation error (downstream - upstream - entrance + exit)
+    error: Option<i64>,
+    /// Error as percent of inflow
+    error_pct: Option<f64>,
+}
+
+/// Ramp flow balance for a corridor
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Balance<'a> {
+    corridor: &'a str,
+    segments: Vec<Segment<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+    /// Configuration warnings
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Sum volume over intervals with complete data
+fn sum_complete(series: &[&Volume], k: usize) -> Option<i64> {
+    series.iter().map(|s| s[k].map(i64::from)).sum()
+}
+
+/// Calculate balance for one segment
+fn segment<'a>(
+    up: &'a Location,
+    down: &'a Location,
+    up_vol: Option<Volume>,
+    down_vol: Option<Volume>,
+    entrances: Option<Vec<Volume>>,
+    exits: Option<Vec<Volume>>,
+) -> Segment<'a> {
+    let mut seg = Segment {
+        from: up.id(),
+        to: down.id(),
+        miles: down.mile - up.mile,
+        intervals: 0,
+        upstream: 0,
+        entrance: 0,
+        exit: 0,
+        downstream: 0,
+        error: None,
+        error_pct: None,
+    };
+    let (up_vol, down_vol, entrances, exits) =
+        match (up_vol, down_vol, entrances, exits) {
+            (Some(u), Some(d), Some(en), Some(ex)) => (u, d, en, ex),
+            _ => return seg,
+        };
+    let entrances: Vec<&Volume> = entrances.iter().collect();
+    let exits: Vec<&Volume> = exits.iter().collect();
+    for k in 0..up_vol.len().min(down_vol.len()) {
+        let vals = (
+            up_vol[k],
+            down_vol[k],
+            sum_complete(&entrances, k),
+            sum_complete(&exits, k),
+        );
+        if let (Some(u), Some(d), Some(en), Some(ex)) = vals {
+            seg.intervals += 1;
+            seg.upstream += i64::from(u);
+            seg.downstream += i64::from(d);
+            seg.entrance += en;
+            seg.exit += ex;
+        }
+    }
+    if seg.intervals > 0 {
+        let error = seg.downstream - seg.upstream - seg.entrance + seg.exit;
+        let inflow = seg.upstream + seg.entrance;
+        seg.error = Some(error);
+        if inflow > 0 {
+            seg.error_pct = Some(error as f64 * 100.0 / inflow as f64);
+        }
+    }
+    seg
+}
+
+/// Read volume of ramps, or `None` if any ramp has no data
+fn read_ramps(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    ramps: &[Vec<&str>],
+) -> Result<Option<Vec<Volume>>, Error> {
+    let mut vols = vec![];
+    for dets in ramps.iter().filter(|dets| !dets.is_empty()) {
+        match read_volume(state, district, date, dets)? {
+            Some(vol) => vols.push(vol),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(vols))
+}
+
+/// Handle request for ramp flow balance along a corridor
+pub fn handle_balance(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<CorridorParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let corridor = params.corridor()?;
+    let Locations { locs, warnings } = load_locations(state, date, corridor)?;
+    let stations: Vec<usize> = (0..locs.len())
+        .filter(|i| {
+            locs[*i].is_station() && !locs[*i].mainline(params.lanes).is_empty()
+        })
+        .collect();
+    let mut segments = vec![];
+    for pair in stations.windows(2) {
+        let (i, j) = (pair[0], pair[1]);
+        let between = &locs[i + 1..j];
+        let entrances: Vec<Vec<&str>> = between
+            .iter()
+            .filter(|loc| loc.node.n_type == "Entrance")
+            .map(|loc| loc.entrance())
+            .collect();
+        let exits: Vec<Vec<&str>> = between
+            .iter()
+            .filter(|loc| loc.node.n_type == "Exit")
+            .map(|loc| loc.exit())
+            .collect();
+        segments.push(segment(
+            &locs[i],
+            &locs[j],
+            read_station_volume(state, district, date, &locs[i], params.lanes)?,
+            read_station_volume(state, district, date, &locs[j], params.lanes)?,
+            read_ramps(state, district, date, &entrances)?,
+            read_ramps(state, district, date, &exits)?,
+        ));
+    }
+    let balance = Balance {
+        corridor,
+        segments,
+        annotations: load_matching(state, district, date, corridor)?,
+        warnings,
+    };
+    Ok(json_response(serde_json::to_string(&balance)?))
+}
@@ -0,0 +1,120 @@
+// baseline.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Comparison of a day of samples with a same weekday baseline.
+//
+use crate::dates::DATE_FMT;
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use crate::schema::JsonSchema;
+use crate::sensor::{json_response, read_series, sample_scale, sample_type};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Default sample extension
+const EXT_DEFAULT: &str = "v30";
+
+/// Default number of previous weeks in baseline
+const WEEKS_DEFAULT: u32 = 4;
+
+/// Maximum number of previous weeks in baseline
+const MAX_WEEKS: u32 = 52;
+
+/// Query parameters for baseline requests
+#[derive(Deserialize)]
+struct BaselineParams {
+    /// Sample file extension
+    ext: Option<String>,
+    /// Number of previous same weekdays to average
+    weeks: Option<u32>,
+}
+
+/// Day of samples with baseline (JSON)
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Comparison<'a> {
+    sid: &'a str,
+    ext: &'a str,
+    date: &'a str,
+    /// Sample period (seconds)
+    period: u32,
+    /// Previous same weekdays with data, used for baseline
+    dates: Vec<String>,
+    /// Sample values on the date (decoded)
+    values: Vec<Option<f64>>,
+    /// Average of values on baseline dates, per interval
+    baseline: Vec<Option<f64>>,
+}
+
+/// Get decoded (scaled) sample values
+fn scaled(series: &SampleSeries, scale: f64) -> Vec<Option<f64>> {
+    series
+        .values()
+        .iter()
+        .map(|v| v.map(|v| f64::from(v) * scale))
+        .collect()
+}
+
+/// Average values per interval, skipping missing samples
+fn average(days: &[Vec<Option<f64>>], len: usize) -> Vec<Option<f64>> {
+    (0..len)
+        .map(|i| {
+            let vals: Vec<f64> = days.iter().filter_map(|d| d[i]).collect();
+            match vals.len() {
+                0 => None,
+                n => Some(vals.iter().sum::<f64>() / n as f64),
+            }
+        })
+        .collect()
+}
+
+/// Handle request for a day of samples with same weekday baseline
+pub fn handle_baseline(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<BaselineParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let ext = params.ext.as_deref().unwrap_or(EXT_DEFAULT);
+    let (prefix, _) = sample_type(ext)
+        .filter(|_| ext != "vlog")
+        .ok_or_else(|| Error::InvalidParam(format!("ext: {}", ext)))?;
+    let weeks = params.weeks.unwrap_or(WEEKS_DEFAULT);
+    if !(1..=MAX_WEEKS).contains(&weeks) {
+        return Err(Error::InvalidParam(format!("weeks: {}", weeks)));
+    }
+    let scale = sample_scale(prefix);
+    let day = NaiveDate::parse_from_str(date, DATE_FMT)
+        .map_err(|_| Error::InvalidParam(format!("date: {}", date)))?;
+    let series =
+        read_series(state, district, date, sid, ext)?.ok_or(Error::NotFound)?;
+    let period = series.period();
+    let values = scaled(&series, scale);
+    let mut dates = vec![];
+    let mut days = vec![];
+    for week in 1..=weeks {
+        let prev = day - Duration::weeks(i64::from(week));
+        let prev = prev.format(DATE_FMT).to_string();
+        if let Some(series) = read_series(state, district, &prev, sid, ext)? {
+            if series.period() == period {
+                dates.push(prev);
+                days.push(scaled(&series, scale));
+            }
+        }
+    }
+    let res = Comparison {
+        sid,
+        ext,
+        date,
+        period,
+        dates,
+        baseline: average(&days, values.len()),
+        values,
+    };
+    Ok(json_response(serde_json::to_string(&res)?))
+}
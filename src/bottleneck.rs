@@ -0,0 +1,220 @@
+// bottleneck.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::annotate::{load_matching, Annotation};
+use crate::corridor::{
+    bin_speed, bin_volume, load_locations, read_speed, read_station_volume,
+    required, time_of_day, Lanes, Location, Locations, Weight,
+};
+use crate::error::Error;
+use crate::sample::Mean;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// Number of 30-second samples in an analysis interval (5 minutes)
+const BIN_SAMPLES: usize = 10;
+
+/// Analysis interval (seconds)
+const BIN_SECS: u32 = 300;
+
+/// Query parameters for bottleneck requests
+#[derive(Deserialize)]
+struct BottleneckParams {
+    /// Corridor (`route_dir`)
+    corridor: Option<String>,
+    /// Upstream speed threshold (mph)
+    speed: Option<f64>,
+    /// Minimum speed drop from downstream to upstream (mph)
+    drop: Option<f64>,
+    /// Minimum consecutive 5-minute intervals
+    sustain: Option<usize>,
+    /// Reference (free-flow) speed for delay (mph)
+    free: Option<f64>,
+    /// Mainline lane group
+    #[serde(default)]
+    lanes: Lanes,
+    /// Mean for aggregating speeds
+    #[serde(default)]
+    mean: Mean,
+    /// Weighting for aggregating speeds
+    #[serde(default)]
+    weight: Weight,
+}
+
+/// Active bottleneck episode
+#[derive(JsonSchema, Serialize)]
+struct Bottleneck<'a> {
+    /// Bottleneck (downstream) station
+    station: &'a str,
+    /// Upstream (queued) station
+    upstream: &'a str,
+    /// Activation time (HH:MM)
+    activation: String,
+    /// Deactivation time (HH:MM)
+    deactivation: String,
+    /// Number of active 5-minute intervals
+    intervals: usize,
+    /// Mean downstream discharge flow (vehicles per hour)
+    discharge: Option<f64>,
+    /// Delay on upstream segment (vehicle-hours)
+    delay: f64,
+}
+
+/// Bottlenecks on a corridor
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Bottlenecks<'a> {
+    corridor: &'a str,
+    bottlenecks: Vec<Bottleneck<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+    /// Configuration warnings
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Analysis thresholds
+struct Thresholds {
+    speed: f64,
+    drop: f64,
+    sustain: usize,
+    free: f64,
+}
+
+impl Thresholds {
+    /// Get thresholds from query parameters
+    fn new(params: &BottleneckParams) -> Result<Self, Error> {
+        let th = Thresholds {
+            speed: params.speed.unwrap_or(40.0),
+            drop: params.drop.unwrap_or(20.0),
+            sustain: params.sustain.unwrap_or(3),
+            free: params.free.unwrap_or(60.0),
+        };
+        for (name, val) in [("speed", th.speed), ("drop", th.drop)] {
+            if !val.is_finite() || val <= 0.0 {
+                return Err(Error::InvalidParam(format!("{}: {}", name, val)));
+            }
+        }
+        if !th.free.is_finite() || th.free <= 0.0 {
+            return Err(Error::InvalidParam(format!("free: {}", th.free)));
+        }
+        if th.sustain == 0 {
+            return Err(Error::InvalidParam("sustain: 0".into()));
+        }
+        Ok(th)
+    }
+}
+
+/// Binned station data
+struct StationBins<'a> {
+    loc: &'a Location,
+    volume: Vec<Option<i32>>,
+    speed: Vec<Option<f64>>,
+}
+
+/// Find bottleneck episodes between two stations
+fn find_episodes<'a>(
+    up: &StationBins<'a>,
+    down: &StationBins<'a>,
+    th: &Thresholds,
+) -> Vec<Bottleneck<'a>> {
+    let len = up.speed.len().min(down.speed.len());
+    let active: Vec<bool> = (0..len)
+        .map(|k| match (up.speed[k], down.speed[k]) {
+            (Some(su), Some(sd)) => su < th.speed && sd - su >= th.drop,
+            _ => false,
+        })
+        .collect();
+    let miles = down.loc.mile - up.loc.mile;
+    let mut episodes = vec![];
+    let mut k = 0;
+    while k < len {
+        if !active[k] {
+            k += 1;
+            continue;
+        }
+        let start = k;
+        while k < len && active[k] {
+            k += 1;
+        }
+        if k - start < th.sustain {
+            continue;
+        }
+        let flows: Vec<f64> = (start..k)
+            .filter_map(|i| *down.volume.get(i)?)
+            .map(|v| f64::from(v) * f64::from(3600 / BIN_SECS))
+            .collect();
+        let discharge = match flows.len() {
+            0 => None,
+            n => Some(flows.iter().sum::<f64>() / n as f64),
+        };
+        let delay = (start..k)
+            .filter_map(|i| match (up.volume.get(i)?, up.speed[i]) {
+                (Some(v), Some(s)) if s > 0.0 && s < th.free => {
+                    Some(f64::from(*v) * miles * (1.0 / s - 1.0 / th.free))
+                }
+                _ => None,
+            })
+            .sum();
+        episodes.push(Bottleneck {
+            station: down.loc.id(),
+            upstream: up.loc.id(),
+            activation: time_of_day(start as u32 * BIN_SECS),
+            deactivation: time_of_day(k as u32 * BIN_SECS),
+            intervals: k - start,
+            discharge,
+            delay,
+        });
+    }
+    episodes
+}
+
+/// Handle request for active bottlenecks along a corridor
+pub fn handle_bottlenecks(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<BottleneckParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let corridor = required(params.corridor.as_deref())?;
+    let th = Thresholds::new(&params)?;
+    let Locations { locs, warnings } = load_locations(state, date, corridor)?;
+    let mut stations = vec![];
+    for loc in locs.iter().filter(|loc| loc.is_station()) {
+        let speed = read_speed(
+            state,
+            district,
+            date,
+            &loc.mainline_fields(params.lanes),
+            params.mean,
+            params.weight,
+        )?;
+        if let Some(speed) = speed {
+            let volume =
+                read_station_volume(state, district, date, loc, params.lanes)?
+                    .unwrap_or_default();
+            let (mean, weight) = (params.mean, params.weight);
+            stations.push(StationBins {
+                loc,
+                volume: bin_volume(&volume, BIN_SAMPLES),
+                speed: bin_speed(&speed, &volume, BIN_SAMPLES, mean, weight),
+            });
+        }
+    }
+    let bottlenecks = stations
+        .windows(2)
+        .flat_map(|pair| find_episodes(&pair[0], &pair[1], &th))
+        .collect();
+    let res = Bottlenecks {
+        corridor,
+        bottlenecks,
+        annotations: load_matching(state, district, date, corridor)?,
+        warnings,
+    };
+    Ok(json_response(serde_json::to_string(&res)?))
+}
@@ -0,0 +1,244 @@
+// cache.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// In-memory TTL cache for derived responses, local to one server process.
+//
+use crate::apikey::identity;
+use crate::error::Error;
+use crate::state::AppState;
+use actix_web::body::MessageBody;
+use actix_web::http::header::{self, HeaderMap, HeaderName};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum number of cached responses
+const MAX_ENTRIES: usize = 1024;
+
+/// Header indicating cache `HIT` or `MISS`
+const X_CACHE: &str = "x-cache";
+
+/// Headers which are not cached (hop-by-hop, or set for each response)
+const UNCACHED: &[HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Cached response
+struct Entry {
+    /// Expiration time
+    expires: Instant,
+    /// Response headers
+    headers: HeaderMap,
+    /// Response body
+    body: Bytes,
+}
+
+/// In-memory cache of expensive responses
+pub struct ResponseCache {
+    /// Time to live for entries (zero disables caching)
+    ttl: Duration,
+    /// Cached entries by normalized URL
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Number of cache hits
+    hits: AtomicU64,
+    /// Number of cache misses
+    misses: AtomicU64,
+}
+
+/// Summary of a cached entry
+pub struct EntryInfo {
+    /// Cache key (path and sorted query parameters)
+    pub key: String,
+    /// Body size in bytes
+    pub bytes: usize,
+    /// Time until expiration
+    pub expires_in: Duration,
+}
+
+/// Build a response from a cached entry
+fn build_response(entry: &Entry, hit: &'static str) -> HttpResponse {
+    let mut res = HttpResponse::Ok();
+    for (name, value) in &entry.headers {
+        res.append_header((name.clone(), value.clone()));
+    }
+    res.insert_header((X_CACHE, hit)).body(entry.body.clone())
+}
+
+/// Check if a response header is cached
+fn is_cached(name: &HeaderName) -> bool {
+    !UNCACHED.contains(name) && name != X_CACHE && name != "keep-alive"
+}
+
+/// Get a cache key from a request path, normalized query and identity.
+///
+/// Query parameters are decoded and sorted, so equivalent queries share
+/// one entry.  Responses for API key holders are cached separately for each
+/// holder, since their scopes may differ.
+pub fn cache_key(req: &HttpRequest) -> String {
+    let mut params =
+        web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+            .map(|q| q.into_inner())
+            .unwrap_or_default();
+    params.sort();
+    let holder = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| identity(state, req));
+    match holder {
+        Some(name) => format!("{} {:?} {}", req.path(), params, name),
+        None => format!("{} {:?}", req.path(), params),
+    }
+}
+
+/// Get the request path of a cache key
+pub fn key_path(key: &str) -> &str {
+    key.split_once(' ').map_or(key, |(path, _)| path)
+}
+
+impl ResponseCache {
+    /// Create a new response cache
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the number of cache hits
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of cache misses
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Get summaries of unexpired entries, sorted by key
+    pub fn entries(&self) -> Vec<EntryInfo> {
+        let now = Instant::now();
+        let mut infos: Vec<EntryInfo> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, e)| e.expires > now)
+            .map(|(key, e)| EntryInfo {
+                key: key.clone(),
+                bytes: e.body.len(),
+                expires_in: e.expires - now,
+            })
+            .collect();
+        infos.sort_by(|a, b| a.key.cmp(&b.key));
+        infos
+    }
+
+    /// Remove entries with a matching request path, returning the number
+    /// removed
+    pub fn flush<F>(&self, matches: F) -> usize
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| !matches(key_path(key)));
+        before - entries.len()
+    }
+
+    /// Check if caching is enabled
+    fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// Get a cached response, or build one and cache it if successful
+    pub fn get_or_insert<F>(
+        &self,
+        req: &HttpRequest,
+        build: F,
+    ) -> Result<HttpResponse, Error>
+    where
+        F: FnOnce() -> Result<HttpResponse, Error>,
+    {
+        if !self.is_enabled() {
+            return build();
+        }
+        let key = cache_key(req);
+        match self.lookup(&key) {
+            Some(res) => Ok(res),
+            None => Ok(self.store(key, build()?)),
+        }
+    }
+
+    /// Look up a cached response by key (counting a hit or miss)
+    pub fn lookup(&self, key: &str) -> Option<HttpResponse> {
+        if !self.is_enabled() {
+            return None;
+        }
+        if let Some(entry) = self.entries.lock().unwrap().get(key) {
+            if entry.expires > Instant::now() {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(build_response(entry, "HIT"));
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Store a response if successful, returning it
+    pub fn store(&self, key: String, res: HttpResponse) -> HttpResponse {
+        if !self.is_enabled() || res.status() != StatusCode::OK {
+            return res;
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in res.headers().iter().filter(|(n, _)| is_cached(n))
+        {
+            headers.append(name.clone(), value.clone());
+        }
+        let (res, body) = res.into_parts();
+        let body = match body.try_into_bytes() {
+            Ok(body) => body,
+            // streaming bodies are not cached
+            Err(body) => return res.set_body(body),
+        };
+        let now = Instant::now();
+        let entry = Entry {
+            expires: now + self.ttl,
+            headers,
+            body,
+        };
+        let res = build_response(&entry, "MISS");
+        self.insert(key, entry, now);
+        res
+    }
+
+    /// Insert an entry, evicting others if the cache is full
+    fn insert(&self, key: String, entry: Entry, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, e| e.expires > now);
+        }
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, e)| e.expires)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, entry);
+    }
+}
@@ -0,0 +1,236 @@
+// client.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Async client for the trafdat HTTP API.
+//
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use crate::sensor::{sample_period, sample_type};
+use crate::signing;
+use crate::sync::FileSum;
+use crate::upstream::{get_request, parse_response, range_request};
+use crate::webhook::parse_url;
+use crate::wire::{Aligned, Corridor, CorridorFeature, YearDates};
+use serde::de::DeserializeOwned;
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Timeout for requests
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client for a trafdat server
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// Base URL
+    url: String,
+    /// Host and port
+    addr: String,
+    /// Base path (URL prefix, without trailing slash)
+    base: String,
+    /// Key for signing archive file requests
+    signing_key: Option<String>,
+}
+
+/// Make a timeout error
+fn timed_out(url: &str) -> Error {
+    Error::Upstream(format!("{}: timed out", url))
+}
+
+impl Client {
+    /// Create a client from a base URL (`http://host[:port]/trafdat`)
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let (addr, path) = parse_url(url)?;
+        Ok(Client {
+            url: url.to_string(),
+            addr,
+            base: path.trim_end_matches('/').to_string(),
+            signing_key: None,
+        })
+    }
+
+    /// Sign archive file requests with a key (`TRAFDAT_SIGNING_KEY` of the
+    /// server)
+    pub fn with_signing_key(mut self, key: String) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
+    /// Make a GET request for a path below the base URL
+    async fn get(&self, path: &str) -> Result<Vec<u8>, Error> {
+        self.get_range(path, None).await
+    }
+
+    /// Make a GET request for a byte range of a path below the base URL
+    async fn get_range(
+        &self,
+        path: &str,
+        range: Option<&Range<u64>>,
+    ) -> Result<Vec<u8>, Error> {
+        let path = format!("{}{}", self.base, path);
+        let request = match range {
+            Some(r) => range_request(&self.addr, &path, r.start, r.end - 1),
+            None => get_request(&self.addr, &path),
+        };
+        let data = timeout(TIMEOUT, async {
+            let mut stream = TcpStream::connect(&self.addr).await?;
+            stream.write_all(request.as_bytes()).await?;
+            let mut data = vec![];
+            stream.read_to_end(&mut data).await?;
+            Ok::<_, std::io::Error>(data)
+        })
+        .await
+        .map_err(|_| timed_out(&self.url))??;
+        let res = parse_response(&self.url, &data)?;
+        match (res.status, range) {
+            (200, None) | (206, Some(_)) => Ok(res.body),
+            // range ignored by server
+            (200, Some(r)) => res
+                .body
+                .get(r.start as usize..r.end as usize)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| Error::Upstream(format!("{}: range", self.url))),
+            (status, _) => Err(self.status_error(status, &res.body)),
+        }
+    }
+
+    /// Make an error for a response status
+    fn status_error(&self, status: u16, body: &[u8]) -> Error {
+        match status {
+            400 => {
+                Error::InvalidParam(String::from_utf8_lossy(body).into_owned())
+            }
+            403 => Error::Forbidden,
+            404 => Error::NotFound,
+            // unaligned series
+            409 => {
+                Error::InvalidParam(String::from_utf8_lossy(body).into_owned())
+            }
+            503 => Error::Unavailable,
+            status => Error::Upstream(format!("{}: {}", self.url, status)),
+        }
+    }
+
+    /// Make a GET request for a JSON response
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<T, Error> {
+        Ok(serde_json::from_slice(&self.get(path).await?)?)
+    }
+
+    /// List districts
+    pub async fn list_districts(&self) -> Result<Vec<String>, Error> {
+        self.get_json("/districts").await
+    }
+
+    /// List sampled years, with number of dates
+    pub async fn years(&self, district: &str) -> Result<Vec<YearDates>, Error> {
+        self.get_json(&format!("/{}/years.json", district)).await
+    }
+
+    /// List sampled dates (yyyyMMdd) in a year
+    pub async fn dates(
+        &self,
+        district: &str,
+        year: &str,
+    ) -> Result<Vec<String>, Error> {
+        self.get_json(&format!("/{}/{}.json", district, year)).await
+    }
+
+    /// List sampled sensors on a date
+    pub async fn sensors(
+        &self,
+        district: &str,
+        date: &str,
+    ) -> Result<Vec<String>, Error> {
+        self.get_json(&format!("/{}/{}", district, date)).await
+    }
+
+    /// Get decoded samples for a sensor and extension (e.g. `v30`)
+    pub async fn get_samples(
+        &self,
+        district: &str,
+        date: &str,
+        sensor: &str,
+        ext: &str,
+    ) -> Result<SampleSeries, Error> {
+        let (_prefix, bytes) = sample_period(ext)
+            .and(sample_type(ext))
+            .ok_or_else(|| Error::InvalidParam(ext.to_string()))?;
+        let path = format!("/{}/{}/{}.{}", district, date, sensor, ext);
+        let data = self.get(&path).await?;
+        Ok(SampleSeries::decode(&data, bytes))
+    }
+
+    /// Get aligned (decoded) samples for sensors and extensions
+    pub async fn aligned(
+        &self,
+        district: &str,
+        date: &str,
+        sensors: &[&str],
+        exts: &[&str],
+    ) -> Result<Aligned<'static>, Error> {
+        let path = format!(
+            "/{}/{}/aligned.json?sensors={}&ext={}",
+            district,
+            date,
+            sensors.join(","),
+            exts.join(",")
+        );
+        self.get_json(&path).await
+    }
+
+    /// List checksums of archive files in a year
+    pub async fn checksums(
+        &self,
+        district: &str,
+        year: &str,
+    ) -> Result<Vec<FileSum>, Error> {
+        self.get_json(&format!("/{}/{}/checksums.json", district, year))
+            .await
+    }
+
+    /// Get an archive file of a year (named as in the checksums), or a byte
+    /// range of it
+    pub async fn archive_file(
+        &self,
+        district: &str,
+        year: &str,
+        name: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut path = format!("/{}/{}/files/{}", district, year, name);
+        if let Some(key) = &self.signing_key {
+            let expires = SystemTime::now() + TIMEOUT;
+            let query = signing::sign(key.as_bytes(), &path, expires);
+            path = format!("{}?{}", path, query);
+        }
+        self.get_range(&path, range.as_ref()).await
+    }
+
+    /// Get a corridor from the metro_config on a date
+    pub async fn corridor(
+        &self,
+        date: &str,
+        route: &str,
+        dir: &str,
+    ) -> Result<Corridor, Error> {
+        let path = format!("/metro_config/{}/{}_{}.json", date, route, dir);
+        self.get_json(&path).await
+    }
+
+    /// Get a corridor line string (GeoJSON) from the metro_config on a date
+    pub async fn corridor_geojson(
+        &self,
+        date: &str,
+        route: &str,
+        dir: &str,
+    ) -> Result<CorridorFeature, Error> {
+        let path = format!("/metro_config/{}/{}_{}.geojson", date, route, dir);
+        self.get_json(&path).await
+    }
+}
@@ -0,0 +1,456 @@
+// corridor.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::geo;
+use crate::metro::{lookup_corridor, RoadNode};
+use crate::sample::Mean;
+use crate::schema::JsonSchema;
+use crate::sensor::read_series;
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// Volume sample extension for corridor analysis
+const VOLUME_EXT: &str = "v30";
+
+/// Speed sample extension for corridor analysis
+const SPEED_EXT: &str = "s30";
+
+/// Occupancy sample extension for corridor analysis
+const OCCUPANCY_EXT: &str = "o30";
+
+/// Number of 30-second samples per hour
+const SAMPLES_PER_HOUR: f64 = 120.0;
+
+/// Feet per mile
+const FEET_PER_MILE: f64 = 5280.0;
+
+/// Maximum valid speed (mph)
+const MAX_SPEED: f64 = 120.0;
+
+/// Category codes of managed (HOV / HOT) lane detectors
+const MANAGED_CODES: &[&str] = &["H", "HT"];
+
+/// Mainline lane group for station aggregates
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Lanes {
+    /// General purpose lanes (no category code)
+    General,
+    /// Managed (HOV / HOT) lanes
+    Managed,
+    /// All mainline lanes combined
+    #[default]
+    All,
+}
+
+impl Lanes {
+    /// Check if a detector category code is in the group
+    fn contains(self, code: &str) -> bool {
+        match self {
+            Lanes::General => code.is_empty(),
+            Lanes::Managed => MANAGED_CODES.contains(&code),
+            Lanes::All => code.is_empty() || MANAGED_CODES.contains(&code),
+        }
+    }
+}
+
+/// Weighting of speeds combined across lanes and intervals
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Weight {
+    /// Weighted by flow (volume)
+    #[default]
+    Flow,
+    /// Equal weights
+    Equal,
+}
+
+impl Weight {
+    /// Combine speeds, with optional volume weights.
+    ///
+    /// Falls back to equal weights unless every valid speed has a volume,
+    /// and their total is positive.
+    fn combine(self, vals: &[(f64, Option<f64>)], mean: Mean) -> Option<f64> {
+        if self == Weight::Flow {
+            let weighted: Option<Vec<(f64, f64)>> =
+                vals.iter().map(|(s, w)| Some((*s, (*w)?))).collect();
+            if let Some(spd) = weighted.and_then(|w| mean.weighted(&w)) {
+                return Some(spd);
+            }
+        }
+        let spd: Vec<f64> = vals.iter().map(|(s, _)| *s).collect();
+        mean.of(&spd)
+    }
+}
+
+/// Query parameters for corridor analysis requests
+#[derive(Deserialize)]
+pub struct CorridorParams {
+    /// Corridor (`route_dir`)
+    pub corridor: Option<String>,
+    /// Mainline lane group
+    #[serde(default)]
+    pub lanes: Lanes,
+}
+
+impl CorridorParams {
+    /// Get the corridor parameter
+    pub fn corridor(&self) -> Result<&str, Error> {
+        required(self.corridor.as_deref())
+    }
+}
+
+/// Check that a corridor parameter is present
+pub fn required(corridor: Option<&str>) -> Result<&str, Error> {
+    corridor.ok_or_else(|| Error::InvalidParam("corridor required".into()))
+}
+
+/// R_Node located along a corridor
+pub struct Location {
+    /// Distance from first node (miles)
+    pub mile: f64,
+    /// R_Node
+    pub node: RoadNode,
+}
+
+impl Location {
+    /// Check if the node is a mainline station
+    pub fn is_station(&self) -> bool {
+        self.node.n_type == "Station" && self.node.station_id.is_some()
+    }
+
+    /// Get the station ID (or r_node name)
+    pub fn id(&self) -> &str {
+        self.node.station_id.as_deref().unwrap_or(&self.node.name)
+    }
+
+    /// Get detectors with any of the category codes
+    pub fn detectors(&self, codes: &[&str]) -> Vec<&str> {
+        self.node
+            .detectors
+            .iter()
+            .filter(|(_, cat, _, _)| codes.contains(&cat.as_str()))
+            .map(|(name, _, _, _)| name.as_str())
+            .collect()
+    }
+
+    /// Get mainline detectors in a lane group
+    pub fn mainline(&self, lanes: Lanes) -> Vec<&str> {
+        self.node
+            .detectors
+            .iter()
+            .filter(|(_, cat, _, _)| lanes.contains(cat))
+            .map(|(name, _, _, _)| name.as_str())
+            .collect()
+    }
+
+    /// Get mainline detectors in a lane group with field lengths (feet)
+    pub fn mainline_fields(&self, lanes: Lanes) -> Vec<(&str, f64)> {
+        self.node
+            .detectors
+            .iter()
+            .filter(|(_, cat, _, _)| lanes.contains(cat))
+            .map(|(name, _, field, _)| (name.as_str(), *field))
+            .collect()
+    }
+
+    /// Get mainline detectors in a lane group with lane numbers and field
+    /// lengths (feet)
+    pub fn mainline_lanes(
+        &self,
+        lanes: Lanes,
+    ) -> Vec<(&str, Option<u32>, f64)> {
+        self.node
+            .detectors
+            .iter()
+            .filter(|(_, cat, _, _)| lanes.contains(cat))
+            .map(|(name, _, field, lane)| (name.as_str(), *lane, *field))
+            .collect()
+    }
+
+    /// Get entrance ramp detectors (merge, else passage, else queue)
+    pub fn entrance(&self) -> Vec<&str> {
+        [&["M", "B"][..], &["P"], &["Q"]]
+            .iter()
+            .map(|codes| self.detectors(codes))
+            .find(|dets| !dets.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// Get exit ramp detectors
+    pub fn exit(&self) -> Vec<&str> {
+        self.detectors(&["X"])
+    }
+}
+
+/// Component samples of one mainline lane
+#[derive(JsonSchema, Serialize)]
+pub struct LaneData<'a> {
+    /// Detector name
+    pub detector: &'a str,
+    /// Lane number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lane: Option<u32>,
+    /// Volume per interval
+    pub volume: Option<Vec<Option<i32>>>,
+    /// Speed (mph) per interval
+    pub speed: Option<Vec<Option<f64>>>,
+}
+
+/// Located r_nodes of a corridor
+pub struct Locations {
+    /// R_Nodes, in corridor order
+    pub locs: Vec<Location>,
+    /// Configuration warnings (such as duplicate detectors)
+    pub warnings: Vec<String>,
+}
+
+/// Load active r_nodes of a corridor, with distance along the corridor.
+///
+/// Detectors listed more than once are kept only at their first r_node (in
+/// corridor order), with a warning for each duplicate.
+pub fn load_locations(
+    state: &AppState,
+    date: &str,
+    corridor: &str,
+) -> Result<Locations, Error> {
+    let mut locs = vec![];
+    let mut warnings = vec![];
+    let mut seen = HashMap::new();
+    let mut mile = 0.0;
+    let mut prev = None;
+    for mut node in lookup_corridor(state, date, corridor)? {
+        if let (Some(a), Some(b)) = (prev, node.pos) {
+            mile += geo::distance_miles(a, b);
+        }
+        prev = node.pos.or(prev);
+        let name = node.name.clone();
+        node.detectors
+            .retain(|(det, _, _, _)| match seen.entry(det.clone()) {
+                Entry::Vacant(e) => {
+                    e.insert(name.clone());
+                    true
+                }
+                Entry::Occupied(e) => {
+                    warnings.push(format!(
+                        "duplicate detector {} in {} (kept {})",
+                        det,
+                        name,
+                        e.get()
+                    ));
+                    false
+                }
+            });
+        locs.push(Location { mile, node });
+    }
+    Ok(Locations { locs, warnings })
+}
+
+/// Read total volume of detectors, per interval.
+///
+/// Intervals are missing if any detector sample is missing; returns `None`
+/// if there are no detectors or any has no data.
+pub fn read_volume(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    dets: &[&str],
+) -> Result<Option<Vec<Option<i32>>>, Error> {
+    let mut total: Option<Vec<Option<i32>>> = None;
+    for det in dets {
+        let series = match read_series(state, district, date, det, VOLUME_EXT)?
+        {
+            Some(series) => series,
+            None => return Ok(None),
+        };
+        total = Some(match total {
+            None => series.values().to_vec(),
+            Some(tot) => tot
+                .iter()
+                .zip(series.values())
+                .map(|(a, b)| Some((*a)? + (*b)?))
+                .collect(),
+        });
+    }
+    Ok(total)
+}
+
+/// Read total mainline volume of a station's lane group, per interval.
+///
+/// For all lanes, general purpose and managed lane groups with no data are
+/// left out; returns `None` if no group has data.
+pub fn read_station_volume(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    loc: &Location,
+    lanes: Lanes,
+) -> Result<Option<Vec<Option<i32>>>, Error> {
+    if lanes != Lanes::All {
+        return read_volume(state, district, date, &loc.mainline(lanes));
+    }
+    let general = loc.mainline(Lanes::General);
+    let general = read_volume(state, district, date, &general)?;
+    let managed = loc.mainline(Lanes::Managed);
+    let managed = read_volume(state, district, date, &managed)?;
+    Ok(match (general, managed) {
+        (Some(gen), Some(man)) => Some(
+            gen.iter()
+                .zip(&man)
+                .map(|(a, b)| Some((*a)? + (*b)?))
+                .collect(),
+        ),
+        (gen, man) => gen.or(man),
+    })
+}
+
+/// Read speed (mph) of one detector, per interval.
+///
+/// Speed samples are used if available; otherwise speed is estimated from
+/// volume, occupancy and field length.
+fn read_detector_speed(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    det: &str,
+    field: f64,
+) -> Result<Option<Vec<Option<f64>>>, Error> {
+    if let Some(series) = read_series(state, district, date, det, SPEED_EXT)? {
+        return Ok(Some(
+            series.values().iter().map(|v| v.map(f64::from)).collect(),
+        ));
+    }
+    let vol = read_series(state, district, date, det, VOLUME_EXT)?;
+    let occ = read_series(state, district, date, det, OCCUPANCY_EXT)?;
+    let (vol, occ) = match (vol, occ) {
+        (Some(vol), Some(occ)) => (vol, occ),
+        _ => return Ok(None),
+    };
+    Ok(Some(
+        vol.values()
+            .iter()
+            .zip(occ.values())
+            .map(|(v, o)| {
+                let flow = f64::from((*v)?) * SAMPLES_PER_HOUR;
+                // occupancy is in hundredths of a percent
+                let density =
+                    f64::from((*o)?) / 10_000.0 * FEET_PER_MILE / field;
+                if density > 0.0 {
+                    Some((flow / density).min(MAX_SPEED))
+                } else {
+                    None
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// Read volume and speed of each mainline lane of a station
+pub fn read_lanes<'a>(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    loc: &'a Location,
+    lanes: Lanes,
+) -> Result<Vec<LaneData<'a>>, Error> {
+    let mut data = vec![];
+    for (det, lane, field) in loc.mainline_lanes(lanes) {
+        let volume = read_series(state, district, date, det, VOLUME_EXT)?
+            .map(|series| series.values().to_vec());
+        let speed = read_detector_speed(state, district, date, det, field)?;
+        data.push(LaneData {
+            detector: det,
+            lane,
+            volume,
+            speed,
+        });
+    }
+    Ok(data)
+}
+
+/// Read average speed (mph) of detectors, per interval.
+///
+/// With flow weights, the volume of each detector is read alongside its
+/// speed.  Returns `None` if no detector has speed data.
+pub fn read_speed(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    dets: &[(&str, f64)],
+    mean: Mean,
+    weight: Weight,
+) -> Result<Option<Vec<Option<f64>>>, Error> {
+    let mut all = vec![];
+    for (det, field) in dets {
+        if let Some(spd) =
+            read_detector_speed(state, district, date, det, *field)?
+        {
+            let vol = match weight {
+                Weight::Flow => {
+                    read_series(state, district, date, det, VOLUME_EXT)?
+                        .map(|series| series.values().to_vec())
+                }
+                Weight::Equal => None,
+            };
+            all.push((spd, vol));
+        }
+    }
+    let len = match all.iter().map(|(s, _)| s.len()).min() {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    Ok(Some(
+        (0..len)
+            .map(|k| {
+                let vals: Vec<(f64, Option<f64>)> = all
+                    .iter()
+                    .filter_map(|(s, v)| {
+                        let w = v.as_ref().and_then(|v| *v.get(k)?);
+                        Some((s[k]?, w.map(f64::from)))
+                    })
+                    .collect();
+                weight.combine(&vals, mean)
+            })
+            .collect(),
+    ))
+}
+
+/// Aggregate volume into bins of `n` intervals (missing if any is missing)
+pub fn bin_volume(vol: &[Option<i32>], n: usize) -> Vec<Option<i32>> {
+    vol.chunks(n).map(|c| c.iter().copied().sum()).collect()
+}
+
+/// Aggregate speed into bins of `n` intervals (mean of valid speeds).
+///
+/// With flow weights, intervals are weighted by volume.
+pub fn bin_speed(
+    spd: &[Option<f64>],
+    vol: &[Option<i32>],
+    n: usize,
+    mean: Mean,
+    weight: Weight,
+) -> Vec<Option<f64>> {
+    spd.chunks(n)
+        .enumerate()
+        .map(|(b, c)| {
+            let vals: Vec<(f64, Option<f64>)> = c
+                .iter()
+                .enumerate()
+                .filter_map(|(i, s)| {
+                    let v = vol.get(b * n + i).copied().flatten();
+                    Some(((*s)?, v.map(f64::from)))
+                })
+                .collect();
+            weight.combine(&vals, mean)
+        })
+        .collect()
+}
+
+/// Format seconds past midnight as `HH:MM`
+pub fn time_of_day(secs: u32) -> String {
+    format!("{:02}:{:02}", secs / 3600, secs / 60 % 60)
+}
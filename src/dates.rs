@@ -0,0 +1,44 @@
+// dates.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Archive dates (yyyyMMdd) and date ranges.
+//
+use crate::error::Error;
+use chrono::NaiveDate;
+
+/// Date format for archive directories
+pub const DATE_FMT: &str = "%Y%m%d";
+
+/// Maximum number of days in a range
+pub const MAX_DAYS: i64 = 366;
+
+/// Parse a date, naming the parameter or argument in errors
+pub fn parse_date(name: &str, date: &str) -> Result<NaiveDate, Error> {
+    NaiveDate::parse_from_str(date, DATE_FMT)
+        .map_err(|_| Error::InvalidParam(format!("{}: {}", name, date)))
+}
+
+/// Parse `start` and `end` dates of a range (inclusive), of up to
+/// `MAX_DAYS` days
+pub fn parse_range(
+    start: &str,
+    end: &str,
+) -> Result<(NaiveDate, NaiveDate), Error> {
+    let start = parse_date("start", start)?;
+    let end = parse_date("end", end)?;
+    let days = (end - start).num_days() + 1;
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(Error::InvalidParam(format!("days: {}", days)));
+    }
+    Ok((start, end))
+}
+
+/// Get all dates (yyyyMMdd) in a range, inclusive
+pub fn dates_in(start: NaiveDate, end: NaiveDate) -> Vec<String> {
+    start
+        .iter_days()
+        .take_while(|date| *date <= end)
+        .map(|date| date.format(DATE_FMT).to_string())
+        .collect()
+}
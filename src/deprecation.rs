@@ -0,0 +1,142 @@
+// deprecation.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Deprecated route forms, and their sunset policy.
+//
+// Responses to deprecated routes have a `Deprecation` header, a `Link` to
+// the successor route and (if configured) a `Sunset` date.  Usage of each
+// form is counted in metrics, and they can be disabled entirely.
+//
+use crate::error::Error;
+use crate::state::AppState;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, LINK};
+use actix_web::web;
+
+/// `Deprecation` header name
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// `Sunset` header name
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Deprecated route form
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Form {
+    /// Dates in a year of the default district (`/{year}`)
+    YearDates,
+    /// Sensors on a date of the default district (`/{year}/{date}`)
+    YearSensors,
+    /// Sample data of the default district (`/{year}/{date}/{sid}.{ext}`)
+    YearSample,
+}
+
+/// Policy for deprecated routes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Served, with deprecation headers
+    Warn,
+    /// Not found
+    Disabled,
+}
+
+impl Form {
+    /// All deprecated forms
+    pub const ALL: [Form; 3] =
+        [Form::YearDates, Form::YearSensors, Form::YearSample];
+
+    /// Get the form name (for metrics)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Form::YearDates => "year_dates",
+            Form::YearSensors => "year_sensors",
+            Form::YearSample => "year_sample",
+        }
+    }
+
+    /// Get the form index
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Get the deprecated form of a request path (after URL prefix)
+    fn from_path(state: &AppState, path: &str) -> Option<Form> {
+        let segs: Vec<&str> =
+            path.split('/').filter(|s| !s.is_empty()).collect();
+        let is_digits = |s: &str, len| {
+            s.len() == len && s.bytes().all(|b| b.is_ascii_digit())
+        };
+        // a year-named district is not a deprecated form
+        let is_year_date = |y: &str, d: &str| {
+            is_digits(y, 4) && is_digits(d, 8) && !state.storage.has_district(y)
+        };
+        match segs.as_slice() {
+            [y] if is_digits(y, 4) => Some(Form::YearDates),
+            [y, d] if is_year_date(y, d) => Some(Form::YearSensors),
+            [y, d, _] if is_year_date(y, d) => Some(Form::YearSample),
+            _ => None,
+        }
+    }
+
+    /// Get the successor of a deprecated path, with an explicit district
+    fn successor(self, district: &str, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        match self {
+            Form::YearDates => format!("/{}/{}", district, path),
+            _ => {
+                let rest = path.split_once('/').map_or("", |(_, rest)| rest);
+                format!("/{}/{}", district, rest)
+            }
+        }
+    }
+}
+
+/// Get request path after the URL prefix
+fn route_path<'a>(state: &AppState, path: &'a str) -> &'a str {
+    path.strip_prefix(state.config.url_prefix.as_str())
+        .unwrap_or(path)
+}
+
+/// Check that a request is not for a disabled deprecated route
+pub fn check(req: &ServiceRequest) -> Result<(), Error> {
+    match req.app_data::<web::Data<AppState>>() {
+        Some(state) if state.config.deprecated_routes == Policy::Disabled => {
+            match Form::from_path(state, route_path(state, req.path())) {
+                Some(_) => Err(Error::NotFound),
+                None => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Add deprecation headers to a response, counting its usage
+pub fn add_headers<B>(res: &mut ServiceResponse<B>) {
+    let state = match res.request().app_data::<web::Data<AppState>>() {
+        Some(state) => state.clone(),
+        None => return,
+    };
+    let path = route_path(&state, res.request().path()).to_string();
+    let form = match Form::from_path(&state, &path) {
+        Some(form) => form,
+        None => return,
+    };
+    state.metrics.deprecated_request(form);
+    let config = &state.config;
+    let headers = res.headers_mut();
+    headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+    let successor = form.successor(&config.district_default, &path);
+    let link = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        config.url_prefix, successor
+    );
+    if let Ok(link) = HeaderValue::from_str(&link) {
+        headers.insert(LINK, link);
+    }
+    if let Some(sunset) = config.sunset {
+        let sunset = sunset.format("%a, %d %b %Y 00:00:00 GMT").to_string();
+        if let Ok(sunset) = HeaderValue::from_str(&sunset) {
+            headers.insert(SUNSET, sunset);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// diskcache.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Disk cache of upstream responses, with size-based LRU eviction.
+//
+use crate::error::Error;
+use crate::upstream::UpstreamResponse;
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Cache file extension
+const EXT: &str = "cache";
+
+/// Cached file metadata
+struct Meta {
+    /// File size in bytes
+    bytes: u64,
+    /// Time stored
+    stored: SystemTime,
+    /// Last use (cache clock)
+    last_used: u64,
+}
+
+/// Cache index
+#[derive(Default)]
+struct Index {
+    /// Clock incremented on each access
+    clock: u64,
+    /// Total size of cached files
+    total: u64,
+    /// Cached files by name
+    files: HashMap<String, Meta>,
+}
+
+/// Disk cache of successful upstream responses
+pub struct DiskCache {
+    /// Cache directory
+    dir: PathBuf,
+    /// Maximum total size of cached files
+    max_bytes: u64,
+    /// Maximum age of cached files
+    max_age: Duration,
+    /// Cache index
+    index: Mutex<Index>,
+}
+
+/// Get the file name for a cache key
+fn file_name(key: &str) -> String {
+    let mut name = String::with_capacity(70);
+    for b in Sha256::digest(key.as_bytes()) {
+        write!(name, "{:02x}", b).unwrap();
+    }
+    name.push('.');
+    name.push_str(EXT);
+    name
+}
+
+impl Index {
+    /// Remove a file from the index
+    fn remove(&mut self, name: &str) {
+        if let Some(meta) = self.files.remove(name) {
+            self.total -= meta.bytes;
+        }
+    }
+}
+
+impl DiskCache {
+    /// Open a disk cache, indexing existing files
+    pub fn open(
+        dir: &Path,
+        max_bytes: u64,
+        max_age: Duration,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let mut found = vec![];
+        for ent in fs::read_dir(dir)?.flatten() {
+            let path = ent.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(EXT) {
+                continue;
+            }
+            let name = ent.file_name().to_string_lossy().into_owned();
+            let meta = ent.metadata()?;
+            let stored = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((stored, name, meta.len()));
+        }
+        // oldest files are least recently used
+        found.sort();
+        let mut index = Index::default();
+        for (stored, name, bytes) in found {
+            index.clock += 1;
+            index.total += bytes;
+            let last_used = index.clock;
+            let meta = Meta {
+                bytes,
+                stored,
+                last_used,
+            };
+            index.files.insert(name, meta);
+        }
+        let cache = DiskCache {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_age,
+            index: Mutex::new(index),
+        };
+        cache.evict();
+        Ok(cache)
+    }
+
+    /// Get the total size of cached files
+    pub fn total_bytes(&self) -> u64 {
+        self.index.lock().unwrap().total
+    }
+
+    /// Get the number of cached files
+    pub fn len(&self) -> usize {
+        self.index.lock().unwrap().files.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get a cached response
+    pub fn get(&self, key: &str) -> Option<UpstreamResponse> {
+        let name = file_name(key);
+        {
+            let mut index = self.index.lock().unwrap();
+            index.clock += 1;
+            let clock = index.clock;
+            let max_age = self.max_age;
+            let meta = index.files.get_mut(&name)?;
+            let expired = meta.stored.elapsed().is_ok_and(|age| age > max_age);
+            if !expired {
+                meta.last_used = clock;
+            } else {
+                index.remove(&name);
+                drop(index);
+                let _ = fs::remove_file(self.dir.join(&name));
+                return None;
+            }
+        }
+        let data = match fs::read(self.dir.join(&name)) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("disk cache {}: {}", name, e);
+                self.index.lock().unwrap().remove(&name);
+                return None;
+            }
+        };
+        let split = data.iter().position(|b| *b == b'\n')?;
+        let content_type = String::from_utf8_lossy(&data[..split]);
+        Some(UpstreamResponse {
+            status: 200,
+            content_type: Some(content_type.into_owned())
+                .filter(|ct| !ct.is_empty()),
+            body: data[split + 1..].to_vec(),
+        })
+    }
+
+    /// Store a successful response
+    pub fn put(&self, key: &str, res: &UpstreamResponse) -> Result<(), Error> {
+        if res.status != 200 {
+            return Ok(());
+        }
+        let name = file_name(key);
+        let content_type = res.content_type.as_deref().unwrap_or("");
+        let mut data =
+            Vec::with_capacity(content_type.len() + 1 + res.body.len());
+        data.extend_from_slice(content_type.as_bytes());
+        data.push(b'\n');
+        data.extend_from_slice(&res.body);
+        let bytes = data.len() as u64;
+        if bytes > self.max_bytes {
+            return Ok(());
+        }
+        let path = self.dir.join(&name);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &data)?;
+        fs::rename(&tmp, &path)?;
+        {
+            let mut index = self.index.lock().unwrap();
+            index.remove(&name);
+            index.clock += 1;
+            index.total += bytes;
+            let last_used = index.clock;
+            let meta = Meta {
+                bytes,
+                stored: SystemTime::now(),
+                last_used,
+            };
+            index.files.insert(name, meta);
+        }
+        self.evict();
+        Ok(())
+    }
+
+    /// Evict least recently used files until under the size limit
+    fn evict(&self) {
+        let mut index = self.index.lock().unwrap();
+        while index.total > self.max_bytes {
+            let lru = index
+                .files
+                .iter()
+                .min_by_key(|(_, m)| m.last_used)
+                .map(|(n, _)| n.clone());
+            let name = match lru {
+                Some(name) => name,
+                None => break,
+            };
+            index.remove(&name);
+            if let Err(e) = fs::remove_file(self.dir.join(&name)) {
+                warn!("disk cache evict {}: {}", name, e);
+            }
+        }
+    }
+}
@@ -1,15 +1,47 @@
 // error.rs
 //
-// Copyright (c) 2019  Minnesota Department of Transportation
+// Copyright (c) 2019-2021  Minnesota Department of Transportation
 //
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::{HttpResponse, ResponseError};
 use std::fmt;
 use std::io;
 use std::net::AddrParseError;
+use zip::result::ZipError;
 
+/// Errors for trafdat
 #[derive(Debug)]
 pub enum Error {
+    /// I/O error
     Io(io::Error),
+    /// Socket address parse error
     AddrParse(AddrParseError),
+    /// Zip archive error
+    Zip(ZipError),
+    /// XML parse error (file name)
+    Xml(String),
+    /// JSON serialization error
+    Json(serde_json::Error),
+    /// Invalid request parameter
+    InvalidParam(String),
+    /// Resource not found
+    NotFound,
+    /// Access forbidden (missing or invalid signature)
+    Forbidden,
+    /// Authentication required (missing or invalid admin token)
+    Unauthorized,
+    /// Storage temporarily unavailable (circuit breaker open)
+    Unavailable,
+    /// Invalid configuration (file name)
+    Config(String),
+    /// Upstream server error (URL and message)
+    Upstream(String),
+    /// Archive entry too large (entry name and size)
+    EntryTooLarge(String),
+    /// Response too large (description and size)
+    TooLarge(String),
+    /// Database server error (message)
+    Database(String),
 }
 
 impl fmt::Display for Error {
@@ -17,6 +49,21 @@ impl fmt::Display for Error {
         match self {
             Error::Io(e) => write!(f, "{}", e),
             Error::AddrParse(e) => write!(f, "{}", e),
+            Error::Zip(e) => write!(f, "Corrupt archive: {}", e),
+            Error::Xml(p) => write!(f, "Config parse error: {}", p),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::InvalidParam(p) => write!(f, "Invalid parameter: {}", p),
+            Error::NotFound => write!(f, "Not Found"),
+            Error::Forbidden => write!(f, "Forbidden"),
+            Error::Unauthorized => write!(f, "Unauthorized"),
+            Error::Unavailable => write!(f, "Service Unavailable"),
+            Error::Config(p) => write!(f, "Invalid configuration: {}", p),
+            Error::Upstream(p) => write!(f, "Upstream error: {}", p),
+            Error::EntryTooLarge(p) => {
+                write!(f, "Archive entry too large: {}", p)
+            }
+            Error::TooLarge(p) => write!(f, "Response too large: {}", p),
+            Error::Database(p) => write!(f, "Database error: {}", p),
         }
     }
 }
@@ -26,6 +73,9 @@ impl std::error::Error for Error {
         match self {
             Error::Io(e) => Some(e),
             Error::AddrParse(e) => Some(e),
+            Error::Zip(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -41,3 +91,64 @@ impl From<AddrParseError> for Error {
         Error::AddrParse(e)
     }
 }
+
+impl From<ZipError> for Error {
+    fn from(e: ZipError) -> Self {
+        Error::Zip(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl ResponseError for Error {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Error::Zip(_) => HttpResponse::BadGateway().body("Corrupt archive"),
+            Error::Upstream(_) => {
+                HttpResponse::BadGateway().body("Bad Gateway")
+            }
+            Error::Xml(_) | Error::EntryTooLarge(_) => {
+                HttpResponse::BadGateway().body(self.to_string())
+            }
+            Error::InvalidParam(_) => {
+                HttpResponse::BadRequest().body(self.to_string())
+            }
+            Error::TooLarge(_) => {
+                HttpResponse::PayloadTooLarge().body(self.to_string())
+            }
+            Error::NotFound => HttpResponse::NotFound().body("Not Found"),
+            Error::Forbidden => HttpResponse::Forbidden().body("Forbidden"),
+            Error::Unauthorized => HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Basic realm=\"trafdat\""))
+                .body("Unauthorized"),
+            Error::Unavailable => {
+                HttpResponse::ServiceUnavailable().body("Service Unavailable")
+            }
+            _ => HttpResponse::InternalServerError().body("Server error"),
+        }
+    }
+}
+
+/// Fall through to other handlers for results which are not found
+pub trait OrTry {
+    /// Try another handler if this result is `NotFound`
+    fn or_try<F>(self, f: F) -> Self
+    where
+        F: FnOnce() -> Self;
+}
+
+impl<T> OrTry for Result<T, Error> {
+    fn or_try<F>(self, f: F) -> Self
+    where
+        F: FnOnce() -> Self,
+    {
+        match self {
+            Err(Error::NotFound) => f(),
+            res => res,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// export.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Decoded sample data for export subcommands.
+//
+use crate::dates::{dates_in, parse_date};
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use crate::sensor::{lookup_sensors, read_series, sample_scale, sample_type};
+use crate::state::AppState;
+use crate::zone::IntervalTime;
+use log::warn;
+use std::io::{self, Write};
+
+/// Default extensions to export
+pub const DEFAULT_EXTS: &[&str] = &["v30", "o30", "s30"];
+
+/// Get all dates (yyyyMMdd) in a range, inclusive
+pub fn date_range(start: &str, end: &str) -> Result<Vec<String>, Error> {
+    let (start, end) = (parse_date("date", start)?, parse_date("date", end)?);
+    if start > end {
+        return Err(Error::InvalidParam("start date after end date".into()));
+    }
+    Ok(dates_in(start, end))
+}
+
+/// Decoded sample series for one sensor and extension on a date
+pub struct Series {
+    /// Sensor ID
+    pub sensor: String,
+    /// Sample file extension
+    pub ext: String,
+    /// Times of sample intervals
+    times: Vec<IntervalTime>,
+    /// Scale of decoded values
+    scale: f64,
+    /// Decoded samples
+    series: SampleSeries,
+}
+
+impl Series {
+    /// Get valid samples, as (Unix timestamp, scaled value).
+    ///
+    /// Samples at missing local times (when clocks are set forward) are
+    /// skipped.
+    pub fn samples(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.series.values().iter().zip(&self.times).filter_map(
+            move |(val, tm)| {
+                Some((tm.stamp()?, f64::from((*val)?) * self.scale))
+            },
+        )
+    }
+}
+
+/// Get sample series on a date.
+///
+/// * `sensors` Sensor IDs to export (all sampled sensors if empty).
+/// * `exts` Binned sample file extensions.
+///
+/// Series are read as the iterator advances; unreadable files are logged
+/// and skipped.
+pub fn date_series<'a>(
+    state: &'a AppState,
+    district: &'a str,
+    date: &'a str,
+    sensors: &[String],
+    exts: &'a [String],
+) -> Result<impl Iterator<Item = Series> + 'a, Error> {
+    let zone = state.config.zone(district);
+    parse_date("date", date)?;
+    let sensors = if sensors.is_empty() {
+        lookup_sensors(state, district, date)?
+    } else {
+        sensors.to_vec()
+    };
+    Ok(sensors.into_iter().flat_map(move |sid| {
+        exts.iter().filter_map(move |ext| {
+            let (prefix, _) = sample_type(ext)?;
+            match read_series(state, district, date, &sid, ext) {
+                Ok(Some(series)) => Some(Series {
+                    sensor: sid.clone(),
+                    ext: ext.clone(),
+                    times: zone.interval_times(
+                        date,
+                        series.period(),
+                        series.values().len(),
+                    )?,
+                    scale: sample_scale(prefix),
+                    series,
+                }),
+                Ok(None) => None,
+                Err(e) => {
+                    warn!("{} {}.{}: {}", date, sid, ext, e);
+                    None
+                }
+            }
+        })
+    }))
+}
+
+/// Parse a comma-separated list argument
+pub fn split_arg(arg: &str) -> Vec<String> {
+    arg.split(',')
+        .filter(|a| !a.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Quote a SQL string literal
+pub fn sql_quote(val: &str) -> String {
+    format!("'{}'", val.replace('\'', "''"))
+}
+
+/// Writer for batched multi-row SQL INSERT statements
+pub struct SqlBatch<'w, W: Write> {
+    /// Output script
+    out: &'w mut W,
+    /// Start of statement (before first row)
+    insert: &'static str,
+    /// End of statement (after last row, without `;`)
+    end: &'static str,
+    /// Maximum rows per statement
+    max_rows: usize,
+    /// Rows in current statement
+    rows: usize,
+    /// Total rows written
+    total: usize,
+}
+
+impl<'w, W: Write> SqlBatch<'w, W> {
+    /// Create a new batch writer
+    pub fn new(
+        out: &'w mut W,
+        insert: &'static str,
+        end: &'static str,
+        max_rows: usize,
+    ) -> Self {
+        SqlBatch {
+            out,
+            insert,
+            end,
+            max_rows,
+            rows: 0,
+            total: 0,
+        }
+    }
+
+    /// Write other statements (finishing the current batch first)
+    pub fn write(&mut self, sql: &[u8]) -> io::Result<()> {
+        self.finish()?;
+        self.out.write_all(sql)
+    }
+
+    /// Write one row of comma-separated SQL values
+    pub fn row(&mut self, values: &str) -> io::Result<()> {
+        if self.rows == 0 {
+            self.out.write_all(self.insert.as_bytes())?;
+            self.out.write_all(b"\n")?;
+        } else {
+            self.out.write_all(b",\n")?;
+        }
+        write!(self.out, "({})", values)?;
+        self.rows += 1;
+        self.total += 1;
+        if self.rows == self.max_rows {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Finish the current batch
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.rows > 0 {
+            if !self.end.is_empty() {
+                self.out.write_all(b"\n")?;
+                self.out.write_all(self.end.as_bytes())?;
+            }
+            self.out.write_all(b";\n")?;
+            self.rows = 0;
+        }
+        Ok(())
+    }
+
+    /// Get the total number of rows written
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
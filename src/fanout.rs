@@ -0,0 +1,61 @@
+// fanout.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Parallel per-day computations for multi-day requests.
+//
+// Days are computed on a shared rayon pool of up to `TRAFDAT_DAY_WORKERS`
+// threads (created on first use), and results are merged in date order.
+//
+use log::warn;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::OnceLock;
+
+/// Pool for per-day computations
+pub struct DayPool {
+    /// Maximum number of worker threads
+    workers: usize,
+    /// Thread pool
+    pool: OnceLock<Option<ThreadPool>>,
+}
+
+impl DayPool {
+    /// Create a day pool with a maximum number of workers
+    pub fn new(workers: usize) -> Self {
+        DayPool {
+            workers,
+            pool: OnceLock::new(),
+        }
+    }
+
+    /// Get the thread pool, building it if needed
+    fn pool(&self) -> Option<&ThreadPool> {
+        self.pool
+            .get_or_init(|| {
+                ThreadPoolBuilder::new()
+                    .num_threads(self.workers)
+                    .thread_name(|i| format!("trafdat-day-{}", i))
+                    .build()
+                    .map_err(|e| warn!("day pool: {}", e))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// Compute a function for each date, returning results in date order.
+    ///
+    /// Days are computed serially if the pool cannot be built.
+    pub fn map<T, F>(&self, dates: &[String], f: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(&str) -> T + Sync + Send,
+    {
+        match self.pool() {
+            Some(pool) if dates.len() > 1 => {
+                pool.install(|| dates.par_iter().map(|date| f(date)).collect())
+            }
+            _ => dates.iter().map(|date| f(date)).collect(),
+        }
+    }
+}
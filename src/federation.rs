@@ -0,0 +1,162 @@
+// federation.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Federation of upstream trafdat servers, one per district, or a single
+// upstream for all districts (proxy mode).
+//
+use crate::cache::cache_key;
+use crate::error::Error;
+use crate::state::AppState;
+use crate::upstream::{Upstream, UpstreamResponse};
+use actix_web::guard::GuardContext;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use log::warn;
+use std::collections::BTreeMap;
+
+/// Header indicating disk cache `HIT` or `MISS`
+const X_CACHE: &str = "x-cache";
+
+/// Paths served locally in proxy mode
+const LOCAL_PATHS: &[&str] = &[
+    "admin",
+    "browse.html",
+    "healthz",
+    "index.html",
+    "metrics",
+    "schema",
+    "spec.json",
+    "static",
+    "trafdat.css",
+];
+
+/// Upstream servers by district
+#[derive(Default)]
+pub struct Federation {
+    /// Upstream servers
+    upstreams: BTreeMap<String, Upstream>,
+    /// Upstream server for all other districts
+    fallback: Option<Upstream>,
+}
+
+impl Federation {
+    /// Create a federation from (district, URL) pairs, with an optional
+    /// upstream for all other districts
+    pub fn new(
+        upstreams: &[(String, String)],
+        fallback: Option<&str>,
+    ) -> Result<Self, Error> {
+        let mut fed = Federation::default();
+        for (district, url) in upstreams {
+            fed.upstreams.insert(district.clone(), Upstream::new(url)?);
+        }
+        fed.fallback = fallback.map(Upstream::new).transpose()?;
+        Ok(fed)
+    }
+
+    /// Get the upstream for a district (or other first path segment)
+    pub fn get(&self, district: &str) -> Option<&Upstream> {
+        match self.upstreams.get(district) {
+            Some(upstream) => Some(upstream),
+            None if LOCAL_PATHS.contains(&district) => None,
+            None => self.fallback.as_ref(),
+        }
+    }
+
+    /// Check if all districts are proxied
+    pub fn is_proxy(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    /// Get federated districts
+    pub fn districts(&self) -> impl Iterator<Item = &str> {
+        self.upstreams.keys().map(String::as_str)
+    }
+
+    /// Check if no upstreams are configured
+    pub fn is_empty(&self) -> bool {
+        self.upstreams.is_empty() && self.fallback.is_none()
+    }
+}
+
+/// Get the request path after the URL prefix
+fn local_path<'a>(state: &AppState, path: &'a str) -> &'a str {
+    path.strip_prefix(state.config.url_prefix.as_str())
+        .unwrap_or(path)
+}
+
+/// Get the upstream for a request path, if its district is federated
+pub fn upstream<'a>(state: &'a AppState, path: &str) -> Option<&'a Upstream> {
+    if state.federation.is_empty() {
+        return None;
+    }
+    let district =
+        local_path(state, path).split('/').find(|s| !s.is_empty())?;
+    state.federation.get(district)
+}
+
+/// Guard for requests to federated districts
+pub fn is_federated(ctx: &GuardContext) -> bool {
+    match ctx.app_data::<web::Data<AppState>>() {
+        Some(state) => upstream(state, ctx.head().uri.path()).is_some(),
+        None => false,
+    }
+}
+
+/// Handle a request by proxying it to the district's upstream server
+pub async fn handle_proxy(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let upstream = upstream(&state, req.path()).ok_or(Error::NotFound)?;
+    let upstream = upstream.clone();
+    let key = cache_key(&req);
+    if let Some(res) = state.cache.lookup(&key) {
+        return Ok(res);
+    }
+    let mut uri = local_path(&state, req.path()).to_string();
+    if !req.query_string().is_empty() {
+        uri.push('?');
+        uri.push_str(req.query_string());
+    }
+    let url = upstream.url().to_string();
+    let st = state.clone();
+    let disk_key = key.clone();
+    let (res, hit) = web::block(move || fetch(&st, &upstream, &disk_key, &uri))
+        .await
+        .map_err(|e| Error::Upstream(format!("{}: {}", url, e)))??;
+    let status = StatusCode::from_u16(res.status)
+        .map_err(|_| Error::Upstream(format!("{}: {}", url, res.status)))?;
+    let mut builder = HttpResponse::build(status);
+    if let Some(content_type) = res.content_type {
+        builder.content_type(content_type);
+    }
+    if state.disk_cache.is_some() {
+        builder.insert_header((X_CACHE, if hit { "HIT" } else { "MISS" }));
+    }
+    Ok(state.cache.store(key, builder.body(res.body)))
+}
+
+/// Fetch a response from the disk cache or upstream server.
+///
+/// Returns the response and whether it was a disk cache hit.
+fn fetch(
+    state: &AppState,
+    upstream: &Upstream,
+    key: &str,
+    uri: &str,
+) -> Result<(UpstreamResponse, bool), Error> {
+    if let Some(disk) = &state.disk_cache {
+        if let Some(res) = disk.get(key) {
+            return Ok((res, true));
+        }
+    }
+    let res = upstream.get(uri)?;
+    if let Some(disk) = &state.disk_cache {
+        if let Err(e) = disk.put(key, &res) {
+            warn!("disk cache {}: {}", uri, e);
+        }
+    }
+    Ok((res, false))
+}
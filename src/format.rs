@@ -0,0 +1,179 @@
+// format.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use actix_web::web;
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Representation of missing values
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Missing {
+    /// JSON `null`
+    #[serde(rename = "null")]
+    Null,
+    /// Number `-1`
+    #[serde(rename = "-1")]
+    Negative,
+    /// Left out of output
+    #[serde(rename = "omit")]
+    Omit,
+}
+
+/// Layout of sample arrays
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Array of values
+    Values,
+    /// Array of objects with interval number and value
+    Objects,
+}
+
+/// Mode of sample values
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Values {
+    /// Signed values as stored, with negative (missing) values unchanged
+    Raw,
+    /// Scaled values, with missing values as configured
+    Decoded,
+}
+
+/// Query parameters for JSON number formatting
+#[derive(Deserialize)]
+struct FormatParams {
+    /// Missing value representation
+    missing: Option<Missing>,
+    /// Fixed number of decimal places
+    decimals: Option<u8>,
+    /// Array layout
+    layout: Option<Layout>,
+    /// Value mode
+    values: Option<Values>,
+}
+
+/// JSON number formatting for decoded sample output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JsonFormat {
+    /// Missing value representation
+    pub missing: Missing,
+    /// Fixed number of decimal places
+    pub decimals: Option<u8>,
+    /// Array layout
+    pub layout: Layout,
+    /// Value mode
+    pub values: Values,
+}
+
+/// Maximum number of decimal places
+const MAX_DECIMALS: u8 = 6;
+
+impl Default for JsonFormat {
+    fn default() -> Self {
+        JsonFormat {
+            missing: Missing::Null,
+            decimals: None,
+            layout: Layout::Values,
+            values: Values::Decoded,
+        }
+    }
+}
+
+impl JsonFormat {
+    /// Get formatting from a query string.
+    ///
+    /// Returns `None` if no formatting parameters are present.
+    pub fn from_query(query: &str) -> Result<Option<Self>, Error> {
+        let params = web::Query::<FormatParams>::from_query(query)
+            .map_err(|e| Error::InvalidParam(e.to_string()))?;
+        if params.missing.is_none()
+            && params.decimals.is_none()
+            && params.layout.is_none()
+            && params.values.is_none()
+        {
+            return Ok(None);
+        }
+        if let Some(decimals) = params.decimals {
+            if decimals > MAX_DECIMALS {
+                return Err(Error::InvalidParam(format!(
+                    "decimals: {}",
+                    decimals
+                )));
+            }
+        }
+        let def = JsonFormat::default();
+        Ok(Some(JsonFormat {
+            missing: params.missing.unwrap_or(def.missing),
+            decimals: params.decimals,
+            layout: params.layout.unwrap_or(def.layout),
+            values: params.values.unwrap_or(def.values),
+        }))
+    }
+
+    /// Format one number
+    fn number(&self, res: &mut String, val: f64) {
+        match self.decimals {
+            Some(d) => write!(res, "{:.*}", usize::from(d), val),
+            None => write!(res, "{}", val),
+        }
+        .unwrap();
+    }
+
+    /// Format one value (omitted values must be skipped by caller)
+    fn value(&self, res: &mut String, val: Option<f64>) {
+        match (val, self.missing) {
+            (Some(v), _) => self.number(res, v),
+            (None, Missing::Negative) => res.push_str("-1"),
+            (None, _) => res.push_str("null"),
+        }
+    }
+
+    /// Encode a sample series as JSON.
+    ///
+    /// * `series` Decoded samples.
+    /// * `scale` Multiplier for valid values (e.g. 0.01 for occupancy).
+    pub fn encode(&self, series: &SampleSeries, scale: f64) -> String {
+        let mut res = String::from("[");
+        for (i, val) in series.values().iter().enumerate() {
+            let val = val.map(|v| f64::from(v) * scale);
+            if val.is_none() && self.missing == Missing::Omit {
+                continue;
+            }
+            if res.len() > 1 {
+                res.push(',');
+            }
+            match self.layout {
+                Layout::Values => self.value(&mut res, val),
+                Layout::Objects => {
+                    write!(res, "{{\"interval\":{},\"value\":", i).unwrap();
+                    self.value(&mut res, val);
+                    res.push('}');
+                }
+            }
+        }
+        res.push(']');
+        res
+    }
+
+    /// Encode raw (signed) sample values as JSON
+    pub fn encode_raw(&self, values: &[i32]) -> String {
+        let mut res = String::from("[");
+        for (i, val) in values.iter().enumerate() {
+            if res.len() > 1 {
+                res.push(',');
+            }
+            match self.layout {
+                Layout::Values => write!(res, "{}", val),
+                Layout::Objects => {
+                    write!(res, "{{\"interval\":{},\"value\":{}}}", i, val)
+                }
+            }
+            .unwrap();
+        }
+        res.push(']');
+        res
+    }
+}
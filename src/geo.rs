@@ -0,0 +1,88 @@
+// geo.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+
+/// Geographic position (longitude, latitude)
+pub type Position = (f64, f64);
+
+/// Mean radius of the earth (miles)
+const EARTH_RADIUS_MI: f64 = 3958.8;
+
+/// Get the great-circle distance (miles) between two positions
+pub fn distance_miles(a: Position, b: Position) -> f64 {
+    let (lat0, lat1) = (a.1.to_radians(), b.1.to_radians());
+    let dlat = lat1 - lat0;
+    let dlon = (b.0 - a.0).to_radians();
+    let h = (dlat / 2.0).sin().powi(2)
+        + lat0.cos() * lat1.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_MI * h.sqrt().asin()
+}
+
+/// Get the fraction (0 to 1) along a line segment nearest to a point
+fn segment_fraction(pt: Position, a: Position, b: Position) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    if len2 > 0.0 {
+        (((pt.0 - a.0) * dx + (pt.1 - a.1) * dy) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Interpolate a position along a line segment
+pub fn interpolate(a: Position, b: Position, t: f64) -> Position {
+    (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1))
+}
+
+/// Get the perpendicular distance from a point to a line segment
+fn segment_distance(pt: Position, a: Position, b: Position) -> f64 {
+    let (x, y) = interpolate(a, b, segment_fraction(pt, a, b));
+    ((pt.0 - x).powi(2) + (pt.1 - y).powi(2)).sqrt()
+}
+
+/// Project a point onto a line segment.
+///
+/// Longitude is scaled by the cosine of latitude, so the projection is
+/// perpendicular on the ground.  Returns the fraction along the segment.
+pub fn project(pt: Position, a: Position, b: Position) -> f64 {
+    let scale = pt.1.to_radians().cos();
+    let sc = |p: Position| (p.0 * scale, p.1);
+    segment_fraction(sc(pt), sc(a), sc(b))
+}
+
+/// Simplify a polyline using the Douglas-Peucker algorithm.
+///
+/// * `points` Polyline positions.
+/// * `tolerance` Maximum distance (degrees) of removed points from the
+///   simplified line.
+///
+/// Returns a mask of points to keep; the end points are always kept.
+pub fn simplify(points: &[Position], tolerance: f64) -> Vec<bool> {
+    let mut keep = vec![false; points.len()];
+    if points.len() < 3 {
+        keep.iter_mut().for_each(|k| *k = true);
+        return keep;
+    }
+    let last = points.len() - 1;
+    keep[0] = true;
+    keep[last] = true;
+    let mut stack = vec![(0, last)];
+    while let Some((first, end)) = stack.pop() {
+        let mut max_dist = 0.0;
+        let mut index = first;
+        for (i, pt) in points.iter().enumerate().take(end).skip(first + 1) {
+            let dist = segment_distance(*pt, points[first], points[end]);
+            if dist > max_dist {
+                max_dist = dist;
+                index = i;
+            }
+        }
+        if max_dist > tolerance {
+            keep[index] = true;
+            stack.push((first, index));
+            stack.push((index, end));
+        }
+    }
+    keep
+}
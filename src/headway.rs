@@ -0,0 +1,161 @@
+// headway.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use crate::vlog::{is_valid_period, read_vlog, VehicleEvent};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// Default interval period (seconds)
+const PERIOD_DEFAULT: u32 = 900;
+
+/// Default critical gap (seconds)
+const CRITICAL_GAP_DEFAULT: f64 = 4.0;
+
+/// Maximum headway (seconds) for vehicles to be considered a platoon
+const PLATOON_HEADWAY: f64 = 2.0;
+
+/// Upper bounds (seconds) of headway histogram bins (last bin is open)
+const HEADWAY_BINS: &[f64] = &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 15.0];
+
+/// Query parameters for headway requests
+#[derive(Deserialize)]
+struct HeadwayParams {
+    /// Interval period (seconds)
+    period: Option<u32>,
+    /// Critical gap (seconds) for gap acceptance
+    critical: Option<f64>,
+}
+
+/// Headway statistics for one interval
+#[derive(JsonSchema, Serialize)]
+struct IntervalStats {
+    /// Number of vehicles
+    vehicles: usize,
+    /// Mean headway (seconds)
+    headway_mean: Option<f64>,
+    /// Median headway (seconds)
+    headway_median: Option<f64>,
+    /// Headway histogram counts (see `bins`)
+    histogram: Vec<usize>,
+    /// Ratio of vehicles following within platoon headway
+    platoon_ratio: Option<f64>,
+    /// Mean gap (seconds)
+    gap_mean: Option<f64>,
+    /// Number of gaps at least as long as critical gap
+    gaps_accepted: usize,
+    /// Number of gaps shorter than critical gap
+    gaps_rejected: usize,
+}
+
+/// Headway statistics for one sensor on a date
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct HeadwayStats {
+    /// Interval period (seconds)
+    period: u32,
+    /// Critical gap (seconds)
+    critical: f64,
+    /// Platoon headway (seconds)
+    platoon_headway: f64,
+    /// Upper bounds of histogram bins (seconds)
+    bins: &'static [f64],
+    /// Statistics for each interval
+    intervals: Vec<IntervalStats>,
+}
+
+/// Calculate mean of values
+fn mean(vals: &[f64]) -> Option<f64> {
+    if vals.is_empty() {
+        None
+    } else {
+        Some(vals.iter().sum::<f64>() / vals.len() as f64)
+    }
+}
+
+/// Calculate median of sorted values
+fn median(vals: &[f64]) -> Option<f64> {
+    let n = vals.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(vals[n / 2]),
+        _ => Some((vals[n / 2 - 1] + vals[n / 2]) / 2.0),
+    }
+}
+
+/// Calculate statistics for events in one interval
+fn interval_stats(events: &[&VehicleEvent], critical: f64) -> IntervalStats {
+    let mut headways: Vec<f64> = events
+        .iter()
+        .filter_map(|ev| ev.headway)
+        .map(|h| f64::from(h) / 1000.0)
+        .collect();
+    headways.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut histogram = vec![0; HEADWAY_BINS.len() + 1];
+    for h in &headways {
+        let i = HEADWAY_BINS
+            .iter()
+            .position(|b| h < b)
+            .unwrap_or(HEADWAY_BINS.len());
+        histogram[i] += 1;
+    }
+    let platoon_ratio = if headways.is_empty() {
+        None
+    } else {
+        let n = headways.iter().filter(|h| **h <= PLATOON_HEADWAY).count();
+        Some(n as f64 / headways.len() as f64)
+    };
+    let gaps: Vec<f64> = events
+        .iter()
+        .filter_map(|ev| ev.gap())
+        .map(|g| f64::from(g) / 1000.0)
+        .collect();
+    let gaps_accepted = gaps.iter().filter(|g| **g >= critical).count();
+    IntervalStats {
+        vehicles: events.len(),
+        headway_mean: mean(&headways),
+        headway_median: median(&headways),
+        histogram,
+        platoon_ratio,
+        gap_mean: mean(&gaps),
+        gaps_accepted,
+        gaps_rejected: gaps.len() - gaps_accepted,
+    }
+}
+
+/// Handle request for headway statistics
+pub fn handle_headway(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<HeadwayParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let period = params.period.unwrap_or(PERIOD_DEFAULT);
+    if !is_valid_period(period) {
+        return Err(Error::InvalidParam(format!("period: {}", period)));
+    }
+    let critical = params.critical.unwrap_or(CRITICAL_GAP_DEFAULT);
+    if !critical.is_finite() || critical < 0.0 {
+        return Err(Error::InvalidParam(format!("critical: {}", critical)));
+    }
+    let vlog = read_vlog(state, district, date, sid)?.ok_or(Error::NotFound)?;
+    let intervals = vlog
+        .binned(period)
+        .iter()
+        .map(|events| interval_stats(events, critical))
+        .collect();
+    let stats = HeadwayStats {
+        period,
+        critical,
+        platoon_headway: PLATOON_HEADWAY,
+        bins: HEADWAY_BINS,
+        intervals,
+    };
+    Ok(json_response(serde_json::to_string(&stats)?))
+}
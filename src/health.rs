@@ -0,0 +1,218 @@
+// health.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::state::AppState;
+use crate::storage::Layout;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::read_dir;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Consecutive storage failures before a breaker trips
+const THRESHOLD: u32 = 5;
+
+/// Time a tripped breaker fast-fails requests
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Breaker state
+#[derive(Default)]
+struct BreakerState {
+    /// Consecutive failures
+    failures: u32,
+    /// Time when an open breaker may be retried
+    open_until: Option<Instant>,
+}
+
+/// Circuit breaker for one storage root
+pub struct Breaker {
+    /// Storage root was unreadable at startup (until a success)
+    root_unreadable: AtomicBool,
+    /// Breaker state
+    state: Mutex<BreakerState>,
+}
+
+/// Breaker status (JSON)
+#[derive(JsonSchema, Serialize)]
+pub struct BreakerStatus {
+    /// `closed` or `open`
+    pub state: &'static str,
+    /// Consecutive failures
+    pub failures: u32,
+}
+
+/// Health report (JSON)
+#[derive(JsonSchema, Serialize)]
+pub struct HealthReport {
+    /// `ok` or `degraded`
+    pub status: &'static str,
+    pub traffic: BreakerStatus,
+    pub metro_config: BreakerStatus,
+    /// Detected archive layouts by district
+    pub layouts: BTreeMap<String, Layout>,
+}
+
+impl Breaker {
+    /// Create a breaker for a storage root
+    fn new(root: &Path) -> Self {
+        Breaker {
+            root_unreadable: AtomicBool::new(read_dir(root).is_err()),
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Check whether the breaker is open
+    fn is_open(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap();
+        state.open_until.is_some_and(|until| now < until)
+    }
+
+    /// Check that requests may proceed
+    fn check(&self) -> Result<(), Error> {
+        if self.is_open(Instant::now()) {
+            Err(Error::Unavailable)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Record a storage failure
+    fn failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.failures >= THRESHOLD {
+            state.open_until = Some(Instant::now() + COOLDOWN);
+        }
+    }
+
+    /// Record a storage success
+    fn success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.open_until = None;
+    }
+
+    /// Get breaker status
+    fn status(&self) -> BreakerStatus {
+        let open = self.is_open(Instant::now());
+        let failures = self.state.lock().unwrap().failures;
+        BreakerStatus {
+            state: if open { "open" } else { "closed" },
+            failures,
+        }
+    }
+}
+
+/// Circuit breakers for storage roots
+pub struct Health {
+    /// Traffic archive breaker
+    traffic: Breaker,
+    /// Metro config breaker
+    metro: Breaker,
+}
+
+impl Health {
+    /// Create breakers for storage roots
+    pub fn new(traffic_path: &Path, metro_path: &Path) -> Self {
+        Health {
+            traffic: Breaker::new(traffic_path),
+            metro: Breaker::new(metro_path),
+        }
+    }
+
+    /// Get a report of breaker states
+    pub fn report(&self) -> HealthReport {
+        let traffic = self.traffic.status();
+        let metro_config = self.metro.status();
+        let ok = traffic.state == "closed" && metro_config.state == "closed";
+        HealthReport {
+            status: if ok { "ok" } else { "degraded" },
+            traffic,
+            metro_config,
+            layouts: BTreeMap::new(),
+        }
+    }
+
+    /// Get the breaker for a request
+    fn breaker(&self, state: &AppState, req: &HttpRequest) -> Option<&Breaker> {
+        let path = req.path();
+        let path = path
+            .strip_prefix(state.config.url_prefix.as_str())
+            .unwrap_or(path);
+        let first = path.split('/').find(|s| !s.is_empty())?;
+        if state.federation.get(first).is_some() {
+            return None;
+        }
+        match first {
+            "metro_config" => Some(&self.metro),
+            "admin" | "browse.html" | "healthz" | "index.html" | "metrics"
+            | "static" | "trafdat.css" => None,
+            _ => Some(&self.traffic),
+        }
+    }
+}
+
+/// Check storage breaker before handling a request
+pub fn check(req: &ServiceRequest) -> Result<(), Error> {
+    match req.app_data::<web::Data<AppState>>() {
+        Some(state) => match state.health.breaker(state, req.request()) {
+            Some(breaker) => breaker.check(),
+            None => Ok(()),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Record storage success or failure for a response.
+///
+/// I/O errors seen by the handler count as failures, as do `Not Found`
+/// responses while the storage root has been unreadable since startup.
+/// Nothing is checked on disk here, so floods of `Not Found` requests cost
+/// no extra I/O.
+pub fn record<B>(res: &ServiceResponse<B>) {
+    let state = match res.request().app_data::<web::Data<AppState>>() {
+        Some(state) => state,
+        None => return,
+    };
+    let breaker = match state.health.breaker(state, res.request()) {
+        Some(breaker) => breaker,
+        None => return,
+    };
+    let io_error = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<Error>())
+        .is_some_and(|e| matches!(e, Error::Io(_)));
+    if io_error
+        || (res.status() == StatusCode::NOT_FOUND
+            && breaker.root_unreadable.load(Ordering::Relaxed))
+    {
+        breaker.failure();
+    } else if res.status().is_success() {
+        // the root was read successfully
+        breaker.root_unreadable.store(false, Ordering::Relaxed);
+        breaker.success();
+    } else if res.status().is_client_error() {
+        breaker.success();
+    }
+}
+
+/// Handle health check request
+pub async fn handle_healthz(state: web::Data<AppState>) -> HttpResponse {
+    let mut report = state.health.report();
+    report.layouts = state.storage.layouts();
+    let status = if report.status == "ok" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    HttpResponse::build(status).json(report)
+}
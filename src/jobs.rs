@@ -0,0 +1,682 @@
+// jobs.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Asynchronous export jobs.
+//
+// Large exports can take minutes, so they are queued and run by a bounded
+// pool of worker threads, rather than tying up HTTP connections.  Clients
+// poll a job's status, then download its result when done.
+//
+// Each job is saved in the jobs directory (`{id}.json`), and its output is
+// written one date at a time to `{id}.sql.part`.  After a restart, queued
+// jobs are run again, and interrupted jobs resume after their last complete
+// date.
+//
+// Finished jobs are removed after `job_ttl`, or sooner (oldest first) when
+// job output exceeds `job_quota`, so results can't fill the volume.
+//
+// Job routes need an API key or the admin token.  Jobs have random IDs and
+// record the identity which submitted them; other clients (except admins)
+// can't see them.
+//
+use crate::admin::has_admin_token;
+use crate::apikey::Scope;
+use crate::audit;
+use crate::error::Error;
+use crate::export::{date_range, DEFAULT_EXTS};
+use crate::route::is_valid_district;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::sqlite::Study;
+use crate::state::AppState;
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest, HttpResponse, HttpResponseBuilder};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Content type of export results
+const SQL: &str = "application/sql";
+
+/// Interval between cleanups of finished jobs
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Identity of clients presenting the admin token
+const ADMIN: &str = "admin";
+
+/// Export job specification (request body)
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ExportSpec {
+    /// District ID
+    district: String,
+    /// Corridor (`route_dir`)
+    corridor: String,
+    /// First date (yyyyMMdd)
+    start: String,
+    /// Last date (yyyyMMdd)
+    end: String,
+    /// Sample file extensions
+    #[serde(default)]
+    ext: Vec<String>,
+}
+
+/// Status of an export job
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for a worker
+    Queued,
+    /// Being exported
+    Running,
+    /// Finished, with a result
+    Done,
+    /// Export failed
+    Failed,
+    /// Cancelled by a client
+    Cancelled,
+}
+
+/// Export job
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct Job {
+    /// Job ID
+    id: String,
+    /// Identity of submitting client
+    #[serde(default)]
+    owner: String,
+    /// Time submitted (milliseconds since Unix epoch)
+    #[serde(default)]
+    submitted: u64,
+    /// Job status
+    status: JobStatus,
+    /// Export specification
+    spec: ExportSpec,
+    /// Number of dates completed
+    #[serde(default)]
+    dates_done: usize,
+    /// Length of partial output after the last complete date
+    #[serde(default)]
+    part_len: u64,
+    /// Number of sample rows exported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<usize>,
+    /// Error message of a failed job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Result download path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+}
+
+/// Mutable job queue state
+#[derive(Default)]
+struct Inner {
+    /// All jobs, by ID
+    jobs: BTreeMap<String, Job>,
+    /// IDs of queued jobs, in order
+    queue: VecDeque<String>,
+    /// Number of running worker threads
+    workers: usize,
+}
+
+/// Client of job routes
+struct Client {
+    /// Identity (API key holder, or `admin` for the admin token)
+    name: String,
+    /// Allowed to act on all jobs
+    admin: bool,
+}
+
+/// Export job queue
+#[derive(Default)]
+pub struct JobQueue {
+    inner: Mutex<Inner>,
+}
+
+impl ExportSpec {
+    /// Check the specification, getting its dates
+    fn dates(&self) -> Result<Vec<String>, Error> {
+        if !is_valid_district(&self.district) {
+            return Err(Error::InvalidParam(format!(
+                "district: {}",
+                self.district
+            )));
+        }
+        if self.corridor.is_empty() {
+            return Err(Error::InvalidParam("corridor".into()));
+        }
+        date_range(&self.start, &self.end)
+    }
+
+    /// Get sample file extensions to export
+    fn exts(&self) -> Vec<String> {
+        if self.ext.is_empty() {
+            DEFAULT_EXTS.iter().map(|e| e.to_string()).collect()
+        } else {
+            self.ext.clone()
+        }
+    }
+}
+
+impl JobStatus {
+    /// Check if a job with this status is finished
+    fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+impl Client {
+    /// Get the client of a request
+    fn from_request(
+        state: &AppState,
+        req: &HttpRequest,
+    ) -> Result<Self, Error> {
+        if let Some(principal) = state.api_keys.principal(req) {
+            return Ok(Client {
+                name: principal.name.clone(),
+                admin: principal.has_scope(Scope::Admin),
+            });
+        }
+        if has_admin_token(state, req) {
+            return Ok(Client {
+                name: ADMIN.into(),
+                admin: true,
+            });
+        }
+        Err(Error::Unauthorized)
+    }
+
+    /// Check if the client may see a job
+    fn owns(&self, job: &Job) -> bool {
+        self.admin || job.owner == self.name
+    }
+}
+
+/// Make a new random job ID
+fn next_id() -> Result<String, Error> {
+    let mut id = [0; 16];
+    getrandom::fill(&mut id).map_err(io::Error::from)?;
+    Ok(id.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl Inner {
+    /// Spawn a worker thread, if fewer than the maximum are running
+    fn spawn_worker(&mut self, state: &web::Data<AppState>) {
+        if self.workers < state.config.job_workers {
+            self.workers += 1;
+            let state = state.clone();
+            thread::spawn(move || work(state));
+        }
+    }
+}
+
+impl JobQueue {
+    /// Get a job
+    fn get(&self, id: &str) -> Option<Job> {
+        self.inner.lock().unwrap().jobs.get(id).cloned()
+    }
+
+    /// Get a job owned by a client
+    fn get_owned(&self, id: &str, client: &Client) -> Option<Job> {
+        self.get(id).filter(|job| client.owns(job))
+    }
+
+    /// Update a job, returning the updated job
+    fn update<F>(&self, id: &str, f: F) -> Option<Job>
+    where
+        F: FnOnce(&mut Job),
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let job = inner.jobs.get_mut(id)?;
+        f(job);
+        Some(job.clone())
+    }
+}
+
+/// Get path to a job file, with an extension
+fn job_path(state: &AppState, id: &str, ext: &str) -> Option<PathBuf> {
+    let path = state.config.jobs_path.as_ref()?;
+    Some(path.join(format!("{}.{}", id, ext)))
+}
+
+/// Save a job in the jobs directory
+fn save(state: &AppState, job: &Job) -> Result<(), Error> {
+    let path = job_path(state, &job.id, "json").ok_or(Error::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(job)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Save a job, logging any error
+fn save_logged(state: &AppState, job: &Job) {
+    if let Err(e) = save(state, job) {
+        warn!("job {}: save failed, {}", job.id, e);
+    }
+}
+
+/// Remove job output files
+fn remove_output(state: &AppState, id: &str) {
+    for ext in ["sql.part", "sql"] {
+        if let Some(path) = job_path(state, id, ext) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Remove a job and all of its files
+fn remove_job(state: &AppState, inner: &mut Inner, id: &str) {
+    inner.jobs.remove(id);
+    remove_output(state, id);
+    if let Some(path) = job_path(state, id, "json") {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Get the time a job was last saved
+fn saved_time(state: &AppState, id: &str) -> SystemTime {
+    job_path(state, id, "json")
+        .and_then(|path| fs::metadata(path).ok())
+        .and_then(|meta| meta.modified().ok())
+        .unwrap_or(UNIX_EPOCH)
+}
+
+/// Get the size of a job's output files
+fn job_bytes(state: &AppState, id: &str) -> u64 {
+    ["sql.part", "sql"]
+        .iter()
+        .filter_map(|ext| job_path(state, id, ext))
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Get the total size of job output files
+fn output_bytes(state: &AppState) -> u64 {
+    let dir = match &state.config.jobs_path {
+        Some(dir) => dir,
+        None => return 0,
+    };
+    let bytes = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|ent| {
+            let name = ent.file_name();
+            let name = name.to_string_lossy();
+            name.ends_with(".sql") || name.ends_with(".sql.part")
+        })
+        .filter_map(|ent| ent.metadata().ok())
+        .map(|meta| meta.len())
+        .sum();
+    state.metrics.set_job_output_bytes(bytes);
+    bytes
+}
+
+/// Remove expired jobs, then the oldest finished jobs until job output is
+/// within the quota.
+///
+/// Returns the total size of remaining job output.
+pub fn cleanup(state: &AppState) -> u64 {
+    let mut inner = state.jobs.inner.lock().unwrap();
+    let mut finished: Vec<(SystemTime, String)> = inner
+        .jobs
+        .values()
+        .filter(|job| job.status.is_finished())
+        .map(|job| (saved_time(state, &job.id), job.id.clone()))
+        .collect();
+    finished.sort();
+    let mut bytes = output_bytes(state);
+    for (saved, id) in finished {
+        let ttl = state.config.job_ttl;
+        let expired = saved.elapsed().is_ok_and(|age| age > ttl);
+        if !expired && bytes <= state.config.job_quota {
+            break;
+        }
+        let job_bytes = job_bytes(state, &id);
+        if expired {
+            info!("job {}: expired", id);
+            state.metrics.job_expired();
+        } else if job_bytes > 0 {
+            info!("job {}: removed, over quota", id);
+            state.metrics.job_evicted();
+        } else {
+            continue;
+        }
+        bytes = bytes.saturating_sub(job_bytes);
+        remove_job(state, &mut inner, &id);
+    }
+    state.metrics.set_job_output_bytes(bytes);
+    bytes
+}
+
+/// Spawn a thread to clean up finished jobs periodically
+pub fn spawn_cleanup(state: web::Data<AppState>) {
+    if state.config.jobs_path.is_some() {
+        thread::spawn(move || loop {
+            cleanup(&state);
+            thread::sleep(CLEANUP_INTERVAL);
+        });
+    }
+}
+
+/// Open partial output of a job, truncated after its last complete date
+fn open_part(path: &Path, job: &Job) -> Result<File, Error> {
+    if job.dates_done == 0 {
+        return Ok(File::create(path)?);
+    }
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(job.part_len)?;
+    file.seek(io::SeekFrom::End(0))?;
+    Ok(file)
+}
+
+/// Check if a job has been cancelled
+fn is_cancelled(state: &AppState, id: &str) -> bool {
+    state
+        .jobs
+        .get(id)
+        .is_none_or(|job| job.status == JobStatus::Cancelled)
+}
+
+/// Run an export job, writing its result file.
+///
+/// Returns `None` if the job was cancelled.
+fn run_job(state: &AppState, job: &Job) -> Result<Option<usize>, Error> {
+    let path = job_path(state, &job.id, "sql").ok_or(Error::NotFound)?;
+    let part = path.with_extension("sql.part");
+    let study = Study {
+        district: &job.spec.district,
+        corridor: &job.spec.corridor,
+        dates: job.spec.dates()?,
+        exts: job.spec.exts(),
+    };
+    let mut out = BufWriter::new(open_part(&part, job)?);
+    if job.dates_done == 0 {
+        study.write_header(&mut out)?;
+    }
+    let mut rows = job.rows.unwrap_or(0);
+    for (i, date) in study.dates.iter().enumerate().skip(job.dates_done) {
+        if is_cancelled(state, &job.id) {
+            return Ok(None);
+        }
+        rows += study.write_date(state, date, &mut out)?;
+        out.flush()?;
+        out.get_ref().sync_data()?;
+        if output_bytes(state) > state.config.job_quota {
+            let msg = "job quota exceeded";
+            return Err(io::Error::new(io::ErrorKind::StorageFull, msg).into());
+        }
+        let part_len = out.get_mut().stream_position()?;
+        let progress = state.jobs.update(&job.id, |job| {
+            job.dates_done = i + 1;
+            job.part_len = part_len;
+            job.rows = Some(rows);
+        });
+        match progress {
+            Some(job) => save(state, &job)?,
+            None => return Ok(None),
+        }
+    }
+    drop(out);
+    fs::rename(&part, &path)?;
+    Ok(Some(rows))
+}
+
+/// Run queued jobs until the queue is empty
+fn work(state: web::Data<AppState>) {
+    loop {
+        let job = {
+            let mut inner = state.jobs.inner.lock().unwrap();
+            let job = inner.queue.pop_front().and_then(|id| {
+                let job = inner.jobs.get_mut(&id)?;
+                job.status = JobStatus::Running;
+                Some(job.clone())
+            });
+            match job {
+                Some(job) => job,
+                None => {
+                    inner.workers -= 1;
+                    return;
+                }
+            }
+        };
+        info!("job {}: running", job.id);
+        save_logged(&state, &job);
+        let id = job.id.clone();
+        let res = run_job(&state, &job);
+        let prefix = &state.config.url_prefix;
+        let job = state.jobs.update(&id, |job| match res {
+            _ if job.status == JobStatus::Cancelled => {
+                info!("job {}: cancelled", job.id)
+            }
+            Ok(Some(rows)) => {
+                info!("job {}: done, {} rows", job.id, rows);
+                job.status = JobStatus::Done;
+                job.rows = Some(rows);
+                job.result =
+                    Some(format!("{}/jobs/{}/result.sql", prefix, job.id));
+            }
+            Ok(None) => (),
+            Err(e) => {
+                warn!("job {}: {}", job.id, e);
+                job.status = JobStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        });
+        match job {
+            Some(job)
+                if matches!(
+                    job.status,
+                    JobStatus::Cancelled | JobStatus::Failed
+                ) =>
+            {
+                remove_output(&state, &job.id);
+                save_logged(&state, &job);
+            }
+            Some(job) => save_logged(&state, &job),
+            None => (),
+        }
+    }
+}
+
+/// Load saved jobs, resuming any which were queued or running.
+///
+/// Called at startup, before any jobs are submitted.
+pub fn resume(state: &web::Data<AppState>) -> Result<usize, Error> {
+    let dir = match &state.config.jobs_path {
+        Some(dir) => dir,
+        None => return Ok(0),
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut jobs = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            match serde_json::from_slice::<Job>(&fs::read(&path)?) {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!("job {}: {}", path.display(), e),
+            }
+        }
+    }
+    jobs.sort_by(|a, b| (a.submitted, &a.id).cmp(&(b.submitted, &b.id)));
+    let mut inner = state.jobs.inner.lock().unwrap();
+    let mut resumed = 0;
+    for mut job in jobs {
+        if matches!(job.status, JobStatus::Queued | JobStatus::Running) {
+            info!("job {}: resuming after date {}", job.id, job.dates_done);
+            job.status = JobStatus::Queued;
+            inner.queue.push_back(job.id.clone());
+            resumed += 1;
+        }
+        inner.jobs.insert(job.id.clone(), job);
+    }
+    for _ in 0..resumed {
+        inner.spawn_worker(state);
+    }
+    Ok(resumed)
+}
+
+/// Build a JSON response for a job
+fn job_response(
+    mut res: HttpResponseBuilder,
+    job: &Job,
+) -> Result<HttpResponse, Error> {
+    Ok(res
+        .content_type("application/json")
+        .body(serde_json::to_string(job)?))
+}
+
+/// Handle request to submit an export job.
+///
+/// Responds with `503 Service Unavailable` when the queue is full, or job
+/// output is over quota.
+pub fn handle_submit(
+    state: &web::Data<AppState>,
+    req: &HttpRequest,
+    spec: ExportSpec,
+) -> Result<HttpResponse, Error> {
+    if state.config.jobs_path.is_none() {
+        return Err(Error::NotFound);
+    }
+    let client = Client::from_request(state, req)?;
+    spec.dates()?;
+    if cleanup(state) >= state.config.job_quota {
+        return Err(Error::Unavailable);
+    }
+    let job = {
+        let mut inner = state.jobs.inner.lock().unwrap();
+        if inner.queue.len() >= state.config.job_queue {
+            return Err(Error::Unavailable);
+        }
+        let id = next_id()?;
+        let submitted = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let job = Job {
+            id: id.clone(),
+            owner: client.name,
+            submitted,
+            status: JobStatus::Queued,
+            spec,
+            dates_done: 0,
+            part_len: 0,
+            rows: None,
+            error: None,
+            result: None,
+        };
+        save(state, &job)?;
+        inner.jobs.insert(id.clone(), job.clone());
+        inner.queue.push_back(id);
+        inner.spawn_worker(state);
+        job
+    };
+    info!("job {}: queued", job.id);
+    audit::record(state, req, "job.submit", &job.id);
+    let location = format!("{}/jobs/{}", state.config.url_prefix, job.id);
+    let mut res = HttpResponse::Accepted();
+    res.insert_header(("Location", location));
+    job_response(res, &job)
+}
+
+/// Handle request for a list of jobs
+pub fn handle_list(
+    state: &AppState,
+    req: &HttpRequest,
+) -> Result<HttpResponse, Error> {
+    if state.config.jobs_path.is_none() {
+        return Err(Error::NotFound);
+    }
+    let client = Client::from_request(state, req)?;
+    let inner = state.jobs.inner.lock().unwrap();
+    let mut jobs: Vec<&Job> =
+        inner.jobs.values().filter(|job| client.owns(job)).collect();
+    jobs.sort_by_key(|job| (job.submitted, &job.id));
+    Ok(json_response(serde_json::to_string(&jobs)?))
+}
+
+/// Handle request for the status of a job
+pub fn handle_status(
+    state: &AppState,
+    req: &HttpRequest,
+    id: &str,
+) -> Result<HttpResponse, Error> {
+    let client = Client::from_request(state, req)?;
+    let job = state.jobs.get_owned(id, &client).ok_or(Error::NotFound)?;
+    job_response(HttpResponse::Ok(), &job)
+}
+
+/// Handle request to cancel a job.
+///
+/// Running jobs stop after the date being exported.  Output of cancelled
+/// jobs (including results of finished jobs) is removed.
+pub fn handle_cancel(
+    state: &AppState,
+    req: &HttpRequest,
+    id: &str,
+) -> Result<HttpResponse, Error> {
+    let client = Client::from_request(state, req)?;
+    let job = {
+        let mut inner = state.jobs.inner.lock().unwrap();
+        let job = inner
+            .jobs
+            .get_mut(id)
+            .filter(|job| client.owns(job))
+            .ok_or(Error::NotFound)?;
+        let running = job.status == JobStatus::Running;
+        job.status = JobStatus::Cancelled;
+        job.result = None;
+        let job = (!running).then(|| job.clone());
+        inner.queue.retain(|q| q != id);
+        job
+    };
+    // a running job's worker removes its output when it stops
+    if let Some(job) = &job {
+        remove_output(state, id);
+        save(state, job)?;
+    }
+    info!("job {}: cancel requested", id);
+    audit::record(state, req, "job.cancel", id);
+    handle_status(state, req, id)
+}
+
+/// Handle request for the result of a finished job
+pub fn handle_result(
+    state: &AppState,
+    req: &HttpRequest,
+    id: &str,
+) -> Result<HttpResponse, Error> {
+    let client = Client::from_request(state, req)?;
+    match state.jobs.get_owned(id, &client) {
+        Some(job) if job.status == JobStatus::Done => {
+            let path = job_path(state, id, "sql").ok_or(Error::NotFound)?;
+            let file = match NamedFile::open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::NotFound)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let sql = SQL.parse().unwrap();
+            Ok(file.set_content_type(sql).into_response(req))
+        }
+        _ => Err(Error::NotFound),
+    }
+}
@@ -0,0 +1,80 @@
+// lib.rs
+//
+// Copyright (c) 2019-2021  Minnesota Department of Transportation
+//
+#![forbid(unsafe_code)]
+
+mod admin;
+mod align;
+mod annotate;
+mod anomaly;
+pub mod apikey;
+mod assets;
+mod audit;
+mod avail;
+pub mod backfill;
+mod balance;
+mod baseline;
+mod bottleneck;
+mod cache;
+pub mod client;
+mod corridor;
+mod dates;
+pub mod deprecation;
+pub mod diskcache;
+pub mod error;
+pub mod export;
+mod fanout;
+mod federation;
+mod format;
+mod geo;
+mod headway;
+mod health;
+pub mod jobs;
+mod locate;
+mod memo;
+mod metrics;
+pub mod metro;
+pub mod migrate;
+mod multipart;
+mod notfound;
+pub mod output;
+pub mod pgexport;
+#[cfg(feature = "postgres")]
+mod pgwire;
+mod pool;
+pub mod prewarm;
+pub mod proxy;
+mod range;
+mod rename;
+pub mod report;
+mod robots;
+mod route;
+pub mod sample;
+pub mod schema;
+pub mod sensor;
+pub mod server;
+pub mod signing;
+mod spacing;
+mod spec;
+mod speed;
+mod sqlfile;
+pub mod sqlite;
+pub mod state;
+pub mod stats;
+mod storage;
+mod swap;
+pub mod sync;
+mod template;
+mod timing;
+pub mod upstream;
+mod vclass;
+pub mod verify;
+mod vlog;
+mod vmt;
+pub mod warm;
+pub mod watch;
+mod weather;
+pub mod webhook;
+pub mod wire;
+pub mod zone;
@@ -0,0 +1,180 @@
+// locate.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Linear referencing of corridor positions.
+//
+// A corridor is a polyline of r_node positions, with miles measured from
+// its first node.  A mile point or a position (e.g. of a crash) is located
+// along it, and matched to the nearest mainline station.
+//
+use crate::corridor::{load_locations, Location};
+use crate::error::Error;
+use crate::geo::{self, Position};
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// Query parameters for locate requests
+#[derive(Deserialize)]
+struct LocateParams {
+    /// Mile point (miles from first r_node)
+    mile: Option<f64>,
+    /// Longitude
+    lon: Option<f64>,
+    /// Latitude
+    lat: Option<f64>,
+}
+
+/// Nearest station to a located point
+#[derive(JsonSchema, Serialize)]
+struct NearestStation<'a> {
+    /// Station ID
+    id: &'a str,
+    /// R_Node name
+    r_node: &'a str,
+    /// Mile point of station
+    mile: f64,
+    /// Distance from located point to station (miles, negative upstream)
+    distance: f64,
+}
+
+/// Located corridor point (JSON)
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Located<'a> {
+    corridor: &'a str,
+    /// Mile point
+    mile: f64,
+    /// Longitude on corridor
+    lon: f64,
+    /// Latitude on corridor
+    lat: f64,
+    /// Distance from requested position to corridor (miles)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<f64>,
+    station: Option<NearestStation<'a>>,
+}
+
+/// Point along a corridor polyline
+struct Vertex {
+    /// Mile point
+    mile: f64,
+    /// Position
+    pos: Position,
+}
+
+/// Get polyline vertices of corridor locations with a position
+fn vertices(locs: &[Location]) -> Vec<Vertex> {
+    locs.iter()
+        .filter_map(|loc| {
+            loc.node.pos.map(|pos| Vertex {
+                mile: loc.mile,
+                pos,
+            })
+        })
+        .collect()
+}
+
+/// Locate a mile point, clamped to the ends of the corridor
+fn locate_mile(verts: &[Vertex], mile: f64) -> (f64, Position) {
+    let first = &verts[0];
+    if mile <= first.mile {
+        return (first.mile, first.pos);
+    }
+    for pair in verts.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if mile <= b.mile {
+            let len = b.mile - a.mile;
+            let t = if len > 0.0 {
+                (mile - a.mile) / len
+            } else {
+                0.0
+            };
+            return (mile, geo::interpolate(a.pos, b.pos, t));
+        }
+    }
+    let last = &verts[verts.len() - 1];
+    (last.mile, last.pos)
+}
+
+/// Locate the corridor point nearest a position.
+///
+/// Returns mile point, position on corridor and offset (miles).
+fn locate_position(verts: &[Vertex], pt: Position) -> (f64, Position, f64) {
+    let first = &verts[0];
+    let mut best = (first.mile, first.pos, geo::distance_miles(pt, first.pos));
+    for pair in verts.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let t = geo::project(pt, a.pos, b.pos);
+        let pos = geo::interpolate(a.pos, b.pos, t);
+        let offset = geo::distance_miles(pt, pos);
+        if offset < best.2 {
+            best = (a.mile + t * (b.mile - a.mile), pos, offset);
+        }
+    }
+    best
+}
+
+/// Find the station nearest a mile point
+fn nearest_station(locs: &[Location], mile: f64) -> Option<NearestStation<'_>> {
+    locs.iter()
+        .filter(|loc| loc.is_station())
+        .min_by(|a, b| {
+            let da = (a.mile - mile).abs();
+            let db = (b.mile - mile).abs();
+            da.total_cmp(&db)
+        })
+        .map(|loc| NearestStation {
+            id: loc.id(),
+            r_node: &loc.node.name,
+            mile: loc.mile,
+            distance: loc.mile - mile,
+        })
+}
+
+/// Handle request to locate a mile point or position along a corridor
+pub fn handle_locate(
+    state: &AppState,
+    date: &str,
+    rte: &str,
+    dir: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<LocateParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let corridor = format!("{}_{}", rte, dir);
+    let locs = load_locations(state, date, &corridor)?.locs;
+    let verts = vertices(&locs);
+    if verts.is_empty() {
+        return Err(Error::NotFound);
+    }
+    let (mile, pos, offset) = match (params.mile, params.lon, params.lat) {
+        (Some(mile), None, None) if mile.is_finite() => {
+            let (mile, pos) = locate_mile(&verts, mile);
+            (mile, pos, None)
+        }
+        (None, Some(lon), Some(lat))
+            if (-180.0..=180.0).contains(&lon)
+                && (-90.0..=90.0).contains(&lat) =>
+        {
+            let (mile, pos, offset) = locate_position(&verts, (lon, lat));
+            (mile, pos, Some(offset))
+        }
+        _ => {
+            return Err(Error::InvalidParam(
+                "mile, or lon and lat required".into(),
+            ))
+        }
+    };
+    let located = Located {
+        corridor: &corridor,
+        mile,
+        lon: pos.0,
+        lat: pos.1,
+        offset,
+        station: nearest_station(&locs, mile),
+    };
+    Ok(json_response(serde_json::to_string(&located)?))
+}
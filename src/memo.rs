@@ -0,0 +1,94 @@
+// memo.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Memoized results of historical multi-day computations.
+//
+// Archived days never change, so results are kept until the list of dates
+// archived in their range changes (for example, when a missing day is
+// added), rather than expiring after a TTL.
+//
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+/// Maximum number of memoized results
+const MAX_ENTRIES: usize = 1024;
+
+/// Memoized result
+struct Entry<T> {
+    /// Dates archived in the range when computed
+    dates: Vec<String>,
+    /// Computed result
+    value: T,
+}
+
+/// Memo of results, keyed by district, sensors, range and parameters
+pub struct Memo<K, T> {
+    /// Memoized entries by key
+    entries: Mutex<HashMap<K, Entry<T>>>,
+}
+
+impl<K, T> Default for Memo<K, T> {
+    fn default() -> Self {
+        Memo {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// Result of a memo lookup
+pub struct Lookup<T> {
+    /// Result value
+    pub value: T,
+    /// Was the result memoized?
+    pub hit: bool,
+}
+
+impl<K: Eq + Hash, T: Clone> Memo<K, T> {
+    /// Get a memoized result, or compute one and memoize it if successful.
+    ///
+    /// * `key` Memo key (district, sensors, range and parameters).
+    /// * `dates` Dates currently archived in the range.
+    /// * `compute` Function to compute the result.
+    pub fn get_or_compute<E, F>(
+        &self,
+        key: K,
+        dates: Vec<String>,
+        compute: F,
+    ) -> Result<Lookup<T>, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.dates == dates {
+                return Ok(Lookup {
+                    value: entry.value.clone(),
+                    hit: true,
+                });
+            }
+        }
+        let value = compute()?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&key) {
+            entries.clear();
+        }
+        let entry = Entry {
+            dates,
+            value: value.clone(),
+        };
+        entries.insert(key, entry);
+        Ok(Lookup { value, hit: false })
+    }
+
+    /// Remove entries with a matching key, returning the number removed
+    pub fn flush<F>(&self, matches: F) -> usize
+    where
+        F: Fn(&K) -> bool,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| !matches(key));
+        before - entries.len()
+    }
+}
@@ -0,0 +1,193 @@
+// metrics.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::deprecation::Form;
+use crate::state::AppState;
+use crate::timing::{Stage, Timings};
+use actix_web::{web, HttpResponse};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Server metrics
+#[derive(Default)]
+pub struct Metrics {
+    /// Number of corrupt archive reads
+    corrupt_archives: AtomicU64,
+    /// Number of zip archives opened
+    zip_opens: AtomicU64,
+    /// Number of zip archives evicted from pool
+    zip_evictions: AtomicU64,
+    /// Number of length-mismatched sample files served
+    length_mismatches: AtomicU64,
+    /// Number of finished export jobs expired
+    job_expirations: AtomicU64,
+    /// Number of finished export jobs removed to stay within quota
+    job_evictions: AtomicU64,
+    /// Total size of export job output (bytes)
+    job_output_bytes: AtomicU64,
+    /// Number of requests for each deprecated route form
+    deprecated_requests: [AtomicU64; Form::ALL.len()],
+    /// Elapsed time of pipeline stages (microseconds)
+    stage_micros: [AtomicU64; Stage::ALL.len()],
+    /// Number of pipeline stage runs
+    stage_counts: [AtomicU64; Stage::ALL.len()],
+}
+
+impl Metrics {
+    /// Count a corrupt archive read
+    pub fn corrupt_archive(&self) {
+        self.corrupt_archives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a zip archive open
+    pub fn zip_open(&self) {
+        self.zip_opens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a zip archive pool eviction
+    pub fn zip_eviction(&self) {
+        self.zip_evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a length-mismatched sample file served
+    pub fn length_mismatch(&self) {
+        self.length_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an expired export job
+    pub fn job_expired(&self) {
+        self.job_expirations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an export job removed to stay within quota
+    pub fn job_evicted(&self) {
+        self.job_evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set the total size of export job output
+    pub fn set_job_output_bytes(&self, bytes: u64) {
+        self.job_output_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a request for a deprecated route form
+    pub fn deprecated_request(&self, form: Form) {
+        self.deprecated_requests[form.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add the stage timings of a request
+    pub fn record_timings(&self, timings: &Timings) {
+        for (i, stage) in Stage::ALL.iter().enumerate() {
+            let (elapsed, count) = timings.stage(*stage);
+            if count > 0 {
+                let micros = elapsed.as_micros() as u64;
+                self.stage_micros[i].fetch_add(micros, Ordering::Relaxed);
+                self.stage_counts[i]
+                    .fetch_add(u64::from(count), Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Render metrics in Prometheus text format
+    fn render(&self) -> String {
+        let mut res = String::new();
+        write_counter(
+            &mut res,
+            "trafdat_corrupt_archives_total",
+            "Corrupt archive reads",
+            &self.corrupt_archives,
+        );
+        write_counter(
+            &mut res,
+            "trafdat_zip_opens_total",
+            "Zip archives opened",
+            &self.zip_opens,
+        );
+        write_counter(
+            &mut res,
+            "trafdat_zip_evictions_total",
+            "Zip archives evicted from handle pool",
+            &self.zip_evictions,
+        );
+        write_counter(
+            &mut res,
+            "trafdat_length_mismatches_total",
+            "Length-mismatched sample files served (lenient mode)",
+            &self.length_mismatches,
+        );
+        write_counter(
+            &mut res,
+            "trafdat_job_expirations_total",
+            "Finished export jobs expired",
+            &self.job_expirations,
+        );
+        write_counter(
+            &mut res,
+            "trafdat_job_evictions_total",
+            "Finished export jobs removed to stay within quota",
+            &self.job_evictions,
+        );
+        write_gauge(
+            &mut res,
+            "trafdat_job_output_bytes",
+            "Total size of export job output",
+            &self.job_output_bytes,
+        );
+        self.write_deprecated(&mut res);
+        self.write_stages(&mut res);
+        res
+    }
+
+    /// Write requests for deprecated route forms
+    fn write_deprecated(&self, res: &mut String) {
+        let name = "trafdat_deprecated_requests_total";
+        writeln!(res, "# HELP {} Requests for deprecated routes", name)
+            .unwrap();
+        writeln!(res, "# TYPE {} counter", name).unwrap();
+        for form in Form::ALL {
+            let count =
+                self.deprecated_requests[form.index()].load(Ordering::Relaxed);
+            writeln!(res, "{}{{route=\"{}\"}} {}", name, form.as_str(), count)
+                .unwrap();
+        }
+    }
+
+    /// Write pipeline stage timings as a summary
+    fn write_stages(&self, res: &mut String) {
+        let name = "trafdat_stage_seconds";
+        writeln!(res, "# HELP {} Elapsed time of pipeline stages", name)
+            .unwrap();
+        writeln!(res, "# TYPE {} summary", name).unwrap();
+        for (i, stage) in Stage::ALL.iter().enumerate() {
+            let micros = self.stage_micros[i].load(Ordering::Relaxed);
+            let count = self.stage_counts[i].load(Ordering::Relaxed);
+            let stage = stage.as_str();
+            let secs = micros as f64 / 1_000_000.0;
+            writeln!(res, "{}_sum{{stage=\"{}\"}} {}", name, stage, secs)
+                .unwrap();
+            writeln!(res, "{}_count{{stage=\"{}\"}} {}", name, stage, count)
+                .unwrap();
+        }
+    }
+}
+
+/// Write a counter in Prometheus text format
+fn write_counter(res: &mut String, name: &str, help: &str, val: &AtomicU64) {
+    writeln!(res, "# HELP {} {}", name, help).unwrap();
+    writeln!(res, "# TYPE {} counter", name).unwrap();
+    writeln!(res, "{} {}", name, val.load(Ordering::Relaxed)).unwrap();
+}
+
+/// Write a gauge in Prometheus text format
+fn write_gauge(res: &mut String, name: &str, help: &str, val: &AtomicU64) {
+    writeln!(res, "# HELP {} {}", name, help).unwrap();
+    writeln!(res, "# TYPE {} gauge", name).unwrap();
+    writeln!(res, "{} {}", name, val.load(Ordering::Relaxed)).unwrap();
+}
+
+/// Handle request for metrics
+pub async fn handle_metrics(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.metrics.render())
+}
@@ -2,263 +2,772 @@
 //
 // Copyright (c) 2020 Minnesota Department of Transportation
 //
-use actix_web::HttpResponse;
+use crate::error::Error;
+use crate::geo;
+use crate::schema::JsonSchema;
+use crate::state::AppState;
+use crate::wire::{
+    implied, Camera, Commlink, Corridor, CorridorFeature, CorridorProperties,
+    Detector, LineString, RNode, TmsConfig,
+};
+use actix_files::NamedFile;
+use actix_web::http::header::ContentEncoding;
+use actix_web::{web, HttpRequest, HttpResponse};
 use flate2::read::GzDecoder;
 use libxml::parser::Parser;
 use libxml::tree::document::Document;
+use libxml::tree::Node;
 use libxml::xpath::Context;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use serde_xml_rs::from_str;
-use std::fs::File;
-use std::io::Read;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{metadata, read_dir, File};
+use std::io::{self, Read};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use unicode_segmentation::UnicodeSegmentation;
+use xml::reader::EventReader;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct TmsConfig {
-    #[serde(default = "Vec::new")]
-    corridor: Vec<Corridor>,
-    #[serde(default = "Vec::new")]
-    camera: Vec<Camera>,
-    #[serde(default = "Vec::new")]
-    commlink: Vec<Commlink>,
-    #[serde(default = "Vec::new")]
-    controller: Vec<Controller>,
-    #[serde(default = "Vec::new")]
-    dms: Vec<Dms>,
-    time_stamp: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Corridor {
-    #[serde(default = "Vec::new")]
-    r_node: Vec<RNode>,
-    route: String,
-    dir: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct RNode {
-    #[serde(default = "Vec::new")]
-    detector: Vec<Detector>,
-    #[serde(default = "Vec::new")]
-    meter: Vec<Meter>,
-    name: String,
-    #[serde(default = "station_str")]
-    n_type: String,
-    #[serde(default = "false_str")]
-    pickable: String,
-    #[serde(default = "false_str")]
-    above: String,
-    #[serde(default = "none_str")]
-    transition: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    station_id: String,
-    #[serde(default = "String::new")]
-    label: String,
-    lon: String,
-    lat: String,
-    #[serde(default = "zero_str")]
-    lanes: String,
-    #[serde(default = "right_str")]
-    attach_side: String,
-    #[serde(default = "zero_str")]
-    shift: String,
-    #[serde(default = "true_str")]
-    active: String,
-    #[serde(default = "false_str")]
-    abandoned: String,
-    #[serde(default = "ff_str")]
-    s_limit: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    forks: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Detector {
-    name: String,
-    #[serde(default = "future_str")]
-    label: String,
-    #[serde(default = "false_str")]
-    abandoned: String,
-    #[serde(default = "String::new")]
-    category: String,
-    #[serde(default = "zero_str")]
-    lane: String,
-    #[serde(default = "tt_str")]
-    field: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    controller: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Meter {
-    name: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lon: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lat: String,
-    storage: String,
-    #[serde(default = "tfz_str")]
-    max_wait: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Camera {
-    name: String,
-    description: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lon: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lat: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Commlink {
-    name: String,
-    description: String,
-    protocol: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Controller {
-    name: String,
-    //active: String,  // Present in XML DTD but not the actual document
-    condition: String, // Present in document, but not the DTD
-    drop: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    commlink: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lon: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lat: String,
-    location: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    cabinet: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    notes: String,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Dms {
-    name: String,
-    description: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lon: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    lat: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    width_pixels: String,
-    #[serde(default = "implied_str")]
-    #[serde(skip_serializing_if = "implied")]
-    height_pixels: String,
-}
-
-/// Functions to implement defaults from the Document Type Definition (DTD)
-fn station_str() -> String {
-    "Station".to_string()
-}
-fn false_str() -> String {
-    "f".to_string()
-}
-fn true_str() -> String {
-    "t".to_string()
-}
-fn zero_str() -> String {
-    "0".to_string()
-}
-fn none_str() -> String {
-    "None".to_string()
-}
-fn right_str() -> String {
-    "right".to_string()
-}
-fn ff_str() -> String {
-    "55".to_string()
-}
-fn tt_str() -> String {
-    "22.0".to_string()
-}
-fn tfz_str() -> String {
-    "240".to_string()
-}
-fn future_str() -> String {
-    "FUTURE".to_string()
-}
-/// Used as default for #IMPLIED attributes with no default
-fn implied_str() -> String {
-    "#IMPLIED".to_string()
-}
-/// Used to check if #IMPLIED value should be left out
-fn implied(val: &String) -> bool {
-    val == "#IMPLIED"
-}
-
-/// Base metro archive path
-const BASE_PATH: &str = "/var/lib/iris/metro_config";
+/// Query parameters for corridor GeoJSON requests
+#[derive(Deserialize)]
+struct GeoParams {
+    /// Simplification tolerance (degrees)
+    simplify: Option<f64>,
+}
 
-/// Takes the entire metro_config.xml string and converts it to
-/// JSON using the above structs
-fn build_full_json(xmldoc: Option<String>) -> Option<String> {
-    xmldoc.and_then(|xmldoc| {
-        let res: Result<TmsConfig, _> = from_str(&xmldoc);
-        if let Ok(_tmsconfig) = res {
-            if let Ok(js) = serde_json::to_string(&_tmsconfig) {
-                Some(js)
-            } else {
-                None
+/// Query parameters for corridor XML requests
+#[derive(Deserialize)]
+struct CorridorXmlParams {
+    /// Emit a standalone document (declaration and `tms_config` root)
+    standalone: Option<bool>,
+    /// Include the internal DTD subset (implies `standalone`)
+    dtd: Option<bool>,
+}
+
+impl CorridorFeature {
+    /// Create a corridor feature, optionally simplifying the line string
+    fn new(corridor: Corridor, tolerance: Option<f64>) -> Self {
+        let mut names = vec![];
+        let mut points = vec![];
+        for rn in corridor.r_node {
+            if let (Ok(lon), Ok(lat)) = (rn.lon.parse(), rn.lat.parse()) {
+                names.push(rn.name);
+                points.push((lon, lat));
+            }
+        }
+        let keep = match tolerance {
+            Some(tolerance) => geo::simplify(&points, tolerance),
+            None => vec![true; points.len()],
+        };
+        let mut r_node = vec![];
+        let mut coordinates = vec![];
+        for ((name, (lon, lat)), keep) in
+            names.into_iter().zip(points).zip(keep)
+        {
+            if keep {
+                r_node.push(name);
+                coordinates.push([lon, lat]);
             }
-        } else {
-            None
         }
-    })
+        CorridorFeature {
+            kind: "Feature".to_string(),
+            properties: CorridorProperties {
+                route: corridor.route,
+                dir: corridor.dir,
+                r_node,
+            },
+            geometry: LineString {
+                kind: "LineString".to_string(),
+                coordinates,
+            },
+        }
+    }
+}
+
+/// Bounding box (min lon, min lat, max lon, max lat)
+struct BoundingBox([f64; 4]);
+
+impl BoundingBox {
+    /// Parse a bounding box query parameter
+    fn parse(bbox: &str) -> Result<Self, Error> {
+        let err = || Error::InvalidParam(format!("bbox: {}", bbox));
+        let vals = bbox
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| err())?;
+        match vals[..] {
+            [x0, y0, x1, y1]
+                if vals.iter().all(|v| v.is_finite())
+                    && x0 <= x1
+                    && y0 <= y1 =>
+            {
+                Ok(BoundingBox([x0, y0, x1, y1]))
+            }
+            _ => Err(err()),
+        }
+    }
+
+    /// Check if a position (as attribute strings) is within the box
+    fn contains(&self, lon: &str, lat: &str) -> bool {
+        let [x0, y0, x1, y1] = self.0;
+        match (lon.parse::<f64>(), lat.parse::<f64>()) {
+            (Ok(lon), Ok(lat)) => {
+                lon >= x0 && lon <= x1 && lat >= y0 && lat <= y1
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Query parameters for node requests
+#[derive(Deserialize)]
+struct NodeParams {
+    /// Bounding box (min lon, min lat, max lon, max lat)
+    bbox: Option<String>,
+    /// Detector categories (comma separated)
+    category: Option<String>,
+}
+
+/// Query parameters for detector link requests
+#[derive(Deserialize)]
+struct DetectorParams {
+    /// Detector names (comma separated)
+    detectors: Option<String>,
+}
+
+/// Query parameters for corridor JSON requests
+#[derive(Deserialize)]
+struct CorridorParams {
+    /// Detector categories (comma separated)
+    category: Option<String>,
+}
+
+/// Detector category group for query filters
+#[derive(Clone, Copy, PartialEq)]
+enum Category {
+    /// Mainline lanes (no category code)
+    Mainline,
+    /// Exit ramps (`X`)
+    Exit,
+    /// Entrance ramps (queue, passage, merge, bypass, green)
+    Entrance,
+    /// HOV / HOT lanes
+    Hov,
+}
+
+impl Category {
+    /// Parse a comma-separated list of category groups
+    fn parse_list(val: &str) -> Result<Vec<Self>, Error> {
+        val.split(',')
+            .map(|cat| match cat {
+                "mainline" => Ok(Category::Mainline),
+                "exit" => Ok(Category::Exit),
+                "entrance" => Ok(Category::Entrance),
+                "hov" => Ok(Category::Hov),
+                _ => Err(Error::InvalidParam(format!("category: {}", cat))),
+            })
+            .collect()
+    }
+
+    /// Check if a detector category code is in the group
+    fn contains(self, code: &str) -> bool {
+        match self {
+            Category::Mainline => code.is_empty(),
+            Category::Exit => code == "X",
+            Category::Entrance => matches!(code, "Q" | "P" | "M" | "B" | "G"),
+            Category::Hov => matches!(code, "H" | "HT"),
+        }
+    }
+}
+
+/// Retain only detectors matching any category group
+fn filter_detectors<'a, I>(r_nodes: I, cats: &[Category])
+where
+    I: IntoIterator<Item = &'a mut RNode>,
+{
+    for rn in r_nodes {
+        rn.detector
+            .retain(|det| cats.iter().any(|c| c.contains(&det.category)));
+    }
+}
+
+/// R_Node with its corridor
+#[derive(JsonSchema, Serialize)]
+struct CorridorNode<'a> {
+    route: &'a str,
+    dir: &'a str,
+    #[serde(flatten)]
+    r_node: &'a RNode,
+}
+
+/// R_Nodes (with detectors) and cameras within a bounding box
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Nodes<'a> {
+    r_node: Vec<CorridorNode<'a>>,
+    camera: Vec<&'a Camera>,
+}
+
+impl<'a> Nodes<'a> {
+    /// Find nodes within an optional bounding box
+    fn new(config: &'a TmsConfig, bbox: Option<&BoundingBox>) -> Self {
+        let inside = |lon: &str, lat: &str| {
+            bbox.is_none_or(|bbox| bbox.contains(lon, lat))
+        };
+        let mut r_node = vec![];
+        for cor in &config.corridor {
+            for rn in cor.r_node.iter().filter(|rn| inside(&rn.lon, &rn.lat)) {
+                r_node.push(CorridorNode {
+                    route: &cor.route,
+                    dir: &cor.dir,
+                    r_node: rn,
+                });
+            }
+        }
+        let camera = config
+            .camera
+            .iter()
+            .filter(|cam| inside(&cam.lon, &cam.lat))
+            .collect();
+        Nodes { r_node, camera }
+    }
+}
+
+/// Controller of a detector, with its comm link
+#[derive(JsonSchema, Serialize)]
+struct ControllerLink<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drop: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cabinet: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lon: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lat: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commlink: Option<CommlinkRef<'a>>,
+}
+
+/// Comm link of a controller
+#[derive(JsonSchema, Serialize)]
+struct CommlinkRef<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol: Option<&'a str>,
+}
+
+/// Detector joined with its r_node, controller and comm link
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct DetectorLink<'a> {
+    name: &'a str,
+    label: &'a str,
+    category: &'a str,
+    lane: &'a str,
+    abandoned: bool,
+    route: &'a str,
+    dir: &'a str,
+    r_node: &'a str,
+    /// Controller, or `None` if not assigned
+    controller: Option<ControllerLink<'a>>,
+}
+
+/// Get an attribute value, unless implied
+fn explicit(val: &String) -> Option<&str> {
+    (!implied(val)).then_some(val.as_str())
+}
+
+impl<'a> ControllerLink<'a> {
+    /// Join a controller name to its controller and comm link.
+    ///
+    /// Unknown controllers or comm links have only a name.
+    fn new(config: &'a TmsConfig, name: &'a str) -> Self {
+        let ctl = config.controller.iter().find(|c| c.name == name);
+        let commlink = ctl.and_then(|c| explicit(&c.commlink)).map(|name| {
+            let link = config.commlink.iter().find(|l| l.name == name);
+            CommlinkRef::new(name, link)
+        });
+        ControllerLink {
+            name,
+            condition: ctl.map(|c| c.condition.as_str()),
+            drop: ctl.map(|c| c.drop.as_str()),
+            location: ctl.map(|c| c.location.as_str()),
+            cabinet: ctl.and_then(|c| explicit(&c.cabinet)),
+            lon: ctl.and_then(|c| explicit(&c.lon)),
+            lat: ctl.and_then(|c| explicit(&c.lat)),
+            commlink,
+        }
+    }
+}
+
+impl<'a> CommlinkRef<'a> {
+    /// Create a comm link reference
+    fn new(name: &'a str, link: Option<&'a Commlink>) -> Self {
+        CommlinkRef {
+            name,
+            description: link.map(|l| l.description.as_str()),
+            protocol: link.map(|l| l.protocol.as_str()),
+        }
+    }
+}
+
+impl<'a> DetectorLink<'a> {
+    /// Join a detector to its controller and comm link
+    fn new(
+        config: &'a TmsConfig,
+        cor: &'a Corridor,
+        rn: &'a RNode,
+        det: &'a Detector,
+    ) -> Self {
+        let controller =
+            explicit(&det.controller).map(|c| ControllerLink::new(config, c));
+        DetectorLink {
+            name: &det.name,
+            label: &det.label,
+            category: &det.category,
+            lane: &det.lane,
+            abandoned: det.abandoned == "t",
+            route: &cor.route,
+            dir: &cor.dir,
+            r_node: &rn.name,
+            controller,
+        }
+    }
+
+    /// Join all detectors (or only listed names) to their controllers
+    fn list(config: &'a TmsConfig, names: Option<&[&str]>) -> Vec<Self> {
+        let mut links = vec![];
+        for cor in &config.corridor {
+            for rn in &cor.r_node {
+                for det in &rn.detector {
+                    if names.is_none_or(|n| n.contains(&det.name.as_str())) {
+                        links.push(DetectorLink::new(config, cor, rn, det));
+                    }
+                }
+            }
+        }
+        links
+    }
+}
+
+/// Roadway graph vertex (r_node)
+#[derive(JsonSchema, Serialize)]
+struct GraphNode<'a> {
+    id: &'a str,
+    route: &'a str,
+    dir: &'a str,
+    n_type: &'a str,
+    transition: &'a str,
+    lon: &'a str,
+    lat: &'a str,
+}
+
+/// Roadway graph edge
+#[derive(JsonSchema, Serialize)]
+struct GraphEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    /// `link` (next r_node on corridor), `fork`, or lower-case transition
+    kind: String,
+}
+
+/// Roadway network graph
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Graph<'a> {
+    nodes: Vec<GraphNode<'a>>,
+    edges: Vec<GraphEdge<'a>>,
+}
+
+impl<'a> Graph<'a> {
+    /// Build a graph from r_node ordering and fork attributes.
+    ///
+    /// Consecutive r_nodes on a corridor are linked in order.  Forks link an
+    /// r_node to a node on another corridor; for entrances the edge leads
+    /// from the fork into the entrance.
+    fn new(config: &'a TmsConfig) -> Self {
+        let mut nodes = vec![];
+        let mut edges = vec![];
+        for cor in &config.corridor {
+            for rn in &cor.r_node {
+                nodes.push(GraphNode {
+                    id: &rn.name,
+                    route: &cor.route,
+                    dir: &cor.dir,
+                    n_type: &rn.n_type,
+                    transition: &rn.transition,
+                    lon: &rn.lon,
+                    lat: &rn.lat,
+                });
+            }
+            for pair in cor.r_node.windows(2) {
+                edges.push(GraphEdge {
+                    from: &pair[0].name,
+                    to: &pair[1].name,
+                    kind: "link".into(),
+                });
+            }
+        }
+        let names: HashSet<&str> = nodes.iter().map(|n| n.id).collect();
+        let mut linked: HashSet<(&str, &str)> =
+            edges.iter().map(|e| (e.from, e.to)).collect();
+        for rn in config.corridor.iter().flat_map(|c| &c.r_node) {
+            if implied(&rn.forks) || !names.contains(rn.forks.as_str()) {
+                continue;
+            }
+            let kind = match rn.transition.as_str() {
+                "None" => "fork".into(),
+                tr => tr.to_lowercase(),
+            };
+            let (from, to) = match rn.n_type.as_str() {
+                "Entrance" => (rn.forks.as_str(), rn.name.as_str()),
+                _ => (rn.name.as_str(), rn.forks.as_str()),
+            };
+            // Forks are often declared on both ends; keep the first
+            if linked.insert((from, to)) {
+                edges.push(GraphEdge { from, to, kind });
+            }
+        }
+        Graph { nodes, edges }
+    }
+}
+
+/// Roadway node summary for corridor analysis
+pub struct RoadNode {
+    /// R_Node name
+    pub name: String,
+    /// Node type (`Station`, `Entrance`, `Exit`, ...)
+    pub n_type: String,
+    /// Station ID, if any
+    pub station_id: Option<String>,
+    /// Position (lon, lat)
+    pub pos: Option<geo::Position>,
+    /// Active detectors (name, category code, field length in feet, lane)
+    pub detectors: Vec<(String, String, f64, Option<u32>)>,
+}
+
+impl From<&RNode> for RoadNode {
+    fn from(rn: &RNode) -> Self {
+        let pos = match (rn.lon.parse(), rn.lat.parse()) {
+            (Ok(lon), Ok(lat)) => Some((lon, lat)),
+            _ => None,
+        };
+        let station_id = match implied(&rn.station_id) {
+            true => None,
+            false => Some(rn.station_id.clone()),
+        };
+        let detectors = rn
+            .detector
+            .iter()
+            .filter(|det| det.abandoned != "t")
+            .map(|det| {
+                let field = det.field.parse().unwrap_or(22.0);
+                let lane = det.lane.parse().ok().filter(|lane| *lane > 0);
+                (det.name.clone(), det.category.clone(), field, lane)
+            })
+            .collect();
+        RoadNode {
+            name: rn.name.clone(),
+            n_type: rn.n_type.clone(),
+            station_id,
+            pos,
+            detectors,
+        }
+    }
+}
+
+/// Lookup active r_nodes of a corridor (`route_dir`) on a date, in order
+pub fn lookup_corridor(
+    state: &AppState,
+    date: &str,
+    corridor: &str,
+) -> Result<Vec<RoadNode>, Error> {
+    let (rte, dir) = corridor.rsplit_once('_').ok_or_else(|| {
+        Error::InvalidParam(format!("corridor: {}", corridor))
+    })?;
+    let xml = get_xml_file(state, date)?;
+    let cor = get_corridor_on_date(state, date, xml, rte, dir)?;
+    let corridor: Corridor =
+        from_str(&cor).map_err(|e| parse_error(date, &e.to_string()))?;
+    Ok(corridor
+        .r_node
+        .iter()
+        .filter(|rn| rn.active != "f" && rn.abandoned != "t")
+        .map(RoadNode::from)
+        .collect())
+}
+
+/// Lookup the lane of a detector on a date
+pub fn detector_lane(
+    state: &AppState,
+    date: &str,
+    det: &str,
+) -> Option<String> {
+    let table = detector_table(state, date).ok()?;
+    table.get(det)?.lane.clone()
+}
+
+/// Lookup the controller of a detector on a date
+pub fn detector_controller(
+    state: &AppState,
+    date: &str,
+    det: &str,
+) -> Option<String> {
+    let table = detector_table(state, date).ok()?;
+    table.get(det)?.controller.clone()
+}
+
+/// Location of a detector in metro_config
+#[derive(Clone, Debug, JsonSchema, PartialEq, Serialize)]
+pub struct DetectorLocation {
+    /// R_Node name
+    pub r_node: String,
+    /// Station ID of the r_node, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub station: Option<String>,
+    /// Lane number, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lane: Option<String>,
+}
+
+/// Check if a metro_config file exists for a date
+pub fn has_config(state: &AppState, date: &str) -> bool {
+    is_valid_date(date) && xml_path(state, date).is_file()
+}
+
+/// Find the latest metro_config date before a date
+pub fn config_before(state: &AppState, date: &str) -> Option<String> {
+    read_dir(&state.config.metro_path)
+        .ok()?
+        .flatten()
+        .filter_map(|ent| {
+            let name = ent.file_name().into_string().ok()?;
+            let dt = name
+                .strip_prefix("metro_config_")?
+                .strip_suffix(".xml.gz")?
+                .to_string();
+            (is_valid_date(&dt) && dt.as_str() < date).then_some(dt)
+        })
+        .max()
+}
+
+/// Lookup the location (r_node, station and lane) of a detector on a date
+pub fn detector_location(
+    state: &AppState,
+    date: &str,
+    det: &str,
+) -> Result<Option<DetectorLocation>, Error> {
+    let table = detector_table(state, date)?;
+    Ok(table.get(det).and_then(|info| info.location.clone()))
+}
+
+/// Detector attributes from a metro_config
+#[derive(Debug, Default)]
+struct DetectorInfo {
+    /// Lane number, if any
+    lane: Option<String>,
+    /// Controller name
+    controller: Option<String>,
+    /// Location of the first detector element within an r_node
+    location: Option<DetectorLocation>,
+}
+
+/// Table of detectors in a metro_config, by name
+type DetectorTable = HashMap<String, DetectorInfo>;
+
+/// Detector table with metro_config modified time
+type CachedTable = (Option<SystemTime>, Arc<DetectorTable>);
+
+/// Build the detector table of a metro_config document
+fn build_detector_table(
+    state: &AppState,
+    date: &str,
+) -> Result<DetectorTable, Error> {
+    let xml = get_xml_file(state, date)?;
+    let doc = parse_document(state, date, xml)?;
+    let mut context = xpath_context(date, &doc)?;
+    let dets = context
+        .findnodes("//detector", None)
+        .map_err(|_| parse_error(date, "XPath"))?;
+    let mut table = DetectorTable::new();
+    for det in dets {
+        let name = match det.get_attribute("name") {
+            Some(name) => name,
+            None => continue,
+        };
+        let lane = det.get_attribute("lane").filter(|lane| lane != "0");
+        let info = table.entry(name).or_insert_with(|| DetectorInfo {
+            lane: lane.clone(),
+            controller: det.get_attribute("controller"),
+            location: None,
+        });
+        if info.location.is_some() {
+            continue;
+        }
+        if let Some(rn) = det.get_parent().filter(|p| p.get_name() == "r_node")
+        {
+            info.location = Some(DetectorLocation {
+                r_node: rn.get_attribute("name").unwrap_or_default(),
+                station: rn
+                    .get_attribute("station_id")
+                    .filter(|s| !s.is_empty()),
+                lane,
+            });
+        }
+    }
+    Ok(table)
+}
+
+/// Get the detector table of a metro_config, caching it by date
+fn detector_table(
+    state: &AppState,
+    date: &str,
+) -> Result<Arc<DetectorTable>, Error> {
+    if !is_valid_date(date) {
+        return Err(Error::NotFound);
+    }
+    let mtime = modified(state, date);
+    if let Some(table) = state.metro.detectors(date, mtime) {
+        return Ok(table);
+    }
+    let table = Arc::new(build_detector_table(state, date)?);
+    state
+        .metro
+        .record_detectors(date, mtime, Arc::clone(&table));
+    Ok(table)
+}
+
+/// Maximum number of cached detector tables
+const MAX_DETECTOR_TABLES: usize = 64;
+
+/// Cache of metro_config dates which have been checked.
+///
+/// Known-bad (quarantined) files are not parsed again until modified, and
+/// detector tables are kept until their file is modified.
+#[derive(Default)]
+pub struct MetroCache {
+    /// File modified time and whether the document is well-formed, by date
+    checked: Mutex<BTreeMap<String, (Option<SystemTime>, bool)>>,
+    /// File modified time and detector table, by date
+    detectors: Mutex<HashMap<String, CachedTable>>,
+}
+
+impl MetroCache {
+    /// Get the checked status of a date, if file has not been modified
+    fn status(&self, date: &str, mtime: Option<SystemTime>) -> Option<bool> {
+        match self.checked.lock().unwrap().get(date) {
+            Some((mt, ok)) if *mt == mtime => Some(*ok),
+            _ => None,
+        }
+    }
+
+    /// Record the checked status of a date
+    fn record(&self, date: &str, mtime: Option<SystemTime>, ok: bool) {
+        self.checked
+            .lock()
+            .unwrap()
+            .insert(date.to_string(), (mtime, ok));
+    }
+
+    /// Get the detector table of a date, if file has not been modified
+    fn detectors(
+        &self,
+        date: &str,
+        mtime: Option<SystemTime>,
+    ) -> Option<Arc<DetectorTable>> {
+        match self.detectors.lock().unwrap().get(date) {
+            Some((mt, table)) if *mt == mtime => Some(Arc::clone(table)),
+            _ => None,
+        }
+    }
+
+    /// Record the detector table of a date
+    fn record_detectors(
+        &self,
+        date: &str,
+        mtime: Option<SystemTime>,
+        table: Arc<DetectorTable>,
+    ) {
+        let mut detectors = self.detectors.lock().unwrap();
+        if detectors.len() >= MAX_DETECTOR_TABLES
+            && !detectors.contains_key(date)
+        {
+            detectors.clear();
+        }
+        detectors.insert(date.to_string(), (mtime, table));
+    }
+}
+
+/// Takes the entire metro_config.xml string and converts it to
+/// JSON using the above structs
+pub fn build_full_json(
+    state: &AppState,
+    date: &str,
+    xmldoc: &str,
+) -> Result<String, Error> {
+    let tmsconfig = parse_config(state, date, xmldoc)?;
+    Ok(serde_json::to_string(&tmsconfig)?)
+}
+
+/// Parse the entire metro_config.xml string
+fn parse_config(
+    state: &AppState,
+    date: &str,
+    xmldoc: &str,
+) -> Result<TmsConfig, Error> {
+    check_document(state, date, xmldoc)?;
+    from_str(xmldoc).map_err(|e| quarantine(state, date, &e.to_string()))
+}
+
+/// Check the metro_config document for a date, caching its status
+pub fn prewarm(state: &AppState, date: &str) -> Result<(), Error> {
+    let xml = get_xml_file(state, date)?;
+    parse_config(state, date, &xml).map(|_| ())
 }
 
 /// Takes a corridor's XML string and converts it to
 /// JSON using the above structs
-fn build_json(xmldoc: Option<String>) -> Option<String> {
-    xmldoc.and_then(|xmldoc| {
-        let res: Result<Corridor, _> = from_str(&xmldoc);
-        if let Ok(_corridor) = res {
-            if let Ok(js) = serde_json::to_string(&_corridor) {
-                Some(js)
-            } else {
-                None
+fn build_json(
+    date: &str,
+    xmldoc: &str,
+    cats: Option<&[Category]>,
+) -> Result<String, Error> {
+    let res: Result<Corridor, _> = from_str(xmldoc);
+    match res {
+        Ok(mut corridor) => {
+            if let Some(cats) = cats {
+                filter_detectors(&mut corridor.r_node, cats);
             }
-        } else {
-            None
+            Ok(serde_json::to_string(&corridor)?)
         }
-    })
+        Err(e) => Err(parse_error(date, &e.to_string())),
+    }
 }
 
 /// Takes the XML string and builds the response
-fn xml_response(xml: Option<String>) -> Option<HttpResponse> {
-    xml.and_then(|x| {
-        Some(HttpResponse::Ok().content_type("application/xml").body(x))
-    })
+fn xml_response(xml: String) -> HttpResponse {
+    HttpResponse::Ok().content_type("application/xml").body(xml)
 }
 
 /// Takes the JSON string and builds the response
-fn json_response(json: Option<String>) -> Option<HttpResponse> {
-    json.and_then(|j| {
-        Some(HttpResponse::Ok().content_type("application/json").body(j))
-    })
+fn json_response(json: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json)
+}
+
+/// Takes the GeoJSON string and builds the response
+fn geojson_response(json: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .body(json)
 }
 
 fn parse_year(year: &str) -> Option<i32> {
@@ -280,129 +789,383 @@ fn is_valid_date(date: &str) -> bool {
         && parse_day(&date[6..8]).is_some()
 }
 
+/// Get the metro_config.xml.gz file name for the specified date
+fn xml_file_name(date: &str) -> String {
+    format!("metro_config_{}.xml.gz", date)
+}
+
+/// Get the path to the metro_config.xml.gz file for the specified date
+fn xml_path(state: &AppState, date: &str) -> PathBuf {
+    state.config.metro_path.join(xml_file_name(date))
+}
+
+/// Get the modified time of the metro_config file for a date
+fn modified(state: &AppState, date: &str) -> Option<SystemTime> {
+    metadata(xml_path(state, date))
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Log a metro_config parse error
+fn parse_error(date: &str, msg: &str) -> Error {
+    let file = xml_file_name(date);
+    warn!("{}: {}", file, msg);
+    Error::Xml(file)
+}
+
+/// Quarantine a metro_config which failed parsing
+fn quarantine(state: &AppState, date: &str, msg: &str) -> Error {
+    state.metro.record(date, modified(state, date), false);
+    parse_error(date, msg)
+}
+
+/// Check that the metro_config for a date is well-formed
+fn check_document(
+    state: &AppState,
+    date: &str,
+    xml: &str,
+) -> Result<(), Error> {
+    let mtime = modified(state, date);
+    match state.metro.status(date, mtime) {
+        Some(true) => return Ok(()),
+        Some(false) => return Err(Error::Xml(xml_file_name(date))),
+        None => (),
+    }
+    match EventReader::from_str(xml)
+        .into_iter()
+        .find_map(|ev| ev.err())
+    {
+        Some(e) => Err(quarantine(state, date, &e.to_string())),
+        None => {
+            state.metro.record(date, mtime, true);
+            Ok(())
+        }
+    }
+}
+
 /// Get the metro_config.xml.gz file for the specified date and extract it
-fn get_xml_file(date: &str) -> Option<String> {
-    let mut path = PathBuf::from(BASE_PATH);
-    path.push(format!("metro_config_{}.xml.gz", date));
-    if let Ok(file) = File::open(path) {
-        let mut dec = GzDecoder::new(file);
-        let mut metro_file = String::new();
-        if let Ok(_) = dec.read_to_string(&mut metro_file) {
-            return Some(metro_file);
+fn get_xml_file(state: &AppState, date: &str) -> Result<String, Error> {
+    if !is_valid_date(date) {
+        return Err(Error::NotFound);
+    }
+    match File::open(xml_path(state, date)) {
+        Ok(file) => {
+            let mut dec = GzDecoder::new(file);
+            let mut metro_file = String::new();
+            dec.read_to_string(&mut metro_file)
+                .map_err(|e| quarantine(state, date, &e.to_string()))?;
+            Ok(metro_file)
         }
+        Err(_) => Err(Error::NotFound),
     }
-    None
+}
+
+/// Parse the metro_config XML document for a date
+fn parse_document(
+    state: &AppState,
+    date: &str,
+    xml: String,
+) -> Result<Document, Error> {
+    check_document(state, date, &xml)?;
+    let parser: Parser = Default::default();
+    parser
+        .parse_string(xml)
+        .map_err(|e| quarantine(state, date, &format!("{:?}", e)))
+}
+
+/// Create an XPath context for a document
+fn xpath_context(date: &str, doc: &Document) -> Result<Context, Error> {
+    Context::new(doc).map_err(|_| parse_error(date, "XPath context"))
 }
 
 /// Get the list of corridors in the XML file as JSON
-fn get_corridors(metro_file_option: Option<String>) -> Option<String> {
-    if let Some(metro_file) = metro_file_option {
-        let parser: Parser = Default::default();
-        let doc: Document = parser.parse_string(metro_file).unwrap();
-        let mut context = Context::new(&doc).unwrap();
-        let xpth = "//corridor/@*[name()='route' or name()='dir']";
-        if let Ok(cors) = context.findnodes(xpth, None) {
-            if cors.len() > 0 {
-                let mut res = "[".to_owned();
-                for i in (0..cors.len()).step_by(2) {
-                    if i > 0 {
-                        res.push(',');
-                    }
-                    let rte = doc.node_to_string(&cors[i]);
-                    // Trim following " character, add underscore to match request format
-                    let rte =
-                        rte[rte.find('=')? + 1..rte.len() - 1].to_owned() + "_";
-                    let dir = doc.node_to_string(&cors[i + 1]);
-                    // Trim leading " character
-                    let dir = dir[dir.find('=')? + 2..].to_owned();
-                    let cor = rte + &dir;
-                    res.push_str(&cor);
-                }
-                res.push(']');
-                return Some(res);
-            }
+fn get_corridors(
+    state: &AppState,
+    date: &str,
+    metro_file: String,
+) -> Result<String, Error> {
+    let doc = parse_document(state, date, metro_file)?;
+    let mut context = xpath_context(date, &doc)?;
+    let xpth = "//corridor/@*[name()='route' or name()='dir']";
+    context
+        .findnodes(xpth, None)
+        .ok()
+        .and_then(|cors| corridor_list(&doc, &cors))
+        .ok_or(Error::NotFound)
+}
+
+/// Build a JSON list of corridors from route and direction attributes
+fn corridor_list(doc: &Document, cors: &[Node]) -> Option<String> {
+    if cors.is_empty() {
+        return None;
+    }
+    let mut res = "[".to_owned();
+    for (i, attrs) in cors.chunks_exact(2).enumerate() {
+        if i > 0 {
+            res.push(',');
         }
+        let rte = doc.node_to_string(&attrs[0]);
+        // Trim following " character, add underscore to match request format
+        let rte = rte[rte.find('=')? + 1..rte.len() - 1].to_owned() + "_";
+        let dir = doc.node_to_string(&attrs[1]);
+        // Trim leading " character
+        let dir = dir[dir.find('=')? + 2..].to_owned();
+        let cor = rte + &dir;
+        res.push_str(&cor);
     }
-    None
+    res.push(']');
+    Some(res)
 }
 
 /// Using the metro config raw XML, find the proper corridor
 fn get_corridor_on_date(
-    metro_file_option: Option<String>,
+    state: &AppState,
+    date: &str,
+    metro_file: String,
     rte: &str,
     dir: &str,
-) -> Option<String> {
-    if let Some(metro_file) = metro_file_option {
-        let parser: Parser = Default::default();
-        let doc: Document = parser.parse_string(metro_file).unwrap();
-        let mut context = Context::new(&doc).unwrap();
-        let xpth: &str =
-            &format!("//corridor[@route='{}' and @dir='{}']", rte, dir);
-        if let Ok(cors) = context.findnodes(xpth, None) {
-            if cors.len() > 0 {
-                let cor = doc.node_to_string(&cors[0]);
-                if cor.graphemes(true).count() > 0 {
-                    return Some(cor);
-                }
-            }
-        }
-    }
-    None
+) -> Result<String, Error> {
+    let doc = parse_document(state, date, metro_file)?;
+    let mut context = xpath_context(date, &doc)?;
+    find_corridor(&doc, &mut context, rte, dir).ok_or(Error::NotFound)
 }
 
-/// Handle metro_config XML request with one parameter (date)
-pub fn handle_1_param_xml(p1: &str) -> Option<HttpResponse> {
-    if is_valid_date(p1) {
-        xml_response(get_xml_file(p1))
+/// Find a corridor element in a document
+fn find_corridor(
+    doc: &Document,
+    context: &mut Context,
+    rte: &str,
+    dir: &str,
+) -> Option<String> {
+    let xpth: &str =
+        &format!("//corridor[@route='{}' and @dir='{}']", rte, dir);
+    let cors = context.findnodes(xpth, None).ok()?;
+    let cor = doc.node_to_string(cors.first()?);
+    if cor.graphemes(true).count() > 0 {
+        Some(cor)
     } else {
         None
     }
 }
 
-/// Handle metro_config JSON request with one parameter (date)
-pub fn handle_1_param_json(p1: &str) -> Option<HttpResponse> {
-    if is_valid_date(p1) {
-        json_response(build_full_json(get_xml_file(p1)))
-    } else {
-        None
+/// Get the internal DTD subset of a document (between `[` and `]>`)
+fn internal_subset(xml: &str) -> Option<&str> {
+    let doctype = xml.find("<!DOCTYPE")?;
+    let rest = &xml[doctype..];
+    let open = rest.find('[')?;
+    if rest.find('>')? < open {
+        return None;
     }
+    let body = &rest[open + 1..];
+    body.match_indices(']')
+        .find(|(i, _)| body[i + 1..].trim_start().starts_with('>'))
+        .map(|(i, _)| &body[..i])
 }
 
-/// Handle metro_config request for corridors on a date
-pub fn handle_corridors(p1: &str) -> Option<HttpResponse> {
-    if is_valid_date(p1) {
-        json_response(get_corridors(get_xml_file(p1)))
+/// Escape text for an XML attribute value
+fn escape_attr(val: &str) -> String {
+    val.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
+
+/// Build a standalone XML document containing one corridor.
+///
+/// The corridor is wrapped in a `tms_config` root element which inherits the
+/// original `time_stamp` attribute.
+fn get_corridor_document(
+    state: &AppState,
+    date: &str,
+    metro_file: String,
+    rte: &str,
+    dir: &str,
+    dtd: bool,
+) -> Result<String, Error> {
+    let subset = if dtd {
+        internal_subset(&metro_file).map(|s| s.to_string())
     } else {
         None
+    };
+    let doc = parse_document(state, date, metro_file)?;
+    let mut context = xpath_context(date, &doc)?;
+    let cor =
+        find_corridor(&doc, &mut context, rte, dir).ok_or(Error::NotFound)?;
+    let time_stamp = context
+        .findvalue("/tms_config/@time_stamp", None)
+        .unwrap_or_default();
+    let mut res = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    if let Some(subset) = subset {
+        res.push_str("<!DOCTYPE tms_config [");
+        res.push_str(&subset);
+        res.push_str("]>\n");
+    }
+    res.push_str("<tms_config time_stamp=\"");
+    res.push_str(&escape_attr(&time_stamp));
+    res.push_str("\">\n");
+    res.push_str(&cor);
+    res.push_str("\n</tms_config>\n");
+    Ok(res)
+}
+
+/// Handle metro_config XML request with one parameter (date)
+pub fn handle_1_param_xml(
+    state: &AppState,
+    p1: &str,
+) -> Result<HttpResponse, Error> {
+    Ok(xml_response(get_xml_file(state, p1)?))
+}
+
+/// Handle metro_config gzip request with one parameter (date)
+pub fn handle_1_param_xml_gz(
+    state: &AppState,
+    req: &HttpRequest,
+    p1: &str,
+) -> Result<HttpResponse, Error> {
+    if !is_valid_date(p1) {
+        return Err(Error::NotFound);
     }
+    let file = match NamedFile::open(xml_path(state, p1)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NotFound)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let xml = "application/xml".parse().unwrap();
+    Ok(file
+        .set_content_type(xml)
+        .set_content_encoding(ContentEncoding::Gzip)
+        .into_response(req))
+}
+
+/// Handle metro_config JSON request with one parameter (date)
+pub fn handle_1_param_json(
+    state: &AppState,
+    p1: &str,
+) -> Result<HttpResponse, Error> {
+    let xml = get_xml_file(state, p1)?;
+    Ok(json_response(build_full_json(state, p1, &xml)?))
+}
+
+/// Handle metro_config request for corridors on a date
+pub fn handle_corridors(
+    state: &AppState,
+    p1: &str,
+) -> Result<HttpResponse, Error> {
+    let xml = get_xml_file(state, p1)?;
+    Ok(json_response(get_corridors(state, p1, xml)?))
 }
 
 /// Handle metro_config XML request with two parameters (date, corridor, and direction)
 pub fn handle_3_params_xml(
+    state: &AppState,
     p1: &str,
     p2: &str,
     p3: &str,
-) -> Option<HttpResponse> {
-    if is_valid_date(p1) {
-        xml_response(get_corridor_on_date(get_xml_file(p1), p2, p3))
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<CorridorXmlParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let dtd = params.dtd.unwrap_or(false);
+    let xml = get_xml_file(state, p1)?;
+    if dtd || params.standalone.unwrap_or(false) {
+        let doc = get_corridor_document(state, p1, xml, p2, p3, dtd)?;
+        Ok(xml_response(doc))
     } else {
-        None
+        Ok(xml_response(get_corridor_on_date(state, p1, xml, p2, p3)?))
     }
 }
 
 /// Handle metro_config JSON request with two parameters (date, corridor, and direction)
 pub fn handle_3_params_json(
+    state: &AppState,
     p1: &str,
     p2: &str,
     p3: &str,
-) -> Option<HttpResponse> {
-    if is_valid_date(p1) {
-        json_response(build_json(get_corridor_on_date(
-            get_xml_file(p1),
-            p2,
-            p3,
-        )))
-    } else {
-        None
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<CorridorParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let cats = match &params.category {
+        Some(cats) => Some(Category::parse_list(cats)?),
+        None => None,
+    };
+    let xml = get_xml_file(state, p1)?;
+    let cor = get_corridor_on_date(state, p1, xml, p2, p3)?;
+    Ok(json_response(build_json(p1, &cor, cats.as_deref())?))
+}
+
+/// Handle metro_config GeoJSON request for a corridor (date, corridor, and direction)
+pub fn handle_3_params_geojson(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    p3: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<GeoParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    if let Some(tolerance) = params.simplify {
+        if !tolerance.is_finite() || tolerance < 0.0 {
+            return Err(Error::InvalidParam(format!(
+                "simplify: {}",
+                tolerance
+            )));
+        }
+    }
+    let xml = get_xml_file(state, p1)?;
+    let cor = get_corridor_on_date(state, p1, xml, p2, p3)?;
+    let corridor: Corridor =
+        from_str(&cor).map_err(|e| parse_error(p1, &e.to_string()))?;
+    let feature = CorridorFeature::new(corridor, params.simplify);
+    Ok(geojson_response(serde_json::to_string(&feature)?))
+}
+
+/// Handle metro_config request for the roadway graph on a date
+pub fn handle_graph(state: &AppState, p1: &str) -> Result<HttpResponse, Error> {
+    let xml = get_xml_file(state, p1)?;
+    let config = parse_config(state, p1, &xml)?;
+    Ok(json_response(serde_json::to_string(&Graph::new(&config))?))
+}
+
+/// Handle metro_config request for detectors joined to their controllers
+pub fn handle_detectors(
+    state: &AppState,
+    p1: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<DetectorParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let names: Option<Vec<&str>> =
+        params.detectors.as_deref().map(|d| d.split(',').collect());
+    let xml = get_xml_file(state, p1)?;
+    let config = parse_config(state, p1, &xml)?;
+    let links = DetectorLink::list(&config, names.as_deref());
+    Ok(json_response(serde_json::to_string(&links)?))
+}
+
+/// Handle metro_config request for nodes within a bounding box
+pub fn handle_nodes(
+    state: &AppState,
+    p1: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<NodeParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let bbox = match &params.bbox {
+        Some(bbox) => Some(BoundingBox::parse(bbox)?),
+        None => None,
+    };
+    let cats = match &params.category {
+        Some(cats) => Some(Category::parse_list(cats)?),
+        None => None,
+    };
+    let xml = get_xml_file(state, p1)?;
+    let mut config = parse_config(state, p1, &xml)?;
+    if let Some(cats) = &cats {
+        let r_nodes = config.corridor.iter_mut().flat_map(|c| &mut c.r_node);
+        filter_detectors(r_nodes, cats);
     }
+    let nodes = Nodes::new(&config, bbox.as_ref());
+    Ok(json_response(serde_json::to_string(&nodes)?))
 }
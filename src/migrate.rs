@@ -0,0 +1,225 @@
+// migrate.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Migration of archives to zstd-compressed sample files.
+//
+// Each entry of a date's `.traffic` zip archive, and each plain file in its
+// date directory, is compressed to `{sid}.{ext}.zst` in the date directory
+// and verified by decompressing it again.  Since `.zst` files are read
+// transparently, the originals can then be removed.
+//
+use crate::dates::{parse_date, DATE_FMT};
+use crate::error::Error;
+use crate::sensor::{entry_file_name, ZST};
+use crate::state::Config;
+use crate::storage::Storage;
+use log::{info, warn};
+use std::fs::{self, create_dir_all, read_dir, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Result of migrating one date
+#[derive(Debug, Default, PartialEq)]
+pub struct Migrated {
+    /// Number of files written
+    pub files: usize,
+    /// Size of the original files (and zip archive), in bytes
+    pub bytes_before: u64,
+    /// Size of the zstd files, in bytes
+    pub bytes_after: u64,
+}
+
+/// Migration options
+pub struct Options {
+    /// Compression level
+    pub level: i32,
+    /// Maximum size of a sample file, in bytes
+    pub max_size: u64,
+    /// Remove original files (and zip archive) after migrating
+    pub remove: bool,
+}
+
+/// Get the zstd path of a file name in a directory
+fn zst_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}{}", name, ZST))
+}
+
+/// Compress data to a zstd file, verifying it.
+///
+/// Returns the compressed size.
+fn write_zst(
+    dir: &Path,
+    name: &str,
+    data: &[u8],
+    level: i32,
+) -> Result<u64, Error> {
+    let zst = zstd::encode_all(data, level)?;
+    if zstd::decode_all(&zst[..])? != data {
+        let msg = format!("{}: zstd verify failed", name);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+    }
+    let path = zst_path(dir, name);
+    let tmp = path.with_extension("zst.tmp");
+    fs::write(&tmp, &zst)?;
+    fs::rename(&tmp, &path)?;
+    Ok(zst.len() as u64)
+}
+
+/// Read a file, up to a maximum size
+fn read_bounded<R: Read>(
+    reader: R,
+    name: &str,
+    max_size: u64,
+) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    reader.take(max_size + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > max_size {
+        return Err(Error::EntryTooLarge(name.into()));
+    }
+    Ok(data)
+}
+
+/// Migrate plain files in a date directory
+fn migrate_dir(dir: &Path, opts: &Options) -> Result<Migrated, Error> {
+    let mut migrated = Migrated::default();
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(migrated),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|ent| ent.file_type().is_ok_and(|tp| tp.is_file()))
+        .filter_map(|ent| ent.file_name().to_str().map(String::from))
+        .filter(|name| !name.ends_with(ZST) && !name.ends_with(".zst.tmp"))
+        .collect();
+    names.sort();
+    for name in names {
+        let path = dir.join(&name);
+        let data = read_bounded(File::open(&path)?, &name, opts.max_size)?;
+        migrated.bytes_before += data.len() as u64;
+        migrated.bytes_after += write_zst(dir, &name, &data, opts.level)?;
+        migrated.files += 1;
+        if opts.remove {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(migrated)
+}
+
+/// Migrate entries of a zip archive into a date directory.
+///
+/// Entries shadowed by a file in the directory are skipped.
+fn migrate_zip(
+    dir: &Path,
+    path: &Path,
+    opts: &Options,
+    migrated: &mut Migrated,
+) -> Result<(), Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    migrated.bytes_before += file.metadata()?.len();
+    let mut zip = ZipArchive::new(file)?;
+    create_dir_all(dir)?;
+    for i in 0..zip.len() {
+        let zf = zip.by_index(i)?;
+        if !zf.is_file() {
+            continue;
+        }
+        let name = match entry_file_name(zf.name(), zf.name_raw()) {
+            Ok(name) => name.to_string(),
+            Err(reason) => {
+                warn!("{}: skipped entry #{}: {}", path.display(), i, reason);
+                continue;
+            }
+        };
+        if dir.join(&name).exists() || zst_path(dir, &name).exists() {
+            continue;
+        }
+        let data = read_bounded(zf, &name, opts.max_size)?;
+        migrated.bytes_after += write_zst(dir, &name, &data, opts.level)?;
+        migrated.files += 1;
+    }
+    if opts.remove {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Migrate one date to zstd-compressed files.
+///
+/// * `traffic_path` Base traffic archive path.
+pub fn migrate_date(
+    traffic_path: &Path,
+    district: &str,
+    date: &str,
+    opts: &Options,
+) -> Result<Migrated, Error> {
+    let dir = Storage::new(traffic_path).date_path(district, date);
+    let mut migrated = migrate_dir(&dir, opts)?;
+    migrate_zip(&dir, &dir.with_extension("traffic"), opts, &mut migrated)?;
+    Ok(migrated)
+}
+
+/// Run `migrate-zstd` subcommand.
+///
+/// * `args` Command arguments: district, start date and end date, with
+///   optional `--remove`.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (district, start, end, remove) = match args {
+        [district, start, end] => (district, start, end, false),
+        [district, start, end, flag] if flag == "--remove" => {
+            (district, start, end, true)
+        }
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: migrate-zstd <district> <start_date> <end_date> \
+                 [--remove]"
+                    .into(),
+            ))
+        }
+    };
+    let (start, end) = (parse_date("date", start)?, parse_date("date", end)?);
+    if start > end {
+        return Err(Error::InvalidParam("start date after end date".into()));
+    }
+    let config = Config::from_env()?;
+    let opts = Options {
+        level: config.zstd_level,
+        max_size: config.max_entry_size,
+        remove,
+    };
+    let mut total = Migrated::default();
+    let mut day = start;
+    while day <= end {
+        let date = day.format(DATE_FMT).to_string();
+        match migrate_date(&config.traffic_path, district, &date, &opts) {
+            Ok(m) => {
+                if m.files > 0 {
+                    info!(
+                        "{}: {} -> {} bytes",
+                        date, m.bytes_before, m.bytes_after
+                    );
+                }
+                total.files += m.files;
+                total.bytes_before += m.bytes_before;
+                total.bytes_after += m.bytes_after;
+            }
+            Err(e) => warn!("{}: {}", date, e),
+        }
+        day = match day.succ_opt() {
+            Some(d) => d,
+            None => break,
+        };
+    }
+    println!(
+        "migrated {} files: {} -> {} bytes",
+        total.files, total.bytes_before, total.bytes_after
+    );
+    Ok(())
+}
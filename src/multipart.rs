@@ -0,0 +1,115 @@
+// multipart.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Multipart/mixed responses for requests returning several files.
+//
+use actix_web::{HttpResponse, HttpResponseBuilder};
+
+/// Content type of multipart responses
+const MULTIPART_MIXED: &str = "multipart/mixed";
+
+/// Prefix of part boundaries
+const BOUNDARY_PREFIX: &str = "trafdat-part-";
+
+/// One part (file) of a multipart response
+struct Part {
+    /// Content type
+    content_type: String,
+    /// File name
+    filename: String,
+    /// File data
+    data: Vec<u8>,
+}
+
+/// Multipart/mixed response builder
+#[derive(Default)]
+pub struct Multipart {
+    /// Parts, in order
+    parts: Vec<Part>,
+}
+
+/// Check if an `Accept` header value requests a multipart response
+pub fn accepts_multipart(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|range| {
+            let mime = range.split(';').next().unwrap_or("").trim();
+            mime.eq_ignore_ascii_case(MULTIPART_MIXED)
+        })
+    })
+}
+
+/// Quote a file name for a `Content-Disposition` header.
+///
+/// Quotes and backslashes are escaped, and control characters replaced.
+fn quote_filename(name: &str) -> String {
+    let mut res = String::with_capacity(name.len() + 2);
+    res.push('"');
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => {
+                res.push('\\');
+                res.push(c);
+            }
+            c if c.is_control() => res.push('_'),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Check if a byte slice contains a pattern
+fn contains(data: &[u8], pat: &[u8]) -> bool {
+    data.windows(pat.len()).any(|w| w == pat)
+}
+
+impl Multipart {
+    /// Add a part
+    pub fn push(&mut self, content_type: &str, filename: &str, data: Vec<u8>) {
+        self.parts.push(Part {
+            content_type: content_type.to_string(),
+            filename: filename.to_string(),
+            data,
+        });
+    }
+
+    /// Get a boundary which does not occur in any part
+    fn boundary(&self) -> String {
+        (0..)
+            .map(|n| format!("{}{}", BOUNDARY_PREFIX, n))
+            .find(|b| {
+                !self.parts.iter().any(|p| contains(&p.data, b.as_bytes()))
+            })
+            .unwrap()
+    }
+
+    /// Encode the multipart body, with its boundary
+    fn encode(&self) -> (String, Vec<u8>) {
+        let boundary = self.boundary();
+        let mut body = vec![];
+        for part in &self.parts {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Type: {}\r\n\
+                     Content-Disposition: attachment; filename={}\r\n\r\n",
+                    boundary,
+                    part.content_type,
+                    quote_filename(&part.filename)
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(&part.data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        (boundary, body)
+    }
+
+    /// Build a response, with the multipart content type
+    pub fn into_response(self, res: &mut HttpResponseBuilder) -> HttpResponse {
+        let (boundary, body) = self.encode();
+        res.content_type(format!("{}; boundary={}", MULTIPART_MIXED, boundary))
+            .body(body)
+    }
+}
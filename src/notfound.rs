@@ -0,0 +1,128 @@
+// notfound.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Friendly "Not Found" pages for HTML clients.
+//
+// When a browser requests a missing sensor data resource, the path is
+// classified and the parent listings which exist (years of the district,
+// dates of the year and sensors of the date) are linked.  Other clients, and
+// all clients in legacy mode, get the bare response.
+//
+use crate::error::Error;
+use crate::route::{self, Parent, Shape};
+use crate::sensor::{lookup_archived, lookup_dates};
+use crate::state::AppState;
+use crate::template::escape_html;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::HttpResponse;
+use std::fmt::Write;
+
+/// Check if an `Accept` header value prefers an HTML response
+fn accepts_html(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|range| {
+            let mime = range.split(';').next().unwrap_or("").trim();
+            mime.eq_ignore_ascii_case("text/html")
+        })
+    })
+}
+
+impl Parent<'_> {
+    /// Check if a parent listing has any entries
+    fn exists(&self, state: &AppState) -> bool {
+        match self {
+            Parent::Years(did) => state.storage.has_district(did.as_str()),
+            Parent::Dates(did, year) => {
+                !lookup_dates(state, did.as_str(), year.as_str()).is_empty()
+            }
+            Parent::Sensors(did, date) => {
+                !lookup_archived(state, did.as_str(), date.as_str()).is_empty()
+            }
+        }
+    }
+
+    /// Get path (relative to URL prefix) and description of a listing
+    fn link(&self) -> (String, String) {
+        match self {
+            Parent::Years(did) => (
+                format!("{}/years.json", did.as_str()),
+                format!("Years sampled in {}", did.as_str()),
+            ),
+            Parent::Dates(did, year) => (
+                format!("{}/{}", did.as_str(), year.as_str()),
+                format!("Dates in {} ({})", year.as_str(), did.as_str()),
+            ),
+            Parent::Sensors(did, date) => (
+                format!("{}/{}", did.as_str(), date.as_str()),
+                format!("Sensors on {} ({})", date.as_str(), did.as_str()),
+            ),
+        }
+    }
+}
+
+/// Build a "Not Found" page linking to existing parent listings
+fn page(state: &AppState, shape: Shape, params: &[&str]) -> String {
+    let prefix = &state.config.url_prefix;
+    let default = &state.config.district_default;
+    let is_district = |did: &str| state.storage.has_district(did);
+    let parents = match route::classify(shape, params, default, &is_district) {
+        Ok(Some(route)) => route.parents(),
+        _ => vec![],
+    };
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<title>Not Found</title>\n</head>\n\
+         <body>\n<h1>Not Found</h1>\n",
+    );
+    writeln!(
+        html,
+        "<p>No data at <code>{}/{}</code>.</p>\n<ul>",
+        escape_html(prefix),
+        escape_html(&params.join("/"))
+    )
+    .unwrap();
+    for parent in parents.iter().filter(|p| p.exists(state)) {
+        let (path, desc) = parent.link();
+        writeln!(
+            html,
+            "<li><a href=\"{}/{}\">{}</a></li>",
+            escape_html(prefix),
+            escape_html(&path),
+            escape_html(&desc)
+        )
+        .unwrap();
+    }
+    writeln!(
+        html,
+        "<li><a href=\"{0}/browse.html\">Archive browser</a></li>\n\
+         <li><a href=\"{0}/\">API index</a></li>\n</ul>\n</body>\n</html>",
+        escape_html(prefix)
+    )
+    .unwrap();
+    html
+}
+
+/// Replace a "Not Found" result with an HTML page for browsers.
+///
+/// * `accept` Value of `Accept` header.
+/// * `shape` Request path shape.
+/// * `params` Path parameters (without suffix).
+pub fn or_page(
+    state: &AppState,
+    accept: Option<&str>,
+    shape: Shape,
+    params: &[&str],
+    res: Result<HttpResponse, Error>,
+) -> Result<HttpResponse, Error> {
+    match res {
+        Err(Error::NotFound)
+            if accepts_html(accept) && !state.config.legacy =>
+        {
+            Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .insert_header((CACHE_CONTROL, "no-store"))
+                .body(page(state, shape, params)))
+        }
+        res => res,
+    }
+}
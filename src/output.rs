@@ -0,0 +1,304 @@
+// output.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::format::{JsonFormat, Values};
+use crate::metro;
+use crate::sample::{decode_raw, SampleSeries};
+use crate::sensor::{archived_type, build_json};
+use crate::state::AppState;
+use crate::zone::IntervalTime;
+use std::fmt::Write;
+
+/// Sample data for one sensor, to be encoded by an output format
+pub struct SampleData<'a> {
+    /// Application state
+    pub state: &'a AppState,
+    /// District ID
+    pub district: &'a str,
+    /// Date (yyyyMMdd)
+    pub date: &'a str,
+    /// Sensor ID
+    pub sid: &'a str,
+    /// Sample file extension (e.g. `v30`)
+    pub ext: &'a str,
+    /// Raw sample file contents
+    pub raw: &'a [u8],
+    /// Decoded samples (`None` for non-binned data, such as `vlog`)
+    pub series: Option<&'a SampleSeries>,
+    /// Scale of decoded values (e.g. 0.01 for occupancy)
+    pub scale: f64,
+    /// Scan counts decoded to occupancy (percent)
+    pub occupancy: bool,
+    /// Request query string
+    pub query: &'a str,
+}
+
+impl SampleData<'_> {
+    /// Get the times of sample intervals, in the district time zone
+    pub fn interval_times(
+        &self,
+        series: &SampleSeries,
+    ) -> Result<Vec<IntervalTime>, Error> {
+        self.state
+            .config
+            .zone(self.district)
+            .interval_times(self.date, series.period(), series.values().len())
+            .ok_or_else(|| Error::InvalidParam(self.date.to_string()))
+    }
+
+    /// Get raw (signed) sample values, including missing values
+    pub fn raw_values(&self) -> Result<Vec<i32>, Error> {
+        match (self.series, archived_type(self.ext)) {
+            (Some(_), Some((_, bytes))) => Ok(decode_raw(self.raw, bytes)),
+            _ => Err(Error::InvalidParam(format!(
+                "raw values not supported for {}",
+                self.ext
+            ))),
+        }
+    }
+}
+
+/// Output format for sample data
+pub trait OutputFormat: Send + Sync {
+    /// Format name, used as a request extension suffix (`.v30.{name}`)
+    fn name(&self) -> &str;
+
+    /// HTTP content type
+    fn content_type(&self) -> &str;
+
+    /// Encode sample data
+    fn encode(&self, data: &SampleData) -> Result<Vec<u8>, Error>;
+}
+
+/// Raw sample bytes (`application/octet_stream`)
+struct OctetOutput;
+
+impl OutputFormat for OctetOutput {
+    fn name(&self) -> &str {
+        "octet"
+    }
+
+    fn content_type(&self) -> &str {
+        "application/octet_stream"
+    }
+
+    fn encode(&self, data: &SampleData) -> Result<Vec<u8>, Error> {
+        Ok(data.raw.to_vec())
+    }
+}
+
+/// JSON array of raw bytes, or decoded numbers with formatting parameters
+struct JsonOutput;
+
+impl OutputFormat for JsonOutput {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn encode(&self, data: &SampleData) -> Result<Vec<u8>, Error> {
+        let fmt = match JsonFormat::from_query(data.query)? {
+            None if data.occupancy => Some(JsonFormat::default()),
+            fmt => fmt,
+        };
+        if let Some(fmt) = fmt {
+            if fmt.values == Values::Raw {
+                let values = data.raw_values()?;
+                return Ok(fmt.encode_raw(&values).into_bytes());
+            }
+            if let Some(series) = data.series {
+                return Ok(fmt.encode(series, data.scale).into_bytes());
+            }
+        }
+        Ok(build_json(data.raw.to_vec())?.into_bytes())
+    }
+}
+
+/// CSV with interval, decoded value, time and DST flag columns
+struct CsvOutput;
+
+impl OutputFormat for CsvOutput {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn content_type(&self) -> &str {
+        "text/csv"
+    }
+
+    fn encode(&self, data: &SampleData) -> Result<Vec<u8>, Error> {
+        let series = data.series.ok_or_else(|| {
+            Error::InvalidParam(format!("csv not supported for {}", data.ext))
+        })?;
+        let times = data.interval_times(series)?;
+        let raw = match JsonFormat::from_query(data.query)? {
+            Some(fmt) if fmt.values == Values::Raw => Some(data.raw_values()?),
+            _ => None,
+        };
+        let mut res = String::from("interval,value,time,dst\n");
+        for (i, (val, tm)) in series.values().iter().zip(times).enumerate() {
+            write!(res, "{},", i).unwrap();
+            match (&raw, val) {
+                (Some(raw), _) => write!(res, "{}", raw[i]).unwrap(),
+                (None, Some(v)) => {
+                    write!(res, "{}", f64::from(*v) * data.scale).unwrap()
+                }
+                (None, None) => (),
+            }
+            res.push(',');
+            if let Some(time) = tm.time {
+                res.push_str(&time.to_rfc3339());
+            }
+            res.push(',');
+            if let Some(flag) = tm.flag {
+                res.push_str(flag.as_str());
+            }
+            res.push('\n');
+        }
+        Ok(res.into_bytes())
+    }
+}
+
+/// InfluxDB line protocol, with one line per valid sample
+struct InfluxOutput;
+
+impl OutputFormat for InfluxOutput {
+    fn name(&self) -> &str {
+        "influx"
+    }
+
+    fn content_type(&self) -> &str {
+        "text/plain; charset=utf-8"
+    }
+
+    fn encode(&self, data: &SampleData) -> Result<Vec<u8>, Error> {
+        let unsupported = || {
+            Error::InvalidParam(format!(
+                "influx not supported for {}",
+                data.ext
+            ))
+        };
+        let series = data.series.ok_or_else(unsupported)?;
+        let (prefix, _) = archived_type(data.ext).ok_or_else(unsupported)?;
+        let prefix = if data.occupancy { "o" } else { prefix };
+        let times = data.interval_times(series)?;
+        let mut tags = format!(
+            "{},district={},sensor={}",
+            escape_key(measurement(prefix)),
+            escape_key(data.district),
+            escape_key(data.sid),
+        );
+        if let Some(lane) =
+            metro::detector_lane(data.state, data.date, data.sid)
+        {
+            write!(tags, ",lane={}", escape_key(&lane)).unwrap();
+        }
+        let mut res = String::new();
+        for (val, tm) in series.values().iter().zip(times) {
+            if let (Some(v), Some(stamp)) = (val, tm.stamp()) {
+                let value = f64::from(*v) * data.scale;
+                write!(res, "{} value={}", tags, value).unwrap();
+                if let Some(flag) = tm.flag {
+                    write!(res, ",dst=\"{}\"", flag.as_str()).unwrap();
+                }
+                writeln!(res, " {}", stamp * 1_000_000_000).unwrap();
+            }
+        }
+        Ok(res.into_bytes())
+    }
+}
+
+/// Get measurement name for a sample type prefix
+fn measurement(prefix: &str) -> &str {
+    match prefix {
+        "vmc" => "motorcycle",
+        "vs" => "short",
+        "vm" => "medium",
+        "vl" => "long",
+        "v" => "volume",
+        "o" => "occupancy",
+        "c" => "scans",
+        "s" => "speed",
+        "pr" => "precip_rate",
+        "pt" => "precip_type",
+        "sst" => "subsurface_temp",
+        "st" => "surface_temp",
+        "at" => "air_temp",
+        "dp" => "dew_point",
+        "rh" => "humidity",
+        "ws" => "wind_speed",
+        "wg" => "wind_gust",
+        "wd" => "wind_dir",
+        "vis" => "visibility",
+        "ps" => "surface_status",
+        _ => prefix,
+    }
+}
+
+/// Escape a line protocol measurement, tag key or tag value
+fn escape_key(key: &str) -> String {
+    let mut res = String::with_capacity(key.len());
+    for c in key.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Registry of output formats
+pub struct FormatRegistry {
+    formats: Vec<Box<dyn OutputFormat>>,
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        FormatRegistry {
+            formats: vec![
+                Box::new(OctetOutput),
+                Box::new(JsonOutput),
+                Box::new(CsvOutput),
+                Box::new(InfluxOutput),
+            ],
+        }
+    }
+}
+
+impl FormatRegistry {
+    /// Register an output format, replacing any with the same name
+    pub fn register(&mut self, format: Box<dyn OutputFormat>) {
+        self.formats.retain(|f| f.name() != format.name());
+        self.formats.push(format);
+    }
+
+    /// Get a format by name
+    pub fn by_name(&self, name: &str) -> Option<&dyn OutputFormat> {
+        self.formats
+            .iter()
+            .find(|f| f.name() == name)
+            .map(|f| f.as_ref())
+    }
+
+    /// Get the first format matching an `Accept` header.
+    ///
+    /// Wildcards and quality values are ignored; the default (octet) format
+    /// is used for those.
+    pub fn by_accept(&self, accept: &str) -> Option<&dyn OutputFormat> {
+        accept
+            .split(',')
+            .filter_map(|media| media.split(';').next())
+            .map(str::trim)
+            .find_map(|media| {
+                self.formats
+                    .iter()
+                    .find(|f| f.content_type() == media)
+                    .map(|f| f.as_ref())
+            })
+    }
+}
@@ -0,0 +1,207 @@
+// pool.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::metrics::Metrics;
+use crate::timing::{self, Stage};
+use std::collections::HashMap;
+use std::fs::{metadata, File};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Open zip archive, with an index of entry names by base name
+pub struct IndexedZip {
+    /// Zip archive
+    archive: ZipArchive<File>,
+    /// Entry names by base name (built on first use)
+    basenames: Option<HashMap<String, String>>,
+}
+
+/// Shared open zip archive
+pub type SharedZip = Arc<Mutex<IndexedZip>>;
+
+impl Deref for IndexedZip {
+    type Target = ZipArchive<File>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.archive
+    }
+}
+
+impl DerefMut for IndexedZip {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.archive
+    }
+}
+
+impl IndexedZip {
+    /// Create an indexed zip archive
+    fn new(archive: ZipArchive<File>) -> Self {
+        IndexedZip {
+            archive,
+            basenames: None,
+        }
+    }
+
+    /// Find the name of an entry by base name (without directories).
+    ///
+    /// If several entries have the same base name, the first in sorted
+    /// order is found.
+    pub fn by_basename(&mut self, basename: &str) -> Option<String> {
+        let archive = &self.archive;
+        let basenames = self.basenames.get_or_insert_with(|| {
+            let mut basenames = HashMap::new();
+            let mut names: Vec<&str> = archive.file_names().collect();
+            names.sort_unstable();
+            for name in names {
+                let base = name.rsplit('/').next().unwrap_or(name);
+                if !base.is_empty() && !basenames.contains_key(base) {
+                    basenames.insert(base.to_string(), name.to_string());
+                }
+            }
+            basenames
+        });
+        basenames.get(basename).cloned()
+    }
+}
+
+/// Pooled zip archive
+struct Entry {
+    /// Modified time when opened
+    mtime: Option<SystemTime>,
+    /// Last use (pool clock)
+    last_used: u64,
+    /// Open archive
+    zip: SharedZip,
+}
+
+/// Pool state
+#[derive(Default)]
+struct Inner {
+    /// Clock incremented on each access
+    clock: u64,
+    /// Open archives by path
+    entries: HashMap<PathBuf, Entry>,
+}
+
+/// Bounded pool of open zip archives, with LRU eviction
+pub struct ZipPool {
+    /// Maximum number of open archives (zero disables pooling)
+    capacity: usize,
+    /// Pool state
+    inner: Mutex<Inner>,
+}
+
+impl ZipPool {
+    /// Create a new zip archive pool
+    pub fn new(capacity: usize) -> Self {
+        ZipPool {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Get the number of open archives
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Check if no archives are open
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Close archives with a matching path, returning the number closed
+    pub fn flush<F>(&self, matches: F) -> usize
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.entries.len();
+        inner.entries.retain(|path, _| !matches(path));
+        before - inner.entries.len()
+    }
+
+    /// Get an open zip archive.
+    ///
+    /// Returns `Ok(None)` if the file does not exist.  Archives are reopened
+    /// if their modified time has changed.
+    pub fn get(
+        &self,
+        metrics: &Metrics,
+        path: &Path,
+    ) -> Result<Option<SharedZip>, ZipError> {
+        timing::time(Stage::ZipOpen, || self.open(metrics, path))
+    }
+
+    /// Get an archive from the pool, or open it
+    fn open(
+        &self,
+        metrics: &Metrics,
+        path: &Path,
+    ) -> Result<Option<SharedZip>, ZipError> {
+        let mtime = match metadata(path) {
+            Ok(meta) => meta.modified().ok(),
+            Err(_) => return Ok(None),
+        };
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some(entry) = inner.entries.get_mut(path) {
+                if entry.mtime == mtime {
+                    entry.last_used = clock;
+                    return Ok(Some(Arc::clone(&entry.zip)));
+                }
+            }
+        }
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Ok(None),
+        };
+        let zip = ZipArchive::new(file);
+        metrics.zip_open();
+        let zip = Arc::new(Mutex::new(IndexedZip::new(zip?)));
+        if self.capacity > 0 {
+            self.insert(metrics, path, mtime, Arc::clone(&zip));
+        }
+        Ok(Some(zip))
+    }
+
+    /// Insert an archive, evicting the least recently used if full
+    fn insert(
+        &self,
+        metrics: &Metrics,
+        path: &Path,
+        mtime: Option<SystemTime>,
+        zip: SharedZip,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.entries.contains_key(path)
+            && inner.entries.len() >= self.capacity
+        {
+            let lru = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(p, _)| p.clone());
+            if let Some(lru) = lru {
+                inner.entries.remove(&lru);
+                metrics.zip_eviction();
+            }
+        }
+        let last_used = inner.clock;
+        inner.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                mtime,
+                last_used,
+                zip,
+            },
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// prewarm.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::dates::{dates_in, parse_range, MAX_DAYS};
+use crate::error::Error;
+use crate::metro;
+use crate::sensor::lookup_archived;
+use crate::state::AppState;
+use actix_web::web;
+use chrono::{Duration as Days, Local, NaiveDate};
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::Duration;
+
+/// Pause between dates while pre-warming
+const THROTTLE: Duration = Duration::from_millis(250);
+
+/// Span of hot dates
+#[derive(Clone, Debug, PartialEq)]
+enum DateSpan {
+    /// Fixed range of dates (inclusive)
+    Fixed(NaiveDate, NaiveDate),
+    /// Most recent number of days, ending yesterday
+    Recent(i64),
+}
+
+/// Hot dates to pre-warm for a district
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotDates {
+    /// District ID
+    district: String,
+    /// Span of dates
+    span: DateSpan,
+}
+
+impl HotDates {
+    /// Parse hot dates, e.g. `tms:20210601-20210630` or `tms:7d`
+    pub fn parse(hot: &str) -> Result<Self, Error> {
+        let err = || Error::Config(format!("hot dates: {}", hot));
+        let (district, span) = hot.trim().split_once(':').ok_or_else(err)?;
+        if district.is_empty() {
+            return Err(err());
+        }
+        let span = match span.strip_suffix('d') {
+            Some(days) => {
+                let days = days.parse().map_err(|_| err())?;
+                if !(1..=MAX_DAYS).contains(&days) {
+                    return Err(err());
+                }
+                DateSpan::Recent(days)
+            }
+            None => {
+                let (start, end) = span.split_once('-').ok_or_else(err)?;
+                let (start, end) =
+                    parse_range(start, end).map_err(|_| err())?;
+                DateSpan::Fixed(start, end)
+            }
+        };
+        Ok(HotDates {
+            district: district.to_string(),
+            span,
+        })
+    }
+
+    /// Get hot dates, relative to the current date
+    fn dates(&self, today: NaiveDate) -> Vec<String> {
+        let (start, end) = match self.span {
+            DateSpan::Fixed(start, end) => (start, end),
+            DateSpan::Recent(days) => {
+                (today - Days::days(days), today - Days::days(1))
+            }
+        };
+        dates_in(start, end)
+    }
+}
+
+/// Pre-warm all configured hot dates once.
+///
+/// Sensor listings are read for each date (opening zip archive indexes), and
+/// metro_config documents are checked.  Returns the number of dates warmed.
+pub fn warm(state: &AppState, today: NaiveDate, pause: Duration) -> usize {
+    let mut metro_dates = BTreeSet::new();
+    let mut n_dates = 0;
+    for hot in &state.config.hot_dates {
+        for date in hot.dates(today) {
+            lookup_archived(state, &hot.district, &date);
+            metro_dates.insert(date);
+            n_dates += 1;
+            thread::sleep(pause);
+        }
+    }
+    for date in metro_dates {
+        match metro::prewarm(state, &date) {
+            Ok(()) | Err(Error::NotFound) => (),
+            Err(e) => warn!("prewarm metro_config {}: {}", date, e),
+        }
+        thread::sleep(pause);
+    }
+    n_dates
+}
+
+/// Get time until the next local midnight
+fn until_midnight() -> Duration {
+    let now = Local::now().naive_local();
+    let midnight = (now.date() + Days::days(1)).and_hms_opt(0, 0, 0).unwrap();
+    (midnight - now).to_std().unwrap_or(Duration::from_secs(60))
+}
+
+/// Spawn a background thread to pre-warm hot dates at startup and nightly
+pub fn spawn(state: web::Data<AppState>) {
+    thread::spawn(move || loop {
+        let today = Local::now().date_naive();
+        let n_dates = warm(&state, today, THROTTLE);
+        info!("pre-warmed {} hot dates", n_dates);
+        thread::sleep(until_midnight());
+    });
+}
@@ -0,0 +1,89 @@
+// proxy.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use actix_web::http::header::{HeaderMap, HOST};
+use actix_web::HttpRequest;
+use std::net::IpAddr;
+
+/// Client connection details, resolved through trusted proxies
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientInfo {
+    /// Client address
+    pub addr: Option<IpAddr>,
+    /// Request scheme (`http` or `https`)
+    pub scheme: String,
+    /// Requested host
+    pub host: String,
+}
+
+/// Get the first value of a comma-separated header
+fn first_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Get the client address from `X-Forwarded-For`.
+///
+/// Addresses are checked right-to-left, skipping trusted proxies.
+fn forwarded_for(headers: &HeaderMap, trusted: &[IpAddr]) -> Option<IpAddr> {
+    let hops: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+    hops.iter()
+        .rev()
+        .find(|hop| !trusted.contains(hop))
+        .or_else(|| hops.first())
+        .copied()
+}
+
+impl ClientInfo {
+    /// Get client info for a request.
+    ///
+    /// `X-Forwarded-*` headers are only honored when the peer is one of the
+    /// `trusted` proxy addresses.
+    pub fn from_request(req: &HttpRequest, trusted: &[IpAddr]) -> Self {
+        let peer = req.peer_addr().map(|a| a.ip());
+        let headers = req.headers();
+        let scheme = if req.app_config().secure() {
+            "https"
+        } else {
+            "http"
+        };
+        let host = headers
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_else(|| req.app_config().host())
+            .to_string();
+        let info = ClientInfo {
+            addr: peer,
+            scheme: scheme.to_string(),
+            host,
+        };
+        match peer {
+            Some(peer) if trusted.contains(&peer) => ClientInfo {
+                addr: forwarded_for(headers, trusted).or(info.addr),
+                scheme: first_value(headers, "x-forwarded-proto")
+                    .unwrap_or(info.scheme),
+                host: first_value(headers, "x-forwarded-host")
+                    .unwrap_or(info.host),
+            },
+            _ => info,
+        }
+    }
+
+    /// Format client address for logging
+    pub fn addr_string(&self) -> String {
+        match self.addr {
+            Some(addr) => addr.to_string(),
+            None => "-".to_string(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// range.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Raw sample bytes for a sensor over a range of dates.
+//
+// The response is a sequence of frames, one per date in order.  Each frame
+// has a 12-byte header: the date (8 ASCII digits, yyyyMMdd) and the length
+// of its data (32-bit big-endian), followed by that many bytes.  Days with
+// no data have a zero length.  Alternatively, a multipart/mixed response has
+// one part per date with data.
+//
+use crate::dates::{parse_range, DATE_FMT};
+use crate::error::Error;
+use crate::multipart::Multipart;
+use crate::sensor::{read_sample, sample_file_ext};
+use crate::state::AppState;
+use crate::swap;
+use actix_web::{web, HttpResponse};
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+/// Content type of raw sample data
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Query parameters for date range requests
+#[derive(Deserialize)]
+struct RangeParams {
+    /// First date (inclusive)
+    start: String,
+    /// Last date (inclusive)
+    end: String,
+}
+
+/// Parse `start` and `end` query parameters into a first date and day count
+pub fn date_range(query: &str) -> Result<(NaiveDate, usize), Error> {
+    let params = web::Query::<RangeParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let (start, end) = parse_range(&params.start, &params.end)?;
+    Ok((start, (end - start).num_days() as usize + 1))
+}
+
+/// Append a frame for one date
+fn push_frame(body: &mut Vec<u8>, date: &str, data: &[u8]) {
+    body.extend_from_slice(date.as_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    body.extend_from_slice(data);
+}
+
+/// Handle request for raw sample data over a date range
+pub fn handle_sample_range(
+    state: &AppState,
+    district: &str,
+    sid: &str,
+    ext: &str,
+    query: &str,
+    multipart: bool,
+) -> Result<HttpResponse, Error> {
+    if sample_file_ext(ext, &state.config.sample_periods).is_none() {
+        return Err(Error::NotFound);
+    }
+    let (start, days) = date_range(query)?;
+    let mut body = vec![];
+    let mut parts = Multipart::default();
+    let mut missing = 0;
+    let dates: Vec<String> = start
+        .iter_days()
+        .take(days)
+        .map(|date| date.format(DATE_FMT).to_string())
+        .collect();
+    let samples = state
+        .days
+        .map(&dates, |date| read_sample(state, district, date, sid, ext));
+    for (date, sample) in dates.into_iter().zip(samples) {
+        match sample? {
+            Some(data) if multipart => {
+                let name = format!("{}/{}.{}", date, sid, ext);
+                parts.push(OCTET_STREAM, &name, data);
+            }
+            Some(data) => push_frame(&mut body, &date, &data),
+            None => {
+                push_frame(&mut body, &date, &[]);
+                missing += 1;
+            }
+        }
+    }
+    if missing == days {
+        return Err(Error::NotFound);
+    }
+    let mut res = HttpResponse::Ok();
+    res.insert_header(("X-Trafdat-Missing-Days", missing.to_string()));
+    let changes = swap::location_changes(state, sid, start, days)?.changes;
+    if !changes.is_empty() {
+        let dates: Vec<&str> =
+            changes.iter().map(|c| c.date.as_str()).collect();
+        res.insert_header(("X-Trafdat-Location-Changes", dates.join(",")));
+    }
+    if multipart {
+        Ok(parts.into_response(&mut res))
+    } else {
+        Ok(res.content_type(OCTET_STREAM).body(body))
+    }
+}
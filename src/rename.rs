@@ -0,0 +1,92 @@
+// rename.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+
+/// Sensor rename file name (in district directory)
+const RENAME_FILE: &str = "sensor_renames.json";
+
+/// Maximum number of IDs to resolve (guards against rename cycles)
+const MAX_IDS: usize = 16;
+
+/// Sensor rename entry
+#[derive(Deserialize, Debug)]
+struct Rename {
+    /// Sensor ID before rename
+    old: String,
+    /// Sensor ID after rename
+    new: String,
+    /// Effective date (yyyyMMdd) of rename
+    effective: String,
+}
+
+/// Mapping of renamed (or merged) sensors for one district
+#[derive(Debug, Default)]
+pub struct RenameMap {
+    renames: Vec<Rename>,
+}
+
+impl RenameMap {
+    /// Load rename map from a district directory.
+    ///
+    /// A missing file results in an empty map.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let path = path.join(RENAME_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(RenameMap::default())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let renames = serde_json::from_reader(BufReader::new(file))
+            .map_err(|_| Error::Config(path.display().to_string()))?;
+        Ok(RenameMap { renames })
+    }
+
+    /// Get all archived IDs to try for a sensor on a date.
+    ///
+    /// The requested ID is always first, followed by IDs it was renamed
+    /// from (before effective date) or to (on or after effective date).
+    pub fn resolve(&self, sid: &str, date: &str) -> Vec<String> {
+        let mut ids = vec![sid.to_string()];
+        let mut i = 0;
+        while i < ids.len() && ids.len() < MAX_IDS {
+            for rn in &self.renames {
+                let id = if date < rn.effective.as_str() && rn.new == ids[i] {
+                    &rn.old
+                } else if date >= rn.effective.as_str() && rn.old == ids[i] {
+                    &rn.new
+                } else {
+                    continue;
+                };
+                if !ids.contains(id) {
+                    ids.push(id.to_string());
+                }
+            }
+            i += 1;
+        }
+        ids
+    }
+
+    /// Get the current ID of a sensor archived on a date
+    pub fn current(&self, sid: &str, date: &str) -> String {
+        let mut id = sid;
+        for _ in 0..MAX_IDS {
+            match self
+                .renames
+                .iter()
+                .find(|rn| rn.old == id && date < rn.effective.as_str())
+            {
+                Some(rn) => id = &rn.new,
+                None => break,
+            }
+        }
+        id.to_string()
+    }
+}
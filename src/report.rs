@@ -0,0 +1,538 @@
+// report.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Scheduled summary reports (data availability and detector health), sent
+// by email through an SMTP relay.
+//
+use crate::dates::DATE_FMT;
+use crate::error::Error;
+use crate::sensor::{lookup_sensors, read_series};
+use crate::state::{AppState, Config};
+use actix_web::web;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{
+    DateTime, Datelike, Duration as Days, Local, NaiveDate, TimeZone, Weekday,
+};
+use log::{info, warn};
+use serde::Deserialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Timeout for SMTP connections
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time to wait before retrying to load a report configuration
+const RETRY: Duration = Duration::from_secs(60 * 60);
+
+/// Valid sample fraction below which a detector is reported as partial
+const PARTIAL: f64 = 0.9;
+
+/// Maximum detectors listed in a report
+const MAX_LISTED: usize = 100;
+
+/// Report schedule
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    /// Every day, covering the previous day
+    Daily,
+    /// Every Monday, covering the previous 7 days
+    Weekly,
+}
+
+/// Report specification
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReportSpec {
+    /// Report name (used in subject)
+    pub name: String,
+    /// District ID
+    pub district: String,
+    /// Schedule
+    pub schedule: Schedule,
+    /// Sample file extensions to check
+    #[serde(default = "default_exts")]
+    pub exts: Vec<String>,
+    /// Email recipients
+    pub recipients: Vec<String>,
+}
+
+/// SMTP relay credentials (`AUTH PLAIN`)
+#[derive(Clone, Debug, Deserialize)]
+pub struct Credentials {
+    /// User name
+    pub username: String,
+    /// Password
+    pub password: String,
+}
+
+/// Report configuration file
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReportConfig {
+    /// SMTP relay address (`host:port`)
+    pub smtp: String,
+    /// SMTP relay credentials
+    #[serde(default)]
+    pub auth: Option<Credentials>,
+    /// Sender address
+    pub from: String,
+    /// Local hour to send reports
+    #[serde(default = "default_hour")]
+    pub hour: u32,
+    /// Report specifications
+    pub reports: Vec<ReportSpec>,
+}
+
+/// Default extensions to check
+fn default_exts() -> Vec<String> {
+    vec!["v30".into()]
+}
+
+/// Default hour to send reports
+fn default_hour() -> u32 {
+    6
+}
+
+impl ReportConfig {
+    /// Load report configuration from a JSON file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let config: ReportConfig =
+            serde_json::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| Error::Config(format!("{:?}: {}", path, e)))?;
+        if config.hour > 23 {
+            return Err(Error::Config(format!("{:?}: hour", path)));
+        }
+        Ok(config)
+    }
+}
+
+impl ReportSpec {
+    /// Check if the report is due on a date
+    pub fn is_due(&self, today: NaiveDate) -> bool {
+        match self.schedule {
+            Schedule::Daily => true,
+            Schedule::Weekly => today.weekday() == Weekday::Mon,
+        }
+    }
+
+    /// Get the dates covered by a report sent on a date
+    pub fn dates(&self, today: NaiveDate) -> Vec<String> {
+        let days = match self.schedule {
+            Schedule::Daily => 1,
+            Schedule::Weekly => 7,
+        };
+        (1..=days)
+            .rev()
+            .map(|d| (today - Days::days(d)).format(DATE_FMT).to_string())
+            .collect()
+    }
+
+    /// Get the report subject for a set of dates
+    pub fn subject(&self, dates: &[String]) -> String {
+        match (dates.first(), dates.last()) {
+            (Some(first), Some(last)) if first != last => {
+                format!("{}: {} - {}", self.name, first, last)
+            }
+            (Some(date), _) => format!("{}: {}", self.name, date),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// Detector health issue
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Issue {
+    /// No valid samples
+    NoData,
+    /// Too many missing samples
+    Partial,
+    /// Same non-zero value for every sample
+    Constant,
+}
+
+impl Issue {
+    /// Get issue description
+    fn as_str(self) -> &'static str {
+        match self {
+            Issue::NoData => "no data",
+            Issue::Partial => "partial",
+            Issue::Constant => "constant",
+        }
+    }
+}
+
+/// Detector health for one sensor, extension and date
+struct Health {
+    sensor: String,
+    ext: String,
+    date: String,
+    valid: f64,
+    issue: Issue,
+}
+
+/// Data availability for one date
+struct Availability {
+    date: String,
+    sensors: usize,
+    complete: usize,
+    samples: usize,
+    valid: usize,
+}
+
+/// Check data for one date
+fn check_date(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    exts: &[String],
+    health: &mut Vec<Health>,
+) -> Availability {
+    let sensors = lookup_sensors(state, district, date).unwrap_or_default();
+    let mut avail = Availability {
+        date: date.to_string(),
+        sensors: sensors.len(),
+        complete: 0,
+        samples: 0,
+        valid: 0,
+    };
+    for sid in &sensors {
+        let mut complete = true;
+        for ext in exts {
+            let series = match read_series(state, district, date, sid, ext) {
+                Ok(Some(series)) => series,
+                Ok(None) => {
+                    complete = false;
+                    continue;
+                }
+                Err(e) => {
+                    warn!("report {} {}.{}: {}", date, sid, ext, e);
+                    complete = false;
+                    continue;
+                }
+            };
+            let values = series.values();
+            let valid: Vec<i32> = values.iter().flatten().copied().collect();
+            avail.samples += values.len();
+            avail.valid += valid.len();
+            complete &= valid.len() == values.len();
+            let frac = valid.len() as f64 / values.len().max(1) as f64;
+            let issue = if valid.is_empty() {
+                Some(Issue::NoData)
+            } else if frac < PARTIAL {
+                Some(Issue::Partial)
+            } else if valid[0] > 0 && valid.iter().all(|v| *v == valid[0]) {
+                Some(Issue::Constant)
+            } else {
+                None
+            };
+            if let Some(issue) = issue {
+                health.push(Health {
+                    sensor: sid.clone(),
+                    ext: ext.clone(),
+                    date: date.to_string(),
+                    valid: frac,
+                    issue,
+                });
+            }
+        }
+        if complete {
+            avail.complete += 1;
+        }
+    }
+    avail
+}
+
+/// Format a fraction as a percentage
+fn percent(num: usize, den: usize) -> String {
+    if den > 0 {
+        format!("{:.1}%", num as f64 * 100.0 / den as f64)
+    } else {
+        "-".into()
+    }
+}
+
+/// Render a report as plain text
+pub fn render(state: &AppState, spec: &ReportSpec, dates: &[String]) -> String {
+    let mut health = vec![];
+    let avail: Vec<Availability> = dates
+        .iter()
+        .map(|date| {
+            check_date(state, &spec.district, date, &spec.exts, &mut health)
+        })
+        .collect();
+    let mut res = String::new();
+    writeln!(res, "{}", spec.subject(dates)).unwrap();
+    writeln!(
+        res,
+        "District: {}  Extensions: {}",
+        spec.district,
+        spec.exts.join(",")
+    )
+    .unwrap();
+    writeln!(res, "\nData availability").unwrap();
+    writeln!(
+        res,
+        "{:<10} {:>8} {:>9} {:>8}",
+        "date", "sensors", "complete", "valid"
+    )
+    .unwrap();
+    for av in &avail {
+        writeln!(
+            res,
+            "{:<10} {:>8} {:>9} {:>8}",
+            av.date,
+            av.sensors,
+            av.complete,
+            percent(av.valid, av.samples)
+        )
+        .unwrap();
+    }
+    writeln!(res, "\nDetector health").unwrap();
+    if health.is_empty() {
+        writeln!(res, "No issues found").unwrap();
+        return res;
+    }
+    health.sort_by(|a, b| {
+        a.issue
+            .cmp(&b.issue)
+            .then_with(|| a.sensor.cmp(&b.sensor))
+            .then_with(|| a.ext.cmp(&b.ext))
+            .then_with(|| a.date.cmp(&b.date))
+    });
+    writeln!(
+        res,
+        "{:<10} {:<6} {:<10} {:<9} {:>7}",
+        "sensor", "ext", "date", "issue", "valid"
+    )
+    .unwrap();
+    for h in health.iter().take(MAX_LISTED) {
+        writeln!(
+            res,
+            "{:<10} {:<6} {:<10} {:<9} {:>6.1}%",
+            h.sensor,
+            h.ext,
+            h.date,
+            h.issue.as_str(),
+            h.valid * 100.0
+        )
+        .unwrap();
+    }
+    if health.len() > MAX_LISTED {
+        writeln!(res, "... and {} more", health.len() - MAX_LISTED).unwrap();
+    }
+    res
+}
+
+/// SMTP client session
+struct Smtp {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Smtp {
+    /// Read a (possibly multi-line) reply, checking its status code.
+    ///
+    /// Returns the text of each line.
+    fn reply(&mut self, expected: u16) -> io::Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::other("SMTP connection closed"));
+            }
+            let code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+            if code != Some(expected) {
+                return Err(io::Error::other(format!(
+                    "SMTP: {}",
+                    line.trim_end()
+                )));
+            }
+            let more = line.as_bytes().get(3) == Some(&b'-');
+            lines.push(line.get(4..).unwrap_or("").trim_end().to_string());
+            // a dash after the code continues the reply
+            if !more {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Send a command, checking the reply
+    fn command(&mut self, cmd: &str, expected: u16) -> io::Result<Vec<String>> {
+        write!(self.stream, "{}\r\n", cmd)?;
+        self.stream.flush()?;
+        self.reply(expected)
+    }
+
+    /// Authenticate with `AUTH PLAIN`, if the relay offers it
+    fn auth(&mut self, auth: &Credentials) -> io::Result<()> {
+        let ehlo = self.command("EHLO trafdat", 250)?;
+        let plain = ehlo.iter().any(|line| {
+            let mut words = line.split_whitespace();
+            words.next().is_some_and(|w| w.eq_ignore_ascii_case("AUTH"))
+                && words.any(|w| w.eq_ignore_ascii_case("PLAIN"))
+        });
+        if !plain {
+            return Err(io::Error::other("SMTP: AUTH PLAIN not offered"));
+        }
+        let token = format!("\0{}\0{}", auth.username, auth.password);
+        let cmd = format!("AUTH PLAIN {}", STANDARD.encode(token));
+        self.command(&cmd, 235)?;
+        Ok(())
+    }
+}
+
+/// Send a plain text email through an SMTP relay.
+///
+/// With credentials, the session is authenticated with `AUTH PLAIN`.  There
+/// is no TLS implementation (so no `STARTTLS`), and credentials are sent in
+/// the clear: use a relay on a trusted network, or a local TLS tunnel.
+pub fn send_mail(
+    smtp: &str,
+    auth: Option<&Credentials>,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<(), Error> {
+    let stream = TcpStream::connect(smtp)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut session = Smtp {
+        reader: BufReader::new(stream.try_clone()?),
+        stream,
+    };
+    session.reply(220)?;
+    match auth {
+        Some(auth) => session.auth(auth)?,
+        None => {
+            session.command("HELO trafdat", 250)?;
+        }
+    }
+    session.command(&format!("MAIL FROM:<{}>", from), 250)?;
+    for rcpt in to {
+        session.command(&format!("RCPT TO:<{}>", rcpt), 250)?;
+    }
+    session.command("DATA", 354)?;
+    let mut msg = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        subject,
+        Local::now().to_rfc2822(),
+    );
+    for line in body.lines() {
+        // dot-stuffing
+        if line.starts_with('.') {
+            msg.push('.');
+        }
+        msg.push_str(line);
+        msg.push_str("\r\n");
+    }
+    msg.push('.');
+    session.command(&msg, 250)?;
+    session.command("QUIT", 221)?;
+    Ok(())
+}
+
+/// Send all reports due on a date
+pub fn send_due(state: &AppState, config: &ReportConfig, today: NaiveDate) {
+    for spec in config.reports.iter().filter(|s| s.is_due(today)) {
+        let dates = spec.dates(today);
+        let body = render(state, spec, &dates);
+        let subject = spec.subject(&dates);
+        match send_mail(
+            &config.smtp,
+            config.auth.as_ref(),
+            &config.from,
+            &spec.recipients,
+            &subject,
+            &body,
+        ) {
+            Ok(()) => info!("sent report {}", subject),
+            Err(e) => warn!("report {}: {}", subject, e),
+        }
+    }
+}
+
+/// Get the start of a local hour on a date.
+///
+/// An hour skipped by a daylight saving time change starts at the next hour
+/// which exists, and a repeated hour at its first occurrence.
+fn local_hour(date: NaiveDate, hour: u32) -> Option<DateTime<Local>> {
+    (hour..24).find_map(|h| {
+        Local
+            .from_local_datetime(&date.and_hms_opt(h, 0, 0)?)
+            .earliest()
+    })
+}
+
+/// Get time until the next occurrence of a local hour
+fn until_hour(hour: u32) -> Duration {
+    let now = Local::now();
+    let today = now.date_naive();
+    local_hour(today, hour)
+        .filter(|next| *next > now)
+        .or_else(|| local_hour(today + Days::days(1), hour))
+        .and_then(|next| (next - now).to_std().ok())
+        .unwrap_or(Duration::from_secs(60))
+}
+
+/// Spawn a background thread to send reports daily
+pub fn spawn(state: web::Data<AppState>) {
+    let path = match &state.config.reports_path {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    thread::spawn(move || {
+        let mut config = None;
+        loop {
+            // reload each day, so changes take effect without a restart;
+            // after an error, the last valid configuration is used
+            match ReportConfig::load(&path) {
+                Ok(cfg) => config = Some(cfg),
+                Err(e) => warn!("reports: {}", e),
+            }
+            match &config {
+                Some(config) => {
+                    thread::sleep(until_hour(config.hour));
+                    send_due(&state, config, Local::now().date_naive());
+                }
+                None => thread::sleep(RETRY),
+            }
+        }
+    });
+}
+
+/// Print reports (without sending) as of a date.
+///
+/// * `args` Command arguments: config file and optional date (default
+///   today).
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (path, today) = match args {
+        [path] => (path, Local::now().date_naive()),
+        [path, date] => (
+            path,
+            NaiveDate::parse_from_str(date, DATE_FMT)
+                .map_err(|_| Error::InvalidParam(format!("date: {}", date)))?,
+        ),
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: report <config.json> [date]".into(),
+            ))
+        }
+    };
+    let config = ReportConfig::load(Path::new(path))?;
+    let state = AppState::new(Config::from_env()?);
+    for spec in config.reports.iter().filter(|s| s.is_due(today)) {
+        println!("To: {}", spec.recipients.join(", "));
+        println!("{}", render(&state, spec, &spec.dates(today)));
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+// robots.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::state::AppState;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, HttpResponse};
+use std::fmt::Write;
+
+/// Robots tag for data and listing responses
+const NOINDEX: &str = "noindex, nofollow";
+
+/// Pages (relative to URL prefix) which crawlers may index
+const PAGES: &[&str] = &["/index.html", "/browse.html"];
+
+/// Build default robots.txt, allowing only documentation pages
+fn default_robots(prefix: &str) -> String {
+    let mut res = String::from("User-agent: *\n");
+    writeln!(res, "Allow: {}/$", prefix).unwrap();
+    for page in PAGES {
+        writeln!(res, "Allow: {}{}", prefix, page).unwrap();
+    }
+    writeln!(res, "Disallow: {}/", prefix).unwrap();
+    res
+}
+
+/// Check if a path (after URL prefix) may be indexed
+fn is_indexable(path: &str) -> bool {
+    path.is_empty()
+        || path == "/"
+        || PAGES.contains(&path)
+        || path.starts_with("/static/")
+}
+
+/// Add `X-Robots-Tag` header to data and listing responses
+pub fn add_tag<B>(res: &mut ServiceResponse<B>) {
+    let state = match res.request().app_data::<web::Data<AppState>>() {
+        Some(state) => state,
+        None => return,
+    };
+    let path = res.request().path();
+    let path = path
+        .strip_prefix(state.config.url_prefix.as_str())
+        .unwrap_or(path);
+    if !is_indexable(path) {
+        res.headers_mut().insert(
+            HeaderName::from_static("x-robots-tag"),
+            HeaderValue::from_static(NOINDEX),
+        );
+    }
+}
+
+/// Handle request for robots.txt
+pub async fn handle_robots(state: web::Data<AppState>) -> HttpResponse {
+    let config = &state.config;
+    let body = match &config.robots_txt {
+        Some(robots) => robots.clone(),
+        None => default_robots(&config.url_prefix),
+    };
+    HttpResponse::Ok().content_type("text/plain").body(body)
+}
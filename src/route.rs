@@ -0,0 +1,456 @@
+// route.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Request classifier for sensor data paths.
+//
+// Path segments are parsed once into typed values, then matched against a
+// table of request forms.  Deprecated forms (without a district ID) begin
+// with a year followed by a date in that year, unless a district with that
+// name exists.
+//
+use crate::error::Error;
+
+/// Parse year parameter
+fn parse_year(year: &str) -> Option<i32> {
+    year.parse().ok().filter(|yr| *yr >= 1900 && *yr <= 9999)
+}
+
+/// Parse month parameter
+fn parse_month(month: &str) -> Option<i32> {
+    month.parse().ok().filter(|mo| *mo >= 1 && *mo <= 12)
+}
+
+/// Parse day parameter
+fn parse_day(day: &str) -> Option<i32> {
+    day.parse().ok().filter(|da| *da >= 1 && *da <= 31)
+}
+
+/// Check if a year is valid
+pub fn is_valid_year(year: &str) -> bool {
+    year.len() == 4 && parse_year(year).is_some()
+}
+
+/// Check if a date is valid
+pub fn is_valid_date(date: &str) -> bool {
+    date.len() == 8
+        && parse_year(&date[..4]).is_some()
+        && parse_month(&date[4..6]).is_some()
+        && parse_day(&date[6..8]).is_some()
+}
+
+/// Check if a district ID (e.g. from a request body) is one path component
+pub fn is_valid_district(district: &str) -> bool {
+    !district.is_empty()
+        && district != "."
+        && district != ".."
+        && !district.contains(['/', '\\'])
+}
+
+/// District ID segment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct District<'a>(&'a str);
+
+/// Year segment (4 digits)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Year<'a>(&'a str);
+
+/// Date segment (yyyyMMdd)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Date<'a>(&'a str);
+
+/// Sensor ID segment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorId<'a>(&'a str);
+
+impl<'a> District<'a> {
+    /// Get district ID
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+impl<'a> Date<'a> {
+    /// Get date string
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    /// Check that a date is within a year
+    fn check_year(&self, year: Year) -> Result<(), Error> {
+        if &self.0[..4] == year.0 {
+            Ok(())
+        } else {
+            Err(Error::InvalidParam(format!("{} not in {}", self.0, year.0)))
+        }
+    }
+}
+
+impl<'a> Date<'a> {
+    /// Get the year of a date
+    pub fn year(&self) -> Year<'a> {
+        Year(&self.0[..4])
+    }
+}
+
+impl<'a> Year<'a> {
+    /// Get year string
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+impl<'a> SensorId<'a> {
+    /// Get sensor ID
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+/// Typed path segment
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Segment<'a> {
+    /// 4-digit year
+    Year(Year<'a>),
+    /// 8-digit date
+    Date(Date<'a>),
+    /// Sensor ID with extension
+    SidExt(SensorId<'a>, &'a str),
+    /// Any other name
+    Name(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    /// Parse a path segment
+    fn parse(seg: &'a str) -> Self {
+        if is_valid_year(seg) {
+            Segment::Year(Year(seg))
+        } else if is_valid_date(seg) {
+            Segment::Date(Date(seg))
+        } else if let Some((sid, ext)) = seg.split_once('.') {
+            Segment::SidExt(SensorId(sid), ext)
+        } else {
+            Segment::Name(seg)
+        }
+    }
+
+    /// Get segment as a district ID
+    fn district(self) -> Option<District<'a>> {
+        match self {
+            Segment::Year(Year(name)) | Segment::Name(name) => {
+                Some(District(name))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Kind of derived (decoded) data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Derived {
+    /// Sample data anomalies
+    Anomalies,
+    /// Same weekday baseline comparison
+    Baseline,
+    /// Vehicle length classes
+    Classes,
+    /// Headway statistics
+    Headway,
+    /// Speed histogram
+    SpeedHist,
+}
+
+impl Derived {
+    /// Get derived kind from an extension
+    fn from_ext(ext: &str) -> Option<Self> {
+        match ext {
+            "anomalies" => Some(Derived::Anomalies),
+            "baseline" => Some(Derived::Baseline),
+            "classes" => Some(Derived::Classes),
+            "headway" => Some(Derived::Headway),
+            "speed_hist" => Some(Derived::SpeedHist),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of corridor analysis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Analysis {
+    /// Ramp flow balance
+    Balance,
+    /// Active bottlenecks
+    Bottlenecks,
+    /// Vehicle-miles traveled and delay
+    Vmt,
+}
+
+impl Analysis {
+    /// Get analysis kind from a request name
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "balance" => Some(Analysis::Balance),
+            "bottlenecks" => Some(Analysis::Bottlenecks),
+            "vmt" => Some(Analysis::Vmt),
+            _ => None,
+        }
+    }
+}
+
+/// Output form of a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output<'a> {
+    /// JSON (`.json` suffix)
+    Json,
+    /// Plain text or raw bytes
+    Raw,
+    /// Named output format (`.{ext}.{name}` suffix)
+    Named(&'a str),
+}
+
+/// Classified sensor data request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Route<'a> {
+    /// Years with sampled dates
+    Years(District<'a>),
+    /// Construction and workzone annotations
+    Annotations(District<'a>),
+    /// Dates with complete data for a set of sensors
+    CompleteDates(District<'a>),
+    /// Average daily traffic for a set of sensors
+    Aadt(District<'a>),
+    /// Percentiles of sample values for a set of sensors
+    Percentiles(District<'a>),
+    /// Checksums of archive files in a year
+    Checksums(District<'a>, Year<'a>),
+    /// Dates sampled in a year
+    Dates(District<'a>, Year<'a>, Output<'a>),
+    /// Sensors sampled on a date
+    Sensors(District<'a>, Date<'a>),
+    /// Whole day's zip archive
+    Archive(District<'a>, Date<'a>),
+    /// Extensions sampled for a sensor
+    Extensions(District<'a>, Date<'a>, SensorId<'a>),
+    /// Raw sample data for a sensor over a date range
+    SampleRange(District<'a>, SensorId<'a>, &'a str),
+    /// Detector locations over a date range
+    Locations(District<'a>, SensorId<'a>),
+    /// Sample data for a sensor
+    Sample(District<'a>, Date<'a>, SensorId<'a>, &'a str, Output<'a>),
+    /// Derived data for a sensor
+    Derived(District<'a>, Date<'a>, SensorId<'a>, Derived),
+    /// Aligned data for multiple sensors
+    Aligned(District<'a>, Date<'a>),
+    /// Corridor analysis
+    Analysis(District<'a>, Date<'a>, Analysis),
+}
+
+impl Route<'_> {
+    /// Check if a route is for derived data (which may be cached)
+    pub fn is_derived(&self) -> bool {
+        matches!(
+            self,
+            Route::Derived(..)
+                | Route::Aligned(..)
+                | Route::Analysis(..)
+                | Route::Checksums(..)
+        )
+    }
+
+    /// Check if a route was served by the legacy (Java) servlet
+    pub fn is_legacy(&self) -> bool {
+        matches!(
+            self,
+            Route::Dates(..)
+                | Route::Sensors(..)
+                | Route::Archive(..)
+                | Route::Extensions(..)
+                | Route::Sample(_, _, _, _, Output::Json | Output::Raw)
+        )
+    }
+}
+
+/// Parent listing of a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parent<'a> {
+    /// Years sampled in a district
+    Years(District<'a>),
+    /// Dates sampled in a year
+    Dates(District<'a>, Year<'a>),
+    /// Sensors sampled on a date
+    Sensors(District<'a>, Date<'a>),
+}
+
+impl<'a> Route<'a> {
+    /// Get parent listings of a route, outermost first
+    pub fn parents(&self) -> Vec<Parent<'a>> {
+        match *self {
+            Route::Years(did)
+            | Route::Annotations(did)
+            | Route::CompleteDates(did)
+            | Route::Aadt(did)
+            | Route::Percentiles(did)
+            | Route::SampleRange(did, ..)
+            | Route::Locations(did, _) => vec![Parent::Years(did)],
+            Route::Checksums(did, year) | Route::Dates(did, year, _) => {
+                vec![Parent::Years(did), Parent::Dates(did, year)]
+            }
+            Route::Sensors(did, date) | Route::Archive(did, date) => {
+                vec![Parent::Years(did), Parent::Dates(did, date.year())]
+            }
+            Route::Extensions(did, date, _)
+            | Route::Sample(did, date, ..)
+            | Route::Derived(did, date, ..)
+            | Route::Aligned(did, date)
+            | Route::Analysis(did, date, _) => vec![
+                Parent::Years(did),
+                Parent::Dates(did, date.year()),
+                Parent::Sensors(did, date),
+            ],
+        }
+    }
+}
+
+/// Path shapes (number of segments and suffix) of requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    /// `/{p1}`
+    One,
+    /// `/{p1}/{p2}`
+    Two,
+    /// `/{p1}/{p2}.json`
+    TwoJson,
+    /// `/{p1}/{p2}.traffic`
+    TwoTraffic,
+    /// `/{p1}/{p2}/{p3}`
+    Three,
+    /// `/{p1}/{p2}/{p3}.json`
+    ThreeJson,
+}
+
+/// Classify a request path.
+///
+/// * `shape` Path shape.
+/// * `params` Path parameters (without suffix).
+/// * `default` Default district ID for deprecated forms.
+/// * `is_district` Check if a district exists (for year-named districts).
+///
+/// Returns `Ok(None)` for paths which match no request form.
+pub fn classify<'a>(
+    shape: Shape,
+    params: &[&'a str],
+    default: &'a str,
+    is_district: &dyn Fn(&str) -> bool,
+) -> Result<Option<Route<'a>>, Error> {
+    use Segment::*;
+    let segs: Vec<Segment> = params.iter().map(|p| Segment::parse(p)).collect();
+    let dflt = District(default);
+    let route = match (shape, segs.as_slice()) {
+        (Shape::One, [Year(y)]) => Route::Dates(dflt, *y, Output::Raw),
+        (Shape::Two, [Year(y), Date(d)]) if !is_district(y.as_str()) => {
+            d.check_year(*y)?;
+            Route::Sensors(dflt, *d)
+        }
+        (Shape::Two, [p1, Date(d)]) => match p1.district() {
+            Some(did) => Route::Sensors(did, *d),
+            None => return Ok(None),
+        },
+        (Shape::Two, [p1, Year(y)]) => match p1.district() {
+            Some(did) => Route::Dates(did, *y, Output::Raw),
+            None => return Ok(None),
+        },
+        (Shape::Two, [p1, SidExt(s, e)]) => match p1.district() {
+            Some(did) => Route::SampleRange(did, *s, e),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, Name("years")]) => match p1.district() {
+            Some(did) => Route::Years(did),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, Name("annotations")]) => match p1.district() {
+            Some(did) => Route::Annotations(did),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, Name("dates")]) => match p1.district() {
+            Some(did) => Route::CompleteDates(did),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, Name("aadt")]) => match p1.district() {
+            Some(did) => Route::Aadt(did),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, Name("percentiles")]) => match p1.district() {
+            Some(did) => Route::Percentiles(did),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, SidExt(s, "locations")]) => match p1.district() {
+            Some(did) => Route::Locations(did, *s),
+            None => return Ok(None),
+        },
+        (Shape::TwoJson, [p1, Year(y)]) => match p1.district() {
+            Some(did) => Route::Dates(did, *y, Output::Json),
+            None => return Ok(None),
+        },
+        (Shape::TwoTraffic, [p1, Date(d)]) => match p1.district() {
+            Some(did) => Route::Archive(did, *d),
+            None => return Ok(None),
+        },
+        (Shape::Three | Shape::ThreeJson, [Year(y), Date(d), SidExt(s, e)])
+            if !is_district(y.as_str()) =>
+        {
+            d.check_year(*y)?;
+            let (e, out) = sample_output(shape, e);
+            Route::Sample(dflt, *d, *s, e, out)
+        }
+        (Shape::ThreeJson, [p1, Year(y), Name("checksums")]) => {
+            match p1.district() {
+                Some(did) => Route::Checksums(did, *y),
+                None => return Ok(None),
+            }
+        }
+        (Shape::Three, [p1, Year(y), Date(d)]) => match p1.district() {
+            Some(did) => {
+                d.check_year(*y)?;
+                Route::Sensors(did, *d)
+            }
+            None => return Ok(None),
+        },
+        (shape, [p1, Date(d), p3]) => {
+            let did = match p1.district() {
+                Some(did) => did,
+                None => return Ok(None),
+            };
+            match (shape, p3) {
+                (Shape::ThreeJson, Name("aligned")) => Route::Aligned(did, *d),
+                (Shape::ThreeJson, SidExt(s, e)) => {
+                    match Derived::from_ext(e) {
+                        Some(kind) => Route::Derived(did, *d, *s, kind),
+                        None => Route::Sample(did, *d, *s, e, Output::Json),
+                    }
+                }
+                (Shape::ThreeJson, _) => match Analysis::from_name(params[2]) {
+                    Some(kind) => Route::Analysis(did, *d, kind),
+                    None => Route::Extensions(did, *d, SensorId(params[2])),
+                },
+                (Shape::Three, SidExt(s, e)) => {
+                    let (e, out) = sample_output(shape, e);
+                    Route::Sample(did, *d, *s, e, out)
+                }
+                _ => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(route))
+}
+
+/// Get sample extension and output form for a three-parameter shape
+fn sample_output(shape: Shape, ext: &str) -> (&str, Output<'_>) {
+    match shape {
+        Shape::ThreeJson => (ext, Output::Json),
+        _ => match ext.split_once('.') {
+            Some((ext, name)) => (ext, Output::Named(name)),
+            None => (ext, Output::Raw),
+        },
+    }
+}
@@ -0,0 +1,269 @@
+// sample.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use serde::Deserialize;
+
+/// Number of seconds in a day
+pub const DAY_SECS: u64 = 86_400;
+
+/// Marker for missing sample values
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Marker {
+    /// Negative values are missing
+    #[default]
+    Negative,
+    /// One value is missing, as well as negative values
+    Value(i32),
+}
+
+impl Marker {
+    /// Check that a sample value is valid
+    fn valid(self, val: i32) -> Option<i32> {
+        match self {
+            Marker::Value(v) if v == val => None,
+            _ if val < 0 => None,
+            _ => Some(val),
+        }
+    }
+
+    /// Get the value to encode for missing samples
+    fn missing(self) -> i32 {
+        match self {
+            Marker::Negative => -1,
+            Marker::Value(v) => v,
+        }
+    }
+}
+
+/// Method for combining samples when rebinning
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine {
+    /// Sum of counts (missing if any sample is missing)
+    Sum,
+    /// Mean of valid samples (missing if all are missing)
+    Mean,
+    /// Harmonic mean of valid, positive samples (missing if none)
+    Harmonic,
+}
+
+/// Mean for aggregating speeds
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mean {
+    /// Arithmetic (time-mean) speed
+    #[default]
+    Arithmetic,
+    /// Harmonic (space-mean) speed
+    Harmonic,
+}
+
+impl Mean {
+    /// Get the method for combining samples
+    pub fn combine(self) -> Combine {
+        match self {
+            Mean::Arithmetic => Combine::Mean,
+            Mean::Harmonic => Combine::Harmonic,
+        }
+    }
+
+    /// Get the mean of values (harmonic mean of positive values only)
+    pub fn of(self, vals: &[f64]) -> Option<f64> {
+        match self {
+            Mean::Arithmetic if !vals.is_empty() => {
+                Some(vals.iter().sum::<f64>() / vals.len() as f64)
+            }
+            Mean::Arithmetic => None,
+            Mean::Harmonic => {
+                let pos: Vec<f64> =
+                    vals.iter().copied().filter(|v| *v > 0.0).collect();
+                match pos.len() {
+                    0 => None,
+                    n => Some(
+                        n as f64 / pos.iter().map(|v| 1.0 / v).sum::<f64>(),
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Get the weighted mean of `(value, weight)` pairs.
+    ///
+    /// Returns `None` if the total weight is not positive.
+    pub fn weighted(self, vals: &[(f64, f64)]) -> Option<f64> {
+        let (sum, total) = match self {
+            Mean::Arithmetic => vals
+                .iter()
+                .fold((0.0, 0.0), |(s, t), (v, w)| (s + v * w, t + w)),
+            Mean::Harmonic => vals
+                .iter()
+                .filter(|(v, _)| *v > 0.0)
+                .fold((0.0, 0.0), |(s, t), (v, w)| (s + w, t + w / v)),
+        };
+        (total > 0.0).then(|| sum / total)
+    }
+}
+
+impl Combine {
+    /// Combine one bin of samples
+    fn apply(self, bin: &[Option<i32>]) -> Option<i32> {
+        match self {
+            Combine::Sum => bin.iter().copied().sum(),
+            Combine::Mean => {
+                let vals: Vec<i32> = bin.iter().flatten().copied().collect();
+                match vals.len() as i32 {
+                    0 => None,
+                    n => Some((vals.iter().sum::<i32>() + n / 2) / n),
+                }
+            }
+            Combine::Harmonic => {
+                let vals: Vec<f64> =
+                    bin.iter().flatten().map(|v| f64::from(*v)).collect();
+                Mean::Harmonic.of(&vals).map(|v| v.round() as i32)
+            }
+        }
+    }
+}
+
+/// Parse a resampling interval, such as `7m30s`, `1h` or `90` (seconds).
+///
+/// The interval must evenly divide a day.
+pub fn parse_interval(interval: &str) -> Option<u32> {
+    if let Ok(secs) = interval.parse::<u32>() {
+        return (secs > 0 && DAY_SECS.is_multiple_of(u64::from(secs)))
+            .then_some(secs);
+    }
+    let mut secs = 0;
+    let mut num = String::new();
+    let mut last = 0;
+    for c in interval.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let (rank, unit) = match c {
+            'h' => (1, 3600),
+            'm' => (2, 60),
+            's' => (3, 1),
+            _ => return None,
+        };
+        // units in order, each with a number
+        if rank <= last || num.is_empty() {
+            return None;
+        }
+        last = rank;
+        secs += num.parse::<u32>().ok()?.checked_mul(unit)?;
+        num.clear();
+    }
+    (num.is_empty() && secs > 0 && DAY_SECS.is_multiple_of(u64::from(secs)))
+        .then_some(secs)
+}
+
+/// Series of decoded samples for one day
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleSeries {
+    /// Sample period (seconds)
+    period: u32,
+    /// Sample values (`None` for missing)
+    values: Vec<Option<i32>>,
+}
+
+impl SampleSeries {
+    /// Decode binned sample data.
+    ///
+    /// * `data` Raw sample data.
+    /// * `bytes` Number of bytes per sample (1 or 2).
+    ///
+    /// Samples are signed, big-endian values; negative values are missing.
+    pub fn decode(data: &[u8], bytes: u64) -> Self {
+        SampleSeries::decode_marked(data, bytes, Marker::Negative)
+    }
+
+    /// Decode binned sample data with a missing value marker
+    pub fn decode_marked(data: &[u8], bytes: u64, marker: Marker) -> Self {
+        let values = decode_raw(data, bytes)
+            .into_iter()
+            .map(|v| marker.valid(v))
+            .collect();
+        SampleSeries::from_values(values)
+    }
+
+    /// Create a sample series from values for one day
+    pub fn from_values(values: Vec<Option<i32>>) -> Self {
+        let period = if values.is_empty() {
+            0
+        } else {
+            (DAY_SECS / values.len() as u64) as u32
+        };
+        SampleSeries { period, values }
+    }
+
+    /// Encode sample data with a number of bytes per sample (1 or 2).
+    ///
+    /// Missing values are encoded as -1, and large values are clamped.
+    pub fn encode(&self, bytes: u64) -> Vec<u8> {
+        self.encode_marked(bytes, Marker::Negative)
+    }
+
+    /// Encode sample data with a missing value marker
+    pub fn encode_marked(&self, bytes: u64, marker: Marker) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.values.len() * bytes as usize);
+        for val in &self.values {
+            let val = val.unwrap_or(marker.missing());
+            match bytes {
+                2 => {
+                    let v = val.min(i32::from(i16::MAX)) as i16;
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+                _ => data.push(val.min(i32::from(i8::MAX)) as i8 as u8),
+            }
+        }
+        data
+    }
+
+    /// Get the sample period (seconds)
+    pub fn period(&self) -> u32 {
+        self.period
+    }
+
+    /// Get the sample values
+    pub fn values(&self) -> &[Option<i32>] {
+        &self.values
+    }
+
+    /// Rebin samples to a coarser period (seconds).
+    ///
+    /// The period must be a multiple of the current period.
+    pub fn rebin(&self, period: u32, combine: Combine) -> Option<Self> {
+        if self.period == 0 || !period.is_multiple_of(self.period) {
+            return None;
+        }
+        let n = (period / self.period) as usize;
+        let values = self
+            .values
+            .chunks(n)
+            .map(|bin| combine.apply(bin))
+            .collect();
+        Some(SampleSeries::from_values(values))
+    }
+
+    /// Get the sum of all valid samples
+    pub fn total(&self) -> i64 {
+        self.values.iter().flatten().map(|v| i64::from(*v)).sum()
+    }
+}
+
+/// Decode raw (signed, big-endian) sample values, including missing values.
+///
+/// * `data` Raw sample data.
+/// * `bytes` Number of bytes per sample (1 or 2).
+pub fn decode_raw(data: &[u8], bytes: u64) -> Vec<i32> {
+    match bytes {
+        2 => data
+            .chunks_exact(2)
+            .map(|b| i32::from(i16::from_be_bytes([b[0], b[1]])))
+            .collect(),
+        _ => data.iter().map(|b| i32::from(*b as i8)).collect(),
+    }
+}
@@ -0,0 +1,393 @@
+// schema.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// JSON Schemas for response types.
+//
+// Schemas are derived from the response types themselves
+// (`#[derive(JsonSchema)]`), following their `serde` attributes, so they
+// cannot drift from the JSON which is served.
+//
+use crate::admin::AdminStatus;
+use crate::annotate::Annotation;
+use crate::anomaly::Report;
+use crate::audit::AuditEntry;
+use crate::avail::{SensorAadt, SensorPercentiles};
+use crate::balance::Balance;
+use crate::baseline::Comparison;
+use crate::bottleneck::Bottlenecks;
+use crate::error::Error;
+use crate::headway::HeadwayStats;
+use crate::health::HealthReport;
+use crate::jobs::Job;
+use crate::locate::Located;
+use crate::metro::{DetectorLink, Graph, Nodes};
+use crate::sensor::SourceEntry;
+use crate::spec::Spec;
+use crate::speed::SpeedHistogram;
+use crate::stats::StatsRow;
+use crate::swap::Locations;
+use crate::sync::FileSum;
+use crate::vclass::Classes;
+use crate::vmt::CorridorTravel;
+use crate::watch::ArchiveEvent;
+use crate::wire::YearDates;
+use crate::wire::{Aligned, Corridor, CorridorFeature, Mismatch, TmsConfig};
+use actix_web::{web, HttpResponse};
+use serde_json::{json, Map, Value};
+use std::borrow::{Cow, ToOwned};
+use std::collections::{BTreeMap, HashMap};
+
+pub use trafdat_derive::JsonSchema;
+
+/// JSON Schema dialect
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Type with a JSON Schema
+pub trait JsonSchema {
+    /// Get the schema for the type
+    fn schema() -> Value;
+}
+
+/// Schema builder function
+type SchemaFn = fn() -> Value;
+
+/// Published schemas, by name
+const SCHEMAS: &[(&str, SchemaFn)] = &[
+    ("aadt", <Vec<SensorAadt>>::schema),
+    ("admin_status", AdminStatus::schema),
+    ("aligned", Aligned::schema),
+    ("annotations", <Vec<Annotation>>::schema),
+    ("anomalies", Report::schema),
+    ("archive_event", ArchiveEvent::schema),
+    ("audit", <Vec<AuditEntry>>::schema),
+    ("balance", Balance::schema),
+    ("baseline", Comparison::schema),
+    ("bottlenecks", Bottlenecks::schema),
+    ("checksums", <Vec<FileSum>>::schema),
+    ("classes", Classes::schema),
+    ("corridor", Corridor::schema),
+    ("corridor_geojson", CorridorFeature::schema),
+    ("detectors", <Vec<DetectorLink>>::schema),
+    ("districts", districts),
+    ("graph", Graph::schema),
+    ("headway", HeadwayStats::schema),
+    ("healthz", HealthReport::schema),
+    ("job", Job::schema),
+    ("jobs", <Vec<Job>>::schema),
+    ("listing", listing),
+    ("locate", Located::schema),
+    ("locations", Locations::schema),
+    ("metro_config", TmsConfig::schema),
+    ("mismatch", Mismatch::schema),
+    ("nodes", Nodes::schema),
+    ("percentiles", <Vec<SensorPercentiles>>::schema),
+    ("samples", samples),
+    ("sources", <Vec<SourceEntry>>::schema),
+    ("spec", Spec::schema),
+    ("speed_hist", SpeedHistogram::schema),
+    ("stats", <Vec<StatsRow>>::schema),
+    ("vmt", CorridorTravel::schema),
+    ("years", <Vec<YearDates>>::schema),
+];
+
+/// Object schema builder (used by the derive macro)
+pub(crate) struct ObjectSchema {
+    description: Option<&'static str>,
+    properties: Map<String, Value>,
+    required: Vec<Value>,
+    /// Variants of flattened tagged enums
+    variants: Vec<Value>,
+}
+
+impl ObjectSchema {
+    /// Create a new object schema builder
+    pub(crate) fn new(description: Option<&'static str>) -> Self {
+        ObjectSchema {
+            description,
+            properties: Map::new(),
+            required: Vec::new(),
+            variants: Vec::new(),
+        }
+    }
+
+    /// Add a property
+    pub(crate) fn property(
+        mut self,
+        name: &str,
+        mut schema: Value,
+        required: bool,
+        description: Option<&str>,
+    ) -> Self {
+        if let (Some(desc), Value::Object(obj)) = (description, &mut schema) {
+            obj.insert("description".into(), desc.into());
+        }
+        self.properties.insert(name.into(), schema);
+        if required {
+            self.required.push(name.into());
+        }
+        self
+    }
+
+    /// Add the properties of a flattened object (or tagged enum)
+    pub(crate) fn flatten(mut self, schema: Value) -> Self {
+        if let Some(Value::Array(variants)) = schema.get("oneOf") {
+            self.variants.extend(variants.iter().cloned());
+            return self;
+        }
+        merge(&mut self.properties, &mut self.required, &schema);
+        self
+    }
+
+    /// Build the schema
+    pub(crate) fn build(self) -> Value {
+        let mut schema = Map::new();
+        if let Some(desc) = self.description {
+            schema.insert("description".into(), desc.into());
+        }
+        if self.variants.is_empty() {
+            schema.extend(object(self.properties, self.required));
+            return Value::Object(schema);
+        }
+        let variants = self
+            .variants
+            .iter()
+            .map(|variant| {
+                let mut properties = self.properties.clone();
+                let mut required = self.required.clone();
+                merge(&mut properties, &mut required, variant);
+                Value::Object(object(properties, required))
+            })
+            .collect();
+        schema.insert("oneOf".into(), Value::Array(variants));
+        Value::Object(schema)
+    }
+}
+
+/// Merge properties of an object schema
+fn merge(
+    properties: &mut Map<String, Value>,
+    required: &mut Vec<Value>,
+    schema: &Value,
+) {
+    if let Some(Value::Object(props)) = schema.get("properties") {
+        properties.extend(props.clone());
+    }
+    if let Some(Value::Array(req)) = schema.get("required") {
+        required.extend(req.iter().cloned());
+    }
+}
+
+/// Object schema members, with no additional properties
+fn object(
+    properties: Map<String, Value>,
+    required: Vec<Value>,
+) -> Map<String, Value> {
+    let mut schema = Map::new();
+    schema.insert("type".into(), "object".into());
+    schema.insert("properties".into(), Value::Object(properties));
+    schema.insert("required".into(), Value::Array(required));
+    schema.insert("additionalProperties".into(), false.into());
+    schema
+}
+
+/// Schema for one of several variants (used by the derive macro)
+pub(crate) fn one_of(description: Option<&str>, variants: Vec<Value>) -> Value {
+    let mut schema = json!({ "oneOf": variants });
+    if let Some(desc) = description {
+        schema["description"] = desc.into();
+    }
+    schema
+}
+
+/// Schema for a string enumeration (used by the derive macro)
+pub(crate) fn string_enum(description: Option<&str>, names: &[&str]) -> Value {
+    let mut schema = json!({ "type": "string", "enum": names });
+    if let Some(desc) = description {
+        schema["description"] = desc.into();
+    }
+    schema
+}
+
+/// Add a pattern to a string schema (used by the derive macro)
+pub(crate) fn with_pattern(mut schema: Value, pattern: &str) -> Value {
+    schema["pattern"] = pattern.into();
+    schema
+}
+
+impl JsonSchema for str {
+    fn schema() -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl JsonSchema for String {
+    fn schema() -> Value {
+        str::schema()
+    }
+}
+
+impl JsonSchema for bool {
+    fn schema() -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+/// Implement `JsonSchema` for unsigned integer types
+macro_rules! impl_unsigned {
+    ($($ty:ty),*) => {$(
+        impl JsonSchema for $ty {
+            fn schema() -> Value {
+                json!({ "type": "integer", "minimum": 0 })
+            }
+        }
+    )*};
+}
+
+impl_unsigned!(u8, u16, u32, u64, usize);
+
+/// Implement `JsonSchema` for signed integer types
+macro_rules! impl_signed {
+    ($($ty:ty),*) => {$(
+        impl JsonSchema for $ty {
+            fn schema() -> Value {
+                json!({ "type": "integer" })
+            }
+        }
+    )*};
+}
+
+impl_signed!(i8, i16, i32, i64);
+
+impl JsonSchema for f32 {
+    fn schema() -> Value {
+        f64::schema()
+    }
+}
+
+impl JsonSchema for f64 {
+    fn schema() -> Value {
+        json!({ "type": "number" })
+    }
+}
+
+impl<T: JsonSchema + ?Sized> JsonSchema for &T {
+    fn schema() -> Value {
+        T::schema()
+    }
+}
+
+impl<T: JsonSchema + ToOwned + ?Sized> JsonSchema for Cow<'_, T> {
+    fn schema() -> Value {
+        T::schema()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for [T] {
+    fn schema() -> Value {
+        json!({ "type": "array", "items": T::schema() })
+    }
+}
+
+impl<T: JsonSchema, const N: usize> JsonSchema for [T; N] {
+    fn schema() -> Value {
+        json!({
+            "type": "array",
+            "items": T::schema(),
+            "minItems": N,
+            "maxItems": N,
+        })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn schema() -> Value {
+        <[T]>::schema()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn schema() -> Value {
+        let mut schema = T::schema();
+        match schema.get_mut("type") {
+            Some(ty @ Value::String(_)) => {
+                *ty = json!([ty.take(), "null"]);
+                schema
+            }
+            _ => json!({ "anyOf": [schema, { "type": "null" }] }),
+        }
+    }
+}
+
+impl<V: JsonSchema> JsonSchema for BTreeMap<String, V> {
+    fn schema() -> Value {
+        json!({ "type": "object", "additionalProperties": V::schema() })
+    }
+}
+
+impl<V: JsonSchema, S> JsonSchema for HashMap<String, V, S> {
+    fn schema() -> Value {
+        BTreeMap::<String, V>::schema()
+    }
+}
+
+/// Schema for `/districts`
+fn districts() -> Value {
+    let mut schema = <Vec<String>>::schema();
+    schema["description"] = "District names".into();
+    schema
+}
+
+/// Schema for year, date, sensor and extension listings
+fn listing() -> Value {
+    let mut schema = <Vec<String>>::schema();
+    schema["description"] = "Listed names".into();
+    schema
+}
+
+/// Schema for sample data (`.json` sample files).
+///
+/// Samples are encoded directly (without a response type): as strings
+/// (unformatted), numbers (with `null` for missing values), or objects
+/// (`layout=objects`).
+fn samples() -> Value {
+    let value = <Option<f64>>::schema();
+    let objects = ObjectSchema::new(Some("Sample with its interval number"))
+        .property("interval", usize::schema(), true, None)
+        .property("value", value.clone(), true, None)
+        .build();
+    json!({
+        "description": "Sample values, one per interval",
+        "anyOf": [
+            <Vec<String>>::schema(),
+            { "type": "array", "items": value },
+            { "type": "array", "items": objects },
+        ],
+    })
+}
+
+/// Get a published schema by name
+pub fn lookup(name: &str) -> Option<Value> {
+    let (_, schema) = SCHEMAS.iter().find(|(n, _)| *n == name)?;
+    let mut schema = schema();
+    if let Value::Object(obj) = &mut schema {
+        obj.insert("$schema".into(), DIALECT.into());
+        obj.insert("title".into(), name.into());
+    }
+    Some(schema)
+}
+
+/// Get the names of all published schemas
+pub fn names() -> impl Iterator<Item = &'static str> {
+    SCHEMAS.iter().map(|(n, _)| *n)
+}
+
+/// Handle a schema request
+pub async fn handle_schema(
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let schema = lookup(&path.into_inner()).ok_or(Error::NotFound)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/schema+json")
+        .body(schema.to_string()))
+}
@@ -2,19 +2,52 @@
 //
 // Copyright (c) 2019  Minnesota Department of Transportation
 //
-use actix_web::HttpResponse;
+use crate::align::{self, AlignRequest};
+use crate::annotate;
+use crate::anomaly;
+use crate::avail;
+use crate::balance;
+use crate::baseline;
+use crate::bottleneck;
+use crate::error::Error;
+use crate::headway;
+use crate::metrics::Metrics;
+use crate::metro;
+use crate::multipart::{self, Multipart};
+use crate::output::{OutputFormat, SampleData};
+use crate::pool::SharedZip;
+use crate::range;
+use crate::rename::RenameMap;
+use crate::route::{
+    self, is_valid_date, is_valid_year, Analysis, Derived, Output, Route, Shape,
+};
+use crate::sample::{
+    parse_interval, Combine, Marker, Mean, SampleSeries, DAY_SECS,
+};
+use crate::schema::JsonSchema;
+use crate::signing;
+use crate::speed;
+use crate::state::{AppState, LengthCheck};
+use crate::storage::Layout;
+use crate::swap;
+use crate::timing::{self, Stage};
+use crate::vclass;
+use crate::vclass::LENGTH_CLASSES;
+use crate::vmt;
+use crate::weather;
+use crate::wire::YearDates;
+use actix_files::NamedFile;
+use actix_web::http::header::ACCEPT;
+use actix_web::{web, HttpRequest, HttpResponse};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
 use std::fmt::Display;
-use std::fmt::Write;
 use std::fs::{read_dir, File};
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use zip::ZipArchive;
-
-/// Base traffic archive path
-const BASE_PATH: &str = "/var/lib/iris/traffic";
-
-/// Default district ID
-const DISTRICT_DEFAULT: &str = "tms";
+use zip::read::ZipFile;
+use zip::result::ZipError;
 
 /// Traffic file extension
 const DEXT: &str = ".traffic";
@@ -22,105 +55,107 @@ const DEXT: &str = ".traffic";
 /// Traffic file extension without dot
 const EXT: &str = "traffic";
 
+/// Zstandard-compressed sample file extension
+pub const ZST: &str = ".zst";
+
 /// Extension fragments for sample types, plus sample bytes
 const SAMPLE_TYPES: &[(&str, u64)] = &[
-    ("vmc", 1),
-    ("vs", 1),
-    ("vm", 1),
-    ("vl", 1),
-    ("v", 1),
-    ("o", 2),
-    ("c", 2),
-    ("s", 1),
-    ("pr", 2),
-    ("pt", 1),
+    ("vmc", 1), // motorcycle (length class) vehicle counts
+    ("vs", 1),  // short (length class) vehicle counts
+    ("vm", 1),  // medium (length class) vehicle counts
+    ("vl", 1),  // long (length class) vehicle counts
+    ("v", 1),   // vehicle counts (volume)
+    ("o", 2),   // occupancy
+    ("c", 2),   // scan counts
+    ("s", 1),   // speed (mph)
+    ("pr", 2),  // precipitation rate
+    ("pt", 1),  // precipitation type
 ];
 
-/// Tuples of sample period, number of samples per day
-const SAMPLE_PERIODS: &[(&str, u64)] = &[
-    ("60", 1440), // <- deprecated binning interval (precipitation rate)
-    ("30", 2880),
-    ("20", 4320),
-    ("15", 5760),
-    ("10", 8640),
-    ("6", 14400),
-    ("5", 17280),
+/// Default sample periods (seconds)
+pub const SAMPLE_PERIODS: &[u32] = &[
+    60, // <- deprecated binning interval (precipitation rate)
+    30, 20, 15, 10, 6, 5, 1,
 ];
 
-/// Build responses from data
-trait ResponseBuilder {
-    fn build(data: Option<Vec<u8>>) -> Option<HttpResponse>;
-}
-
-/// JSON response output
-struct JsonOutput;
+/// Deprecated sample periods (seconds), with notes
+pub const DEPRECATED_PERIODS: &[(u32, &str)] =
+    &[(60, "precipitation rate; use 30-second bins")];
 
-/// Build JSON response from data
-impl ResponseBuilder for JsonOutput {
-    fn build(data: Option<Vec<u8>>) -> Option<HttpResponse> {
-        data.and_then(|b| json_response(build_json(b)))
-    }
+/// Check if a sample period is deprecated
+pub fn is_deprecated_period(period: u32) -> bool {
+    DEPRECATED_PERIODS.iter().any(|(p, _)| *p == period)
 }
 
 /// Build JSON response from a Vec
-fn build_json<T: Display>(arr: Vec<T>) -> Option<String> {
-    if arr.len() > 0 {
+pub fn build_json<T: Display>(arr: Vec<T>) -> Result<String, Error> {
+    if !arr.is_empty() {
         let mut res = "[".to_string();
         for val in arr {
             if res.len() > 1 {
                 res.push(',');
             }
-            res.push('"');
-            write!(&mut res, "{}", val).unwrap();
-            res.push('"');
+            res.push_str(&serde_json::to_string(&val.to_string())?);
         }
         res.push(']');
-        Some(res)
+        Ok(res)
     } else {
-        None
+        Err(Error::NotFound)
     }
 }
 
 /// Create a JSON response
-fn json_response(json: Option<String>) -> Option<HttpResponse> {
-    json.and_then(|j| {
-        Some(HttpResponse::Ok().content_type("application/json").body(j))
-    })
+pub fn json_response(json: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json)
 }
 
-/// Octet stream response output
-struct OctetStreamOutput;
-
-/// Build octet stream response from data
-impl ResponseBuilder for OctetStreamOutput {
-    fn build(data: Option<Vec<u8>>) -> Option<HttpResponse> {
-        data.and_then(|b| {
-            Some(
-                HttpResponse::Ok()
-                    .content_type("application/octet_stream")
-                    .body(b),
-            )
-        })
+/// Get the file name of a zip entry, if it is valid and safe to list.
+///
+/// * `name` Decoded entry name.
+/// * `raw` Entry name as stored.
+///
+/// Names must be stored as UTF-8, and contain only printable ASCII without
+/// quotes, backslashes or markup characters.
+pub fn entry_file_name<'a>(
+    name: &'a str,
+    raw: &[u8],
+) -> Result<&'a str, &'static str> {
+    if std::str::from_utf8(raw) != Ok(name) {
+        return Err("invalid UTF-8");
+    }
+    let file = name.rsplit('/').next().unwrap_or(name);
+    let safe = |c: char| {
+        c.is_ascii_graphic()
+            && !matches!(c, '"' | '\'' | '\\' | '<' | '>' | '&')
+    };
+    if file.is_empty() || !file.chars().all(safe) {
+        return Err("unsafe characters");
     }
+    Ok(file)
 }
 
 /// List files in a directory or zip file
 trait FileLister {
     /// Check a file or zip entry by name
-    fn check<'a, 'b>(&'a self, name: &'b str, dir: bool) -> Option<&'b str>;
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str>;
 
     /// Get a list of entries in a directory
     fn list_dir(&self, path: &Path) -> Vec<String> {
         let mut list = vec![];
         if let Ok(entries) = read_dir(path) {
-            for entry in entries {
-                if let Ok(ent) = entry {
-                    if let Ok(tp) = ent.file_type() {
-                        if !tp.is_symlink() {
-                            if let Some(name) = ent.file_name().to_str() {
-                                if let Some(e) = self.check(name, tp.is_dir()) {
-                                    list.push(e.to_string())
-                                }
+            for ent in entries.flatten() {
+                if let Ok(tp) = ent.file_type() {
+                    if !tp.is_symlink() {
+                        if let Some(name) = ent.file_name().to_str() {
+                            let name = if tp.is_dir() {
+                                name
+                            } else {
+                                name.strip_suffix(ZST).unwrap_or(name)
+                            };
+                            if let Some(e) = self.check(name, tp.is_dir()) {
+                                list.push(e.to_string())
                             }
                         }
                     }
@@ -130,22 +165,36 @@ trait FileLister {
         list
     }
 
-    /// Get a list of entries in a zip file
-    fn list_zip(&self, path: &Path) -> Vec<String> {
+    /// Get a list of entries in a zip file (with an entry name prefix)
+    fn list_zip(&self, state: &AppState, date_zip: &DateZip) -> Vec<String> {
         let mut list = vec![];
-        if let Ok(file) = File::open(path) {
-            if let Ok(mut zip) = ZipArchive::new(file) {
-                for i in 0..zip.len() {
-                    if let Ok(zf) = zip.by_index(i) {
-                        let ent = Path::new(zf.name());
-                        if let Some(name) = ent.file_name() {
-                            if let Some(name) = name.to_str() {
-                                if let Some(e) = self.check(name, false) {
-                                    list.push(e.to_string())
-                                }
-                            }
-                        }
-                    }
+        let DateZip { path, prefix } = date_zip;
+        let zip = match state.zips.get(&state.metrics, path) {
+            Ok(Some(zip)) => zip,
+            Ok(None) => return list,
+            Err(e) => {
+                corrupt_archive(&state.metrics, path, e);
+                return list;
+            }
+        };
+        let mut zip = zip.lock().unwrap();
+        for i in 0..zip.len() {
+            let zf = match zip.by_index(i) {
+                Ok(zf) => zf,
+                Err(e) => {
+                    corrupt_archive(&state.metrics, path, e);
+                    continue;
+                }
+            };
+            if !prefix.is_empty() {
+                match zf.name().strip_prefix(prefix.as_str()) {
+                    Some(rest) if !rest.contains('/') => (),
+                    _ => continue,
+                }
+            }
+            if let Ok(name) = entry_file_name(zf.name(), zf.name_raw()) {
+                if let Some(e) = self.check(name, false) {
+                    list.push(e.to_string())
                 }
             }
         }
@@ -153,15 +202,61 @@ trait FileLister {
     }
 }
 
+/// Lister checking file names with a function
+struct FnLister<F>(F);
+
+impl<F> FileLister for FnLister<F>
+where
+    F: for<'b> Fn(&'b str) -> Option<&'b str>,
+{
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str> {
+        if dir {
+            None
+        } else {
+            (self.0)(name)
+        }
+    }
+}
+
+/// List checked files archived on a date, from directory and zip file.
+///
+/// Entries are sorted, without duplicates.
+pub fn list_entries<F>(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    check: F,
+) -> Vec<String>
+where
+    F: for<'b> Fn(&'b str) -> Option<&'b str>,
+{
+    let lister = FnLister(check);
+    let path = state.storage.date_path(district, date);
+    let mut list = lister.list_dir(&path);
+    for zip in date_zips(state, district, date) {
+        list.extend(lister.list_zip(state, &zip));
+    }
+    list.sort();
+    list.dedup();
+    list
+}
+
 /// Lister for directories
 struct DirLister;
 
 impl FileLister for DirLister {
-    fn check<'a, 'b>(&'a self, name: &'b str, dir: bool) -> Option<&'b str> {
-        match dir {
-            true => Some(name),
-            false => None,
-        }
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str> {
+        dir.then_some(name)
+    }
+}
+
+/// Lister for valid years
+struct YearLister;
+
+impl FileLister for YearLister {
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str> {
+        let year = if dir { name } else { name.strip_suffix(DEXT)? };
+        is_valid_year(year).then_some(year)
     }
 }
 
@@ -169,7 +264,7 @@ impl FileLister for DirLister {
 struct DateLister;
 
 impl FileLister for DateLister {
-    fn check<'a, 'b>(&'a self, name: &'b str, dir: bool) -> Option<&'b str> {
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str> {
         if dir {
             if is_valid_date(name) {
                 return Some(name);
@@ -185,15 +280,17 @@ impl FileLister for DateLister {
 }
 
 /// Lister for sensor IDs
-struct SidLister;
+struct SidLister<'s> {
+    periods: &'s [u32],
+}
 
-impl FileLister for SidLister {
-    fn check<'a, 'b>(&'a self, name: &'b str, dir: bool) -> Option<&'b str> {
+impl<'s> FileLister for SidLister<'s> {
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str> {
         if !dir {
             let path = Path::new(name);
             path.extension()
                 .and_then(|ext| ext.to_str())
-                .and_then(|ext| sample_file_ext(ext))
+                .and_then(|ext| sample_file_ext(ext, self.periods))
                 .and_then(|_| path.file_stem())
                 .and_then(|f| f.to_str())
         } else {
@@ -205,117 +302,376 @@ impl FileLister for SidLister {
 /// Lister for sample file extensions
 struct ExtLister<'s> {
     sid: &'s str,
+    periods: &'s [u32],
 }
 
 impl<'s> FileLister for ExtLister<'s> {
-    fn check<'a, 'b>(&'a self, name: &'b str, dir: bool) -> Option<&'b str> {
+    fn check<'b>(&self, name: &'b str, dir: bool) -> Option<&'b str> {
         if !dir {
             let path = Path::new(name);
             path.file_stem()
                 .and_then(|st| if st == self.sid { Some(()) } else { None })
                 .and_then(|_| path.extension())
                 .and_then(|ext| ext.to_str())
-                .and_then(|ext| sample_file_ext(ext))
+                .and_then(|ext| sample_file_ext(ext, self.periods))
         } else {
             None
         }
     }
 }
 
-/// Parse year parameter
-fn parse_year(year: &str) -> Option<i32> {
-    year.parse().ok().filter(|yr| *yr >= 1900 && *yr <= 9999)
+/// Source of archived sample files for a date
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// Date directory
+    Dir,
+    /// Date zip archive (`.traffic`), or year archive
+    Zip,
 }
 
-/// Parse month parameter
-fn parse_month(month: &str) -> Option<i32> {
-    month.parse().ok().filter(|mo| *mo >= 1 && *mo <= 12)
+/// Query parameters for listing requests
+#[derive(Deserialize)]
+struct SourceParams {
+    /// Only list entries from one source
+    source: Option<Source>,
+    /// List entries as objects with their sources
+    sources: Option<bool>,
 }
 
-/// Parse day parameter
-fn parse_day(day: &str) -> Option<i32> {
-    day.parse().ok().filter(|da| *da >= 1 && *da <= 31)
+/// Listed entry with its sources (JSON)
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct SourceEntry {
+    name: String,
+    sources: Vec<Source>,
 }
 
-/// Check if a date is valid
-fn is_valid_date(date: &str) -> bool {
-    date.len() == 8
-        && parse_year(&date[..4]).is_some()
-        && parse_month(&date[4..6]).is_some()
-        && parse_day(&date[6..8]).is_some()
+/// Entries listed from each source
+#[derive(Default)]
+struct Listing {
+    /// Entries in date directory
+    dir: Vec<String>,
+    /// Entries in date zip archive
+    zip: Vec<String>,
 }
 
-/// Check if year and date are valid
-fn is_valid_year_date(year: &str, date: &str) -> bool {
-    parse_year(year).is_some() && is_valid_date(date)
+impl SourceParams {
+    /// Get parameters from a query string
+    fn from_query(query: &str) -> Result<Self, Error> {
+        web::Query::<SourceParams>::from_query(query)
+            .map(web::Query::into_inner)
+            .map_err(|e| Error::InvalidParam(e.to_string()))
+    }
+}
+
+impl Listing {
+    /// Get sorted entries with their sources, optionally from one source
+    fn entries(&self, source: Option<Source>) -> Vec<SourceEntry> {
+        let mut names: Vec<&String> = match source {
+            Some(Source::Dir) => self.dir.iter().collect(),
+            Some(Source::Zip) => self.zip.iter().collect(),
+            None => self.dir.iter().chain(&self.zip).collect(),
+        };
+        names.sort();
+        names.dedup();
+        // sets, since zip64 archives can have many entries
+        let dir: HashSet<&String> = self.dir.iter().collect();
+        let zip: HashSet<&String> = self.zip.iter().collect();
+        names
+            .into_iter()
+            .map(|name| {
+                let mut sources = vec![];
+                if dir.contains(name) {
+                    sources.push(Source::Dir);
+                }
+                if zip.contains(name) {
+                    sources.push(Source::Zip);
+                }
+                SourceEntry {
+                    name: name.clone(),
+                    sources,
+                }
+            })
+            .collect()
+    }
+
+    /// Build a listing response
+    fn response(&self, params: &SourceParams) -> Result<HttpResponse, Error> {
+        let entries = self.entries(params.source);
+        if params.sources.unwrap_or(false) {
+            if entries.is_empty() {
+                return Err(Error::NotFound);
+            }
+            return Ok(json_response(serde_json::to_string(&entries)?));
+        }
+        let names = entries.into_iter().map(|e| e.name).collect();
+        Ok(json_response(build_json(names)?))
+    }
 }
 
 /// Handle request for dates in a year
-fn handle_dates_text(district: &str, year: &str) -> Option<String> {
-    let mut dates = lookup_dates(district, year);
-    if dates.len() > 0 {
+fn handle_dates_text(
+    state: &AppState,
+    district: &str,
+    year: &str,
+) -> Result<String, Error> {
+    let mut dates = lookup_dates(state, district, year);
+    if !dates.is_empty() {
         dates.sort();
         let mut res = String::new();
         for date in dates {
             res.push_str(&date);
             res.push('\n');
         }
-        Some(res)
+        Ok(res)
     } else {
-        None
+        Err(Error::NotFound)
     }
 }
 
 /// Lookup all sampled dates in a year
-fn lookup_dates(district: &str, year: &str) -> Vec<String> {
+pub fn lookup_dates(
+    state: &AppState,
+    district: &str,
+    year: &str,
+) -> Vec<String> {
     let lister = DateLister {};
-    let mut path = PathBuf::from(BASE_PATH);
-    path.push(district);
-    path.push(year);
+    let path = state.storage.year_path(district, year);
     // FIXME: use streaming from a separate thread
-    lister.list_dir(&path)
+    let mut dates = lister.list_dir(&path);
+    dates.extend(year_archive_dates(state, district, year).unwrap_or_default());
+    // without year directories, all dates are in one directory
+    dates.retain(|date| date.starts_with(year));
+    dates.sort();
+    dates.dedup();
+    dates
 }
 
-/// Handle request for /did/year (plain text)
-fn handle_did_year(district: &str, year: &str) -> Option<HttpResponse> {
-    parse_year(year)
-        .and_then(|_| handle_dates_text(district, year))
-        .and_then(|d| {
-            Some(HttpResponse::Ok().content_type("text/plain").body(d))
+/// List dates with entries in a consolidated year archive
+pub fn year_archive_dates(
+    state: &AppState,
+    district: &str,
+    year: &str,
+) -> Result<Vec<String>, Error> {
+    let path = state.storage.year_archive_path(district, year);
+    let zip = match state.zips.get(&state.metrics, &path) {
+        Ok(Some(zip)) => zip,
+        Ok(None) => return Ok(vec![]),
+        Err(e) => return Err(corrupt_archive(&state.metrics, &path, e)),
+    };
+    let zip = zip.lock().unwrap();
+    let dates: BTreeSet<&str> = zip
+        .file_names()
+        .filter_map(|name| name.split_once('/'))
+        .map(|(date, _)| date)
+        .filter(|date| is_valid_date(date))
+        .collect();
+    Ok(dates.into_iter().map(String::from).collect())
+}
+
+/// Lookup all sampled years of a district
+fn lookup_years(state: &AppState, district: &str) -> Vec<String> {
+    let path = state.storage.district_path(district);
+    match state.storage.layout(district) {
+        Layout::Yearly => {
+            let mut years = YearLister {}.list_dir(&path);
+            years.sort();
+            years.dedup();
+            years
+        }
+        Layout::Flat => {
+            // year archives, and the years of dates
+            let mut years: Vec<String> = DateLister {}
+                .list_dir(&path)
+                .into_iter()
+                .map(|date| date[..4].to_string())
+                .chain(YearLister {}.list_dir(&path))
+                .collect();
+            years.sort();
+            years.dedup();
+            years
+        }
+    }
+}
+
+/// Handle request for /did/years.json
+fn handle_did_years(
+    state: &AppState,
+    district: &str,
+) -> Result<HttpResponse, Error> {
+    let mut years = lookup_years(state, district);
+    if years.is_empty() {
+        return Err(Error::NotFound);
+    }
+    years.sort();
+    let years: Vec<YearDates> = years
+        .into_iter()
+        .map(|year| {
+            let mut dates = lookup_dates(state, district, &year);
+            dates.sort();
+            dates.dedup();
+            YearDates {
+                year,
+                dates: dates.len(),
+            }
         })
+        .collect();
+    Ok(json_response(serde_json::to_string(&years)?))
+}
+
+/// Handle request for /did/year (plain text)
+fn handle_did_year(
+    state: &AppState,
+    district: &str,
+    year: &str,
+) -> Result<HttpResponse, Error> {
+    let dates = handle_dates_text(state, district, year)?;
+    Ok(HttpResponse::Ok().content_type("text/plain").body(dates))
 }
 
 /// Handle request for /did/date (JSON)
-fn handle_did_date(district: &str, date: &str) -> Option<HttpResponse> {
-    if is_valid_date(date) {
-        json_response(build_json(lookup_sensors(district, date)))
-    } else {
-        None
+fn handle_did_date(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = SourceParams::from_query(query)?;
+    let listing = list_archived(state, district, date);
+    let renames = RenameMap::load(&state.storage.district_path(district))?;
+    let current = |sids: Vec<String>| -> Vec<String> {
+        sids.iter().map(|sid| renames.current(sid, date)).collect()
+    };
+    let listing = Listing {
+        dir: current(listing.dir),
+        zip: current(listing.zip),
+    };
+    listing.response(&params)
+}
+
+/// Handle request for a whole day's zip archive
+fn handle_did_date_archive(
+    state: &AppState,
+    req: &HttpRequest,
+    district: &str,
+    date: &str,
+) -> Result<HttpResponse, Error> {
+    signing::check_request(state, req)?;
+    let mut path = state.storage.date_path(district, date);
+    path.set_extension(EXT);
+    let accept = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok());
+    if multipart::accepts_multipart(accept) {
+        return handle_archive_parts(state, &path);
     }
+    let file = match NamedFile::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NotFound)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let zip = "application/zip".parse().unwrap();
+    Ok(file.set_content_type(zip).into_response(req))
 }
 
-/// Lookup sampled sensors for one date
-fn lookup_sensors(district: &str, date: &str) -> Vec<String> {
-    let mut path = PathBuf::from(BASE_PATH);
-    path.push(district);
-    path.push(&date[..4]);
-    path.push(date);
+/// Handle request for a zip archive's entries as a multipart response.
+///
+/// Responds with `413 Payload Too Large` when the entries total more than
+/// `max_parts_size`.
+fn handle_archive_parts(
+    state: &AppState,
+    path: &Path,
+) -> Result<HttpResponse, Error> {
+    let zip = match state.zips.get(&state.metrics, path) {
+        Ok(Some(zip)) => zip,
+        Ok(None) => return Err(Error::NotFound),
+        Err(e) => return Err(corrupt_archive(&state.metrics, path, e)),
+    };
+    let mut zip = zip.lock().unwrap();
+    let mut parts = Multipart::default();
+    let mut total = 0u64;
+    for i in 0..zip.len() {
+        let mut zf = zip
+            .by_index(i)
+            .map_err(|e| corrupt_archive(&state.metrics, path, e))?;
+        if !zf.is_file() {
+            continue;
+        }
+        let name = match entry_file_name(zf.name(), zf.name_raw()) {
+            Ok(name) => name.to_string(),
+            Err(_) => continue,
+        };
+        check_entry_size(state, path, &name, zf.size())?;
+        total = total.saturating_add(zf.size());
+        if total > state.config.max_parts_size {
+            let msg = format!("{} ({} bytes)", path.display(), total);
+            return Err(Error::TooLarge(msg));
+        }
+        let mut data = Vec::with_capacity(zf.size() as usize);
+        let size = zf.size();
+        zf.by_ref()
+            .take(size)
+            .read_to_end(&mut data)
+            .map_err(|e| corrupt_archive(&state.metrics, path, e.into()))?;
+        parts.push("application/octet-stream", &name, data);
+    }
+    Ok(parts.into_response(&mut HttpResponse::Ok()))
+}
+
+/// Lookup sensors archived on one date, by source
+fn list_archived(state: &AppState, district: &str, date: &str) -> Listing {
+    let path = state.storage.date_path(district, date);
     // FIXME: use streaming from a separate thread
-    let lister = SidLister {};
-    let mut sensors = lister.list_dir(&path);
-    path.set_extension(EXT);
-    sensors.extend(lister.list_zip(&path));
-    sensors
+    let lister = SidLister {
+        periods: &state.config.sample_periods,
+    };
+    let dir = lister.list_dir(&path);
+    let zip = date_zips(state, district, date)
+        .iter()
+        .flat_map(|zip| lister.list_zip(state, zip))
+        .collect();
+    Listing { dir, zip }
+}
+
+/// Lookup sensors archived on one date (without resolving renames)
+pub fn lookup_archived(
+    state: &AppState,
+    district: &str,
+    date: &str,
+) -> Vec<String> {
+    let Listing { mut dir, zip } = list_archived(state, district, date);
+    dir.extend(zip);
+    dir
+}
+
+/// Lookup sampled sensors for one date
+pub fn lookup_sensors(
+    state: &AppState,
+    district: &str,
+    date: &str,
+) -> Result<Vec<String>, Error> {
+    let sensors = lookup_archived(state, district, date);
+    let renames = RenameMap::load(&state.storage.district_path(district))?;
+    let mut sensors: Vec<String> = sensors
+        .iter()
+        .map(|sid| renames.current(sid, date))
+        .collect();
+    sensors.sort();
+    sensors.dedup();
+    Ok(sensors)
 }
 
-/// Check a sample file extension
-fn sample_file_ext(ext: &str) -> Option<&str> {
+/// Check a sample file extension, with valid sample periods
+pub fn sample_file_ext<'e>(ext: &'e str, periods: &[u32]) -> Option<&'e str> {
     if ext == "vlog" {
         return Some(ext);
     }
     if let Some((prefix, _)) = sample_type(ext) {
-        if let Some((suffix, _)) = sample_period(ext) {
-            if prefix.len() + suffix.len() == ext.len() {
+        if let Some((suffix, len)) = sample_period(ext) {
+            let period = (DAY_SECS / len) as u32;
+            if prefix.len() + suffix.len() == ext.len()
+                && periods.contains(&period)
+            {
                 return Some(ext);
             }
         }
@@ -324,7 +680,7 @@ fn sample_file_ext(ext: &str) -> Option<&str> {
 }
 
 /// Get sample type prefix and length for an extension
-fn sample_type(ext: &str) -> Option<(&str, u64)> {
+pub fn sample_type(ext: &str) -> Option<(&str, u64)> {
     for (prefix, len) in SAMPLE_TYPES {
         if ext.starts_with(prefix) {
             return Some((prefix, *len));
@@ -333,251 +689,1111 @@ fn sample_type(ext: &str) -> Option<(&str, u64)> {
     None
 }
 
-/// Get sample period suffix and length for an extension
-fn sample_period(ext: &str) -> Option<(&str, u64)> {
-    for (suffix, len) in SAMPLE_PERIODS {
-        if ext.ends_with(suffix) {
-            return Some((suffix, *len));
+/// Get scale of decoded values for a sample type prefix
+pub fn sample_scale(prefix: &str) -> f64 {
+    match prefix {
+        "o" => 0.01, // hundredths of a percent
+        _ => 1.0,
+    }
+}
+
+/// Get method for combining samples of a type prefix when rebinning
+fn sample_combine(prefix: &str, mean: Mean) -> Option<Combine> {
+    match prefix {
+        "vmc" | "vs" | "vm" | "vl" | "v" | "c" => Some(Combine::Sum),
+        "s" => Some(mean.combine()),
+        "o" | "pr" => Some(Combine::Mean),
+        _ => None,
+    }
+}
+
+/// Get sample period suffix and length for an extension.
+///
+/// The period (seconds) must evenly divide a day.
+pub fn sample_period(ext: &str) -> Option<(&str, u64)> {
+    let start = ext
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    let suffix = &ext[start..];
+    if suffix.starts_with('0') {
+        return None;
+    }
+    let period: u64 = suffix.parse().ok()?;
+    (period > 0 && DAY_SECS.is_multiple_of(period))
+        .then(|| (suffix, DAY_SECS / period))
+}
+
+/// Get sample type prefix and bytes per sample of an archived (traffic or
+/// weather) binned sample file extension
+pub fn archived_type(ext: &str) -> Option<(&str, u64)> {
+    let (suffix, _) = sample_period(ext)?;
+    match sample_type(ext) {
+        Some((prefix, len)) if prefix.len() + suffix.len() == ext.len() => {
+            Some((prefix, len))
         }
+        _ => weather::weather_type(ext),
     }
-    None
+}
+
+/// Get bytes per sample of a traffic or weather sample type prefix
+pub fn prefix_bytes(prefix: &str) -> Option<u64> {
+    SAMPLE_TYPES
+        .iter()
+        .chain(weather::WEATHER_TYPES)
+        .find(|(p, _)| *p == prefix)
+        .map(|(_, len)| *len)
+}
+
+/// Get expected length of a binned sample file with extension
+fn sample_len(ext: &str) -> Option<u64> {
+    let (_, tlen) = archived_type(ext)?;
+    let (_, plen) = sample_period(ext)?;
+    Some(tlen * plen)
 }
 
 /// Check length of a sample file with extension
 fn is_valid_sample_len(ext: &str, len: u64) -> bool {
-    if ext == "vlog" {
-        return true;
+    ext == "vlog" || sample_len(ext) == Some(len)
+}
+
+/// Lookup all sampled dates in a year (JSON).
+///
+/// In legacy mode, dates are sorted without duplicates.
+fn lookup_dates_json(
+    state: &AppState,
+    district: &str,
+    year: &str,
+) -> Result<HttpResponse, Error> {
+    let mut dates = lookup_dates(state, district, year);
+    if state.config.legacy {
+        dates.sort();
+        dates.dedup();
     }
-    if let Some((prefix, tlen)) = sample_type(ext) {
-        if let Some((suffix, plen)) = sample_period(ext) {
-            if prefix.len() + suffix.len() == ext.len() {
-                return (tlen * plen) == len;
-            }
+    Ok(json_response(build_json(dates)?))
+}
+
+/// Handle request for derived (decoded) data
+fn handle_did_date_derived(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    kind: Derived,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    match kind {
+        Derived::Anomalies => {
+            anomaly::handle_anomalies(state, district, date, sid)
+        }
+        Derived::Baseline => {
+            baseline::handle_baseline(state, district, date, sid, query)
+        }
+        Derived::Classes => vclass::handle_classes(state, district, date, sid),
+        Derived::Headway => {
+            headway::handle_headway(state, district, date, sid, query)
+        }
+        Derived::SpeedHist => {
+            speed::handle_speed_hist(state, district, date, sid, query)
+        }
+    }
+}
+
+/// Handle batch request (POST body) with three parameters
+pub fn handle_3_params_batch(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    p3: &str,
+    req: &AlignRequest,
+) -> Result<HttpResponse, Error> {
+    match classify(state, Shape::ThreeJson, &[p1, p2, p3])? {
+        Route::Aligned(did, date) => {
+            align::handle_aligned_batch(state, did.as_str(), date.as_str(), req)
         }
+        _ => Err(Error::NotFound),
     }
-    false
 }
 
-/// Handle request for sensors sampled on a date
-fn handle_did_year_date(
+/// Query parameters for sample requests
+#[derive(Deserialize)]
+struct SampleParams {
+    /// Decode scan counts to occupancy (percent)
+    occupancy: Option<bool>,
+    /// Only read from one source
+    source: Option<Source>,
+    /// Resampling interval (e.g. `7m30s`)
+    resample: Option<String>,
+    /// Method for combining resampled samples
+    agg: Option<Combine>,
+    /// Mean for rebinning speeds
+    #[serde(default)]
+    mean: Mean,
+}
+
+/// Resample decoded samples to an interval
+fn resample_series(
+    series: &SampleSeries,
+    prefix: &str,
+    interval: &str,
+    agg: Option<Combine>,
+    mean: Mean,
+) -> Result<SampleSeries, Error> {
+    let bad = || Error::InvalidParam(format!("resample: {}", interval));
+    let period = parse_interval(interval).ok_or_else(bad)?;
+    let combine =
+        agg.or_else(|| sample_combine(prefix, mean))
+            .ok_or_else(|| {
+                Error::InvalidParam(format!("agg required for {}", prefix))
+            })?;
+    series.rebin(period, combine).ok_or_else(bad)
+}
+
+/// Handle request for sampled data
+fn handle_did_date_sid_ext(
+    state: &AppState,
     district: &str,
-    year: &str,
     date: &str,
-) -> Option<HttpResponse> {
-    if is_valid_year_date(year, date) {
-        if &date[..4] == year {
-            handle_did_date(district, date)
+    sid: &str,
+    ext: &str,
+    format: &dyn OutputFormat,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    if sample_file_ext(ext, &state.config.sample_periods).is_none() {
+        return Err(Error::NotFound);
+    }
+    let params = web::Query::<SampleParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let file = read_sample_from(
+        state,
+        district,
+        date,
+        sid,
+        ext,
+        params.source,
+        params.mean,
+    )?
+    .ok_or(Error::NotFound)?;
+    let mut raw = file.data;
+    // vehicle logs are not binned
+    let typ = sample_period(ext).and(sample_type(ext));
+    let mut series = typ.map(|(prefix, bytes)| {
+        decode_series(&raw, bytes, state.config.marker(prefix))
+    });
+    match (&params.resample, typ, &series) {
+        (Some(interval), Some((prefix, bytes)), Some(fine)) => {
+            let marker = state.config.marker(prefix);
+            let coarse = resample_series(
+                fine,
+                prefix,
+                interval,
+                params.agg,
+                params.mean,
+            )?;
+            raw = coarse.encode_marked(bytes, marker);
+            series = Some(coarse);
+        }
+        (Some(_), _, _) => {
+            return Err(Error::InvalidParam(format!("resample: {}", ext)))
+        }
+        (None, _, _) if params.agg.is_some() => {
+            return Err(Error::InvalidParam("agg requires resample".into()))
+        }
+        _ => (),
+    }
+    let mut scale = typ.map_or(1.0, |(prefix, _)| sample_scale(prefix));
+    let occupancy = params.occupancy.unwrap_or(false);
+    if occupancy {
+        match (typ, &series) {
+            (Some(("c", _)), Some(series)) => {
+                let controller = metro::detector_controller(state, date, sid);
+                let rate = state.config.scan_rate(controller.as_deref());
+                scale = 100.0 / f64::from(rate * series.period());
+            }
+            _ => {
+                return Err(Error::InvalidParam(format!("occupancy: {}", ext)))
+            }
+        }
+    }
+    let data = SampleData {
+        state,
+        district,
+        date,
+        sid,
+        ext,
+        raw: &raw,
+        series: series.as_ref(),
+        scale,
+        occupancy,
+        query,
+    };
+    let mut res = HttpResponse::Ok();
+    res.content_type(format.content_type());
+    if let Some(warning) = file.warning {
+        res.insert_header(("X-Trafdat-Warning", warning));
+    }
+    Ok(res.body(encode_output(format, &data)?))
+}
+
+/// Encode sample data in an output format
+pub fn encode_output(
+    format: &dyn OutputFormat,
+    data: &SampleData,
+) -> Result<Vec<u8>, Error> {
+    timing::time(Stage::Encode, || format.encode(data))
+}
+
+/// Get output format for a sample request
+pub fn sample_format<'s>(
+    state: &'s AppState,
+    output: Output,
+    accept: Option<&str>,
+) -> Result<&'s dyn OutputFormat, Error> {
+    let formats = &state.formats;
+    let format = match output {
+        Output::Json => formats.by_name("json"),
+        Output::Named(name) => formats.by_name(name),
+        Output::Raw => accept
+            .and_then(|accept| formats.by_accept(accept))
+            .or_else(|| formats.by_name("octet")),
+    };
+    format.ok_or(Error::NotFound)
+}
+
+/// Sampled data read from an archive
+struct SampleFile {
+    /// Sample data
+    data: Vec<u8>,
+    /// Warning for a length-mismatched file (lenient mode)
+    warning: Option<String>,
+}
+
+impl SampleFile {
+    /// Create sample data from a valid file
+    fn valid(data: Vec<u8>) -> Self {
+        SampleFile {
+            data,
+            warning: None,
+        }
+    }
+
+    /// Create sample data from a length-mismatched file.
+    ///
+    /// Short data is padded with missing samples (-1).
+    fn mismatched(
+        state: &AppState,
+        sid: &str,
+        ext: &str,
+        mut data: Vec<u8>,
+    ) -> Self {
+        let expected = sample_len(ext).unwrap_or(0) as usize;
+        let warning = if data.len() < expected {
+            format!("{}.{}: short file ({} bytes)", sid, ext, data.len())
         } else {
-            Some(bad_request())
+            format!("{}.{}: long file (truncated)", sid, ext)
+        };
+        warn!("length mismatch: {}", warning);
+        state.metrics.length_mismatch();
+        data.resize(expected, 0xFF);
+        SampleFile {
+            data,
+            warning: Some(warning),
         }
-    } else {
-        None
     }
 }
 
-/// Handle bad request
-fn bad_request() -> HttpResponse {
-    HttpResponse::BadRequest().body("Bad request")
+/// Read sampled data for a sensor on a date (resolving renames).
+///
+/// When no data is stored for the extension, it is rebinned from a finer
+/// sample period if possible.
+pub fn read_sample(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    Ok(read_sample_from(
+        state,
+        district,
+        date,
+        sid,
+        ext,
+        None,
+        Mean::default(),
+    )?
+    .map(|file| file.data))
 }
 
-/// Handle request for sampled dates /did/year (JSON)
-fn handle_did_year_json(district: &str, year: &str) -> Option<HttpResponse> {
-    parse_year(year).and_then(|_| lookup_dates_json(district, year))
+/// Read sampled data for a sensor on a date, optionally from one source.
+///
+/// Rebinned speeds are combined with `mean`.
+fn read_sample_from(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+    source: Option<Source>,
+    mean: Mean,
+) -> Result<Option<SampleFile>, Error> {
+    for id in resolve_ids(state, district, date, sid)? {
+        if let Some(data) =
+            read_date_sid_ext(state, district, date, &id, ext, source)?
+        {
+            return Ok(Some(data));
+        }
+        if state.config.legacy {
+            continue;
+        }
+        if let Some(data) =
+            read_rebinned(state, district, date, &id, ext, source, mean)?
+        {
+            return Ok(Some(data));
+        }
+        if state.config.class_volume {
+            if let Some(data) =
+                read_class_volume(state, district, date, &id, ext, source)?
+            {
+                return Ok(Some(data));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Read volume synthesized by summing length class counts.
+///
+/// Every length class must be archived (or rebinned), since a total missing
+/// one class would undercount.  Intervals are missing if any class is
+/// missing.
+fn read_class_volume(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+    source: Option<Source>,
+) -> Result<Option<SampleFile>, Error> {
+    let period = match ext.strip_prefix('v') {
+        Some(p) if !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()) => p,
+        _ => return Ok(None),
+    };
+    let mut volume: Option<Vec<Option<i32>>> = None;
+    let mut warning = None;
+    for (prefix, _) in LENGTH_CLASSES {
+        let cext = format!("{}{}", prefix, period);
+        let file =
+            match read_date_sid_ext(state, district, date, sid, &cext, source)?
+            {
+                Some(file) => Some(file),
+                None => read_rebinned(
+                    state,
+                    district,
+                    date,
+                    sid,
+                    &cext,
+                    source,
+                    Mean::default(),
+                )?,
+            };
+        let file = match file {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let series = decode_series(&file.data, 1, state.config.marker(prefix));
+        volume = Some(match volume {
+            Some(vol) => vol
+                .iter()
+                .zip(series.values())
+                .map(|(a, b)| Some((*a)? + (*b)?))
+                .collect(),
+            None => series.values().to_vec(),
+        });
+        warning = warning.or(file.warning);
+    }
+    Ok(volume.map(|vol| SampleFile {
+        data: SampleSeries::from_values(vol)
+            .encode_marked(1, state.config.marker("v")),
+        warning,
+    }))
 }
 
-/// Lookup all sampled dates in a year (JSON)
-fn lookup_dates_json(district: &str, year: &str) -> Option<HttpResponse> {
-    json_response(build_json(lookup_dates(district, year)))
+/// Resolve archived IDs of a sensor on a date (following renames)
+fn resolve_ids(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+) -> Result<Vec<String>, Error> {
+    timing::time(Stage::Resolve, || {
+        let renames = RenameMap::load(&state.storage.district_path(district))?;
+        Ok(renames.resolve(sid, date))
+    })
 }
 
-/// Handle request for sampled data
-fn handle_did_date_sidext<B>(
+/// Read stored sampled data for a sensor on a date (without rebinning)
+fn read_stored(
+    state: &AppState,
     district: &str,
     date: &str,
-    sid_ext: &str,
-) -> Option<HttpResponse>
-where
-    B: ResponseBuilder,
-{
-    let mut sp = sid_ext.splitn(2, '.');
-    if let Some(sid) = sp.next() {
-        if let Some(ext) = sp.next() {
-            return handle_did_date_sid_ext::<B>(district, date, sid, ext);
+    sid: &str,
+    ext: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    for id in resolve_ids(state, district, date, sid)? {
+        if let Some(data) = read_archived(state, district, date, &id, ext)? {
+            return Ok(Some(data));
         }
     }
-    None
+    Ok(None)
 }
 
-/// Handle request for sampled data
-fn handle_did_date_sid_ext<B>(
+/// Read sampled data rebinned from the coarsest finer sample period
+fn read_rebinned(
+    state: &AppState,
     district: &str,
     date: &str,
     sid: &str,
     ext: &str,
-) -> Option<HttpResponse>
-where
-    B: ResponseBuilder,
-{
-    if is_valid_date(date) && sample_file_ext(ext).is_some() {
-        let mut path = PathBuf::from(BASE_PATH);
-        path.push(district);
-        path.push(&date[..4]);
-        path.push(date);
-        B::build(read_path_sid_ext(&mut path, sid, ext))
-    } else {
-        None
+    source: Option<Source>,
+    mean: Mean,
+) -> Result<Option<SampleFile>, Error> {
+    let (prefix, bytes) = match sample_type(ext) {
+        Some(typ) => typ,
+        None => return Ok(None),
+    };
+    let (len, combine) =
+        match (sample_period(ext), sample_combine(prefix, mean)) {
+            (Some((suffix, len)), Some(combine))
+                if prefix.len() + suffix.len() == ext.len() =>
+            {
+                (len, combine)
+            }
+            _ => return Ok(None),
+        };
+    let period = (DAY_SECS / len) as u32;
+    let mut finer: Vec<u32> = state
+        .config
+        .sample_periods
+        .iter()
+        .copied()
+        .filter(|p| *p < period && period.is_multiple_of(*p))
+        .collect();
+    finer.sort_unstable_by(|a, b| b.cmp(a));
+    for fine in finer {
+        let fext = format!("{}{}", prefix, fine);
+        if let Some(file) =
+            read_date_sid_ext(state, district, date, sid, &fext, source)?
+        {
+            let marker = state.config.marker(prefix);
+            let series = decode_series(&file.data, bytes, marker);
+            return Ok(series.rebin(period, combine).map(|s| SampleFile {
+                data: s.encode_marked(bytes, marker),
+                warning: file.warning,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Read sampled data archived for a sensor ID (without resolving renames)
+pub fn read_archived(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    Ok(read_date_sid_ext(state, district, date, sid, ext, None)?
+        .map(|file| file.data))
+}
+
+/// Decode sampled data
+pub fn decode_series(data: &[u8], bytes: u64, marker: Marker) -> SampleSeries {
+    timing::time(Stage::Decode, || {
+        SampleSeries::decode_marked(data, bytes, marker)
+    })
+}
+
+/// Read and decode sampled data for a sensor on a date
+pub fn read_series(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<SampleSeries>, Error> {
+    match sample_type(ext) {
+        Some((prefix, bytes)) => {
+            let marker = state.config.marker(prefix);
+            Ok(read_sample(state, district, date, sid, ext)?
+                .map(|data| decode_series(&data, bytes, marker)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Read and decode stored sampled data for a sensor (without rebinning)
+pub fn read_stored_series(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<SampleSeries>, Error> {
+    match sample_type(ext) {
+        Some((prefix, bytes)) => {
+            let marker = state.config.marker(prefix);
+            Ok(read_stored(state, district, date, sid, ext)?
+                .map(|data| decode_series(&data, bytes, marker)))
+        }
+        None => Ok(None),
     }
 }
 
-/// Read sampled data from a path
-fn read_path_sid_ext(
-    path: &mut PathBuf,
+/// Zip archive which may contain entries for a date
+struct DateZip {
+    /// Archive path
+    path: PathBuf,
+    /// Prefix of entry names for the date
+    prefix: String,
+}
+
+/// Get zip archives for a date: the date's archive, then the consolidated
+/// archive of its year (with entries prefixed by `{date}/`)
+fn date_zips(state: &AppState, district: &str, date: &str) -> [DateZip; 2] {
+    let mut path = state.storage.date_path(district, date);
+    path.set_extension(EXT);
+    [
+        DateZip {
+            path,
+            prefix: String::new(),
+        },
+        DateZip {
+            path: state.storage.year_archive_path(district, &date[..4]),
+            prefix: format!("{}/", date),
+        },
+    ]
+}
+
+/// Read sampled data archived on a date, optionally from one source.
+///
+/// A valid file in the date directory (plain, or zstd-compressed) takes
+/// precedence over an entry in the date's zip archive, which takes
+/// precedence over the year archive.  In lenient mode, length-mismatched
+/// files are used when no valid one exists.
+fn read_date_sid_ext(
+    state: &AppState,
+    district: &str,
+    date: &str,
     sid: &str,
     ext: &str,
-) -> Option<Vec<u8>> {
+    source: Option<Source>,
+) -> Result<Option<SampleFile>, Error> {
+    let path = &mut state.storage.date_path(district, date);
     path.push(sid);
     path.set_extension(ext);
-    // FIXME: handle rebinning?
-    if let Ok(mut file) = File::open(&path) {
-        if let Ok(metadata) = file.metadata() {
-            let len = metadata.len();
-            if let Some(data) = read_sample_data(&mut file, ext, len) {
-                return Some(data);
+    let mut mismatch = None;
+    if source != Some(Source::Zip) {
+        match File::open(&path) {
+            Ok(mut file) => {
+                let len = file.metadata()?.len();
+                if is_valid_sample_len(ext, len) {
+                    let data = timing::time(Stage::EntryRead, || {
+                        read_sample_data(&mut file, len)
+                    })?;
+                    return Ok(Some(SampleFile::valid(data)));
+                }
+                if let Some(expected) = lenient_len(state, ext) {
+                    let data = timing::time(Stage::EntryRead, || {
+                        read_mismatched(&mut file, expected)
+                    })?;
+                    mismatch =
+                        Some(SampleFile::mismatched(state, sid, ext, data));
+                }
             }
-        }
-    } else {
-        path.pop(); // sid.ext
-        path.set_extension(EXT);
-        if let Ok(file) = File::open(path) {
-            if let Ok(mut zip) = ZipArchive::new(file) {
-                let name = format!("{}.{}", sid, ext);
-                if let Ok(mut zf) = zip.by_name(&name) {
-                    let len = zf.size();
-                    if let Some(data) = read_sample_data(&mut zf, ext, len) {
-                        return Some(data);
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match read_zst_file(state, path, sid, ext)? {
+                    Some(file) if file.warning.is_none() => {
+                        return Ok(Some(file))
                     }
+                    file => mismatch = file,
                 }
             }
+            Err(e) => return Err(e.into()),
         }
     }
-    // FIXME: open .vlog
-    None
+    if source != Some(Source::Dir) {
+        for zip in date_zips(state, district, date) {
+            match read_zip_entry(state, &zip, date, sid, ext)? {
+                Some(file) if file.warning.is_none() => return Ok(Some(file)),
+                Some(file) if mismatch.is_none() => mismatch = Some(file),
+                _ => (),
+            }
+        }
+    }
+    Ok(mismatch)
 }
 
-/// Read sampled data from a reader
-fn read_sample_data<R: Read>(
-    reader: &mut R,
+/// Read sampled data from a zstd-compressed file (`{sid}.{ext}.zst`)
+fn read_zst_file(
+    state: &AppState,
+    path: &Path,
+    sid: &str,
     ext: &str,
-    len: u64,
-) -> Option<Vec<u8>> {
-    if is_valid_sample_len(ext, len) {
-        let mut data = vec![0; len as usize];
-        if let Ok(()) = reader.read_exact(&mut data[..]) {
-            return Some(data);
-        }
+) -> Result<Option<SampleFile>, Error> {
+    let mut zst = path.as_os_str().to_owned();
+    zst.push(ZST);
+    let zst = PathBuf::from(zst);
+    let file = match File::open(&zst) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let max = state.config.max_entry_size;
+    let data = timing::time(Stage::EntryRead, || -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        zstd::Decoder::new(file)?
+            .take(max + 1)
+            .read_to_end(&mut data)?;
+        Ok(data)
+    })?;
+    let name = format!("{}.{}", sid, ext);
+    check_entry_size(state, &zst, &name, data.len() as u64)?;
+    if is_valid_sample_len(ext, data.len() as u64) {
+        Ok(Some(SampleFile::valid(data)))
+    } else if lenient_len(state, ext).is_some() {
+        Ok(Some(SampleFile::mismatched(state, sid, ext, data)))
+    } else {
+        Ok(None)
     }
-    None
 }
 
-/// Handle request for sampled extensions
-fn handle_did_date_sid(
-    district: &str,
+/// Get expected length for lenient handling of a sample file extension
+fn lenient_len(state: &AppState, ext: &str) -> Option<u64> {
+    match state.config.length_check {
+        LengthCheck::Lenient if !state.config.legacy => sample_len(ext),
+        _ => None,
+    }
+}
+
+/// Read sampled data from a zip archive
+fn read_zip_entry(
+    state: &AppState,
+    date_zip: &DateZip,
     date: &str,
     sid: &str,
-) -> Option<HttpResponse> {
-    if is_valid_date(date) {
-        json_response(build_json(lookup_ext(district, date, sid)))
+    ext: &str,
+) -> Result<Option<SampleFile>, Error> {
+    let path = &date_zip.path;
+    let zip = match state.zips.get(&state.metrics, path) {
+        Ok(Some(zip)) => zip,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(corrupt_archive(&state.metrics, path, e)),
+    };
+    let names: Vec<String> = state
+        .config
+        .entry_patterns
+        .iter()
+        .map(|pat| {
+            let name = pat
+                .replace("{date}", date)
+                .replace("{sid}", sid)
+                .replace("{ext}", ext);
+            format!("{}{}", date_zip.prefix, name)
+        })
+        .collect();
+    // year archive entries must be within their date's directory
+    let basename = format!("{}.{}", sid, ext);
+    let basename = date_zip.prefix.is_empty().then_some(basename.as_str());
+    timing::time(Stage::EntryRead, || {
+        read_zip_file(state, path, &zip, &names, basename, sid, ext)
+    })
+}
+
+/// Read sampled data from an open zip archive.
+///
+/// Entry names are tried in order, then the base name (if any).
+fn read_zip_file(
+    state: &AppState,
+    path: &Path,
+    zip: &SharedZip,
+    names: &[String],
+    basename: Option<&str>,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<SampleFile>, Error> {
+    let mut zip = zip.lock().unwrap();
+    for name in names {
+        match zip.by_name(name) {
+            Ok(zf) => return read_zip_data(state, path, zf, name, sid, ext),
+            Err(ZipError::FileNotFound) => (),
+            Err(e) => return Err(corrupt_archive(&state.metrics, path, e)),
+        }
+    }
+    let name = match basename.and_then(|base| zip.by_basename(base)) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let res = match zip.by_name(&name) {
+        Ok(zf) => read_zip_data(state, path, zf, &name, sid, ext),
+        Err(ZipError::FileNotFound) => Ok(None),
+        Err(e) => Err(corrupt_archive(&state.metrics, path, e)),
+    };
+    res
+}
+
+/// Read sampled data from a zip entry
+fn read_zip_data(
+    state: &AppState,
+    path: &Path,
+    mut zf: ZipFile,
+    name: &str,
+    sid: &str,
+    ext: &str,
+) -> Result<Option<SampleFile>, Error> {
+    let len = zf.size();
+    check_entry_size(state, path, name, len)?;
+    if is_valid_sample_len(ext, len) {
+        let data = read_sample_data(&mut zf, len)
+            .map_err(|e| corrupt_archive(&state.metrics, path, e.into()))?;
+        Ok(Some(SampleFile::valid(data)))
+    } else if let Some(expected) = lenient_len(state, ext) {
+        let data = read_mismatched(&mut zf, expected)
+            .map_err(|e| corrupt_archive(&state.metrics, path, e.into()))?;
+        Ok(Some(SampleFile::mismatched(state, sid, ext, data)))
     } else {
-        None
+        Ok(None)
     }
 }
 
-/// Lookup sampled extensions for a sensor
-fn lookup_ext(district: &str, date: &str, sid: &str) -> Vec<String> {
-    let mut path = PathBuf::from(BASE_PATH);
-    path.push(district);
-    path.push(&date[..4]); // year
-    path.push(date);
-    let lister = ExtLister { sid };
-    let mut exts = lister.list_dir(&path);
-    path.set_extension(EXT);
-    exts.extend(lister.list_zip(&path));
-    exts
+/// Check that a zip entry is not larger than the configured maximum.
+///
+/// Sizes come from the central directory, and may be corrupt.
+fn check_entry_size(
+    state: &AppState,
+    path: &Path,
+    name: &str,
+    size: u64,
+) -> Result<(), Error> {
+    if size <= state.config.max_entry_size {
+        return Ok(());
+    }
+    warn!(
+        "entry too large: {}, {} ({} bytes)",
+        path.display(),
+        name,
+        size
+    );
+    state.metrics.corrupt_archive();
+    Err(Error::EntryTooLarge(format!("{} ({} bytes)", name, size)))
 }
 
-/// Handle request for sampled data
-fn handle_did_year_date_sidext<B>(
+/// Log and count a corrupt archive
+fn corrupt_archive(metrics: &Metrics, path: &Path, err: ZipError) -> Error {
+    warn!("corrupt archive: {}, {}", path.display(), err);
+    metrics.corrupt_archive();
+    Error::Zip(err)
+}
+
+/// Read sampled data from a reader.
+///
+/// The reader must contain exactly `len` bytes; reading to the end allows
+/// zip entries to verify their checksum.
+fn read_sample_data<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(len as usize);
+    reader.take(len + 1).read_to_end(&mut data)?;
+    if data.len() as u64 == len {
+        Ok(data)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "length mismatch",
+        ))
+    }
+}
+
+/// Read a length-mismatched sample file, up to an expected length
+fn read_mismatched<R: Read>(
+    reader: &mut R,
+    expected: u64,
+) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(expected as usize);
+    reader.take(expected).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Handle request for sampled extensions
+fn handle_did_date_sid(
+    state: &AppState,
     district: &str,
-    year: &str,
     date: &str,
-    sid_ext: &str,
-) -> Option<HttpResponse>
-where
-    B: ResponseBuilder,
-{
-    if is_valid_year_date(year, date) {
-        if &date[..4] == year {
-            handle_did_date_sidext::<B>(district, date, sid_ext)
-        } else {
-            Some(bad_request())
+    sid: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = SourceParams::from_query(query)?;
+    lookup_ext(state, district, date, sid)?.response(&params)
+}
+
+/// Lookup sampled extensions for a sensor, by source
+fn lookup_ext(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+) -> Result<Listing, Error> {
+    let renames = RenameMap::load(&state.storage.district_path(district))?;
+    let periods = state.config.listed_periods();
+    let mut listing = Listing::default();
+    for id in renames.resolve(sid, date) {
+        let path = state.storage.date_path(district, date);
+        let lister = ExtLister {
+            sid: &id,
+            periods: &periods,
+        };
+        listing.dir.extend(lister.list_dir(&path));
+        for zip in date_zips(state, district, date) {
+            listing.zip.extend(lister.list_zip(state, &zip));
         }
-    } else {
-        None
     }
+    Ok(listing)
 }
 
 /// Handle districts request
-pub fn handle_districts_json() -> Option<HttpResponse> {
+pub fn handle_districts_json(state: &AppState) -> Result<HttpResponse, Error> {
     let lister = DirLister {};
-    let path = PathBuf::from(BASE_PATH);
-    json_response(build_json(lister.list_dir(&path)))
+    let path = state.storage.base();
+    let mut districts = lister.list_dir(path);
+    for district in state.federation.districts() {
+        if !districts.iter().any(|d| d == district) {
+            districts.push(district.to_string());
+        }
+    }
+    Ok(json_response(build_json(districts)?))
+}
+
+/// Classify a sensor data request path
+fn classify<'a>(
+    state: &'a AppState,
+    shape: Shape,
+    params: &[&'a str],
+) -> Result<Route<'a>, Error> {
+    let is_district = |did: &str| state.storage.has_district(did);
+    let default = &state.config.district_default;
+    let route = route::classify(shape, params, default, &is_district)
+        .map_err(|e| legacy_error(state, e))?
+        .ok_or(Error::NotFound)?;
+    if state.config.legacy && !route.is_legacy() {
+        return Err(Error::NotFound);
+    }
+    Ok(route)
+}
+
+/// Map errors in legacy mode, where invalid requests are not found
+fn legacy_error(state: &AppState, err: Error) -> Error {
+    match err {
+        Error::InvalidParam(_) if state.config.legacy => Error::NotFound,
+        _ => err,
+    }
+}
+
+/// Handle a classified request.
+///
+/// In legacy mode, query parameters and `Accept` headers are ignored.
+fn handle_route(
+    state: &AppState,
+    route: Route,
+    query: &str,
+    accept: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    let (query, accept) = if state.config.legacy {
+        ("", None)
+    } else {
+        (query, accept)
+    };
+    dispatch_route(state, route, query, accept)
+        .map_err(|e| legacy_error(state, e))
+}
+
+/// Dispatch a classified request to its handler
+fn dispatch_route(
+    state: &AppState,
+    route: Route,
+    query: &str,
+    accept: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    match route {
+        Route::Years(did) => handle_did_years(state, did.as_str()),
+        Route::Annotations(did) => {
+            annotate::handle_annotations(state, did.as_str())
+        }
+        Route::CompleteDates(did) => {
+            avail::handle_complete_dates(state, did.as_str(), query)
+        }
+        Route::Aadt(did) => avail::handle_aadt(state, did.as_str(), query),
+        Route::Percentiles(did) => {
+            avail::handle_percentiles(state, did.as_str(), query)
+        }
+        Route::Dates(did, year, Output::Json) => {
+            lookup_dates_json(state, did.as_str(), year.as_str())
+        }
+        Route::Dates(did, year, _) => {
+            handle_did_year(state, did.as_str(), year.as_str())
+        }
+        Route::Sensors(did, date) => {
+            handle_did_date(state, did.as_str(), date.as_str(), query)
+        }
+        Route::Extensions(did, date, sid) => handle_did_date_sid(
+            state,
+            did.as_str(),
+            date.as_str(),
+            sid.as_str(),
+            query,
+        ),
+        Route::Sample(did, date, sid, ext, output) => handle_did_date_sid_ext(
+            state,
+            did.as_str(),
+            date.as_str(),
+            sid.as_str(),
+            ext,
+            sample_format(state, output, accept)?,
+            query,
+        ),
+        Route::SampleRange(did, sid, ext) => range::handle_sample_range(
+            state,
+            did.as_str(),
+            sid.as_str(),
+            ext,
+            query,
+            multipart::accepts_multipart(accept),
+        ),
+        Route::Locations(_did, sid) => {
+            swap::handle_locations(state, sid.as_str(), query)
+        }
+        Route::Derived(did, date, sid, kind) => handle_did_date_derived(
+            state,
+            did.as_str(),
+            date.as_str(),
+            sid.as_str(),
+            kind,
+            query,
+        ),
+        Route::Aligned(did, date) => {
+            align::handle_aligned(state, did.as_str(), date.as_str(), query)
+        }
+        Route::Analysis(did, date, Analysis::Balance) => {
+            balance::handle_balance(state, did.as_str(), date.as_str(), query)
+        }
+        Route::Analysis(did, date, Analysis::Vmt) => {
+            vmt::handle_vmt(state, did.as_str(), date.as_str(), query)
+        }
+        Route::Analysis(did, date, Analysis::Bottlenecks) => {
+            bottleneck::handle_bottlenecks(
+                state,
+                did.as_str(),
+                date.as_str(),
+                query,
+            )
+        }
+        // Archives need the full request (see `handle_2_params_traffic`)
+        Route::Archive(..) => Err(Error::NotFound),
+        // Checksums are listed on a blocking thread (see `checksums_params`)
+        Route::Checksums(..) => Err(Error::NotFound),
+    }
+}
+
+/// Get the district and year of a checksums request, if it is one
+pub fn checksums_params(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    p3: &str,
+) -> Option<(String, String)> {
+    match classify(state, Shape::ThreeJson, &[p1, p2, p3]) {
+        Ok(Route::Checksums(did, year)) => {
+            Some((did.as_str().to_string(), year.as_str().to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Check if a JSON request with three parameters is for derived data
+pub fn is_derived(state: &AppState, p1: &str, p2: &str, p3: &str) -> bool {
+    classify(state, Shape::ThreeJson, &[p1, p2, p3])
+        .is_ok_and(|route| route.is_derived())
 }
 
 /// Handle request with one parameter
-pub fn handle_1_param(year: &str) -> Option<HttpResponse> {
-    handle_did_year(DISTRICT_DEFAULT, year)
+pub fn handle_1_param(
+    state: &AppState,
+    p1: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    handle_route(state, classify(state, Shape::One, &[p1])?, query, None)
 }
 
 /// Handle JSON request with two parameters
-pub fn handle_2_params_json(p1: &str, p2: &str) -> Option<HttpResponse> {
-    handle_did_year_json(p1, p2)
+pub fn handle_2_params_json(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let route = classify(state, Shape::TwoJson, &[p1, p2])?;
+    handle_route(state, route, query, None)
+}
+
+/// Handle request with two parameters.
+///
+/// * `accept` Value of `Accept` header, to select a multipart response.
+pub fn handle_2_params(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    query: &str,
+    accept: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    let route = classify(state, Shape::Two, &[p1, p2])?;
+    handle_route(state, route, query, accept)
 }
 
-/// Handle request with two parameters
-pub fn handle_2_params(p1: &str, p2: &str) -> Option<HttpResponse> {
-    handle_did_date(p1, p2)
-        .or_else(|| handle_did_year_date(DISTRICT_DEFAULT, p1, p2))
-        .or_else(|| handle_did_year(p1, p2))
+/// Handle zip archive request with two parameters
+pub fn handle_2_params_traffic(
+    state: &AppState,
+    req: &HttpRequest,
+    p1: &str,
+    p2: &str,
+) -> Result<HttpResponse, Error> {
+    match classify(state, Shape::TwoTraffic, &[p1, p2])? {
+        Route::Archive(did, date) => {
+            handle_did_date_archive(state, req, did.as_str(), date.as_str())
+        }
+        _ => Err(Error::NotFound),
+    }
 }
 
 /// Handle JSON request with three parameters
 pub fn handle_3_params_json(
+    state: &AppState,
     p1: &str,
     p2: &str,
     p3: &str,
-) -> Option<HttpResponse> {
-    handle_did_date_sidext::<JsonOutput>(p1, p2, p3)
-        .or_else(|| handle_did_date_sid(p1, p2, p3))
-        .or_else(|| {
-            handle_did_year_date_sidext::<JsonOutput>(
-                DISTRICT_DEFAULT,
-                p1,
-                p2,
-                p3,
-            )
-        })
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let route = classify(state, Shape::ThreeJson, &[p1, p2, p3])?;
+    handle_route(state, route, query, None)
 }
 
-/// Handle request with three parameters
-pub fn handle_3_params(p1: &str, p2: &str, p3: &str) -> Option<HttpResponse> {
-    handle_did_date_sidext::<OctetStreamOutput>(p1, p2, p3)
-        .or_else(|| {
-            handle_did_year_date_sidext::<OctetStreamOutput>(
-                DISTRICT_DEFAULT,
-                p1,
-                p2,
-                p3,
-            )
-        })
-        .or_else(|| handle_did_year_date(p1, p2, p3))
+/// Handle request with three parameters.
+///
+/// * `accept` Value of `Accept` header, to select a sample output format.
+pub fn handle_3_params(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    p3: &str,
+    query: &str,
+    accept: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    let route = classify(state, Shape::Three, &[p1, p2, p3])?;
+    handle_route(state, route, query, accept)
 }
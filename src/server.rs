@@ -0,0 +1,597 @@
+// server.rs
+//
+// Copyright (c) 2019-2021  Minnesota Department of Transportation
+//
+use crate::admin;
+use crate::align::AlignRequest;
+use crate::apikey::{self, ApiKeys};
+use crate::assets;
+use crate::audit;
+use crate::deprecation;
+use crate::diskcache::DiskCache;
+use crate::error::Error;
+use crate::federation;
+use crate::health;
+use crate::jobs::{self, ExportSpec};
+use crate::locate;
+use crate::metrics;
+use crate::metro;
+use crate::notfound;
+use crate::prewarm;
+use crate::proxy::ClientInfo;
+use crate::report;
+use crate::robots;
+use crate::route::Shape;
+use crate::schema;
+use crate::sensor;
+use crate::spacing;
+use crate::spec;
+use crate::state::{AppState, Config};
+use crate::stats::{self, AccessStats};
+use crate::sync;
+use crate::timing;
+use crate::watch;
+use crate::weather;
+use actix_web::dev::Service;
+use actix_web::http::header::{ACCEPT, CONTENT_TYPE};
+use actix_web::middleware::Logger;
+use actix_web::{guard, web, App, HttpRequest, HttpResponse, HttpServer};
+use log::info;
+
+/// Run web server with a configuration
+pub async fn run_server(config: Config) -> Result<(), Error> {
+    let sock_addr = config.bind_addr.clone();
+    let prefix = config.url_prefix.clone();
+    let mut state = AppState::new(config);
+    for (district, layout) in state.storage.layouts() {
+        info!("{}: {:?} archive layout", district, layout);
+    }
+    if let Some(path) = &state.config.stats_path {
+        state.stats = AccessStats::load(path)?;
+    }
+    if let Some(path) = &state.config.api_keys_path {
+        state.api_keys = ApiKeys::load(path)?;
+    }
+    if let Some(path) = &state.config.proxy_cache_path {
+        let config = &state.config;
+        let cache = DiskCache::open(
+            path,
+            config.proxy_cache_size,
+            config.proxy_cache_age,
+        )?;
+        state.disk_cache = Some(cache);
+    }
+    let state = web::Data::new(state);
+    let resumed = jobs::resume(&state)?;
+    if resumed > 0 {
+        info!("resumed {} export jobs", resumed);
+    }
+    jobs::spawn_cleanup(state.clone());
+    // no local archive to pre-warm in proxy mode
+    if !state.config.hot_dates.is_empty() && !state.federation.is_proxy() {
+        prewarm::spawn(state.clone());
+    }
+    stats::spawn_persist(state.clone());
+    watch::spawn(state.clone());
+    report::spawn(state.clone());
+    let app_state = state.clone();
+    HttpServer::new(move || {
+        App::new()
+            .wrap(logger(app_state.clone()))
+            .app_data(app_state.clone())
+            .configure(|cfg| configure(cfg, &prefix))
+    })
+    .bind(sock_addr)?
+    .run()
+    .await?;
+    if let Some(path) = &state.config.stats_path {
+        state.stats.persist(path)?;
+    }
+    Ok(())
+}
+
+/// Create request logger, with client info resolved through proxies and
+/// API key identity
+fn logger(state: web::Data<AppState>) -> Logger {
+    let trusted = state.config.trusted_proxies.clone();
+    let trusted_origin = trusted.clone();
+    Logger::new("%{client}xi %{origin}xi %{key}xi \"%r\" %s %b %T")
+        .custom_request_replace("client", move |req| {
+            ClientInfo::from_request(req.request(), &trusted).addr_string()
+        })
+        .custom_request_replace("origin", move |req| {
+            let info = ClientInfo::from_request(req.request(), &trusted_origin);
+            format!("{}://{}", info.scheme, info.host)
+        })
+        .custom_request_replace("key", move |req| {
+            apikey::identity(&state, req.request())
+                .unwrap_or_else(|| "-".into())
+        })
+}
+
+/// Configure routes for the server, mounted at a URL prefix
+pub fn configure(cfg: &mut web::ServiceConfig, prefix: &str) {
+    cfg.route("/robots.txt", web::get().to(robots::handle_robots));
+    cfg.service(
+        web::scope(prefix)
+            .wrap_fn(|req, srv| {
+                let call = match apikey::check(&req)
+                    .and_then(|_| deprecation::check(&req))
+                    .and_then(|_| health::check(&req))
+                {
+                    Ok(()) => Ok(srv.call(req)),
+                    Err(e) => Err(req.error_response(e)),
+                };
+                async {
+                    match call {
+                        Ok(fut) => {
+                            let mut res = fut.await?;
+                            health::record(&res);
+                            stats::record(&res);
+                            admin::record(&res);
+                            robots::add_tag(&mut res);
+                            deprecation::add_headers(&mut res);
+                            Ok(res)
+                        }
+                        // unauthorized, or storage breaker is open
+                        Err(res) => Ok(res),
+                    }
+                }
+            })
+            .service(
+                web::resource("/{district}{tail:.*}")
+                    .guard(guard::fn_guard(federation::is_federated))
+                    .route(web::get().to(federation::handle_proxy)),
+            )
+            .route("/", web::to(assets::handle_index))
+            .route("/index.html", web::to(assets::handle_index))
+            .route("/browse.html", web::to(assets::handle_browse))
+            .route("/trafdat.css", web::to(assets::handle_css))
+            .route("/static/{name}", web::to(assets::handle_static))
+            .route("/districts", web::to(handle_districts))
+            .route("/metrics", web::to(metrics::handle_metrics))
+            .route("/healthz", web::to(health::handle_healthz))
+            .route("/admin", web::get().to(admin::handle_page))
+            .route("/admin/", web::get().to(admin::handle_page))
+            .route("/admin/status.json", web::get().to(admin::handle_status))
+            .route("/admin/flush", web::post().to(admin::handle_flush))
+            .route("/admin/stats.json", web::to(stats::handle_stats))
+            .route("/admin/audit.json", web::get().to(audit::handle_tail))
+            .route("/schema/{name}.json", web::get().to(schema::handle_schema))
+            .route("/spec.json", web::get().to(spec::handle_spec))
+            .route("/jobs/export", web::post().to(handle_job_export))
+            .route("/jobs", web::get().to(handle_jobs))
+            .service(
+                web::resource("/jobs/{id}")
+                    .route(web::get().to(handle_job))
+                    .route(web::delete().to(handle_job_cancel)),
+            )
+            .route("/jobs/{id}/result.sql", web::get().to(handle_job_result))
+            .route("/{p1}", web::to(handle_1))
+            .route("/metro_config/{p1}.json", web::to(handle_metro_1_json))
+            .route("/metro_config/{p1}.xml", web::to(handle_metro_1_xml))
+            .route("/metro_config/{p1}.xml.gz", web::to(handle_metro_1_xml_gz))
+            .route("/{p1}/{p2}.json", web::to(handle_2_json))
+            .route("/{p1}/{p2}.traffic", web::to(handle_2_traffic))
+            .route("/{p1}/{p2}", web::to(handle_2))
+            .route(
+                "/metro_config/{p1}/corridors",
+                web::to(handle_metro_corridors),
+            )
+            .route("/metro_config/{p1}/nodes.json", web::to(handle_metro_nodes))
+            .route("/metro_config/{p1}/graph.json", web::to(handle_metro_graph))
+            .route(
+                "/metro_config/{p1}/detectors.json",
+                web::to(handle_metro_detectors),
+            )
+            .route(
+                "/metro_config/{p1}/{p2}_{p3}.json",
+                web::to(handle_metro_3_json),
+            )
+            .route(
+                "/metro_config/{p1}/{p2}_{p3}.xml",
+                web::to(handle_metro_3_xml),
+            )
+            .route(
+                "/metro_config/{p1}/{p2}_{p3}.geojson",
+                web::to(handle_metro_3_geojson),
+            )
+            .route(
+                "/metro_config/{p1}/{p2}_{p3}/locate.json",
+                web::to(handle_metro_locate),
+            )
+            .route(
+                "/metro_config/{p1}/{p2}_{p3}/spacing.csv",
+                web::to(handle_metro_spacing),
+            )
+            .route("/weather/{p1}/{p2}", web::to(handle_weather_2))
+            .route(
+                "/weather/{p1}/{p2}/{p3}.json",
+                web::to(handle_weather_3_json),
+            )
+            .route("/weather/{p1}/{p2}/{p3}", web::to(handle_weather_3))
+            .route("/{p1}/{p2}/files/{name:.*}", web::get().to(handle_file))
+            .service(
+                web::resource("/{p1}/{p2}/{p3}.json")
+                    .route(web::post().to(handle_3_batch))
+                    .to(handle_3_json),
+            )
+            .route("/{p1}/{p2}/{p3}", web::to(handle_3)),
+    );
+    cfg.default_service(web::route().to(not_found));
+}
+
+/// Build a response, recording its pipeline stage timings.
+///
+/// A `Server-Timing` header is added if configured.
+fn timed<F>(state: &AppState, build: F) -> Result<HttpResponse, Error>
+where
+    F: FnOnce() -> Result<HttpResponse, Error>,
+{
+    let (res, timings) = timing::measure(build);
+    state.metrics.record_timings(&timings);
+    let mut res = res?;
+    if state.config.server_timing {
+        timings.add_header(&mut res);
+    }
+    Ok(res)
+}
+
+/// Get the `Accept` header value of a request
+fn accept(req: &HttpRequest) -> Option<&str> {
+    req.headers().get(ACCEPT).and_then(|v| v.to_str().ok())
+}
+
+/// Handle a request for districts
+async fn handle_districts(
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    sensor::handle_districts_json(&state)
+}
+
+/// Handle not found requests
+async fn not_found() -> Result<HttpResponse, Error> {
+    Err(Error::NotFound)
+}
+
+/// Handle a request with one parameter
+async fn handle_metro_1_xml(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    metro::handle_1_param_xml(&state, &path)
+}
+
+/// Handle a request for gzipped metro_config xml
+async fn handle_metro_1_xml_gz(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    metro::handle_1_param_xml_gz(&state, &req, &path)
+}
+
+/// Handle a request with one parameter
+async fn handle_metro_1_json(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    metro::handle_1_param_json(&state, &path)
+}
+
+/// Handle a request for the corridors on a date
+async fn handle_metro_corridors(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    metro::handle_corridors(&state, &path)
+}
+
+/// Handle a request for the nodes on a date
+async fn handle_metro_nodes(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    state.cache.get_or_insert(&req, || {
+        metro::handle_nodes(&state, &path, req.query_string())
+    })
+}
+
+/// Handle a request for the roadway graph on a date
+async fn handle_metro_graph(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    state
+        .cache
+        .get_or_insert(&req, || metro::handle_graph(&state, &path))
+}
+
+/// Handle a request for detectors joined to their controllers on a date
+async fn handle_metro_detectors(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    state.cache.get_or_insert(&req, || {
+        metro::handle_detectors(&state, &path, req.query_string())
+    })
+}
+
+/// Handle a request for metro_config xml with 2 parameters
+async fn handle_metro_3_xml(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    metro::handle_3_params_xml(&state, &p1, &p2, &p3, req.query_string())
+}
+
+/// Handle a request for metro_config json with 2 parameters
+async fn handle_metro_3_json(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    state.cache.get_or_insert(&req, || {
+        metro::handle_3_params_json(&state, &p1, &p2, &p3, req.query_string())
+    })
+}
+
+/// Handle a request for metro_config GeoJSON with 2 parameters
+async fn handle_metro_3_geojson(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    state.cache.get_or_insert(&req, || {
+        metro::handle_3_params_geojson(
+            &state,
+            &p1,
+            &p2,
+            &p3,
+            req.query_string(),
+        )
+    })
+}
+
+/// Handle a request to locate a point along a metro_config corridor
+async fn handle_metro_locate(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    locate::handle_locate(&state, &p1, &p2, &p3, req.query_string())
+}
+
+/// Handle a request for station spacing of a metro_config corridor
+async fn handle_metro_spacing(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    state
+        .cache
+        .get_or_insert(&req, || spacing::handle_spacing(&state, &p1, &p2, &p3))
+}
+
+/// Handle a request to submit an export job
+async fn handle_job_export(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    body: web::Json<ExportSpec>,
+) -> Result<HttpResponse, Error> {
+    jobs::handle_submit(&state, &req, body.into_inner())
+}
+
+/// Handle a request for a list of export jobs
+async fn handle_jobs(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    jobs::handle_list(&state, &req)
+}
+
+/// Handle a request to cancel an export job
+async fn handle_job_cancel(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    jobs::handle_cancel(&state, &req, &path)
+}
+
+/// Handle a request for the status of an export job
+async fn handle_job(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    jobs::handle_status(&state, &req, &path)
+}
+
+/// Handle a request for the result of an export job
+async fn handle_job_result(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    jobs::handle_result(&state, &req, &path)
+}
+
+/// Handle a weather request with two parameters
+async fn handle_weather_2(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2) = path.into_inner();
+    state
+        .cache
+        .get_or_insert(&req, || weather::handle_2_params(&state, &p1, &p2))
+}
+
+/// Handle a weather JSON request with three parameters
+async fn handle_weather_3_json(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    timed(&state, || {
+        state.cache.get_or_insert(&req, || {
+            let query = req.query_string();
+            weather::handle_3_params(&state, &p1, &p2, &p3, true, query, None)
+        })
+    })
+}
+
+/// Handle a weather request with three parameters
+async fn handle_weather_3(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    let accept = accept(&req);
+    let query = req.query_string();
+    timed(&state, || {
+        weather::handle_3_params(&state, &p1, &p2, &p3, false, query, accept)
+    })
+}
+
+/// Handle a request with one parameter
+async fn handle_1(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let res = timed(&state, || {
+        sensor::handle_1_param(&state, &path, req.query_string())
+    });
+    notfound::or_page(&state, accept(&req), Shape::One, &[&path], res)
+}
+
+/// Handle a JSON request with two parameters
+async fn handle_2_json(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2) = path.into_inner();
+    let res = timed(&state, || {
+        sensor::handle_2_params_json(&state, &p1, &p2, req.query_string())
+    });
+    notfound::or_page(&state, accept(&req), Shape::TwoJson, &[&p1, &p2], res)
+}
+
+/// Handle a zip archive request with two parameters
+async fn handle_2_traffic(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2) = path.into_inner();
+    let res = sensor::handle_2_params_traffic(&state, &req, &p1, &p2);
+    let shape = Shape::TwoTraffic;
+    notfound::or_page(&state, accept(&req), shape, &[&p1, &p2], res)
+}
+
+/// Handle a request with two parameters
+async fn handle_2(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2) = path.into_inner();
+    let accept = accept(&req);
+    let res = timed(&state, || {
+        sensor::handle_2_params(&state, &p1, &p2, req.query_string(), accept)
+    });
+    notfound::or_page(&state, accept, Shape::Two, &[&p1, &p2], res)
+}
+
+/// Handle a JSON request with three parameters
+async fn handle_3_json(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    let params = [p1.as_str(), &p2, &p3];
+    if let Some((did, year)) = sensor::checksums_params(&state, &p1, &p2, &p3) {
+        let res = sync::handle_checksums(state.clone(), &req, did, year).await;
+        return notfound::or_page(
+            &state,
+            accept(&req),
+            Shape::ThreeJson,
+            &params,
+            res,
+        );
+    }
+    let build = || {
+        sensor::handle_3_params_json(&state, &p1, &p2, &p3, req.query_string())
+    };
+    let res = timed(&state, || {
+        if sensor::is_derived(&state, &p1, &p2, &p3) {
+            state.cache.get_or_insert(&req, build)
+        } else {
+            build()
+        }
+    });
+    notfound::or_page(&state, accept(&req), Shape::ThreeJson, &params, res)
+}
+
+/// Handle a request for an archive file of a year, for mirroring
+async fn handle_file(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, name) = path.into_inner();
+    sync::handle_file(&state, &req, &p1, &p2, &name)
+}
+
+/// Handle a batch (POST) request with three parameters.
+///
+/// Bodies may be JSON, or uploaded sensor lists (`text/csv` or
+/// `text/plain`), and may be compressed (`Content-Encoding: gzip`).
+async fn handle_3_batch(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    body: web::Bytes,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let body = AlignRequest::parse(content_type, &body, req.query_string())?;
+    timed(&state, || {
+        sensor::handle_3_params_batch(&state, &p1, &p2, &p3, &body)
+    })
+}
+
+/// Handle a request with three parameters
+async fn handle_3(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (p1, p2, p3) = path.into_inner();
+    let accept = accept(&req);
+    let query = req.query_string();
+    let res = timed(&state, || {
+        sensor::handle_3_params(&state, &p1, &p2, &p3, query, accept)
+    });
+    let params = [p1.as_str(), &p2, &p3];
+    notfound::or_page(&state, accept, Shape::Three, &params, res)
+}
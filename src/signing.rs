@@ -0,0 +1,159 @@
+// signing.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::state::{AppState, Config};
+use actix_web::{web, HttpRequest};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// HMAC-SHA256 signer
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signed URL query parameters
+#[derive(Deserialize)]
+struct SignParams {
+    /// Expiration time (seconds since Unix epoch)
+    expires: Option<u64>,
+    /// Path scope covered by signature (defaults to exact path)
+    scope: Option<String>,
+    /// Hex-encoded signature
+    sig: Option<String>,
+}
+
+/// Create a MAC for a scope and expiration time
+fn mac(key: &[u8], scope: &str, expires: u64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}", scope, expires).as_bytes());
+    mac
+}
+
+/// Decode a hex string
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Percent-encode a query parameter value
+fn encode_param(val: &str) -> String {
+    let mut res = String::new();
+    for b in val.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => res.push(b as char),
+            b'-' | b'_' | b'.' | b'~' | b'/' => res.push(b as char),
+            _ => write!(res, "%{:02X}", b).unwrap(),
+        }
+    }
+    res
+}
+
+/// Get seconds since Unix epoch
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Check if a path is within a scope (on a segment boundary)
+fn in_scope(path: &str, scope: &str) -> bool {
+    match path.strip_prefix(scope) {
+        Some(rest) => {
+            rest.is_empty() || rest.starts_with('/') || scope.ends_with('/')
+        }
+        None => false,
+    }
+}
+
+/// Sign a path scope, returning query parameters to append to URLs.
+///
+/// * `key` Signing key.
+/// * `scope` Path (after the URL prefix) to grant access to, including
+///   any paths below it.
+/// * `expires` Expiration time.
+pub fn sign(key: &[u8], scope: &str, expires: SystemTime) -> String {
+    let expires = unix_secs(expires);
+    let sig = mac(key, scope, expires).finalize().into_bytes();
+    let mut res =
+        format!("expires={}&scope={}&sig=", expires, encode_param(scope));
+    for b in sig {
+        write!(res, "{:02x}", b).unwrap();
+    }
+    res
+}
+
+/// Verify a signed URL.
+///
+/// * `key` Signing key.
+/// * `path` Request path (after the URL prefix).
+/// * `query` Request query string.
+/// * `now` Current time.
+pub fn verify(
+    key: &[u8],
+    path: &str,
+    query: &str,
+    now: SystemTime,
+) -> Result<(), Error> {
+    let params = web::Query::<SignParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let (expires, sig) = match (params.expires, &params.sig) {
+        (Some(expires), Some(sig)) => (expires, sig),
+        _ => return Err(Error::Forbidden),
+    };
+    let scope = params.scope.as_deref().unwrap_or(path);
+    if !in_scope(path, scope) || unix_secs(now) >= expires {
+        return Err(Error::Forbidden);
+    }
+    let sig = decode_hex(sig).ok_or(Error::Forbidden)?;
+    mac(key, scope, expires)
+        .verify_slice(&sig)
+        .map_err(|_| Error::Forbidden)
+}
+
+/// Check if a request has a valid signature (requires a signing key)
+pub fn is_signed(state: &AppState, req: &HttpRequest) -> bool {
+    state.config.signing_key.is_some() && check_request(state, req).is_ok()
+}
+
+/// Verify the signature of a request, if a signing key is configured
+pub fn check_request(state: &AppState, req: &HttpRequest) -> Result<(), Error> {
+    if let Some(key) = &state.config.signing_key {
+        let path = req.path();
+        let path = path
+            .strip_prefix(state.config.url_prefix.as_str())
+            .unwrap_or(path);
+        verify(key.as_bytes(), path, req.query_string(), SystemTime::now())?;
+    }
+    Ok(())
+}
+
+/// Run `sign` subcommand, printing signed query parameters
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (scope, secs) = match args {
+        [scope, secs] => (scope, secs),
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: sign <path_scope> <seconds>".into(),
+            ))
+        }
+    };
+    let secs: u64 = secs
+        .parse()
+        .map_err(|_| Error::InvalidParam(format!("seconds: {}", secs)))?;
+    let config = Config::from_env()?;
+    let key = config
+        .signing_key
+        .ok_or_else(|| Error::Config("TRAFDAT_SIGNING_KEY not set".into()))?;
+    let expires = SystemTime::now() + Duration::from_secs(secs);
+    println!("{}", sign(key.as_bytes(), scope, expires));
+    Ok(())
+}
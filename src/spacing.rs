@@ -0,0 +1,44 @@
+// spacing.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Station spacing table for a corridor.
+//
+use crate::corridor::load_locations;
+use crate::error::Error;
+use crate::state::AppState;
+use actix_web::HttpResponse;
+use std::fmt::Write;
+
+/// CSV header row
+const HEADER: &str = "from,to,from_mile,to_mile,miles\n";
+
+/// Handle request for distances between consecutive corridor stations
+pub fn handle_spacing(
+    state: &AppState,
+    date: &str,
+    rte: &str,
+    dir: &str,
+) -> Result<HttpResponse, Error> {
+    let corridor = format!("{}_{}", rte, dir);
+    let locs = load_locations(state, date, &corridor)?.locs;
+    let stations: Vec<_> = locs
+        .iter()
+        .filter(|loc| loc.is_station() && loc.node.pos.is_some())
+        .collect();
+    let mut csv = String::from(HEADER);
+    for pair in stations.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        writeln!(
+            csv,
+            "{},{},{:.3},{:.3},{:.3}",
+            a.id(),
+            b.id(),
+            a.mile,
+            b.mile,
+            b.mile - a.mile
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok().content_type("text/csv").body(csv))
+}
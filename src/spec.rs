@@ -0,0 +1,86 @@
+// spec.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Sample period specification.
+//
+// `/trafdat/spec.json` describes which configured sample periods are
+// canonical and which are deprecated, and whether deprecated extensions are
+// shown in listings.  Deprecated extensions are always served on direct
+// request.
+//
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::sensor::DEPRECATED_PERIODS;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+
+/// Status of a sample period
+#[derive(JsonSchema, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Canonical,
+    Deprecated,
+}
+
+/// Specification of one sample period
+#[derive(JsonSchema, Serialize)]
+struct PeriodSpec {
+    /// Period in seconds
+    seconds: u32,
+    /// Canonical or deprecated
+    status: Status,
+    /// Are extensions with this period shown in listings?
+    listed: bool,
+    /// Deprecation note
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<&'static str>,
+}
+
+/// Sample period specification
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Spec {
+    /// Configured sample periods
+    sample_periods: Vec<PeriodSpec>,
+    /// Are deprecated extensions hidden from listings?
+    hide_deprecated: bool,
+}
+
+/// Build the sample period specification
+fn spec(state: &AppState) -> Spec {
+    let config = &state.config;
+    let listed = config.listed_periods();
+    let sample_periods = config
+        .sample_periods
+        .iter()
+        .map(|&seconds| {
+            let note = DEPRECATED_PERIODS
+                .iter()
+                .find(|(p, _)| *p == seconds)
+                .map(|(_, note)| *note);
+            PeriodSpec {
+                seconds,
+                status: match note {
+                    Some(_) => Status::Deprecated,
+                    None => Status::Canonical,
+                },
+                listed: listed.contains(&seconds),
+                note,
+            }
+        })
+        .collect();
+    Spec {
+        sample_periods,
+        hide_deprecated: config.hide_deprecated,
+    }
+}
+
+/// Handle request for the sample period specification
+pub async fn handle_spec(
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&spec(&state))?))
+}
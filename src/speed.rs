@@ -0,0 +1,148 @@
+// speed.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::sensor::{json_response, read_series, read_stored_series};
+use crate::state::AppState;
+use crate::vlog::read_vlog;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// Default histogram bin width (mph)
+const BIN_DEFAULT: u32 = 5;
+
+/// Maximum speed (mph) for histogram; faster speeds are in the last bin
+const MAX_SPEED: u32 = 120;
+
+/// Query parameters for speed histogram requests
+#[derive(Deserialize)]
+struct HistogramParams {
+    /// Bin width (mph)
+    bin: Option<u32>,
+}
+
+/// Speed histogram for one sensor on a date
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct SpeedHistogram {
+    /// Data source (`vlog` or binned sample extension)
+    source: String,
+    /// Units of counts (`vehicles` or `intervals`)
+    units: &'static str,
+    /// Bin width (mph)
+    bin: u32,
+    /// Total count
+    total: u32,
+    /// Count for each bin, starting at 0 mph
+    counts: Vec<u32>,
+}
+
+impl SpeedHistogram {
+    /// Create an empty histogram
+    fn new(source: String, units: &'static str, bin: u32) -> Self {
+        let n_bins = MAX_SPEED.div_ceil(bin) as usize;
+        SpeedHistogram {
+            source,
+            units,
+            bin,
+            total: 0,
+            counts: vec![0; n_bins],
+        }
+    }
+
+    /// Add a speed to the histogram
+    fn add(&mut self, speed: u32, count: u32) {
+        let i = ((speed / self.bin) as usize).min(self.counts.len() - 1);
+        self.counts[i] += count;
+        self.total += count;
+    }
+}
+
+/// Build speed histogram from a vehicle event log
+fn vlog_histogram(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    bin: u32,
+) -> Result<Option<SpeedHistogram>, Error> {
+    let vlog = match read_vlog(state, district, date, sid)? {
+        Some(vlog) => vlog,
+        None => return Ok(None),
+    };
+    let mut hist = SpeedHistogram::new("vlog".to_string(), "vehicles", bin);
+    for speed in vlog.events().iter().filter_map(|ev| ev.speed) {
+        hist.add(speed, 1);
+    }
+    if hist.total > 0 {
+        Ok(Some(hist))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Build speed histogram from binned speed samples.
+///
+/// Speeds are weighted by volume when available.
+fn binned_histogram(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    bin: u32,
+) -> Result<Option<SpeedHistogram>, Error> {
+    for period in &state.config.sample_periods {
+        let ext = format!("s{}", period);
+        if let Some(speed) =
+            read_stored_series(state, district, date, sid, &ext)?
+        {
+            let volume = read_series(
+                state,
+                district,
+                date,
+                sid,
+                &format!("v{}", period),
+            )?;
+            let units = if volume.is_some() {
+                "vehicles"
+            } else {
+                "intervals"
+            };
+            let mut hist = SpeedHistogram::new(ext, units, bin);
+            for (i, spd) in speed.values().iter().enumerate() {
+                let count = match &volume {
+                    Some(vol) => vol.values().get(i).copied().flatten(),
+                    None => Some(1),
+                };
+                if let (Some(spd), Some(count)) = (spd, count) {
+                    hist.add(*spd as u32, count as u32);
+                }
+            }
+            return Ok(Some(hist));
+        }
+    }
+    Ok(None)
+}
+
+/// Handle request for speed histogram
+pub fn handle_speed_hist(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<HistogramParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let bin = params.bin.unwrap_or(BIN_DEFAULT);
+    if !(1..=MAX_SPEED).contains(&bin) {
+        return Err(Error::InvalidParam(format!("bin: {}", bin)));
+    }
+    let hist = match vlog_histogram(state, district, date, sid, bin)? {
+        Some(hist) => hist,
+        None => binned_histogram(state, district, date, sid, bin)?
+            .ok_or(Error::NotFound)?,
+    };
+    Ok(json_response(serde_json::to_string(&hist)?))
+}
@@ -0,0 +1,711 @@
+// sqlfile.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Writer for SQLite database files.
+//
+// Tables are written directly in the SQLite file format (version 3, UTF-8,
+// 4 KiB pages), as b-trees built bottom-up from rows in key order.  Each
+// file is written once, from start to finish, so there is no journal,
+// free list or rebalancing.
+//
+use crate::error::Error;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Page size (bytes)
+const PAGE_SIZE: usize = 4096;
+
+/// Size of the database header on page 1
+const DB_HEADER: usize = 100;
+
+/// Maximum payload stored on a table leaf page
+const MAX_LOCAL_TABLE: usize = PAGE_SIZE - 35;
+
+/// Maximum payload stored on an index page
+const MAX_LOCAL_INDEX: usize = (PAGE_SIZE - 12) * 64 / 255 - 23;
+
+/// Minimum payload stored on a page (when spilling to overflow pages)
+const MIN_LOCAL: usize = (PAGE_SIZE - 12) * 32 / 255 - 23;
+
+/// SQLite version number recorded in the header
+const SQLITE_VERSION: u32 = 3_045_000;
+
+/// Column value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Value::Text(val.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Value::Text(val)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(val: i64) -> Self {
+        Value::Integer(val)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::Real(val)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(val: Option<T>) -> Self {
+        val.map_or(Value::Null, Into::into)
+    }
+}
+
+impl Value {
+    /// Get the storage class rank (for sorting)
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+        }
+    }
+
+    /// Compare with another value, using the BINARY collation
+    fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (a, b) if a.rank() == 1 && b.rank() == 1 => {
+                let (a, b) = (a.real(), b.real());
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            }
+            (a, b) => a.rank().cmp(&b.rank()),
+        }
+    }
+
+    /// Get a numeric value as a float
+    fn real(&self) -> f64 {
+        match self {
+            Value::Integer(v) => *v as f64,
+            Value::Real(v) => *v,
+            _ => 0.0,
+        }
+    }
+
+    /// Get the serial type and encoded size
+    fn serial_type(&self) -> (u64, usize) {
+        match self {
+            Value::Null => (0, 0),
+            Value::Integer(0) => (8, 0),
+            Value::Integer(1) => (9, 0),
+            Value::Integer(v) => match int_size(*v) {
+                1 => (1, 1),
+                2 => (2, 2),
+                3 => (3, 3),
+                4 => (4, 4),
+                6 => (5, 6),
+                _ => (6, 8),
+            },
+            Value::Real(_) => (7, 8),
+            Value::Text(s) => (13 + 2 * s.len() as u64, s.len()),
+        }
+    }
+
+    /// Encode the value body
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Null => (),
+            Value::Integer(v) => {
+                let (_, len) = self.serial_type();
+                out.extend_from_slice(&v.to_be_bytes()[8 - len..]);
+            }
+            Value::Real(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Value::Text(s) => out.extend_from_slice(s.as_bytes()),
+        }
+    }
+}
+
+/// Get the number of bytes needed for an integer
+fn int_size(v: i64) -> usize {
+    match v {
+        -0x80..=0x7F => 1,
+        -0x8000..=0x7FFF => 2,
+        -0x80_0000..=0x7F_FFFF => 3,
+        -0x8000_0000..=0x7FFF_FFFF => 4,
+        -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => 6,
+        _ => 8,
+    }
+}
+
+/// Compare rows by their first `n` columns
+fn compare_rows(a: &[Value], b: &[Value], n: usize) -> Ordering {
+    a.iter()
+        .zip(b)
+        .take(n)
+        .map(|(a, b)| a.compare(b))
+        .find(|ord| *ord != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Append a variable-length integer
+fn put_varint(out: &mut Vec<u8>, val: u64) {
+    if val > 0x00FF_FFFF_FFFF_FFFF {
+        let mut buf = [0; 9];
+        buf[8] = val as u8;
+        let mut val = val >> 8;
+        for b in buf[..8].iter_mut().rev() {
+            *b = (val as u8 & 0x7F) | 0x80;
+            val >>= 7;
+        }
+        out.extend_from_slice(&buf);
+        return;
+    }
+    let mut buf = [0; 8];
+    let mut len = 0;
+    let mut val = val;
+    loop {
+        buf[len] = (val as u8 & 0x7F) | 0x80;
+        len += 1;
+        val >>= 7;
+        if val == 0 {
+            break;
+        }
+    }
+    buf[0] &= 0x7F;
+    out.extend(buf[..len].iter().rev());
+}
+
+/// Get the size of a variable-length integer
+fn varint_len(val: u64) -> usize {
+    let mut buf = Vec::with_capacity(9);
+    put_varint(&mut buf, val);
+    buf.len()
+}
+
+/// Encode a record
+fn record(row: &[Value]) -> Vec<u8> {
+    let mut types = vec![];
+    let mut body = vec![];
+    for val in row {
+        put_varint(&mut types, val.serial_type().0);
+        val.encode(&mut body);
+    }
+    // header size includes its own varint
+    let mut len = types.len() + 1;
+    while varint_len(len as u64) + types.len() != len {
+        len = varint_len(len as u64) + types.len();
+    }
+    let mut rec = Vec::with_capacity(len + body.len());
+    put_varint(&mut rec, len as u64);
+    rec.extend(types);
+    rec.extend(body);
+    rec
+}
+
+/// B-tree kind
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Table b-tree (keyed by rowid)
+    Table,
+    /// Index b-tree (keyed by record), also used for WITHOUT ROWID tables
+    Index,
+}
+
+impl Kind {
+    /// Get the page type flag
+    fn flag(self, leaf: bool) -> u8 {
+        match (self, leaf) {
+            (Kind::Table, true) => 0x0D,
+            (Kind::Table, false) => 0x05,
+            (Kind::Index, true) => 0x0A,
+            (Kind::Index, false) => 0x02,
+        }
+    }
+
+    /// Get the maximum payload stored on a page
+    fn max_local(self) -> usize {
+        match self {
+            Kind::Table => MAX_LOCAL_TABLE,
+            Kind::Index => MAX_LOCAL_INDEX,
+        }
+    }
+}
+
+/// Check whether cells fit on a page
+fn fits(offset: usize, leaf: bool, cells: usize, size: usize) -> bool {
+    let header = if leaf { 8 } else { 12 };
+    offset + header + 2 * cells + size <= PAGE_SIZE
+}
+
+/// Build a b-tree page
+fn page(
+    offset: usize,
+    flag: u8,
+    cells: &[Vec<u8>],
+    right: Option<u32>,
+) -> Vec<u8> {
+    let mut buf = vec![0; PAGE_SIZE];
+    let size: usize = cells.iter().map(Vec::len).sum();
+    let content = PAGE_SIZE - size;
+    buf[offset] = flag;
+    buf[offset + 3..offset + 5]
+        .copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    buf[offset + 5..offset + 7]
+        .copy_from_slice(&(content as u16).to_be_bytes());
+    let mut ptr = offset + 8;
+    if let Some(right) = right {
+        buf[offset + 8..offset + 12].copy_from_slice(&right.to_be_bytes());
+        ptr += 4;
+    }
+    let mut pos = PAGE_SIZE;
+    for cell in cells {
+        pos -= cell.len();
+        buf[pos..pos + cell.len()].copy_from_slice(cell);
+        buf[ptr..ptr + 2].copy_from_slice(&(pos as u16).to_be_bytes());
+        ptr += 2;
+    }
+    buf
+}
+
+/// Sequential page writer
+struct Pager {
+    /// Database file
+    out: BufWriter<File>,
+    /// Number of pages written
+    pages: u32,
+}
+
+impl Pager {
+    /// Write the next page
+    fn write(&mut self, buf: &[u8]) -> io::Result<u32> {
+        self.out.write_all(buf)?;
+        self.pages += 1;
+        Ok(self.pages)
+    }
+
+    /// Write a b-tree page
+    fn write_page(
+        &mut self,
+        flag: u8,
+        cells: &[Vec<u8>],
+        right: Option<u32>,
+    ) -> io::Result<u32> {
+        self.write(&page(0, flag, cells, right))
+    }
+
+    /// Build a cell with a payload, spilling to overflow pages if needed
+    fn cell(
+        &mut self,
+        kind: Kind,
+        rowid: Option<i64>,
+        payload: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let mut cell = vec![];
+        put_varint(&mut cell, payload.len() as u64);
+        if let Some(rowid) = rowid {
+            put_varint(&mut cell, rowid as u64);
+        }
+        let len = payload.len();
+        let local = if len <= kind.max_local() {
+            len
+        } else {
+            let k = MIN_LOCAL + (len - MIN_LOCAL) % (PAGE_SIZE - 4);
+            if k <= kind.max_local() {
+                k
+            } else {
+                MIN_LOCAL
+            }
+        };
+        cell.extend_from_slice(&payload[..local]);
+        if local < len {
+            let chunks: Vec<&[u8]> =
+                payload[local..].chunks(PAGE_SIZE - 4).collect();
+            let first = self.pages + 1;
+            cell.extend_from_slice(&first.to_be_bytes());
+            for (i, chunk) in chunks.iter().enumerate() {
+                let next = if i + 1 < chunks.len() {
+                    self.pages + 2
+                } else {
+                    0
+                };
+                let mut buf = vec![0; PAGE_SIZE];
+                buf[..4].copy_from_slice(&next.to_be_bytes());
+                buf[4..4 + chunk.len()].copy_from_slice(chunk);
+                self.write(&buf)?;
+            }
+        }
+        Ok(cell)
+    }
+}
+
+/// B-tree builder, for cells added in key order
+struct Tree {
+    /// B-tree kind
+    kind: Kind,
+    /// Cells of the current leaf page
+    cells: Vec<Vec<u8>>,
+    /// Total size of current cells
+    size: usize,
+    /// Last rowid on the current leaf page
+    rowid: i64,
+    /// Index cell which did not fit, to become a divider
+    pending: Option<Vec<u8>>,
+    /// Child pages of the level above the leaves
+    children: Vec<u32>,
+    /// Divider cells (without child pointers) between children
+    dividers: Vec<Vec<u8>>,
+}
+
+impl Tree {
+    /// Create a new b-tree builder
+    fn new(kind: Kind) -> Self {
+        Tree {
+            kind,
+            cells: vec![],
+            size: 0,
+            rowid: 0,
+            pending: None,
+            children: vec![],
+            dividers: vec![],
+        }
+    }
+
+    /// Write the current leaf page
+    fn flush(&mut self, pager: &mut Pager) -> io::Result<()> {
+        let page = pager.write_page(self.kind.flag(true), &self.cells, None)?;
+        self.children.push(page);
+        self.cells.clear();
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Add a cell to the current leaf page
+    fn push_cell(&mut self, cell: Vec<u8>, rowid: i64) {
+        self.size += cell.len();
+        self.cells.push(cell);
+        self.rowid = rowid;
+    }
+
+    /// Add a leaf cell
+    fn push(
+        &mut self,
+        pager: &mut Pager,
+        cell: Vec<u8>,
+        rowid: i64,
+    ) -> io::Result<()> {
+        if let Some(divider) = self.pending.take() {
+            self.flush(pager)?;
+            self.dividers.push(divider);
+        } else if !fits(0, true, self.cells.len() + 1, self.size + cell.len()) {
+            match self.kind {
+                Kind::Table => {
+                    let mut divider = vec![];
+                    put_varint(&mut divider, self.rowid as u64);
+                    self.flush(pager)?;
+                    self.dividers.push(divider);
+                }
+                Kind::Index => {
+                    // index keys are stored once, so this one moves up
+                    self.pending = Some(cell);
+                    return Ok(());
+                }
+            }
+        }
+        self.push_cell(cell, rowid);
+        Ok(())
+    }
+
+    /// Finish the b-tree, returning its root page
+    fn finish(mut self, pager: &mut Pager) -> io::Result<u32> {
+        if let Some(last) = self.pending.take() {
+            let divider = self.cells.pop().unwrap_or_default();
+            self.size -= divider.len();
+            self.flush(pager)?;
+            self.dividers.push(divider);
+            self.push_cell(last, 0);
+        }
+        self.flush(pager)?;
+        let flag = self.kind.flag(false);
+        let mut children = self.children;
+        let mut dividers = self.dividers;
+        while children.len() > 1 {
+            let mut parents = vec![];
+            let mut promoted = vec![];
+            let mut cells: Vec<Vec<u8>> = vec![];
+            let mut size = 0;
+            let last = children.len() - 1;
+            for (i, (child, divider)) in
+                children.iter().zip(dividers.iter_mut()).enumerate()
+            {
+                let mut cell = child.to_be_bytes().to_vec();
+                cell.append(divider);
+                if fits(0, false, cells.len() + 1, size + cell.len()) {
+                    size += cell.len();
+                    cells.push(cell);
+                    continue;
+                }
+                if i + 1 < last {
+                    // the child becomes the right-most pointer
+                    parents.push(pager.write_page(
+                        flag,
+                        &cells,
+                        Some(*child),
+                    )?);
+                    promoted.push(cell.split_off(4));
+                    cells.clear();
+                    size = 0;
+                } else {
+                    // avoid a final page with no cells
+                    let mut prev = cells.pop().unwrap_or_default();
+                    let right = u32::from_be_bytes([
+                        prev[0], prev[1], prev[2], prev[3],
+                    ]);
+                    parents.push(pager.write_page(
+                        flag,
+                        &cells,
+                        Some(right),
+                    )?);
+                    promoted.push(prev.split_off(4));
+                    size = cell.len();
+                    cells = vec![cell];
+                }
+            }
+            parents.push(pager.write_page(
+                flag,
+                &cells,
+                Some(children[last]),
+            )?);
+            children = parents;
+            dividers = promoted;
+        }
+        Ok(children[0])
+    }
+}
+
+/// SQLite database file writer
+pub struct DbFile {
+    /// Page writer
+    pager: Pager,
+    /// Schema table rows
+    schema: Vec<Vec<Value>>,
+}
+
+/// Writer for rows of a WITHOUT ROWID table
+pub struct TableWriter<'a> {
+    /// Database file
+    db: &'a mut DbFile,
+    /// Schema table row
+    schema: Vec<Value>,
+    /// Number of primary key columns
+    key: usize,
+    /// Previous row
+    prev: Option<Vec<Value>>,
+    /// Table b-tree
+    tree: Tree,
+    /// Number of rows
+    rows: usize,
+}
+
+impl DbFile {
+    /// Create a database file
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let out = BufWriter::new(File::create(path)?);
+        let mut pager = Pager { out, pages: 0 };
+        // page 1 is written last, with the schema table
+        pager.write(&[0; PAGE_SIZE])?;
+        Ok(DbFile {
+            pager,
+            schema: vec![],
+        })
+    }
+
+    /// Add a schema table row
+    fn add_schema(
+        &mut self,
+        kind: &str,
+        name: &str,
+        table: &str,
+        root: u32,
+        sql: Option<&str>,
+    ) {
+        self.schema.push(vec![
+            kind.into(),
+            name.into(),
+            table.into(),
+            i64::from(root).into(),
+            sql.into(),
+        ]);
+    }
+
+    /// Write an index b-tree from records in key order
+    fn write_index<I>(&mut self, key: usize, rows: I) -> Result<u32, Error>
+    where
+        I: IntoIterator<Item = Vec<Value>>,
+    {
+        let mut tree = Tree::new(Kind::Index);
+        let mut prev: Option<Vec<Value>> = None;
+        for row in rows {
+            check_order(prev.as_deref(), &row, key)?;
+            let cell = self.pager.cell(Kind::Index, None, &record(&row))?;
+            tree.push(&mut self.pager, cell, 0)?;
+            prev = Some(row);
+        }
+        Ok(tree.finish(&mut self.pager)?)
+    }
+
+    /// Write a rowid table.
+    ///
+    /// * `key` Number of leading primary key columns, for its automatic
+    ///   index (0 for none).
+    /// * `rows` Rows in primary key order; rowids are assigned in order.
+    pub fn table(
+        &mut self,
+        name: &str,
+        sql: &str,
+        key: usize,
+        rows: &[Vec<Value>],
+    ) -> Result<(), Error> {
+        let mut tree = Tree::new(Kind::Table);
+        for (rowid, row) in (1..).zip(rows) {
+            let cell =
+                self.pager.cell(Kind::Table, Some(rowid), &record(row))?;
+            tree.push(&mut self.pager, cell, rowid)?;
+        }
+        let root = tree.finish(&mut self.pager)?;
+        self.add_schema("table", name, name, root, Some(sql));
+        if key > 0 {
+            let keys = (1..).zip(rows).map(|(rowid, row)| {
+                let mut keys = row[..key].to_vec();
+                keys.push(Value::Integer(rowid));
+                keys
+            });
+            let root = self.write_index(key, keys)?;
+            let index = format!("sqlite_autoindex_{}_1", name);
+            self.add_schema("index", &index, name, root, None);
+        }
+        Ok(())
+    }
+
+    /// Start writing a WITHOUT ROWID table.
+    ///
+    /// * `key` Number of leading primary key columns.
+    pub fn without_rowid(
+        &mut self,
+        name: &str,
+        sql: &str,
+        key: usize,
+    ) -> TableWriter<'_> {
+        TableWriter {
+            db: self,
+            schema: vec![
+                "table".into(),
+                name.into(),
+                name.into(),
+                Value::Null,
+                sql.into(),
+            ],
+            key,
+            prev: None,
+            tree: Tree::new(Kind::Index),
+            rows: 0,
+        }
+    }
+
+    /// Finish the file, writing page 1 and the header
+    pub fn finish(self) -> Result<(), Error> {
+        let mut pager = self.pager;
+        let mut cells = vec![];
+        let mut size = 0;
+        for (rowid, row) in (1..).zip(&self.schema) {
+            let cell = pager.cell(Kind::Table, Some(rowid), &record(row))?;
+            size += cell.len();
+            cells.push(cell);
+        }
+        if !fits(DB_HEADER, true, cells.len(), size) {
+            let msg = "schema too large for page 1";
+            return Err(io::Error::other(msg).into());
+        }
+        let mut buf = page(DB_HEADER, Kind::Table.flag(true), &cells, None);
+        buf[..DB_HEADER].copy_from_slice(&header(pager.pages));
+        let mut out = pager.out;
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&buf)?;
+        let file = out.into_inner().map_err(io::Error::from)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+impl TableWriter<'_> {
+    /// Insert a row (in primary key order)
+    pub fn insert(&mut self, row: Vec<Value>) -> Result<(), Error> {
+        check_order(self.prev.as_deref(), &row, self.key)?;
+        let pager = &mut self.db.pager;
+        let cell = pager.cell(Kind::Index, None, &record(&row))?;
+        self.tree.push(pager, cell, 0)?;
+        self.prev = Some(row);
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Finish the table, returning the number of rows
+    pub fn finish(mut self) -> Result<usize, Error> {
+        let root = self.tree.finish(&mut self.db.pager)?;
+        self.schema[3] = i64::from(root).into();
+        self.db.schema.push(self.schema);
+        Ok(self.rows)
+    }
+}
+
+/// Check that a row follows the previous row in key order
+fn check_order(
+    prev: Option<&[Value]>,
+    row: &[Value],
+    key: usize,
+) -> Result<(), Error> {
+    match prev {
+        Some(prev) if compare_rows(prev, row, key) != Ordering::Less => {
+            let msg = "rows not in primary key order";
+            Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Build the database header
+fn header(pages: u32) -> [u8; DB_HEADER] {
+    let mut buf = [0; DB_HEADER];
+    buf[..16].copy_from_slice(b"SQLite format 3\0");
+    buf[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // file format versions (rollback journal)
+    buf[18] = 1;
+    buf[19] = 1;
+    // payload fractions
+    buf[21] = 64;
+    buf[22] = 32;
+    buf[23] = 32;
+    // file change counter
+    buf[24..28].copy_from_slice(&1u32.to_be_bytes());
+    buf[28..32].copy_from_slice(&pages.to_be_bytes());
+    // schema cookie and format
+    buf[40..44].copy_from_slice(&1u32.to_be_bytes());
+    buf[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // text encoding: UTF-8
+    buf[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // version-valid-for (change counter)
+    buf[92..96].copy_from_slice(&1u32.to_be_bytes());
+    buf[96..100].copy_from_slice(&SQLITE_VERSION.to_be_bytes());
+    buf
+}
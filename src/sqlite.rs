@@ -0,0 +1,366 @@
+// sqlite.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Export a study dataset (corridor configuration and decoded samples) as a
+// SQLite database file or script.
+//
+use crate::corridor::{load_locations, Location};
+use crate::error::Error;
+use crate::export::{
+    date_range, date_series, split_arg, sql_quote, SqlBatch, DEFAULT_EXTS,
+};
+use crate::sqlfile::{DbFile, Value};
+use crate::state::{AppState, Config};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs::{remove_file, rename};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::slice::from_ref;
+
+/// Maximum rows per INSERT statement
+const BATCH: usize = 500;
+
+/// Metadata table
+const METADATA: &str = "\
+CREATE TABLE metadata (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+)";
+
+/// Corridor nodes table
+const NODES: &str = "\
+CREATE TABLE nodes (
+    date TEXT NOT NULL,
+    name TEXT NOT NULL,
+    n_type TEXT NOT NULL,
+    station_id TEXT,
+    lon REAL,
+    lat REAL,
+    mile REAL NOT NULL,
+    PRIMARY KEY (date, name)
+)";
+
+/// Sensors table
+const SENSORS: &str = "\
+CREATE TABLE sensors (
+    date TEXT NOT NULL,
+    sensor TEXT NOT NULL,
+    node TEXT NOT NULL,
+    category TEXT NOT NULL,
+    field REAL NOT NULL,
+    PRIMARY KEY (date, sensor)
+)";
+
+/// Samples table
+const SAMPLES: &str = "\
+CREATE TABLE samples (
+    sensor TEXT NOT NULL,
+    ext TEXT NOT NULL,
+    stamp INTEGER NOT NULL,
+    value REAL NOT NULL,
+    PRIMARY KEY (sensor, ext, stamp)
+) WITHOUT ROWID";
+
+/// Start of a batch INSERT statement for samples
+const INSERT_SAMPLES: &str =
+    "INSERT OR REPLACE INTO samples (sensor, ext, stamp, value) VALUES";
+
+/// Format an optional SQL value
+fn sql_opt<T: ToString>(val: Option<T>) -> String {
+    val.map_or_else(|| "NULL".to_string(), |v| v.to_string())
+}
+
+/// Study dataset specification
+pub struct Study<'a> {
+    /// District ID
+    pub district: &'a str,
+    /// Corridor (`route_dir`)
+    pub corridor: &'a str,
+    /// Dates (yyyyMMdd)
+    pub dates: Vec<String>,
+    /// Sample file extensions
+    pub exts: Vec<String>,
+}
+
+impl Study<'_> {
+    /// Load corridor locations on a date, logging any problems
+    fn locations(&self, state: &AppState, date: &str) -> Option<Vec<Location>> {
+        match load_locations(state, date, self.corridor) {
+            Ok(locations) => {
+                for warning in &locations.warnings {
+                    warn!("{} {}: {}", date, self.corridor, warning);
+                }
+                Some(locations.locs)
+            }
+            Err(e) => {
+                warn!("{} {}: {}", date, self.corridor, e);
+                None
+            }
+        }
+    }
+
+    /// Get metadata (key, value) pairs
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        let first = self.dates.first().map(String::as_str).unwrap_or("");
+        let last = self.dates.last().map(String::as_str).unwrap_or("");
+        vec![
+            ("district", self.district.to_string()),
+            ("corridor", self.corridor.to_string()),
+            ("start_date", first.to_string()),
+            ("end_date", last.to_string()),
+            ("exts", self.exts.join(",")),
+            ("stamp", "unix seconds (interval start)".to_string()),
+        ]
+    }
+
+    /// Write metadata table rows
+    fn write_metadata<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (key, value) in self.metadata() {
+            writeln!(
+                out,
+                "INSERT OR REPLACE INTO metadata VALUES ({},{});",
+                sql_quote(key),
+                sql_quote(&value)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write a SQL script with the study dataset.
+    ///
+    /// Corridor configuration is taken from each date's metro_config; dates
+    /// without one are skipped.  Returns the number of sample rows written.
+    pub fn write_sql<W: Write>(
+        &self,
+        state: &AppState,
+        out: &mut W,
+    ) -> Result<usize, Error> {
+        self.write_header(out)?;
+        let mut rows = 0;
+        for date in &self.dates {
+            rows += self.write_date(state, date, out)?;
+        }
+        Ok(rows)
+    }
+
+    /// Write the schema and metadata at the start of a script
+    pub fn write_header<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        for table in [METADATA, NODES, SENSORS, SAMPLES] {
+            let table = table.replacen("TABLE", "TABLE IF NOT EXISTS", 1);
+            writeln!(out, "{};", table)?;
+        }
+        self.write_metadata(out)?;
+        Ok(())
+    }
+
+    /// Write one date of the study dataset, as a transaction.
+    ///
+    /// Returns the number of sample rows written.
+    pub fn write_date<W: Write>(
+        &self,
+        state: &AppState,
+        date: &str,
+        out: &mut W,
+    ) -> Result<usize, Error> {
+        let locs = match self.locations(state, date) {
+            Some(locs) => locs,
+            None => return Ok(0),
+        };
+        let mut batch = SqlBatch::new(out, INSERT_SAMPLES, "", BATCH);
+        batch.write(b"BEGIN;\n")?;
+        let dt = sql_quote(date);
+        let mut sensors = vec![];
+        for loc in &locs {
+            let node = &loc.node;
+            let name = sql_quote(&node.name);
+            let stmt = format!(
+                "INSERT OR REPLACE INTO nodes VALUES ({},{},{},{},{},{},{});\n",
+                dt,
+                name,
+                sql_quote(&node.n_type),
+                sql_opt(node.station_id.as_deref().map(sql_quote)),
+                sql_opt(node.pos.map(|(lon, _)| lon)),
+                sql_opt(node.pos.map(|(_, lat)| lat)),
+                loc.mile,
+            );
+            batch.write(stmt.as_bytes())?;
+            for (det, cat, field, _lane) in &node.detectors {
+                let stmt = format!(
+                    "INSERT OR REPLACE INTO sensors VALUES ({},{},{},{},{});\n",
+                    dt,
+                    sql_quote(det),
+                    name,
+                    sql_quote(cat),
+                    field,
+                );
+                batch.write(stmt.as_bytes())?;
+                sensors.push(det.clone());
+            }
+        }
+        if !sensors.is_empty() {
+            let district = self.district;
+            for series in
+                date_series(state, district, date, &sensors, &self.exts)?
+            {
+                let sid = sql_quote(&series.sensor);
+                let ext = sql_quote(&series.ext);
+                for (stamp, value) in series.samples() {
+                    batch
+                        .row(&format!("{},{},{},{}", sid, ext, stamp, value))?;
+                }
+            }
+        }
+        batch.write(b"COMMIT;\n")?;
+        Ok(batch.total())
+    }
+
+    /// Write the study dataset to a SQLite database file.
+    ///
+    /// The database is written to `{path}.part`, which is renamed to `path`
+    /// once complete.  Returns the number of samples.
+    pub fn write_db(
+        &self,
+        state: &AppState,
+        path: &Path,
+    ) -> Result<usize, Error> {
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = Path::new(&part);
+        match self.write_file(state, part) {
+            Ok(rows) => {
+                rename(part, path)?;
+                Ok(rows)
+            }
+            Err(e) => {
+                let _ = remove_file(part);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write the study dataset to a new database file.
+    ///
+    /// Rows are gathered in primary key order: samples are read by sensor,
+    /// extension and then date.
+    fn write_file(
+        &self,
+        state: &AppState,
+        path: &Path,
+    ) -> Result<usize, Error> {
+        let mut nodes = BTreeMap::new();
+        let mut sensors = BTreeMap::new();
+        let mut sensor_dates = BTreeMap::<String, Vec<&str>>::new();
+        for date in &self.dates {
+            for loc in self.locations(state, date).into_iter().flatten() {
+                let node = &loc.node;
+                let row = vec![
+                    date.as_str().into(),
+                    node.name.as_str().into(),
+                    node.n_type.as_str().into(),
+                    node.station_id.as_deref().into(),
+                    node.pos.map(|(lon, _)| lon).into(),
+                    node.pos.map(|(_, lat)| lat).into(),
+                    loc.mile.into(),
+                ];
+                nodes.insert((date, node.name.clone()), row);
+                for (det, cat, field, _lane) in &node.detectors {
+                    let row = vec![
+                        date.as_str().into(),
+                        det.as_str().into(),
+                        node.name.as_str().into(),
+                        cat.as_str().into(),
+                        Value::Real(*field),
+                    ];
+                    sensors.insert((date, det.clone()), row);
+                }
+            }
+        }
+        for (date, det) in sensors.keys() {
+            sensor_dates.entry(det.clone()).or_default().push(date);
+        }
+        let meta: BTreeMap<_, _> = self.metadata().into_iter().collect();
+        let meta: Vec<Vec<Value>> = meta
+            .into_iter()
+            .map(|(key, value)| vec![key.into(), value.into()])
+            .collect();
+        let mut exts = self.exts.clone();
+        exts.sort();
+        exts.dedup();
+        let mut db = DbFile::create(path)?;
+        db.table("metadata", METADATA, 1, &meta)?;
+        let nodes: Vec<_> = nodes.into_values().collect();
+        db.table("nodes", NODES, 2, &nodes)?;
+        let sensors: Vec<_> = sensors.into_values().collect();
+        db.table("sensors", SENSORS, 2, &sensors)?;
+        let mut samples = db.without_rowid("samples", SAMPLES, 3);
+        for (sensor, dates) in sensor_dates {
+            let sid = [sensor];
+            for ext in &exts {
+                for date in &dates {
+                    let district = self.district;
+                    for series in
+                        date_series(state, district, date, &sid, from_ref(ext))?
+                    {
+                        for (stamp, value) in series.samples() {
+                            samples.insert(vec![
+                                sid[0].as_str().into(),
+                                ext.as_str().into(),
+                                stamp.into(),
+                                value.into(),
+                            ])?;
+                        }
+                    }
+                }
+            }
+        }
+        let rows = samples.finish()?;
+        db.finish()?;
+        Ok(rows)
+    }
+}
+
+/// Export a study dataset as a SQLite database file.
+///
+/// * `args` Command arguments: district, corridor, start date, end date,
+///   database path (`-` for a script on stdout) and optional comma-separated
+///   extensions.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (district, corridor, start, end, db, exts) = match args {
+        [district, corridor, start, end, db] => {
+            (district, corridor, start, end, db, None)
+        }
+        [district, corridor, start, end, db, exts] => {
+            (district, corridor, start, end, db, Some(exts))
+        }
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: sqlite-export <district> <corridor> <start_date> \
+                 <end_date> <study.db|-> [ext,...]"
+                    .into(),
+            ))
+        }
+    };
+    let study = Study {
+        district,
+        corridor,
+        dates: date_range(start, end)?,
+        exts: match exts {
+            Some(exts) => split_arg(exts),
+            None => DEFAULT_EXTS.iter().map(|e| e.to_string()).collect(),
+        },
+    };
+    let state = AppState::new(Config::from_env()?);
+    let rows = if db == "-" {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let rows = study.write_sql(&state, &mut out)?;
+        out.flush()?;
+        rows
+    } else {
+        study.write_db(&state, Path::new(db))?
+    };
+    eprintln!("exported {} samples", rows);
+    Ok(())
+}
@@ -0,0 +1,733 @@
+// state.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::admin::ErrorLog;
+use crate::apikey::ApiKeys;
+use crate::avail::{RangeKey, SensorAadt, SensorPercentiles};
+use crate::cache::ResponseCache;
+use crate::dates::DATE_FMT;
+use crate::deprecation::Policy as DeprecationPolicy;
+use crate::diskcache::DiskCache;
+use crate::error::Error;
+use crate::fanout::DayPool;
+use crate::federation::Federation;
+use crate::health::Health;
+use crate::jobs::JobQueue;
+use crate::memo::Memo;
+use crate::metrics::Metrics;
+use crate::metro::MetroCache;
+use crate::output::FormatRegistry;
+use crate::pool::ZipPool;
+use crate::prewarm::HotDates;
+use crate::sample::{Marker, DAY_SECS};
+use crate::sensor::{is_deprecated_period, prefix_bytes, SAMPLE_PERIODS};
+use crate::stats::AccessStats;
+use crate::storage::Storage;
+use crate::sync::DigestCache;
+use crate::watch::WatchState;
+use crate::weather::WEATHER_PERIODS;
+use crate::webhook::parse_url;
+use crate::zone::Zone;
+use chrono::NaiveDate;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Handling of sample files with invalid length
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthCheck {
+    /// Files are not found
+    Strict,
+    /// Files are padded or truncated, and served with a warning
+    Lenient,
+}
+
+/// Server configuration
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Socket address to bind
+    pub bind_addr: String,
+    /// Base traffic archive path
+    pub traffic_path: PathBuf,
+    /// Base metro_config path
+    pub metro_path: PathBuf,
+    /// Default district ID
+    pub district_default: String,
+    /// URL path prefix for all routes (may be empty)
+    pub url_prefix: String,
+    /// Reverse proxy addresses trusted for `X-Forwarded-*` headers
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Time to live for cached derived responses (zero disables cache)
+    pub cache_ttl: Duration,
+    /// Hot dates to pre-warm at startup and nightly
+    pub hot_dates: Vec<HotDates>,
+    /// File for persisting access statistics
+    pub stats_path: Option<PathBuf>,
+    /// Key for signed URLs (required for archive downloads when set)
+    pub signing_key: Option<String>,
+    /// Contents of robots.txt (default allows only documentation pages)
+    pub robots_txt: Option<String>,
+    /// Maximum number of open zip archives (zero disables pooling)
+    pub zip_handles: usize,
+    /// Maximum size of a zip archive entry, in bytes
+    pub max_entry_size: u64,
+    /// Maximum total size of a multipart archive response, in bytes
+    pub max_parts_size: u64,
+    /// Compression level for migrating archives to zstd files
+    pub zstd_level: i32,
+    /// Zip entry name patterns (`{date}`, `{sid}` and `{ext}`), in order
+    pub entry_patterns: Vec<String>,
+    /// NATS server URL for archive events
+    pub nats_url: Option<String>,
+    /// NATS subject for archive events
+    pub nats_subject: String,
+    /// Webhook URLs for archive events
+    pub webhooks: Vec<String>,
+    /// Key for signing webhook requests
+    pub webhook_secret: Option<String>,
+    /// Report configuration file
+    pub reports_path: Option<PathBuf>,
+    /// Token for admin pages (admin pages are disabled when not set)
+    pub admin_token: Option<String>,
+    /// API keys file (keys not required if `None`)
+    pub api_keys_path: Option<PathBuf>,
+    /// Audit log file (not logged if `None`)
+    pub audit_path: Option<PathBuf>,
+    /// Upstream server base URLs for federated districts
+    pub upstreams: Vec<(String, String)>,
+    /// Upstream server base URL for all districts (proxy mode)
+    pub proxy_upstream: Option<String>,
+    /// Disk cache directory for proxied responses
+    pub proxy_cache_path: Option<PathBuf>,
+    /// Maximum size of disk cache, in bytes
+    pub proxy_cache_size: u64,
+    /// Maximum age of disk cache entries
+    pub proxy_cache_age: Duration,
+    /// Time zone of sample intervals
+    pub time_zone: Zone,
+    /// Time zones of districts (overriding `time_zone`)
+    pub district_zones: Vec<(String, Zone)>,
+    /// Valid sample periods (seconds)
+    pub sample_periods: Vec<u32>,
+    /// Hide extensions with deprecated periods from listings
+    pub hide_deprecated: bool,
+    /// Synthesize missing volume by summing length class counts
+    pub class_volume: bool,
+    /// Valid weather (RWIS) sample periods (seconds)
+    pub weather_periods: Vec<u32>,
+    /// Detector scan rate of controllers (Hz)
+    pub scan_rate: u32,
+    /// Scan rates of controllers (overriding `scan_rate`)
+    pub controller_scan_rates: Vec<(String, u32)>,
+    /// Handling of sample files with invalid length
+    pub length_check: LengthCheck,
+    /// Missing value markers of sample types (by prefix)
+    pub missing_markers: Vec<(String, Marker)>,
+    /// Compatibility with legacy (Java) servlet responses
+    pub legacy: bool,
+    /// Add `Server-Timing` headers with pipeline stage timings
+    pub server_timing: bool,
+    /// Directory for export job results (jobs disabled if `None`)
+    pub jobs_path: Option<PathBuf>,
+    /// Maximum number of export job worker threads
+    pub job_workers: usize,
+    /// Maximum number of threads for per-day computations
+    pub day_workers: usize,
+    /// Days after which a range is memoized, even if not finalized
+    pub settle_days: u32,
+    /// Maximum number of queued export jobs
+    pub job_queue: usize,
+    /// Maximum total size of export job output, in bytes
+    pub job_quota: u64,
+    /// Time to keep finished export jobs
+    pub job_ttl: Duration,
+    /// Policy for deprecated routes
+    pub deprecated_routes: DeprecationPolicy,
+    /// Sunset date of deprecated routes
+    pub sunset: Option<NaiveDate>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_addr: "0.0.0.0:8080".into(),
+            traffic_path: "/var/lib/iris/traffic".into(),
+            metro_path: "/var/lib/iris/metro_config".into(),
+            district_default: "tms".into(),
+            url_prefix: "/trafdat".into(),
+            trusted_proxies: Vec::new(),
+            cache_ttl: Duration::ZERO,
+            hot_dates: Vec::new(),
+            stats_path: None,
+            signing_key: None,
+            robots_txt: None,
+            zip_handles: 64,
+            max_entry_size: 64 * 1024 * 1024,
+            max_parts_size: 256 * 1024 * 1024,
+            zstd_level: 19,
+            entry_patterns: vec![
+                "{sid}.{ext}".into(),
+                "{date}/{sid}.{ext}".into(),
+            ],
+            nats_url: None,
+            nats_subject: "trafdat.archive".into(),
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            reports_path: None,
+            admin_token: None,
+            api_keys_path: None,
+            audit_path: None,
+            upstreams: Vec::new(),
+            proxy_upstream: None,
+            proxy_cache_path: None,
+            proxy_cache_size: 1024 * 1024 * 1024,
+            proxy_cache_age: Duration::from_secs(24 * 60 * 60),
+            time_zone: Zone::Local,
+            district_zones: Vec::new(),
+            sample_periods: SAMPLE_PERIODS.to_vec(),
+            hide_deprecated: false,
+            class_volume: false,
+            weather_periods: WEATHER_PERIODS.to_vec(),
+            scan_rate: 60,
+            controller_scan_rates: Vec::new(),
+            length_check: LengthCheck::Strict,
+            missing_markers: Vec::new(),
+            legacy: false,
+            server_timing: false,
+            jobs_path: None,
+            job_workers: 2,
+            day_workers: 8,
+            settle_days: 2,
+            job_queue: 16,
+            job_quota: 10 * 1024 * 1024 * 1024,
+            job_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            deprecated_routes: DeprecationPolicy::Warn,
+            sunset: None,
+        }
+    }
+}
+
+impl Config {
+    /// Create configuration from `TRAFDAT_*` environment variables.
+    ///
+    /// Variables which are not set keep their default values.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = Config::default();
+        if let Ok(addr) = env::var("TRAFDAT_BIND_ADDR") {
+            config.bind_addr = addr;
+        }
+        if let Some(path) = env::var_os("TRAFDAT_TRAFFIC_PATH") {
+            config.traffic_path = path.into();
+        }
+        if let Some(path) = env::var_os("TRAFDAT_METRO_PATH") {
+            config.metro_path = path.into();
+        }
+        if let Ok(district) = env::var("TRAFDAT_DISTRICT") {
+            config.district_default = district;
+        }
+        if let Ok(prefix) = env::var("TRAFDAT_URL_PREFIX") {
+            config.url_prefix = normalize_prefix(&prefix)?;
+        }
+        if let Ok(proxies) = env::var("TRAFDAT_TRUSTED_PROXIES") {
+            config.trusted_proxies = parse_addrs(&proxies)?;
+        }
+        if let Ok(ttl) = env::var("TRAFDAT_CACHE_TTL") {
+            let secs = ttl
+                .parse()
+                .map_err(|_| Error::Config(format!("cache ttl: {}", ttl)))?;
+            config.cache_ttl = Duration::from_secs(secs);
+        }
+        if let Ok(handles) = env::var("TRAFDAT_ZIP_HANDLES") {
+            config.zip_handles = handles.parse().map_err(|_| {
+                Error::Config(format!("zip handles: {}", handles))
+            })?;
+        }
+        if let Ok(size) = env::var("TRAFDAT_MAX_ENTRY_SIZE") {
+            let mb: u64 = size.parse().map_err(|_| {
+                Error::Config(format!("max entry size: {}", size))
+            })?;
+            config.max_entry_size = mb.saturating_mul(1024 * 1024);
+        }
+        if let Ok(size) = env::var("TRAFDAT_MAX_PARTS_SIZE") {
+            let mb: u64 = size.parse().map_err(|_| {
+                Error::Config(format!("max parts size: {}", size))
+            })?;
+            config.max_parts_size = mb.saturating_mul(1024 * 1024);
+        }
+        if let Ok(patterns) = env::var("TRAFDAT_ENTRY_PATTERNS") {
+            config.entry_patterns = parse_entry_patterns(&patterns)?;
+        }
+        if let Ok(level) = env::var("TRAFDAT_ZSTD_LEVEL") {
+            config.zstd_level = level
+                .parse()
+                .ok()
+                .filter(|l| (1..=22).contains(l))
+                .ok_or_else(|| {
+                    Error::Config(format!("zstd level: {}", level))
+                })?;
+        }
+        if let Ok(hot) = env::var("TRAFDAT_HOT_DATES") {
+            config.hot_dates = hot
+                .split(',')
+                .filter(|h| !h.trim().is_empty())
+                .map(HotDates::parse)
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(path) = env::var_os("TRAFDAT_STATS_PATH") {
+            config.stats_path = Some(path.into());
+        }
+        if let Ok(key) = env::var("TRAFDAT_SIGNING_KEY") {
+            config.signing_key = Some(key);
+        }
+        if let Some(path) = env::var_os("TRAFDAT_ROBOTS_PATH") {
+            config.robots_txt = Some(fs::read_to_string(path)?);
+        }
+        if let Ok(url) = env::var("TRAFDAT_NATS_URL") {
+            config.nats_url = Some(url);
+        }
+        if let Ok(subject) = env::var("TRAFDAT_NATS_SUBJECT") {
+            config.nats_subject = subject;
+        }
+        if let Ok(hooks) = env::var("TRAFDAT_WEBHOOKS") {
+            config.webhooks = parse_webhooks(&hooks)?;
+        }
+        if let Ok(secret) = env::var("TRAFDAT_WEBHOOK_SECRET") {
+            config.webhook_secret = Some(secret);
+        }
+        if let Some(path) = env::var_os("TRAFDAT_REPORTS_PATH") {
+            config.reports_path = Some(path.into());
+        }
+        if let Ok(token) = env::var("TRAFDAT_ADMIN_TOKEN") {
+            config.admin_token = Some(token);
+        }
+        if let Some(path) = env::var_os("TRAFDAT_API_KEYS") {
+            config.api_keys_path = Some(path.into());
+        }
+        if let Some(path) = env::var_os("TRAFDAT_AUDIT_PATH") {
+            config.audit_path = Some(path.into());
+        }
+        if let Ok(upstreams) = env::var("TRAFDAT_UPSTREAMS") {
+            config.upstreams = parse_upstreams(&upstreams)?;
+        }
+        if let Ok(url) = env::var("TRAFDAT_PROXY_UPSTREAM") {
+            parse_url(&url)?;
+            config.proxy_upstream = Some(url);
+        }
+        if let Some(path) = env::var_os("TRAFDAT_PROXY_CACHE_PATH") {
+            config.proxy_cache_path = Some(path.into());
+        }
+        if let Ok(size) = env::var("TRAFDAT_PROXY_CACHE_SIZE") {
+            let mb: u64 = size.parse().map_err(|_| {
+                Error::Config(format!("proxy cache size: {}", size))
+            })?;
+            config.proxy_cache_size = mb.saturating_mul(1024 * 1024);
+        }
+        if let Ok(age) = env::var("TRAFDAT_PROXY_CACHE_AGE") {
+            let secs = age.parse().map_err(|_| {
+                Error::Config(format!("proxy cache age: {}", age))
+            })?;
+            config.proxy_cache_age = Duration::from_secs(secs);
+        }
+        if let Ok(zone) = env::var("TRAFDAT_TIME_ZONE") {
+            config.time_zone = Zone::parse(&zone)?;
+        }
+        if let Ok(zones) = env::var("TRAFDAT_DISTRICT_ZONES") {
+            config.district_zones = parse_zones(&zones)?;
+        }
+        if let Ok(periods) = env::var("TRAFDAT_SAMPLE_PERIODS") {
+            config.sample_periods = parse_periods(&periods)?;
+        }
+        if let Some(hide) =
+            env_bool("TRAFDAT_HIDE_DEPRECATED", "hide deprecated")?
+        {
+            config.hide_deprecated = hide;
+        }
+        if let Some(class) = env_bool("TRAFDAT_CLASS_VOLUME", "class volume")? {
+            config.class_volume = class;
+        }
+        if let Ok(periods) = env::var("TRAFDAT_WEATHER_PERIODS") {
+            config.weather_periods = parse_periods(&periods)?;
+        }
+        if let Ok(rate) = env::var("TRAFDAT_SCAN_RATE") {
+            config.scan_rate = parse_scan_rate(&rate)?;
+        }
+        if let Ok(rates) = env::var("TRAFDAT_CONTROLLER_SCAN_RATES") {
+            config.controller_scan_rates = parse_scan_rates(&rates)?;
+        }
+        if let Ok(markers) = env::var("TRAFDAT_MISSING_MARKERS") {
+            config.missing_markers = parse_markers(&markers)?;
+        }
+        if let Ok(compat) = env::var("TRAFDAT_COMPAT") {
+            config.legacy = match compat.as_str() {
+                "current" => false,
+                "legacy" => true,
+                _ => return Err(Error::Config(format!("compat: {}", compat))),
+            };
+        }
+        if let Some(timing) =
+            env_bool("TRAFDAT_SERVER_TIMING", "server timing")?
+        {
+            config.server_timing = timing;
+        }
+        if let Some(path) = env::var_os("TRAFDAT_JOBS_PATH") {
+            config.jobs_path = Some(path.into());
+        }
+        if let Ok(workers) = env::var("TRAFDAT_JOB_WORKERS") {
+            config.job_workers =
+                workers.parse().ok().filter(|w| *w > 0).ok_or_else(|| {
+                    Error::Config(format!("job workers: {}", workers))
+                })?;
+        }
+        if let Ok(workers) = env::var("TRAFDAT_DAY_WORKERS") {
+            config.day_workers =
+                workers.parse().ok().filter(|w| *w > 0).ok_or_else(|| {
+                    Error::Config(format!("day workers: {}", workers))
+                })?;
+        }
+        if let Ok(days) = env::var("TRAFDAT_SETTLE_DAYS") {
+            config.settle_days = days
+                .parse()
+                .map_err(|_| Error::Config(format!("settle days: {}", days)))?;
+        }
+        if let Ok(queue) = env::var("TRAFDAT_JOB_QUEUE") {
+            config.job_queue = queue
+                .parse()
+                .map_err(|_| Error::Config(format!("job queue: {}", queue)))?;
+        }
+        if let Ok(quota) = env::var("TRAFDAT_JOB_QUOTA") {
+            let mb: u64 = quota
+                .parse()
+                .map_err(|_| Error::Config(format!("job quota: {}", quota)))?;
+            config.job_quota = mb.saturating_mul(1024 * 1024);
+        }
+        if let Ok(ttl) = env::var("TRAFDAT_JOB_TTL") {
+            let secs = ttl
+                .parse()
+                .map_err(|_| Error::Config(format!("job ttl: {}", ttl)))?;
+            config.job_ttl = Duration::from_secs(secs);
+        }
+        if let Ok(check) = env::var("TRAFDAT_LENGTH_CHECK") {
+            config.length_check = match check.as_str() {
+                "strict" => LengthCheck::Strict,
+                "lenient" => LengthCheck::Lenient,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "length check: {}",
+                        check
+                    )))
+                }
+            };
+        }
+        if let Ok(policy) = env::var("TRAFDAT_DEPRECATED_ROUTES") {
+            config.deprecated_routes = match policy.as_str() {
+                "warn" => DeprecationPolicy::Warn,
+                "disabled" => DeprecationPolicy::Disabled,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "deprecated routes: {}",
+                        policy
+                    )))
+                }
+            };
+        }
+        if let Ok(sunset) = env::var("TRAFDAT_SUNSET") {
+            let date = NaiveDate::parse_from_str(&sunset, DATE_FMT)
+                .map_err(|_| Error::Config(format!("sunset: {}", sunset)))?;
+            config.sunset = Some(date);
+        }
+        Ok(config)
+    }
+
+    /// Get the time zone of a district
+    pub fn zone(&self, district: &str) -> &Zone {
+        self.district_zones
+            .iter()
+            .find(|(d, _)| d == district)
+            .map_or(&self.time_zone, |(_, zone)| zone)
+    }
+
+    /// Get sample periods shown in listings
+    pub fn listed_periods(&self) -> Vec<u32> {
+        self.sample_periods
+            .iter()
+            .copied()
+            .filter(|p| !(self.hide_deprecated && is_deprecated_period(*p)))
+            .collect()
+    }
+
+    /// Get the missing value marker of a sample type prefix
+    pub fn marker(&self, prefix: &str) -> Marker {
+        self.missing_markers
+            .iter()
+            .find(|(p, _)| p == prefix)
+            .map_or(Marker::Negative, |(_, marker)| *marker)
+    }
+
+    /// Get the detector scan rate of a controller (Hz)
+    pub fn scan_rate(&self, controller: Option<&str>) -> u32 {
+        self.controller_scan_rates
+            .iter()
+            .find(|(c, _)| Some(c.as_str()) == controller)
+            .map_or(self.scan_rate, |(_, rate)| *rate)
+    }
+}
+
+/// Get a boolean (`true` or `false`) environment variable, if set
+fn env_bool(var: &str, name: &str) -> Result<Option<bool>, Error> {
+    match env::var(var) {
+        Ok(val) => match val.as_str() {
+            "true" => Ok(Some(true)),
+            "false" => Ok(Some(false)),
+            _ => Err(Error::Config(format!("{}: {}", name, val))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parse a comma-separated list of IP addresses
+fn parse_addrs(addrs: &str) -> Result<Vec<IpAddr>, Error> {
+    addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| {
+            a.parse()
+                .map_err(|_| Error::Config(format!("trusted proxy: {}", a)))
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of webhook URLs
+fn parse_webhooks(hooks: &str) -> Result<Vec<String>, Error> {
+    hooks
+        .split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(|h| parse_url(h).map(|_| h.to_string()))
+        .collect()
+}
+
+/// Parse a comma-separated list of `district=url` upstream servers
+fn parse_upstreams(upstreams: &str) -> Result<Vec<(String, String)>, Error> {
+    upstreams
+        .split(',')
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+        .map(|u| {
+            let (district, url) = u
+                .split_once('=')
+                .filter(|(d, _)| !d.is_empty() && !d.contains('/'))
+                .ok_or_else(|| Error::Config(format!("upstream: {}", u)))?;
+            parse_url(url)?;
+            Ok((district.to_string(), url.to_string()))
+        })
+        .collect()
+}
+
+/// Parse a semicolon-separated list of `district=zone` time zones.
+///
+/// Semicolons are used since POSIX `TZ` strings contain commas.
+fn parse_zones(zones: &str) -> Result<Vec<(String, Zone)>, Error> {
+    zones
+        .split(';')
+        .map(str::trim)
+        .filter(|z| !z.is_empty())
+        .map(|z| {
+            let (district, zone) = z
+                .split_once('=')
+                .filter(|(d, _)| !d.is_empty())
+                .ok_or_else(|| Error::Config(format!("time zone: {}", z)))?;
+            Ok((district.to_string(), Zone::parse(zone)?))
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of sample periods (seconds).
+///
+/// Each period must evenly divide a day.
+fn parse_periods(periods: &str) -> Result<Vec<u32>, Error> {
+    periods
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            p.parse::<u32>()
+                .ok()
+                .filter(|p| *p > 0 && DAY_SECS.is_multiple_of(u64::from(*p)))
+                .ok_or_else(|| Error::Config(format!("sample period: {}", p)))
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of `prefix=value` missing value markers.
+///
+/// Values must fit in the sample type (negative values are always missing).
+fn parse_markers(markers: &str) -> Result<Vec<(String, Marker)>, Error> {
+    markers
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(|m| {
+            let bad = || Error::Config(format!("missing marker: {}", m));
+            let (prefix, val) = m.split_once('=').ok_or_else(bad)?;
+            let bytes = prefix_bytes(prefix).ok_or_else(bad)?;
+            let max = match bytes {
+                2 => i32::from(i16::MAX),
+                _ => i32::from(i8::MAX),
+            };
+            let val = val
+                .parse::<i32>()
+                .ok()
+                .filter(|v| (0..=max).contains(v))
+                .ok_or_else(bad)?;
+            Ok((prefix.to_string(), Marker::Value(val)))
+        })
+        .collect()
+}
+
+/// Parse a detector scan rate (Hz)
+fn parse_scan_rate(rate: &str) -> Result<u32, Error> {
+    rate.parse()
+        .ok()
+        .filter(|r| *r > 0)
+        .ok_or_else(|| Error::Config(format!("scan rate: {}", rate)))
+}
+
+/// Parse a comma-separated list of zip entry name patterns.
+///
+/// Each pattern must contain `{sid}` and `{ext}`, and be a relative name.
+fn parse_entry_patterns(patterns: &str) -> Result<Vec<String>, Error> {
+    let patterns: Vec<String> = patterns
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            if p.contains("{sid}") && p.contains("{ext}") && !p.starts_with('/')
+            {
+                Ok(p.to_string())
+            } else {
+                Err(Error::Config(format!("entry pattern: {}", p)))
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    if patterns.is_empty() {
+        return Err(Error::Config("entry patterns: (none)".into()));
+    }
+    Ok(patterns)
+}
+
+/// Parse a comma-separated list of `controller=rate` scan rates
+fn parse_scan_rates(rates: &str) -> Result<Vec<(String, u32)>, Error> {
+    rates
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| {
+            let (controller, rate) = r
+                .split_once('=')
+                .filter(|(c, _)| !c.is_empty())
+                .ok_or_else(|| Error::Config(format!("scan rate: {}", r)))?;
+            Ok((controller.to_string(), parse_scan_rate(rate)?))
+        })
+        .collect()
+}
+
+/// Normalize a URL prefix, removing trailing slashes.
+///
+/// An empty prefix (or `/`) mounts routes at the root.
+pub fn normalize_prefix(prefix: &str) -> Result<String, Error> {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() || (prefix.starts_with('/') && !prefix.contains("//"))
+    {
+        Ok(prefix.to_string())
+    } else {
+        Err(Error::Config(format!("url prefix: {}", prefix)))
+    }
+}
+
+/// Application state shared by all handlers
+pub struct AppState {
+    /// Server configuration
+    pub config: Config,
+    /// Traffic archive storage
+    pub storage: Storage,
+    /// Checked metro_config documents
+    pub metro: MetroCache,
+    /// Server metrics
+    pub metrics: Metrics,
+    /// Cache of derived responses
+    pub cache: ResponseCache,
+    /// Access statistics
+    pub stats: AccessStats,
+    /// Pool of open zip archives
+    pub zips: ZipPool,
+    /// Storage circuit breakers
+    pub health: Health,
+    /// Sample output formats
+    pub formats: FormatRegistry,
+    /// Archive watcher status
+    pub watch: WatchState,
+    /// Recent server errors
+    pub errors: ErrorLog,
+    /// Upstream servers for federated districts
+    pub federation: Federation,
+    /// Disk cache of proxied responses
+    pub disk_cache: Option<DiskCache>,
+    /// Export job queue
+    pub jobs: JobQueue,
+    /// API keys
+    pub api_keys: ApiKeys,
+    /// Pool for per-day computations
+    pub days: DayPool,
+    /// Memoized complete dates of historical ranges
+    pub complete_dates: Memo<RangeKey, Vec<String>>,
+    /// Memoized average daily traffic of historical ranges
+    pub aadt: Memo<RangeKey, Vec<SensorAadt>>,
+    /// Memoized percentiles of historical ranges
+    pub percentiles: Memo<(RangeKey, Vec<u32>), Vec<SensorPercentiles>>,
+    /// Cached digests of archive files
+    pub digests: DigestCache,
+}
+
+impl AppState {
+    /// Create application state from configuration
+    pub fn new(config: Config) -> Self {
+        let storage = Storage::new(config.traffic_path.clone());
+        storage.detect();
+        let cache = ResponseCache::new(config.cache_ttl);
+        let zips = ZipPool::new(config.zip_handles);
+        let days = DayPool::new(config.day_workers);
+        let health = Health::new(&config.traffic_path, &config.metro_path);
+        // upstream URLs are checked when parsing configuration
+        let federation = Federation::new(
+            &config.upstreams,
+            config.proxy_upstream.as_deref(),
+        )
+        .unwrap_or_default();
+        AppState {
+            config,
+            storage,
+            metro: MetroCache::default(),
+            metrics: Metrics::default(),
+            cache,
+            stats: AccessStats::default(),
+            zips,
+            health,
+            formats: FormatRegistry::default(),
+            watch: WatchState::default(),
+            errors: ErrorLog::default(),
+            federation,
+            disk_cache: None,
+            jobs: JobQueue::default(),
+            api_keys: ApiKeys::default(),
+            days,
+            complete_dates: Memo::default(),
+            aadt: Memo::default(),
+            percentiles: Memo::default(),
+            digests: DigestCache::default(),
+        }
+    }
+}
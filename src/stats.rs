@@ -0,0 +1,172 @@
+// stats.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::apikey;
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::state::AppState;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::{web, HttpResponse};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Interval between persisting statistics
+const PERSIST_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Access counts for one district and date
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize)]
+struct Counts {
+    /// Number of requests
+    requests: u64,
+    /// Body bytes served
+    bytes: u64,
+}
+
+/// Access statistics row (JSON)
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub(crate) struct StatsRow {
+    district: String,
+    date: String,
+    /// API key identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(flatten)]
+    counts: Counts,
+}
+
+/// Access statistics key (district, date and API key identity)
+type StatsKey = (String, String, Option<String>);
+
+/// Access statistics by district, date and API key
+#[derive(Default)]
+pub struct AccessStats {
+    /// Counts by key
+    counts: Mutex<BTreeMap<StatsKey, Counts>>,
+}
+
+/// Check if a path segment names a date (with optional extension)
+pub fn is_date_segment(seg: &str) -> bool {
+    let stem = seg.split('.').next().unwrap_or(seg);
+    stem.len() == 8 && stem.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Get the district and date of a request path (after URL prefix)
+fn district_date(path: &str) -> Option<(&str, &str)> {
+    let mut segs = path.split('/').filter(|s| !s.is_empty());
+    let district = segs.next()?;
+    let date = segs.find(|s| is_date_segment(s))?;
+    Some((district, &date[..8]))
+}
+
+impl AccessStats {
+    /// Load statistics from a file (missing file is empty)
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let rows: Vec<StatsRow> = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|_| Error::Config(path.display().to_string()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let counts = rows
+            .into_iter()
+            .map(|row| ((row.district, row.date, row.key), row.counts))
+            .collect();
+        Ok(AccessStats {
+            counts: Mutex::new(counts),
+        })
+    }
+
+    /// Record one request
+    fn record(
+        &self,
+        district: &str,
+        date: &str,
+        key: Option<String>,
+        bytes: u64,
+    ) {
+        let mut counts = self.counts.lock().unwrap();
+        let counts = counts
+            .entry((district.to_string(), date.to_string(), key))
+            .or_default();
+        counts.requests += 1;
+        counts.bytes += bytes;
+    }
+
+    /// Render statistics as JSON
+    fn to_json(&self) -> Result<String, Error> {
+        let rows: Vec<StatsRow> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((district, date, key), counts)| StatsRow {
+                district: district.clone(),
+                date: date.clone(),
+                key: key.clone(),
+                counts: *counts,
+            })
+            .collect();
+        Ok(serde_json::to_string(&rows)?)
+    }
+
+    /// Persist statistics to a file
+    pub fn persist(&self, path: &Path) -> Result<(), Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.to_json()?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Record access statistics for a successful response
+pub fn record<B: MessageBody>(res: &ServiceResponse<B>) {
+    if !res.status().is_success() {
+        return;
+    }
+    let state = match res.request().app_data::<web::Data<AppState>>() {
+        Some(state) => state,
+        None => return,
+    };
+    let path = res.request().path();
+    let path = path
+        .strip_prefix(state.config.url_prefix.as_str())
+        .unwrap_or(path);
+    if let Some((district, date)) = district_date(path) {
+        let bytes = match res.response().body().size() {
+            BodySize::Sized(n) => n,
+            _ => 0,
+        };
+        let key = apikey::identity(state, res.request());
+        state.stats.record(district, date, key, bytes);
+    }
+}
+
+/// Spawn a background thread to persist statistics periodically
+pub fn spawn_persist(state: web::Data<AppState>) {
+    if let Some(path) = state.config.stats_path.clone() {
+        thread::spawn(move || loop {
+            thread::sleep(PERSIST_INTERVAL);
+            if let Err(e) = state.stats.persist(&path) {
+                warn!("persist stats {:?}: {}", path, e);
+            }
+        });
+    }
+}
+
+/// Handle request for access statistics
+pub async fn handle_stats(
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(state.stats.to_json()?))
+}
@@ -0,0 +1,158 @@
+// storage.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Traffic archive storage.
+//
+// Different IRIS versions wrote slightly different archive layouts: date
+// directories and `.traffic` files inside year directories, or directly in
+// the district directory.  The layout of each district is detected at
+// startup (or when first used, for districts added later), and paths are
+// built accordingly.
+//
+// Some agencies also consolidate a whole year into one `{yyyy}.traffic`
+// archive in the district directory, with entries named `{date}/{file}`.
+//
+use crate::route::{is_valid_date, is_valid_year};
+use crate::schema::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Archive layout of a district
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Dates within year directories (`{district}/{yyyy}/{date}`)
+    #[default]
+    Yearly,
+    /// Dates without year directories (`{district}/{date}`)
+    Flat,
+}
+
+impl Layout {
+    /// Detect the layout of a district directory.
+    ///
+    /// Returns `None` if the directory has no years or dates.
+    fn detect(path: &Path) -> Option<Self> {
+        let mut flat = false;
+        for ent in read_dir(path).ok()?.flatten() {
+            let is_dir = ent.file_type().is_ok_and(|tp| tp.is_dir());
+            let name = match ent.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if is_dir && is_valid_year(&name) {
+                return Some(Layout::Yearly);
+            }
+            let date = if is_dir {
+                Some(name.as_str())
+            } else {
+                name.strip_suffix(".traffic")
+            };
+            flat |= date.is_some_and(is_valid_date);
+        }
+        flat.then_some(Layout::Flat)
+    }
+}
+
+/// Traffic archive storage
+pub struct Storage {
+    /// Base traffic archive path
+    base: PathBuf,
+    /// Detected layouts by district
+    layouts: RwLock<HashMap<String, Layout>>,
+}
+
+impl Storage {
+    /// Create archive storage at a base path
+    pub fn new<P: Into<PathBuf>>(base: P) -> Self {
+        Storage {
+            base: base.into(),
+            layouts: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Get the base archive path
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Detect layouts of all districts
+    pub fn detect(&self) {
+        let entries = match read_dir(&self.base) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let mut layouts = self.layouts.write().unwrap();
+        for ent in entries.flatten() {
+            if !ent.file_type().is_ok_and(|tp| tp.is_dir()) {
+                continue;
+            }
+            if let Ok(district) = ent.file_name().into_string() {
+                if let Some(layout) = Layout::detect(&ent.path()) {
+                    layouts.insert(district, layout);
+                }
+            }
+        }
+    }
+
+    /// Get detected layouts of all districts
+    pub fn layouts(&self) -> BTreeMap<String, Layout> {
+        let layouts = self.layouts.read().unwrap();
+        layouts.iter().map(|(d, l)| (d.clone(), *l)).collect()
+    }
+
+    /// Get the layout of a district, detecting it if needed.
+    ///
+    /// Districts without years or dates are not recorded, and have the
+    /// default layout.
+    pub fn layout(&self, district: &str) -> Layout {
+        if let Some(layout) = self.layouts.read().unwrap().get(district) {
+            return *layout;
+        }
+        match Layout::detect(&self.district_path(district)) {
+            Some(layout) => {
+                let mut layouts = self.layouts.write().unwrap();
+                layouts.insert(district.to_string(), layout);
+                layout
+            }
+            None => Layout::default(),
+        }
+    }
+
+    /// Check if a district directory exists
+    pub fn has_district(&self, district: &str) -> bool {
+        self.district_path(district).is_dir()
+    }
+
+    /// Get path to a district directory
+    pub fn district_path(&self, district: &str) -> PathBuf {
+        self.base.join(district)
+    }
+
+    /// Get path to the directory containing dates of a year
+    pub fn year_path(&self, district: &str, year: &str) -> PathBuf {
+        let mut path = self.district_path(district);
+        if self.layout(district) == Layout::Yearly {
+            path.push(year);
+        }
+        path
+    }
+
+    /// Get path to a consolidated year archive
+    pub fn year_archive_path(&self, district: &str, year: &str) -> PathBuf {
+        let mut path = self.district_path(district);
+        path.push(format!("{}.traffic", year));
+        path
+    }
+
+    /// Get path to a date directory (without extension)
+    pub fn date_path(&self, district: &str, date: &str) -> PathBuf {
+        let mut path = self.year_path(district, &date[..4]);
+        path.push(date);
+        path
+    }
+}
@@ -0,0 +1,122 @@
+// swap.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Detector location changes between metro_config dates.
+//
+// When a detector is reassigned to another r_node or lane, its archived
+// samples come from a different physical location.  Checking each date's
+// metro_config in a range finds these changes, so that aggregations over
+// the range do not silently mix locations.
+//
+use crate::dates::DATE_FMT;
+use crate::error::Error;
+use crate::metro::{self, DetectorLocation};
+use crate::range::date_range;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use actix_web::HttpResponse;
+use chrono::NaiveDate;
+use log::warn;
+use serde::Serialize;
+
+/// Dates with the same detector location
+#[derive(JsonSchema, Serialize)]
+pub struct Span {
+    /// First date (yyyyMMdd)
+    pub start: String,
+    /// Last date (yyyyMMdd), inclusive
+    pub end: String,
+    /// Location, or `None` if unknown or not in metro_config
+    pub location: Option<DetectorLocation>,
+}
+
+/// Change of detector location
+#[derive(JsonSchema, Serialize)]
+pub struct Change {
+    /// First date (yyyyMMdd) with the new location
+    pub date: String,
+    /// Previous location
+    pub from: Option<DetectorLocation>,
+    /// New location
+    pub to: Option<DetectorLocation>,
+}
+
+/// Detector locations over a date range (JSON)
+#[derive(JsonSchema, Serialize)]
+pub struct Locations {
+    /// Number of metro_config dates checked
+    pub configs: usize,
+    /// Spans of dates with the same location
+    pub spans: Vec<Span>,
+    /// Location changes
+    pub changes: Vec<Change>,
+}
+
+/// Find detector location changes over a date range.
+///
+/// Each date uses the most recent metro_config on or before it; dates with
+/// none have an unknown location.
+pub fn location_changes(
+    state: &AppState,
+    det: &str,
+    start: NaiveDate,
+    days: usize,
+) -> Result<Locations, Error> {
+    let mut configs = 0;
+    let mut spans: Vec<Span> = vec![];
+    let mut changes = vec![];
+    // `None` until a metro_config has been checked
+    let mut current: Option<Option<DetectorLocation>> = None;
+    let first = start.format(DATE_FMT).to_string();
+    if let Some(date) = metro::config_before(state, &first) {
+        current = metro::detector_location(state, &date, det).ok();
+    }
+    for date in start.iter_days().take(days) {
+        let date = date.format(DATE_FMT).to_string();
+        if metro::has_config(state, &date) {
+            match metro::detector_location(state, &date, det) {
+                Ok(loc) => {
+                    configs += 1;
+                    if let Some(prev) = &current {
+                        if *prev != loc {
+                            changes.push(Change {
+                                date: date.clone(),
+                                from: prev.clone(),
+                                to: loc.clone(),
+                            });
+                        }
+                    }
+                    current = Some(loc);
+                }
+                Err(e) => warn!("detector location: {} {}", date, e),
+            }
+        }
+        let location = current.clone().flatten();
+        match spans.last_mut() {
+            Some(span) if span.location == location => span.end = date,
+            _ => spans.push(Span {
+                start: date.clone(),
+                end: date,
+                location,
+            }),
+        }
+    }
+    Ok(Locations {
+        configs,
+        spans,
+        changes,
+    })
+}
+
+/// Handle request for detector locations over a date range
+pub fn handle_locations(
+    state: &AppState,
+    sid: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let (start, days) = date_range(query)?;
+    let locations = location_changes(state, sid, start, days)?;
+    Ok(json_response(serde_json::to_string(&locations)?))
+}
@@ -0,0 +1,565 @@
+// sync.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Mirroring of archive years.
+//
+// The checksums manifest of a year lists each file with a SHA-256 digest,
+// plus digests of each block for files larger than one block.  A mirror
+// fetches only new or changed files, and for files it already has, only the
+// changed blocks (with ranged requests).  Files no longer listed are removed
+// from the mirror.
+//
+use crate::cache::cache_key;
+use crate::client::Client;
+use crate::error::Error;
+use crate::route::is_valid_year;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::signing;
+use crate::state::AppState;
+use crate::storage::Layout;
+use actix_files::NamedFile;
+use actix_web::rt::System;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Write;
+use std::fs::{
+    create_dir_all, read_dir, remove_dir, remove_file, rename, DirEntry, File,
+};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Maximum number of cached file digests
+const MAX_DIGESTS: usize = 1 << 20;
+
+/// Size of blocks with separate digests (bytes)
+pub const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Archive file checksum
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct FileSum {
+    /// Path relative to the year directory
+    pub name: String,
+    /// File size (bytes)
+    pub size: u64,
+    /// SHA-256 digest (hex)
+    pub sha256: String,
+    /// SHA-256 digests (hex) of each block, for files larger than one block
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<String>,
+}
+
+/// Plan for re-mirroring a year of archives
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    /// Files which must be fetched
+    pub fetch: Vec<FileSum>,
+    /// Local files no longer listed, which must be removed
+    pub remove: Vec<String>,
+    /// Number of unchanged files
+    pub unchanged: usize,
+    /// Bytes of unchanged files (not transferred)
+    pub bytes_saved: u64,
+}
+
+/// Summary of a mirror sync
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncReport {
+    /// Number of files fetched
+    pub fetched: usize,
+    /// Number of files removed
+    pub removed: usize,
+    /// Number of unchanged files
+    pub unchanged: usize,
+    /// Bytes transferred
+    pub bytes_fetched: u64,
+    /// Bytes not transferred (unchanged files and blocks)
+    pub bytes_saved: u64,
+}
+
+/// Digests of a file
+#[derive(Clone)]
+struct Digests {
+    /// SHA-256 digest (hex) of whole file
+    sha256: String,
+    /// SHA-256 digests (hex) of blocks, if more than one
+    blocks: Vec<String>,
+}
+
+/// Cached digests of an archive file
+struct CachedDigest {
+    /// File size (bytes)
+    size: u64,
+    /// File modified time
+    mtime: Option<SystemTime>,
+    /// File digests
+    digests: Digests,
+}
+
+/// Cache of archive file digests.
+///
+/// Digests are reused while a file's size and modified time are unchanged,
+/// so archive files are only read again after they are modified.
+#[derive(Default)]
+pub struct DigestCache {
+    /// Cached digests by path
+    digests: Mutex<HashMap<PathBuf, CachedDigest>>,
+}
+
+impl DigestCache {
+    /// Get the digests of a file, calculating them if needed
+    fn digests(
+        &self,
+        path: &Path,
+        size: u64,
+        mtime: Option<SystemTime>,
+    ) -> io::Result<Digests> {
+        if let Some(cached) = self.digests.lock().unwrap().get(path) {
+            if cached.size == size && cached.mtime == mtime {
+                return Ok(cached.digests.clone());
+            }
+        }
+        let digests = file_digests(path)?;
+        let mut cache = self.digests.lock().unwrap();
+        if cache.len() >= MAX_DIGESTS && !cache.contains_key(path) {
+            cache.clear();
+        }
+        let cached = CachedDigest {
+            size,
+            mtime,
+            digests: digests.clone(),
+        };
+        cache.insert(path.to_path_buf(), cached);
+        Ok(digests)
+    }
+}
+
+/// Encode a digest as hex
+fn hex(digest: &[u8]) -> String {
+    let mut res = String::new();
+    for b in digest {
+        write!(res, "{:02x}", b).unwrap();
+    }
+    res
+}
+
+/// Calculate the SHA-256 digest (hex) of data
+fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Calculate the digests of a file (whole file and blocks)
+fn file_digests(path: &Path) -> io::Result<Digests> {
+    let mut file = File::open(path)?;
+    let mut whole = Sha256::new();
+    let mut block = Sha256::new();
+    let mut in_block = 0;
+    let mut blocks = vec![];
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        whole.update(&buf[..n]);
+        let mut data = &buf[..n];
+        while !data.is_empty() {
+            let take = data.len().min(BLOCK_SIZE - in_block);
+            block.update(&data[..take]);
+            in_block += take;
+            data = &data[take..];
+            if in_block == BLOCK_SIZE {
+                blocks.push(hex(&block.finalize_reset()));
+                in_block = 0;
+            }
+        }
+    }
+    if in_block > 0 {
+        blocks.push(hex(&block.finalize()));
+    }
+    if blocks.len() < 2 {
+        blocks.clear();
+    }
+    Ok(Digests {
+        sha256: hex(&whole.finalize()),
+        blocks,
+    })
+}
+
+/// Check if a path name is safe (can not escape its base directory)
+fn is_safe_name(name: &str) -> bool {
+    !name
+        .split('/')
+        .any(|s| s.is_empty() || s == "." || s == "..")
+}
+
+/// Visit files in a directory (recursively), named relative to a base.
+///
+/// Entries with names not starting with `prefix` are skipped, without
+/// descending into directories.
+fn visit_files<F>(
+    base: &Path,
+    dir: &Path,
+    prefix: &str,
+    visit: &mut F,
+) -> io::Result<()>
+where
+    F: FnMut(String, &DirEntry) -> io::Result<()>,
+{
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = match path.strip_prefix(base) {
+            Ok(rel) => rel.to_string_lossy().replace('\\', "/"),
+            Err(_) => continue,
+        };
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            visit_files(base, &path, prefix, visit)?;
+        } else {
+            visit(name, &entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// List checksums of files in a directory with names starting with a prefix
+pub fn list_sums(
+    digests: &DigestCache,
+    dir: &Path,
+    prefix: &str,
+) -> io::Result<Vec<FileSum>> {
+    let mut sums = vec![];
+    visit_files(dir, dir, prefix, &mut |name, entry| {
+        let meta = entry.metadata()?;
+        let size = meta.len();
+        let Digests { sha256, blocks } =
+            digests.digests(&entry.path(), size, meta.modified().ok())?;
+        sums.push(FileSum {
+            name,
+            size,
+            sha256,
+            blocks,
+        });
+        Ok(())
+    })?;
+    sums.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(sums)
+}
+
+/// List checksums of archive files in a year
+fn year_sums(
+    state: &AppState,
+    district: &str,
+    year: &str,
+) -> Result<Vec<FileSum>, Error> {
+    let path = state.storage.year_path(district, year);
+    // a flat district directory has dates of every year
+    let prefix = match state.storage.layout(district) {
+        Layout::Flat => year,
+        Layout::Yearly => "",
+    };
+    match list_sums(&state.digests, &path, prefix) {
+        Ok(sums) => Ok(sums),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::NotFound),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Handle request for checksums of archive files in a year.
+///
+/// Files are read on a blocking thread, to avoid stalling a worker.
+pub async fn handle_checksums(
+    state: web::Data<AppState>,
+    req: &HttpRequest,
+    district: String,
+    year: String,
+) -> Result<HttpResponse, Error> {
+    let key = cache_key(req);
+    if let Some(res) = state.cache.lookup(&key) {
+        return Ok(res);
+    }
+    let st = state.clone();
+    let sums = web::block(move || year_sums(&st, &district, &year))
+        .await
+        .map_err(|e| Error::Io(io::Error::other(e)))??;
+    let res = json_response(serde_json::to_string(&sums)?);
+    Ok(state.cache.store(key, res))
+}
+
+/// Handle request for an archive file of a year, for mirroring.
+///
+/// Files are named as in the checksums manifest, and ranged requests are
+/// supported.  Signed URLs are required, as for whole-day archives.
+pub fn handle_file(
+    state: &AppState,
+    req: &HttpRequest,
+    district: &str,
+    year: &str,
+    name: &str,
+) -> Result<HttpResponse, Error> {
+    if state.config.legacy
+        || !is_valid_year(year)
+        || !is_safe_name(district)
+        || !is_safe_name(name)
+    {
+        return Err(Error::NotFound);
+    }
+    signing::check_request(state, req)?;
+    if state.storage.layout(district) == Layout::Flat && !name.starts_with(year)
+    {
+        return Err(Error::NotFound);
+    }
+    let path = state.storage.year_path(district, year).join(name);
+    if !path.is_file() {
+        return Err(Error::NotFound);
+    }
+    let file = match NamedFile::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NotFound)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let octet = "application/octet-stream".parse().unwrap();
+    Ok(file.set_content_type(octet).into_response(req))
+}
+
+/// Plan a sync of a local mirror (year directory) against a manifest
+pub fn plan(mirror: &Path, manifest: &[FileSum]) -> SyncPlan {
+    let mut res = SyncPlan::default();
+    for sum in manifest {
+        // Reject names which could escape the mirror directory
+        if !is_safe_name(&sum.name) {
+            continue;
+        }
+        let path = mirror.join(&sum.name);
+        let same = path.metadata().is_ok_and(|m| m.len() == sum.size)
+            && file_digests(&path).is_ok_and(|d| d.sha256 == sum.sha256);
+        if same {
+            res.unchanged += 1;
+            res.bytes_saved += sum.size;
+        } else {
+            res.fetch.push(sum.clone());
+        }
+    }
+    let listed: HashSet<&str> = manifest.iter().map(|s| &s.name[..]).collect();
+    // a missing mirror has nothing to remove
+    let _ = visit_files(mirror, mirror, "", &mut |name, _| {
+        if !listed.contains(&name[..]) {
+            res.remove.push(name);
+        }
+        Ok(())
+    });
+    res.remove.sort();
+    res
+}
+
+/// Remove a file from a mirror, along with any emptied directories
+fn remove_from_mirror(mirror: &Path, name: &str) -> io::Result<()> {
+    let path = mirror.join(name);
+    remove_file(&path)?;
+    for dir in path.ancestors().skip(1) {
+        if dir == mirror || remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Get the byte range of a block in a file
+fn block_range(sum: &FileSum, i: usize) -> Range<usize> {
+    let start = i * BLOCK_SIZE;
+    start..(start + BLOCK_SIZE).min(sum.size as usize)
+}
+
+/// Check if a block of a local copy matches the manifest
+fn block_matches(local: &[u8], sum: &FileSum, i: usize) -> bool {
+    let range = block_range(sum, i);
+    local
+        .get(range)
+        .is_some_and(|block| sha256(block) == sum.blocks[i])
+}
+
+/// Fetch changed blocks of a file, copying unchanged blocks from a local
+/// copy.
+///
+/// Consecutive changed blocks are fetched with one ranged request.  Returns
+/// the file data and number of bytes transferred.
+async fn fetch_blocks(
+    client: &Client,
+    district: &str,
+    year: &str,
+    sum: &FileSum,
+    local: &[u8],
+) -> Result<(Vec<u8>, u64), Error> {
+    let n_blocks = sum.blocks.len();
+    let mut data = Vec::with_capacity(sum.size as usize);
+    let mut bytes = 0;
+    let mut i = 0;
+    while i < n_blocks {
+        if block_matches(local, sum, i) {
+            data.extend_from_slice(&local[block_range(sum, i)]);
+            i += 1;
+            continue;
+        }
+        let mut j = i + 1;
+        while j < n_blocks && !block_matches(local, sum, j) {
+            j += 1;
+        }
+        let range = block_range(sum, i).start..block_range(sum, j - 1).end;
+        let range = range.start as u64..range.end as u64;
+        let chunk = client
+            .archive_file(district, year, &sum.name, Some(range))
+            .await?;
+        bytes += chunk.len() as u64;
+        data.extend_from_slice(&chunk);
+        i = j;
+    }
+    Ok((data, bytes))
+}
+
+/// Fetch a file into a mirror, returning the number of bytes transferred.
+///
+/// The file is verified against its digest before replacing a local copy.
+async fn fetch_file(
+    client: &Client,
+    district: &str,
+    year: &str,
+    mirror: &Path,
+    sum: &FileSum,
+) -> Result<u64, Error> {
+    let path = mirror.join(&sum.name);
+    let local = if sum.blocks.is_empty() {
+        None
+    } else {
+        std::fs::read(&path).ok()
+    };
+    let (data, bytes) = match local {
+        Some(local) => {
+            fetch_blocks(client, district, year, sum, &local).await?
+        }
+        None => {
+            let data =
+                client.archive_file(district, year, &sum.name, None).await?;
+            let bytes = data.len() as u64;
+            (data, bytes)
+        }
+    };
+    if data.len() as u64 != sum.size || sha256(&data) != sum.sha256 {
+        return Err(Error::Upstream(format!("{}: digest mismatch", sum.name)));
+    }
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut part = path.clone().into_os_string();
+    part.push(".part");
+    std::fs::write(&part, &data)?;
+    rename(&part, &path)?;
+    Ok(bytes)
+}
+
+/// Sync a local mirror (year directory) from a trafdat server.
+///
+/// New and changed files are fetched, and verified against the manifest.
+/// Files which are no longer listed are removed.
+pub async fn mirror(
+    client: &Client,
+    district: &str,
+    year: &str,
+    mirror: &Path,
+) -> Result<SyncReport, Error> {
+    let manifest = client.checksums(district, year).await?;
+    let plan = plan(mirror, &manifest);
+    let mut report = SyncReport {
+        unchanged: plan.unchanged,
+        bytes_saved: plan.bytes_saved,
+        ..Default::default()
+    };
+    for sum in &plan.fetch {
+        let bytes = fetch_file(client, district, year, mirror, sum).await?;
+        report.fetched += 1;
+        report.bytes_fetched += bytes;
+        report.bytes_saved += sum.size.saturating_sub(bytes);
+    }
+    for name in &plan.remove {
+        remove_from_mirror(mirror, name)?;
+        report.removed += 1;
+    }
+    Ok(report)
+}
+
+/// Run `sync` subcommand, printing a summary.
+///
+/// File requests are signed when `TRAFDAT_SIGNING_KEY` is set.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (url, district, year, dir) = match args {
+        [url, district, year, dir] => (url, district, year, dir),
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: sync <url> <district> <year> <mirror_dir>".into(),
+            ))
+        }
+    };
+    let mut client = Client::new(url)?;
+    if let Ok(key) = env::var("TRAFDAT_SIGNING_KEY") {
+        client = client.with_signing_key(key);
+    }
+    let report = System::new().block_on(mirror(
+        &client,
+        district,
+        year,
+        Path::new(dir),
+    ))?;
+    println!(
+        "{} files fetched ({} bytes), {} unchanged, {} removed, {} bytes saved",
+        report.fetched,
+        report.bytes_fetched,
+        report.unchanged,
+        report.removed,
+        report.bytes_saved
+    );
+    Ok(())
+}
+
+/// Run `sync-plan` subcommand, printing files to fetch and a summary
+pub fn run_plan(args: &[String]) -> Result<(), Error> {
+    let (mirror, manifest) = match args {
+        [mirror, manifest] => (mirror, manifest),
+        _ => {
+            return Err(Error::InvalidParam(
+                "usage: sync-plan <mirror_dir> <checksums.json>".into(),
+            ))
+        }
+    };
+    let manifest: Vec<FileSum> =
+        serde_json::from_reader(File::open(manifest)?)?;
+    let plan = plan(Path::new(mirror), &manifest);
+    let mut bytes = 0;
+    for sum in &plan.fetch {
+        println!("fetch {} {}", sum.name, sum.size);
+        bytes += sum.size;
+    }
+    for name in &plan.remove {
+        println!("remove {}", name);
+    }
+    println!(
+        "{} files to fetch ({} bytes), {} unchanged ({} bytes saved), {} to \
+         remove",
+        plan.fetch.len(),
+        bytes,
+        plan.unchanged,
+        plan.bytes_saved,
+        plan.remove.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,49 @@
+// template.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+
+/// Escape text for HTML content or attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Render an HTML template.
+///
+/// Each `{{name}}` placeholder is replaced with the HTML-escaped value of a
+/// matching variable; unknown placeholders are left unchanged.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        res.push_str(&rest[..start]);
+        let tail = &rest[start + 2..];
+        match tail.find("}}") {
+            Some(end) => {
+                let name = tail[..end].trim();
+                match vars.iter().find(|(n, _)| *n == name) {
+                    Some((_, val)) => res.push_str(&escape_html(val)),
+                    None => res.push_str(&rest[start..start + end + 4]),
+                }
+                rest = &tail[end + 2..];
+            }
+            None => {
+                res.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
@@ -0,0 +1,142 @@
+// timing.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Latency of sample data pipeline stages, per request.
+//
+// Request handlers are synchronous, so stage timings are collected in a
+// thread-local while a response is built, then reported in a
+// `Server-Timing` header and added to metrics.
+//
+use actix_web::http::header::HeaderName;
+use actix_web::HttpResponse;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// `Server-Timing` header name
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Sample data pipeline stage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Sensor rename and archive path resolution
+    Resolve,
+    /// Opening zip archives (or getting them from the handle pool)
+    ZipOpen,
+    /// Reading sample files and zip entries
+    EntryRead,
+    /// Decoding raw samples
+    Decode,
+    /// Encoding output formats
+    Encode,
+}
+
+/// Stage timings of one request
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    /// Elapsed time of each stage
+    elapsed: [Duration; Stage::ALL.len()],
+    /// Number of times each stage ran
+    counts: [u32; Stage::ALL.len()],
+    /// Total time building the response
+    total: Duration,
+}
+
+thread_local! {
+    /// Timings of the response being built on this thread
+    static CURRENT: RefCell<Option<Timings>> = const { RefCell::new(None) };
+}
+
+impl Stage {
+    /// All stages, in pipeline order
+    pub const ALL: [Stage; 5] = [
+        Stage::Resolve,
+        Stage::ZipOpen,
+        Stage::EntryRead,
+        Stage::Decode,
+        Stage::Encode,
+    ];
+
+    /// Get the stage name
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Resolve => "resolve",
+            Stage::ZipOpen => "zip_open",
+            Stage::EntryRead => "entry_read",
+            Stage::Decode => "decode",
+            Stage::Encode => "encode",
+        }
+    }
+
+    /// Get the stage index
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Timings {
+    /// Get the elapsed time and run count of a stage
+    pub fn stage(&self, stage: Stage) -> (Duration, u32) {
+        (self.elapsed[stage.index()], self.counts[stage.index()])
+    }
+
+    /// Get the total time building the response
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Get `Server-Timing` header value (durations in milliseconds)
+    pub fn header_value(&self) -> String {
+        let mut val = String::new();
+        for stage in Stage::ALL {
+            let (elapsed, count) = self.stage(stage);
+            if count > 0 {
+                write!(val, "{};dur={}, ", stage.as_str(), millis(elapsed))
+                    .unwrap();
+            }
+        }
+        write!(val, "total;dur={}", millis(self.total)).unwrap();
+        val
+    }
+
+    /// Add the `Server-Timing` header to a response
+    pub fn add_header(&self, res: &mut HttpResponse) {
+        if let Ok(val) = self.header_value().parse() {
+            res.headers_mut().insert(SERVER_TIMING, val);
+        }
+    }
+}
+
+/// Format a duration in milliseconds
+fn millis(dur: Duration) -> String {
+    format!("{:.3}", dur.as_secs_f64() * 1000.0)
+}
+
+/// Run a pipeline stage, adding its elapsed time to the current request
+pub fn time<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = f();
+    let elapsed = start.elapsed();
+    CURRENT.with(|cur| {
+        if let Some(timings) = cur.borrow_mut().as_mut() {
+            timings.elapsed[stage.index()] += elapsed;
+            timings.counts[stage.index()] += 1;
+        }
+    });
+    res
+}
+
+/// Build a response, collecting the timings of its stages
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Timings) {
+    let outer = CURRENT.with(|cur| cur.replace(Some(Timings::default())));
+    let start = Instant::now();
+    let res = f();
+    let total = start.elapsed();
+    let timings = CURRENT.with(|cur| cur.replace(outer));
+    let timings = Timings {
+        total,
+        ..timings.unwrap_or_default()
+    };
+    (res, timings)
+}
@@ -0,0 +1,202 @@
+// upstream.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Minimal HTTP client for upstream trafdat servers.
+//
+// Only plain HTTP is spoken: no TLS implementation is linked into trafdat,
+// so `https://` upstreams must be reached through a local TLS proxy.
+//
+use crate::error::Error;
+use crate::webhook::parse_url;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Timeout for upstream connections
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default maximum size of an upstream response (headers and body)
+const MAX_RESPONSE: u64 = 256 * 1024 * 1024;
+
+/// Upstream trafdat server
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    /// Base URL (for logging)
+    url: String,
+    /// Host and port
+    addr: String,
+    /// Base path (URL prefix, without trailing slash)
+    base: String,
+    /// Maximum size of a response (headers and body)
+    max_response: u64,
+}
+
+/// Response from an upstream server
+#[derive(Debug)]
+pub struct UpstreamResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// Content type
+    pub content_type: Option<String>,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+/// Make an upstream protocol error
+fn bad_response(url: &str, msg: &str) -> Error {
+    Error::Upstream(format!("{}: {}", url, msg))
+}
+
+/// Decode a chunked transfer-encoded body
+fn decode_chunked(url: &str, mut data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut body = vec![];
+    loop {
+        let eol = find_crlf(data).ok_or_else(|| bad_response(url, "chunk"))?;
+        let size = String::from_utf8_lossy(&data[..eol]);
+        let size = size.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| bad_response(url, "chunk size"))?;
+        data = &data[eol + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            return Err(bad_response(url, "truncated chunk"));
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+/// Find the first CRLF in a buffer
+fn find_crlf(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|w| w == b"\r\n")
+}
+
+/// Parse a complete HTTP response (read until the connection closed)
+pub fn parse_response(
+    url: &str,
+    data: &[u8],
+) -> Result<UpstreamResponse, Error> {
+    let end = data
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| bad_response(url, "truncated headers"))?;
+    let head = String::from_utf8_lossy(&data[..end]);
+    let body = &data[end + 4..];
+    let mut lines = head.split("\r\n");
+    let line = lines.next().unwrap_or("");
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| bad_response(url, line))?;
+    let mut content_type = None;
+    let mut length = None;
+    let mut chunked = false;
+    for header in lines {
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-type" => content_type = Some(value.to_string()),
+                "content-length" => length = value.parse::<usize>().ok(),
+                "transfer-encoding" => {
+                    chunked = value.eq_ignore_ascii_case("chunked")
+                }
+                _ => (),
+            }
+        }
+    }
+    let body = if chunked {
+        decode_chunked(url, body)?
+    } else {
+        match length {
+            Some(len) if body.len() < len => {
+                return Err(bad_response(url, "truncated body"))
+            }
+            Some(len) => body[..len].to_vec(),
+            None => body.to_vec(),
+        }
+    };
+    Ok(UpstreamResponse {
+        status,
+        content_type,
+        body,
+    })
+}
+
+/// Make a GET request header
+pub fn get_request(addr: &str, path: &str) -> String {
+    request_header(addr, path, "")
+}
+
+/// Make a GET request header for a byte range (`first` to `last` inclusive)
+pub fn range_request(addr: &str, path: &str, first: u64, last: u64) -> String {
+    let range = format!("Range: bytes={}-{}\r\n", first, last);
+    request_header(addr, path, &range)
+}
+
+/// Make a GET request header with extra header lines
+fn request_header(addr: &str, path: &str, extra: &str) -> String {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: trafdat\r\n{}\
+         Connection: close\r\n\r\n",
+        path, addr, extra
+    )
+}
+
+impl Upstream {
+    /// Create an upstream from a base URL (`http://host[:port]/prefix`)
+    pub fn new(url: &str) -> Result<Self, Error> {
+        if url.starts_with("https://") {
+            return Err(Error::Config(format!(
+                "upstream {}: https is not supported (use a local TLS proxy)",
+                url
+            )));
+        }
+        let (addr, path) = parse_url(url)?;
+        Ok(Upstream {
+            url: url.to_string(),
+            addr,
+            base: path.trim_end_matches('/').to_string(),
+            max_response: MAX_RESPONSE,
+        })
+    }
+
+    /// Set the maximum size of a response
+    pub fn with_max_response(mut self, bytes: u64) -> Self {
+        self.max_response = bytes;
+        self
+    }
+
+    /// Get the base URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Make a GET request for a path (with query) below the base URL.
+    ///
+    /// Responses larger than the maximum size are an error (`502 Bad
+    /// Gateway`), rather than being truncated.
+    pub fn get(&self, path: &str) -> Result<UpstreamResponse, Error> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| bad_response(&self.url, "no address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+            .map_err(|e| bad_response(&self.url, &e.to_string()))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let path = format!("{}{}", self.base, path);
+        stream.write_all(get_request(&self.addr, &path).as_bytes())?;
+        stream.flush()?;
+        let mut data = vec![];
+        stream.take(self.max_response + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > self.max_response {
+            return Err(bad_response(&self.url, "response too large"));
+        }
+        parse_response(&self.url, &data)
+    }
+}
@@ -0,0 +1,95 @@
+// vclass.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::schema::JsonSchema;
+use crate::sensor::{json_response, read_stored_series};
+use crate::state::AppState;
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// Vehicle length classes (extension prefix, class name)
+pub const LENGTH_CLASSES: &[(&str, &str)] = &[
+    ("vmc", "motorcycle"),
+    ("vs", "short"),
+    ("vm", "medium"),
+    ("vl", "long"),
+];
+
+/// Counts for one length class
+#[derive(JsonSchema, Serialize)]
+struct ClassCounts {
+    /// Class name
+    class: &'static str,
+    /// Sample file extension
+    ext: String,
+    /// Total count for the day
+    total: i64,
+    /// Share of all classified vehicles for the day
+    share: Option<f64>,
+    /// Per-interval counts
+    counts: Vec<Option<i32>>,
+}
+
+/// Vehicle classification data for one sensor on a date
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct Classes {
+    /// Sample period (seconds)
+    period: u32,
+    /// Counts for each class
+    classes: Vec<ClassCounts>,
+}
+
+/// Lookup class counts for the first sample period with any class data
+fn lookup_classes(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+) -> Result<Option<Classes>, Error> {
+    for period in &state.config.sample_periods {
+        let mut classes = vec![];
+        let mut prd = 0;
+        for (prefix, class) in LENGTH_CLASSES {
+            let ext = format!("{}{}", prefix, period);
+            if let Some(series) =
+                read_stored_series(state, district, date, sid, &ext)?
+            {
+                prd = series.period();
+                classes.push(ClassCounts {
+                    class,
+                    ext,
+                    total: series.total(),
+                    share: None,
+                    counts: series.values().to_vec(),
+                });
+            }
+        }
+        if !classes.is_empty() {
+            let sum: i64 = classes.iter().map(|c| c.total).sum();
+            if sum > 0 {
+                for c in classes.iter_mut() {
+                    c.share = Some(c.total as f64 / sum as f64);
+                }
+            }
+            return Ok(Some(Classes {
+                period: prd,
+                classes,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Handle request for vehicle classification counts
+pub fn handle_classes(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+) -> Result<HttpResponse, Error> {
+    let classes =
+        lookup_classes(state, district, date, sid)?.ok_or(Error::NotFound)?;
+    Ok(json_response(serde_json::to_string(&classes)?))
+}
@@ -0,0 +1,147 @@
+// verify.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Verification of `.traffic` archives.
+//
+// Every entry is read (checking its CRC), and entries which would be skipped
+// by listings or requests are reported: names which are not UTF-8 or have
+// unsafe characters, entries larger than the maximum size, and unreadable
+// entries.
+//
+use crate::error::Error;
+use crate::sensor::entry_file_name;
+use crate::state::Config;
+use serde::Serialize;
+use std::fs::{read_dir, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Entry skipped in an archive
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SkippedEntry {
+    /// Entry name, with non-printable bytes escaped
+    pub name: String,
+    /// Reason for skipping
+    pub reason: String,
+}
+
+/// Verification report for one archive
+#[derive(Debug, Default, Serialize)]
+pub struct ArchiveReport {
+    /// Number of file entries
+    pub entries: usize,
+    /// Skipped entries
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// Escape a raw entry name for display
+fn escape_name(raw: &[u8]) -> String {
+    raw.iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect()
+}
+
+/// Verify all entries of an archive.
+///
+/// * `path` Archive file.
+/// * `max_size` Maximum entry size, in bytes.
+pub fn verify_archive(
+    path: &Path,
+    max_size: u64,
+) -> Result<ArchiveReport, Error> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let mut report = ArchiveReport::default();
+    for i in 0..zip.len() {
+        let mut zf = match zip.by_index(i) {
+            Ok(zf) => zf,
+            Err(e) => {
+                report.skipped.push(SkippedEntry {
+                    name: format!("#{}", i),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if !zf.is_file() {
+            continue;
+        }
+        report.entries += 1;
+        let name = escape_name(zf.name_raw());
+        let reason = if let Err(reason) =
+            entry_file_name(zf.name(), zf.name_raw())
+        {
+            reason.to_string()
+        } else if zf.size() > max_size {
+            format!("too large ({} bytes)", zf.size())
+        } else {
+            match io::copy(&mut zf.by_ref().take(max_size), &mut io::sink()) {
+                Ok(_) => continue,
+                Err(e) => e.to_string(),
+            }
+        };
+        report.skipped.push(SkippedEntry { name, reason });
+    }
+    Ok(report)
+}
+
+/// Find archives in a path (file or directory, recursively), sorted
+fn find_archives(path: &Path, archives: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        archives.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut paths: Vec<PathBuf> = read_dir(path)?
+        .flatten()
+        .map(|ent| ent.path())
+        .filter(|p| p.is_dir() || p.extension().is_some_and(|e| e == "traffic"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        find_archives(&path, archives)?;
+    }
+    Ok(())
+}
+
+/// Run `verify` subcommand, printing a report for each archive
+///
+/// * `args` Archive files or directories of archives.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    if args.is_empty() {
+        return Err(Error::InvalidParam(
+            "usage: verify <archive|dir> [<archive|dir> ...]".into(),
+        ));
+    }
+    let config = Config::from_env()?;
+    let mut archives = vec![];
+    for arg in args {
+        find_archives(Path::new(arg), &mut archives)?;
+    }
+    let mut bad = 0;
+    for path in &archives {
+        match verify_archive(path, config.max_entry_size) {
+            Ok(report) => {
+                println!(
+                    "{}: {} entries, {} skipped",
+                    path.display(),
+                    report.entries,
+                    report.skipped.len()
+                );
+                for skip in &report.skipped {
+                    println!("  skipped {}: {}", skip.name, skip.reason);
+                }
+                if !report.skipped.is_empty() {
+                    bad += 1;
+                }
+            }
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                bad += 1;
+            }
+        }
+    }
+    println!("{} archives, {} with problems", archives.len(), bad);
+    Ok(())
+}
@@ -0,0 +1,208 @@
+// vlog.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use crate::sample::SampleSeries;
+use crate::sensor::read_sample;
+use crate::state::AppState;
+use std::ops::Range;
+
+/// Vehicle event log file extension
+pub const VLOG_EXT: &str = "vlog";
+
+/// Number of milliseconds in a day
+const DAY_MS: u32 = 86_400_000;
+
+/// One vehicle event from a vehicle event log
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VehicleEvent {
+    /// Duration detector was occupied (ms)
+    pub duration: Option<u32>,
+    /// Headway from start of previous vehicle (ms)
+    pub headway: Option<u32>,
+    /// Time stamp (ms since midnight)
+    pub stamp: Option<u32>,
+    /// Speed (mph)
+    pub speed: Option<u32>,
+    /// Duration of previous vehicle (ms)
+    pub prev_duration: Option<u32>,
+}
+
+impl VehicleEvent {
+    /// Get the gap from the end of the previous vehicle (ms)
+    pub fn gap(&self) -> Option<u32> {
+        match (self.headway, self.prev_duration) {
+            (Some(h), Some(d)) if h >= d => Some(h - d),
+            _ => None,
+        }
+    }
+}
+
+/// Vehicle event log for one sensor on a date
+#[derive(Clone, Debug, Default)]
+pub struct VehicleLog {
+    events: Vec<VehicleEvent>,
+}
+
+/// Parse an optional numeric field (`?` is unknown)
+fn parse_num(tok: Option<&str>) -> Option<u32> {
+    tok.and_then(|t| t.parse().ok())
+}
+
+/// Parse a time stamp field (HH:MM:SS)
+fn parse_stamp(tok: Option<&str>) -> Option<u32> {
+    let mut hms = tok?.split(':');
+    let h: u32 = hms.next()?.parse().ok()?;
+    let m: u32 = hms.next()?.parse().ok()?;
+    let s: u32 = hms.next()?.parse().ok()?;
+    if hms.next().is_none() && h < 24 && m < 60 && s < 60 {
+        Some(((h * 60 + m) * 60 + s) * 1000)
+    } else {
+        None
+    }
+}
+
+impl VehicleLog {
+    /// Parse a vehicle event log.
+    ///
+    /// Each line contains: duration (ms), headway (ms), time stamp
+    /// (HH:MM:SS, optional) and speed (mph, optional).  Unknown values are
+    /// `?`, and a line containing `*` indicates a log reset.
+    pub fn parse(text: &str) -> Self {
+        let mut events = vec![];
+        let mut stamp: Option<u32> = None;
+        let mut prev_duration: Option<u32> = None;
+        let mut reset = true;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "*" {
+                stamp = None;
+                prev_duration = None;
+                reset = true;
+                continue;
+            }
+            let mut toks = line.split_whitespace();
+            let duration = parse_num(toks.next());
+            let mut headway = parse_num(toks.next());
+            if reset {
+                headway = None;
+            }
+            let st = parse_stamp(toks.next());
+            let speed = parse_num(toks.next());
+            stamp = match (st, stamp, headway) {
+                (Some(st), _, _) => Some(st),
+                (None, Some(prev), Some(h)) => prev.checked_add(h),
+                _ => None,
+            }
+            .filter(|st| *st < DAY_MS);
+            events.push(VehicleEvent {
+                duration,
+                headway,
+                stamp,
+                speed,
+                prev_duration,
+            });
+            prev_duration = duration;
+            reset = false;
+        }
+        VehicleLog { events }
+    }
+
+    /// Get vehicle events
+    pub fn events(&self) -> &[VehicleEvent] {
+        &self.events
+    }
+
+    /// Get events grouped by interval.
+    ///
+    /// Events without a known time stamp are skipped.
+    pub fn binned(&self, period: u32) -> Vec<Vec<&VehicleEvent>> {
+        let n_bins = (DAY_MS / (period * 1000)) as usize;
+        let mut bins = vec![vec![]; n_bins];
+        for ev in &self.events {
+            if let Some(st) = ev.stamp {
+                let i = (st / (period * 1000)) as usize;
+                if i < n_bins {
+                    bins[i].push(ev);
+                }
+            }
+        }
+        bins
+    }
+
+    /// Get the range of intervals covered by the log
+    fn covered(&self, period: u32) -> Range<usize> {
+        let mut stamps = self.events.iter().filter_map(|ev| ev.stamp);
+        match stamps.next() {
+            Some(first) => {
+                let last = stamps.next_back().unwrap_or(first);
+                let p = period * 1000;
+                (first / p) as usize..(last / p) as usize + 1
+            }
+            None => 0..0,
+        }
+    }
+
+    /// Bin vehicle data by interval, using a function to summarize events
+    fn bin_with<F>(&self, period: u32, f: F) -> SampleSeries
+    where
+        F: Fn(&[&VehicleEvent]) -> Option<i32>,
+    {
+        let covered = self.covered(period);
+        let values = self
+            .binned(period)
+            .iter()
+            .enumerate()
+            .map(|(i, evs)| if covered.contains(&i) { f(evs) } else { None })
+            .collect();
+        SampleSeries::from_values(values)
+    }
+
+    /// Bin vehicle counts (volume)
+    pub fn bin_volume(&self, period: u32) -> SampleSeries {
+        self.bin_with(period, |evs| Some(evs.len() as i32))
+    }
+
+    /// Bin average vehicle speeds (mph)
+    pub fn bin_speed(&self, period: u32) -> SampleSeries {
+        self.bin_with(period, |evs| {
+            let speeds: Vec<u32> =
+                evs.iter().filter_map(|ev| ev.speed).collect();
+            if speeds.is_empty() {
+                None
+            } else {
+                let sum: u32 = speeds.iter().sum();
+                Some((sum as f32 / speeds.len() as f32).round() as i32)
+            }
+        })
+    }
+
+    /// Bin occupancy (hundredths of a percent)
+    pub fn bin_occupancy(&self, period: u32) -> SampleSeries {
+        self.bin_with(period, |evs| {
+            let ms: u32 = evs.iter().filter_map(|ev| ev.duration).sum();
+            let occ = ms as f32 * 10_000.0 / (period as f32 * 1000.0);
+            Some((occ.round() as i32).min(10_000))
+        })
+    }
+}
+
+/// Read vehicle event log for a sensor on a date
+pub fn read_vlog(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    sid: &str,
+) -> Result<Option<VehicleLog>, Error> {
+    Ok(read_sample(state, district, date, sid, VLOG_EXT)?
+        .map(|data| VehicleLog::parse(&String::from_utf8_lossy(&data))))
+}
+
+/// Check that an interval period (seconds) evenly divides a day
+pub fn is_valid_period(period: u32) -> bool {
+    period >= 30 && (DAY_MS / 1000).is_multiple_of(period)
+}
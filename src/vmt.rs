@@ -0,0 +1,217 @@
+// vmt.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use crate::annotate::{load_matching, Annotation};
+use crate::corridor::{
+    load_locations, read_lanes, read_speed, read_station_volume, required,
+    LaneData, Lanes, Location, Locations, Weight,
+};
+use crate::error::Error;
+use crate::sample::Mean;
+use crate::schema::JsonSchema;
+use crate::sensor::json_response;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// Default reference (free-flow) speed (mph)
+const FREE_DEFAULT: f64 = 60.0;
+
+/// Query parameters for VMT requests
+#[derive(Deserialize)]
+struct VmtParams {
+    /// Corridor (`route_dir`)
+    corridor: Option<String>,
+    /// Reference (free-flow) speed for delay (mph)
+    free: Option<f64>,
+    /// Mainline lane group
+    #[serde(default)]
+    lanes: Lanes,
+    /// Mean for aggregating speeds
+    #[serde(default)]
+    mean: Mean,
+    /// Weighting for aggregating speeds
+    #[serde(default)]
+    weight: Weight,
+    /// Include per-lane volume and speed
+    by_lane: Option<bool>,
+}
+
+impl VmtParams {
+    /// Get the reference (free-flow) speed (mph)
+    fn free_speed(&self) -> f64 {
+        self.free.unwrap_or(FREE_DEFAULT)
+    }
+}
+
+/// Travel totals for a station or corridor
+#[derive(Default, JsonSchema, Serialize)]
+struct Travel {
+    /// Vehicle-miles traveled
+    vmt: f64,
+    /// Vehicle-hours traveled
+    vht: f64,
+    /// Vehicle-hours of delay
+    delay: f64,
+}
+
+/// Travel totals for one station
+#[derive(JsonSchema, Serialize)]
+struct StationTravel<'a> {
+    station: &'a str,
+    /// Length of segment represented by station (miles)
+    miles: f64,
+    #[serde(flatten)]
+    travel: Travel,
+    /// General purpose lane travel (stations with managed lanes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    general: Option<Travel>,
+    /// Managed lane travel (stations with managed lanes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    managed: Option<Travel>,
+    /// Per-lane volume and speed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    lanes: Vec<LaneData<'a>>,
+}
+
+/// Travel totals for a corridor on a date
+#[derive(JsonSchema, Serialize)]
+pub(crate) struct CorridorTravel<'a> {
+    corridor: &'a str,
+    date: &'a str,
+    #[serde(flatten)]
+    total: Travel,
+    stations: Vec<StationTravel<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+    /// Configuration warnings
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+impl Travel {
+    /// Calculate travel over a segment from volume and speed
+    fn new(
+        miles: f64,
+        volume: &[Option<i32>],
+        speed: Option<&[Option<f64>]>,
+        free: f64,
+    ) -> Self {
+        let mut travel = Travel::default();
+        for (k, vol) in volume.iter().enumerate() {
+            let vm = match vol {
+                Some(v) => f64::from(*v) * miles,
+                None => continue,
+            };
+            travel.vmt += vm;
+            if let Some(s) = speed.and_then(|s| *s.get(k)?) {
+                if s > 0.0 {
+                    travel.vht += vm / s;
+                    travel.delay += (vm / s - vm / free).max(0.0);
+                }
+            }
+        }
+        travel
+    }
+
+    /// Add travel totals
+    fn add(&mut self, other: &Travel) {
+        self.vmt += other.vmt;
+        self.vht += other.vht;
+        self.delay += other.delay;
+    }
+}
+
+/// Calculate travel for a lane group of a station.
+///
+/// Returns `None` if there is no volume data.
+fn lane_travel(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    loc: &Location,
+    lanes: Lanes,
+    miles: f64,
+    params: &VmtParams,
+) -> Result<Option<Travel>, Error> {
+    let volume = match read_station_volume(state, district, date, loc, lanes)? {
+        Some(volume) => volume,
+        None => return Ok(None),
+    };
+    let fields = loc.mainline_fields(lanes);
+    let (mean, weight) = (params.mean, params.weight);
+    let speed = read_speed(state, district, date, &fields, mean, weight)?;
+    let free = params.free_speed();
+    Ok(Some(Travel::new(miles, &volume, speed.as_deref(), free)))
+}
+
+/// Handle request for VMT and delay along a corridor
+pub fn handle_vmt(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let params = web::Query::<VmtParams>::from_query(query)
+        .map_err(|e| Error::InvalidParam(e.to_string()))?;
+    let corridor = required(params.corridor.as_deref())?;
+    let free = params.free_speed();
+    if !free.is_finite() || free <= 0.0 {
+        return Err(Error::InvalidParam(format!("free: {}", free)));
+    }
+    let Locations { locs, warnings } = load_locations(state, date, corridor)?;
+    let stations: Vec<_> = locs
+        .iter()
+        .filter(|loc| {
+            loc.is_station() && !loc.mainline(params.lanes).is_empty()
+        })
+        .collect();
+    let mut total = Travel::default();
+    let mut travel = vec![];
+    for (i, loc) in stations.iter().enumerate() {
+        // Each station represents half the distance to its neighbors
+        let prev = i.checked_sub(1).map(|p| stations[p].mile);
+        let next = stations.get(i + 1).map(|n| n.mile);
+        let miles = (next.unwrap_or(loc.mile) - prev.unwrap_or(loc.mile)) / 2.0;
+        let travel_of = |lanes| {
+            lane_travel(state, district, date, loc, lanes, miles, &params)
+        };
+        let tr = match travel_of(params.lanes)? {
+            Some(tr) => tr,
+            None => continue,
+        };
+        // Separate lane groups, so managed lanes can be compared
+        let (general, managed) = match params.lanes {
+            Lanes::All
+                if !loc.mainline(Lanes::General).is_empty()
+                    && !loc.mainline(Lanes::Managed).is_empty() =>
+            {
+                (travel_of(Lanes::General)?, travel_of(Lanes::Managed)?)
+            }
+            _ => (None, None),
+        };
+        let lanes = match params.by_lane {
+            Some(true) => read_lanes(state, district, date, loc, params.lanes)?,
+            _ => Vec::new(),
+        };
+        total.add(&tr);
+        travel.push(StationTravel {
+            station: loc.id(),
+            miles,
+            travel: tr,
+            general,
+            managed,
+            lanes,
+        });
+    }
+    let res = CorridorTravel {
+        corridor,
+        date,
+        total,
+        stations: travel,
+        annotations: load_matching(state, district, date, corridor)?,
+        warnings,
+    };
+    Ok(json_response(serde_json::to_string(&res)?))
+}
@@ -0,0 +1,146 @@
+// warm.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Cache priming from access logs.
+//
+// The most popular request paths in an access log are replayed against a
+// running server, so its response caches (and proxy disk cache) are warm
+// after a restart or archive migration.
+//
+use crate::error::Error;
+use crate::state::Config;
+use crate::upstream::Upstream;
+use log::warn;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Default number of paths to replay
+const TOP_DEFAULT: usize = 500;
+
+/// Route prefixes which are not replayed
+const SKIP: &[&str] = &["admin", "jobs", "metrics", "healthz", "static"];
+
+/// Parse the path and status of a GET request in an access log line.
+///
+/// Works with the server's own log format and common/combined formats: the
+/// first quoted `GET` request line, followed by the status code.
+fn parse_line(line: &str) -> Option<(&str, u16)> {
+    let start = line.find("\"GET ")? + 5;
+    let rest = &line[start..];
+    let end = rest.find('"')?;
+    let path = rest[..end].split(' ').next()?;
+    let status = rest[end + 1..].split_whitespace().next()?.parse().ok()?;
+    Some((path, status))
+}
+
+/// Check if a request path (after URL prefix) should be replayed
+fn is_replayable(path: &str) -> bool {
+    let route = path.split('?').next().unwrap_or(path);
+    let first = route
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or("");
+    !first.is_empty() && !first.contains('.') && !SKIP.contains(&first)
+}
+
+/// Get the most popular successful request paths from an access log.
+///
+/// * `log` Access log lines.
+/// * `prefix` URL prefix of the server.
+/// * `top` Maximum number of paths.
+///
+/// Returns paths (after the prefix) with their request counts, most popular
+/// first.
+pub fn popular_paths<R: BufRead>(
+    log: R,
+    prefix: &str,
+    top: usize,
+) -> Result<Vec<(String, u64)>, Error> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for line in log.lines() {
+        let line = line?;
+        let (path, status) = match parse_line(&line) {
+            Some(req) => req,
+            None => continue,
+        };
+        if status != 200 {
+            continue;
+        }
+        match path.strip_prefix(prefix) {
+            Some(route) if is_replayable(route) => {
+                *counts.entry(route.to_string()).or_default() += 1;
+            }
+            _ => (),
+        }
+    }
+    let mut paths: Vec<(String, u64)> = counts.into_iter().collect();
+    paths.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    paths.truncate(top);
+    Ok(paths)
+}
+
+/// Replay request paths (below its base URL) against a server.
+///
+/// Returns the number of successful responses.
+pub fn replay(server: &Upstream, paths: &[(String, u64)]) -> usize {
+    let mut ok = 0;
+    for (path, _count) in paths {
+        match server.get(path) {
+            Ok(res) if res.status == 200 => ok += 1,
+            Ok(res) => warn!("warm {}: status {}", path, res.status),
+            Err(e) => warn!("warm {}: {}", path, e),
+        }
+    }
+    ok
+}
+
+/// Get the local URL of the configured server
+fn local_url(config: &Config) -> String {
+    let port = config.bind_addr.rsplit(':').next().unwrap_or("8080");
+    format!("http://127.0.0.1:{}{}", port, config.url_prefix)
+}
+
+/// Run `warm` subcommand.
+///
+/// * `args` Command arguments: `--from-access-log FILE`, with optional
+///   `--top N` and `--server URL` (default the local server).
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let usage = || {
+        Error::InvalidParam(
+            "usage: warm --from-access-log <file> [--top <n>] [--server <url>]"
+                .into(),
+        )
+    };
+    let mut log = None;
+    let mut top = TOP_DEFAULT;
+    let mut server = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let val = args.next().ok_or_else(usage)?;
+        match arg.as_str() {
+            "--from-access-log" => log = Some(val),
+            "--top" => top = val.parse().map_err(|_| usage())?,
+            "--server" => server = Some(val.clone()),
+            _ => return Err(usage()),
+        }
+    }
+    let log = log.ok_or_else(usage)?;
+    let config = Config::from_env()?;
+    let server = Upstream::new(&server.unwrap_or_else(|| local_url(&config)))?;
+    let paths = popular_paths(
+        BufReader::new(File::open(log)?),
+        &config.url_prefix,
+        top,
+    )?;
+    let ok = replay(&server, &paths);
+    println!(
+        "warmed {} of {} paths from {}",
+        ok,
+        paths.len(),
+        server.url()
+    );
+    Ok(())
+}
@@ -0,0 +1,516 @@
+// watch.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Archive watcher, publishing events when new dates are finalized or
+// metro_config documents are updated.
+//
+// New archives (`{date}.traffic`, or consolidated `{yyyy}.traffic` year
+// archives) are only checked once their size and modified time are unchanged
+// between two scans, so archives still being written are not reported as
+// corrupt.
+//
+use crate::error::Error;
+use crate::metro;
+use crate::route::{is_valid_date, is_valid_year};
+use crate::schema::JsonSchema;
+use crate::sensor::{lookup_archived, year_archive_dates};
+use crate::state::AppState;
+use crate::storage::Layout;
+use crate::webhook::WebhookSink;
+use actix_web::web;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::read_dir;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Interval between archive scans
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Timeout for event sink connections
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of NATS publish attempts
+const ATTEMPTS: u32 = 4;
+
+/// Default delay before first NATS retry (doubled for each retry)
+const BACKOFF: Duration = Duration::from_secs(2);
+
+/// Traffic file extension
+const DEXT: &str = ".traffic";
+
+/// metro_config file name prefix
+const METRO_PREFIX: &str = "metro_config_";
+
+/// metro_config file name suffix
+const METRO_SUFFIX: &str = ".xml.gz";
+
+/// Number of recent events kept for status
+const RECENT_EVENTS: usize = 50;
+
+/// Archive event
+#[derive(Clone, Debug, JsonSchema, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ArchiveEvent {
+    /// A date was finalized (zipped into a `.traffic` archive)
+    NewDate {
+        /// District ID
+        district: String,
+        /// Date (yyyyMMdd)
+        date: String,
+        /// Number of sampled sensors
+        sensors: usize,
+    },
+    /// A metro_config document was added or replaced
+    ConfigUpdated {
+        /// Date (yyyyMMdd)
+        date: String,
+    },
+    /// A new archive or metro_config document could not be read
+    IntegrityFailure {
+        /// District ID (none for metro_config)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        district: Option<String>,
+        /// Date (yyyyMMdd), or year (yyyy) of a year archive
+        date: String,
+        /// Error message
+        error: String,
+    },
+}
+
+impl ArchiveEvent {
+    /// Get the event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchiveEvent::NewDate { .. } => "new_date",
+            ArchiveEvent::ConfigUpdated { .. } => "config_updated",
+            ArchiveEvent::IntegrityFailure { .. } => "integrity_failure",
+        }
+    }
+}
+
+/// Destination for archive events
+pub trait EventSink: Send {
+    /// Publish an event
+    fn publish(&self, event: &ArchiveEvent) -> Result<(), Error>;
+}
+
+/// Make a protocol error
+fn protocol_error(msg: &str) -> Error {
+    Error::Io(std::io::Error::other(msg.to_string()))
+}
+
+/// NATS subject publisher (core protocol, one connection per event)
+pub struct NatsSink {
+    /// Server address (`host:port`)
+    addr: String,
+    /// Subject for events
+    subject: String,
+    /// Number of publish attempts
+    attempts: u32,
+    /// Delay before first retry
+    backoff: Duration,
+}
+
+impl NatsSink {
+    /// Create a NATS sink from a URL (`nats://host:port` or `host:port`)
+    pub fn new(url: &str, subject: &str) -> Self {
+        let addr = url.strip_prefix("nats://").unwrap_or(url);
+        let addr = if addr.contains(':') {
+            addr.to_string()
+        } else {
+            format!("{}:4222", addr)
+        };
+        NatsSink {
+            addr,
+            subject: subject.to_string(),
+            attempts: ATTEMPTS,
+            backoff: BACKOFF,
+        }
+    }
+
+    /// Set the number of publish attempts and delay before first retry
+    pub fn with_retry(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Make one publish attempt
+    fn publish_once(&self, payload: &str) -> Result<(), Error> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| protocol_error("no address"))?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("INFO") {
+            return Err(protocol_error("expected INFO"));
+        }
+        let mut stream = stream;
+        write!(
+            stream,
+            "CONNECT {{\"verbose\":false,\"pedantic\":false,\"name\":\"trafdat\"}}\r\n\
+             PUB {} {}\r\n{}\r\nPING\r\n",
+            self.subject,
+            payload.len(),
+            payload
+        )?;
+        stream.flush()?;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(protocol_error("connection closed"));
+            }
+            match line.trim_end() {
+                "PONG" => return Ok(()),
+                l if l.starts_with("-ERR") => return Err(protocol_error(l)),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl EventSink for NatsSink {
+    fn publish(&self, event: &ArchiveEvent) -> Result<(), Error> {
+        let payload = serde_json::to_string(event)?;
+        let mut delay = self.backoff;
+        for attempt in 1..=self.attempts {
+            match self.publish_once(&payload) {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!(
+                    "nats {} {} attempt {}: {}",
+                    self.addr,
+                    event.name(),
+                    attempt,
+                    e
+                ),
+            }
+            if attempt < self.attempts {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+        Err(protocol_error(&format!(
+            "nats {} failed after {} attempts",
+            self.addr, self.attempts
+        )))
+    }
+}
+
+/// Watcher status snapshot
+#[derive(Clone, Default)]
+pub struct WatchStatus {
+    /// Background thread is running
+    pub running: bool,
+    /// Number of completed scans
+    pub scans: u64,
+    /// Time of last scan
+    pub last_scan: Option<SystemTime>,
+    /// Recent events, newest first
+    pub recent: VecDeque<(SystemTime, ArchiveEvent)>,
+}
+
+/// Shared watcher status
+#[derive(Default)]
+pub struct WatchState {
+    /// Current status
+    status: Mutex<WatchStatus>,
+}
+
+impl WatchState {
+    /// Get a snapshot of the status
+    pub fn status(&self) -> WatchStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Mark the background thread running
+    fn set_running(&self) {
+        self.status.lock().unwrap().running = true;
+    }
+
+    /// Record a completed scan
+    fn record(&self, events: &[ArchiveEvent]) {
+        let now = SystemTime::now();
+        let mut status = self.status.lock().unwrap();
+        status.scans += 1;
+        status.last_scan = Some(now);
+        for event in events {
+            status.recent.push_front((now, event.clone()));
+        }
+        status.recent.truncate(RECENT_EVENTS);
+    }
+}
+
+/// Size and modified time of a file
+type Stamp = (u64, Option<SystemTime>);
+
+/// Get the stamp of a file
+fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = path.metadata().ok()?;
+    Some((meta.len(), meta.modified().ok()))
+}
+
+/// Archive file of a district
+enum Archive {
+    /// Archive of one date (yyyyMMdd)
+    Date(String),
+    /// Consolidated archive of a year (yyyy)
+    Year(String),
+}
+
+/// Archive watcher state
+#[derive(Default)]
+pub struct Watcher {
+    /// Finalized dates for each district (`None` before first scan)
+    known: Option<HashMap<String, BTreeSet<String>>>,
+    /// Stamps of checked year archives
+    years: HashMap<PathBuf, Stamp>,
+    /// Stamps of new archives, which may still be written
+    pending: HashMap<PathBuf, Stamp>,
+    /// Modified times of metro_config documents (`None` before first scan)
+    configs: Option<HashMap<String, Option<SystemTime>>>,
+}
+
+/// List entries of a directory
+fn list_names(path: &Path, dir: bool) -> Vec<String> {
+    let mut names = vec![];
+    if let Ok(entries) = read_dir(path) {
+        for ent in entries.flatten() {
+            if ent.file_type().is_ok_and(|tp| tp.is_dir() == dir) {
+                if let Some(name) = ent.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Scan archive files of all districts
+fn scan_archives(state: &AppState) -> Vec<(String, PathBuf, Archive)> {
+    let mut archives = vec![];
+    for district in list_names(state.storage.base(), true) {
+        let path = state.storage.district_path(&district);
+        for name in list_names(&path, false) {
+            if let Some(year) = name.strip_suffix(DEXT) {
+                if is_valid_year(year) {
+                    let archive = Archive::Year(year.to_string());
+                    archives.push((
+                        district.clone(),
+                        path.join(&name),
+                        archive,
+                    ));
+                }
+            }
+        }
+        let years = match state.storage.layout(&district) {
+            Layout::Yearly => list_names(&path, true),
+            // without year directories, scan the district directory once
+            Layout::Flat => vec![String::new()],
+        };
+        for year in years {
+            if year.is_empty() || is_valid_year(&year) {
+                let dir = path.join(&year);
+                for name in list_names(&dir, false) {
+                    if let Some(date) = name.strip_suffix(DEXT) {
+                        if is_valid_date(date) && date.starts_with(&year) {
+                            let archive = Archive::Date(date.to_string());
+                            archives.push((
+                                district.clone(),
+                                dir.join(&name),
+                                archive,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    archives
+}
+
+/// Scan metro_config documents, with modified times
+fn scan_configs(state: &AppState) -> HashMap<String, Option<SystemTime>> {
+    let mut configs = HashMap::new();
+    let path = &state.config.metro_path;
+    for name in list_names(path, false) {
+        let date = name
+            .strip_prefix(METRO_PREFIX)
+            .and_then(|n| n.strip_suffix(METRO_SUFFIX));
+        if let Some(date) = date.filter(|d| is_valid_date(d)) {
+            let mtime = path.join(&name).metadata().and_then(|m| m.modified());
+            configs.insert(date.to_string(), mtime.ok());
+        }
+    }
+    configs
+}
+
+/// Check a new archive, making an event
+fn new_date_event(
+    state: &AppState,
+    district: &str,
+    date: &str,
+) -> ArchiveEvent {
+    let mut path = state.storage.date_path(district, date);
+    path.set_extension(&DEXT[1..]);
+    if let Err(e) = state.zips.get(&state.metrics, &path) {
+        return ArchiveEvent::IntegrityFailure {
+            district: Some(district.to_string()),
+            date: date.to_string(),
+            error: e.to_string(),
+        };
+    }
+    let mut sensors = lookup_archived(state, district, date);
+    sensors.sort();
+    sensors.dedup();
+    ArchiveEvent::NewDate {
+        district: district.to_string(),
+        date: date.to_string(),
+        sensors: sensors.len(),
+    }
+}
+
+/// Check an updated metro_config document, making an event
+fn config_event(state: &AppState, date: &str) -> ArchiveEvent {
+    match metro::prewarm(state, date) {
+        Ok(()) => ArchiveEvent::ConfigUpdated {
+            date: date.to_string(),
+        },
+        Err(e) => ArchiveEvent::IntegrityFailure {
+            district: None,
+            date: date.to_string(),
+            error: e.to_string(),
+        },
+    }
+}
+
+impl Watcher {
+    /// Scan archives and metro_config documents, returning events for
+    /// changes since the last scan.  The first scan records existing files
+    /// without events.
+    pub fn scan(&mut self, state: &AppState) -> Vec<ArchiveEvent> {
+        let first = self.known.is_none();
+        let mut events = vec![];
+        let mut pending = HashMap::new();
+        let known = self.known.get_or_insert_with(HashMap::new);
+        for (district, path, archive) in scan_archives(state) {
+            let dates = known.entry(district.clone()).or_default();
+            let stamp = match stamp(&path) {
+                Some(stamp) => stamp,
+                None => continue,
+            };
+            let checked = match &archive {
+                Archive::Date(date) => dates.contains(date),
+                Archive::Year(_) => self.years.get(&path) == Some(&stamp),
+            };
+            if checked {
+                continue;
+            }
+            // wait until the archive is no longer being written
+            if !first && self.pending.get(&path) != Some(&stamp) {
+                pending.insert(path, stamp);
+                continue;
+            }
+            match archive {
+                Archive::Date(date) => {
+                    if !first {
+                        events.push(new_date_event(state, &district, &date));
+                    }
+                    dates.insert(date);
+                }
+                Archive::Year(year) => {
+                    self.years.insert(path, stamp);
+                    match year_archive_dates(state, &district, &year) {
+                        Ok(year_dates) => {
+                            for date in year_dates {
+                                if !dates.contains(&date) && !first {
+                                    events.push(new_date_event(
+                                        state, &district, &date,
+                                    ));
+                                }
+                                dates.insert(date);
+                            }
+                        }
+                        Err(e) if !first => {
+                            events.push(ArchiveEvent::IntegrityFailure {
+                                district: Some(district),
+                                date: year,
+                                error: e.to_string(),
+                            })
+                        }
+                        Err(_) => (),
+                    }
+                }
+            }
+        }
+        self.pending = pending;
+        let configs = scan_configs(state);
+        if let Some(known) = &self.configs {
+            for (date, mtime) in &configs {
+                if known.get(date) != Some(mtime) {
+                    events.push(config_event(state, date));
+                }
+            }
+        }
+        self.configs = Some(configs);
+        state.watch.record(&events);
+        events
+    }
+}
+
+/// Create event sinks from configuration
+fn configured_sinks(state: &AppState) -> Vec<Box<dyn EventSink>> {
+    let mut sinks: Vec<Box<dyn EventSink>> = vec![];
+    if let Some(url) = &state.config.nats_url {
+        sinks.push(Box::new(NatsSink::new(url, &state.config.nats_subject)));
+    }
+    for url in &state.config.webhooks {
+        let secret = state.config.webhook_secret.as_deref();
+        match WebhookSink::new(url, secret) {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(e) => warn!("{}", e),
+        }
+    }
+    sinks
+}
+
+/// Publish an event to all sinks
+pub fn publish(sinks: &[Box<dyn EventSink>], event: &ArchiveEvent) {
+    for sink in sinks {
+        match sink.publish(event) {
+            Ok(()) => info!("published {:?}", event),
+            Err(e) => warn!("publish {:?}: {}", event, e),
+        }
+    }
+}
+
+/// Spawn a background thread to watch for archive changes (if any event
+/// sinks are configured)
+pub fn spawn(state: web::Data<AppState>) {
+    let sinks = configured_sinks(&state);
+    if sinks.is_empty() {
+        return;
+    }
+    state.watch.set_running();
+    thread::spawn(move || {
+        let mut watcher = Watcher::default();
+        loop {
+            for event in watcher.scan(&state) {
+                publish(&sinks, &event);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
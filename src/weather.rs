@@ -0,0 +1,178 @@
+// weather.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Road weather information system (RWIS) observations.
+//
+// IRIS archives weather sensor samples in the same district date directories
+// and zip files as traffic samples, but with site IDs and their own sample
+// types.  They are served under `/weather/{district}`.
+//
+use crate::error::Error;
+use crate::output::{OutputFormat, SampleData};
+use crate::route::{is_valid_date, Output};
+use crate::sample::DAY_SECS;
+use crate::sensor::{
+    build_json, decode_series, encode_output, json_response, list_entries,
+    read_sample, sample_format, sample_period,
+};
+use crate::state::AppState;
+use actix_web::HttpResponse;
+use std::path::Path;
+
+/// Default weather sample periods (seconds)
+pub const WEATHER_PERIODS: &[u32] = &[60, 300, 600];
+
+/// Weather sample types (prefix and bytes per sample)
+pub const WEATHER_TYPES: &[(&str, u64)] = &[
+    ("sst", 2), // subsurface temperature (0.01 °C)
+    ("st", 2),  // pavement surface temperature (0.01 °C)
+    ("at", 2),  // air temperature (0.01 °C)
+    ("dp", 2),  // dew point temperature (0.01 °C)
+    ("rh", 1),  // relative humidity (percent)
+    ("ws", 1),  // average wind speed (kph)
+    ("wg", 1),  // wind gust speed (kph)
+    ("wd", 2),  // wind direction (degrees)
+    ("vis", 2), // visibility (meters)
+    ("ps", 1),  // pavement surface status code
+];
+
+/// Get weather sample type prefix and bytes per sample of an extension
+pub fn weather_type(ext: &str) -> Option<(&'static str, u64)> {
+    let (suffix, _) = sample_period(ext)?;
+    let prefix = &ext[..ext.len() - suffix.len()];
+    WEATHER_TYPES.iter().copied().find(|(p, _)| *p == prefix)
+}
+
+/// Get scale of decoded values for a weather sample type prefix
+fn weather_scale(prefix: &str) -> f64 {
+    match prefix {
+        "sst" | "st" | "at" | "dp" => 0.01, // hundredths of a degree
+        _ => 1.0,
+    }
+}
+
+/// Check a weather sample file extension, with valid sample periods
+fn weather_file_ext<'e>(ext: &'e str, periods: &[u32]) -> Option<&'e str> {
+    weather_type(ext)?;
+    let (_, len) = sample_period(ext)?;
+    let period = (DAY_SECS / len) as u32;
+    periods.contains(&period).then_some(ext)
+}
+
+/// Split a file name into site ID and weather extension
+fn site_ext<'n>(name: &'n str, periods: &[u32]) -> Option<(&'n str, &'n str)> {
+    let path = Path::new(name);
+    let ext = path.extension()?.to_str()?;
+    let site = path.file_stem()?.to_str()?;
+    weather_file_ext(ext, periods).map(|ext| (site, ext))
+}
+
+/// Handle request for weather sites sampled on a date
+fn handle_sites(
+    state: &AppState,
+    district: &str,
+    date: &str,
+) -> Result<HttpResponse, Error> {
+    let periods = &state.config.weather_periods;
+    let sites = list_entries(state, district, date, |name| {
+        site_ext(name, periods).map(|(site, _)| site)
+    });
+    Ok(json_response(build_json(sites)?))
+}
+
+/// Handle request for weather extensions sampled for a site on a date
+fn handle_site_exts(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    site: &str,
+) -> Result<HttpResponse, Error> {
+    let periods = &state.config.weather_periods;
+    let exts = list_entries(state, district, date, |name| {
+        site_ext(name, periods)
+            .filter(|(s, _)| *s == site)
+            .map(|(_, ext)| ext)
+    });
+    Ok(json_response(build_json(exts)?))
+}
+
+/// Handle request for weather observations of a site on a date
+fn handle_observations(
+    state: &AppState,
+    district: &str,
+    date: &str,
+    site: &str,
+    ext: &str,
+    format: &dyn OutputFormat,
+    query: &str,
+) -> Result<HttpResponse, Error> {
+    let periods = &state.config.weather_periods;
+    let (prefix, bytes) = weather_file_ext(ext, periods)
+        .and_then(weather_type)
+        .ok_or(Error::NotFound)?;
+    let raw = read_sample(state, district, date, site, ext)?
+        .ok_or(Error::NotFound)?;
+    let series = decode_series(&raw, bytes, state.config.marker(prefix));
+    let data = SampleData {
+        state,
+        district,
+        date,
+        sid: site,
+        ext,
+        raw: &raw,
+        series: Some(&series),
+        scale: weather_scale(prefix),
+        occupancy: false,
+        query,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(encode_output(format, &data)?))
+}
+
+/// Handle weather request with two parameters (district and date)
+pub fn handle_2_params(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+) -> Result<HttpResponse, Error> {
+    if !is_valid_date(p2) {
+        return Err(Error::NotFound);
+    }
+    handle_sites(state, p1, p2)
+}
+
+/// Handle weather request with three parameters.
+///
+/// * `json` Request has a `.json` suffix.
+/// * `accept` Value of `Accept` header, to select an output format.
+pub fn handle_3_params(
+    state: &AppState,
+    p1: &str,
+    p2: &str,
+    p3: &str,
+    json: bool,
+    query: &str,
+    accept: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    if !is_valid_date(p2) {
+        return Err(Error::NotFound);
+    }
+    match (json, p3.split_once('.')) {
+        (true, None) => handle_site_exts(state, p1, p2, p3),
+        (true, Some((site, ext))) => {
+            let format = sample_format(state, Output::Json, None)?;
+            handle_observations(state, p1, p2, site, ext, format, query)
+        }
+        (false, Some((site, ext))) => {
+            let (ext, output) = match ext.split_once('.') {
+                Some((ext, name)) => (ext, Output::Named(name)),
+                None => (ext, Output::Raw),
+            };
+            let format = sample_format(state, output, accept)?;
+            handle_observations(state, p1, p2, site, ext, format, query)
+        }
+        (false, None) => Err(Error::NotFound),
+    }
+}
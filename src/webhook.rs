@@ -0,0 +1,172 @@
+// webhook.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Webhook notifications for archive events (plain HTTP POST).
+//
+use crate::error::Error;
+use crate::watch::{ArchiveEvent, EventSink};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use sha2::Sha256;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+/// HMAC-SHA256 type
+type HmacSha256 = Hmac<Sha256>;
+
+/// Timeout for webhook connections
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of delivery attempts
+const ATTEMPTS: u32 = 4;
+
+/// Default delay before first retry (doubled for each retry)
+const BACKOFF: Duration = Duration::from_secs(2);
+
+/// Webhook endpoint
+pub struct WebhookSink {
+    /// Full URL (for logging)
+    url: String,
+    /// Host and port
+    addr: String,
+    /// Request path
+    path: String,
+    /// Key for signing request bodies
+    secret: Option<Vec<u8>>,
+    /// Number of delivery attempts
+    attempts: u32,
+    /// Delay before first retry
+    backoff: Duration,
+}
+
+/// Split an `http://` URL into host:port and path
+pub fn parse_url(url: &str) -> Result<(String, String), Error> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| Error::Config(format!("webhook: {}", url)))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(Error::Config(format!("webhook: {}", url)));
+    }
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((addr, path.to_string()))
+}
+
+/// Sign a request body, as a hex HMAC-SHA256 digest
+pub fn sign_body(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret)
+        .expect("HMAC accepts any key length");
+    mac.update(body);
+    let mut res = String::new();
+    for b in mac.finalize().into_bytes() {
+        write!(res, "{:02x}", b).unwrap();
+    }
+    res
+}
+
+impl WebhookSink {
+    /// Create a webhook sink.
+    ///
+    /// * `url` Endpoint (`http://host[:port]/path`).
+    /// * `secret` Key for `X-Trafdat-Signature` headers.
+    pub fn new(url: &str, secret: Option<&str>) -> Result<Self, Error> {
+        let (addr, path) = parse_url(url)?;
+        Ok(WebhookSink {
+            url: url.to_string(),
+            addr,
+            path,
+            secret: secret.map(|s| s.as_bytes().to_vec()),
+            attempts: ATTEMPTS,
+            backoff: BACKOFF,
+        })
+    }
+
+    /// Set the number of delivery attempts and delay before first retry
+    pub fn with_retry(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Make one delivery attempt, returning the HTTP status
+    fn post(&self, event: &str, body: &str) -> io::Result<u16> {
+        let addr = self.addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no address")
+        })?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut req = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: trafdat\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\
+             X-Trafdat-Event: {}\r\n",
+            self.path,
+            self.addr,
+            body.len(),
+            event,
+        );
+        if let Some(secret) = &self.secret {
+            let sig = sign_body(secret, body.as_bytes());
+            write!(req, "X-Trafdat-Signature: sha256={}\r\n", sig).unwrap();
+        }
+        req.push_str("Connection: close\r\n\r\n");
+        req.push_str(body);
+        stream.write_all(req.as_bytes())?;
+        stream.flush()?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, status))
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn publish(&self, event: &ArchiveEvent) -> Result<(), Error> {
+        let body = serde_json::to_string(event)?;
+        let mut delay = self.backoff;
+        for attempt in 1..=self.attempts {
+            match self.post(event.name(), &body) {
+                Ok(status) if (200..300).contains(&status) => {
+                    info!("webhook {} {}: {}", self.url, event.name(), status);
+                    return Ok(());
+                }
+                Ok(status) => warn!(
+                    "webhook {} {} attempt {}: {}",
+                    self.url,
+                    event.name(),
+                    attempt,
+                    status
+                ),
+                Err(e) => warn!(
+                    "webhook {} {} attempt {}: {}",
+                    self.url,
+                    event.name(),
+                    attempt,
+                    e
+                ),
+            }
+            if attempt < self.attempts {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+        Err(Error::Io(io::Error::other(format!(
+            "webhook {} failed after {} attempts",
+            self.url, self.attempts
+        ))))
+    }
+}
@@ -0,0 +1,291 @@
+// wire.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// JSON response types, shared by the server and client.
+//
+use crate::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Sampled year (`years.json`)
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct YearDates {
+    /// Year (yyyy)
+    #[schema(pattern = "^[0-9]{4}$")]
+    pub year: String,
+    /// Number of sampled dates
+    pub dates: usize,
+}
+
+/// Period and sample count of one requested series
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct SeriesInfo<'a> {
+    /// District ID, if requested as a `{district, sid}` pair
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<Cow<'a, str>>,
+    pub sid: Cow<'a, str>,
+    pub ext: Cow<'a, str>,
+    /// Sample period (seconds), or `None` if not archived
+    pub period: Option<u32>,
+    /// Number of samples, or `None` if not archived
+    pub samples: Option<usize>,
+}
+
+/// Structured error for series which are not aligned
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct Mismatch<'a> {
+    pub error: Cow<'a, str>,
+    pub series: Vec<SeriesInfo<'a>>,
+}
+
+/// One aligned series
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct AlignedSeries<'a> {
+    /// District ID, if requested as a `{district, sid}` pair
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<Cow<'a, str>>,
+    pub sid: Cow<'a, str>,
+    pub ext: Cow<'a, str>,
+    pub values: Cow<'a, [Option<i32>]>,
+}
+
+/// Aligned series sharing period and sample count (`aligned.json`)
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct Aligned<'a> {
+    pub period: u32,
+    pub samples: usize,
+    pub series: Vec<AlignedSeries<'a>>,
+}
+
+/// Metro configuration (`metro_config` JSON)
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct TmsConfig {
+    #[serde(default = "Vec::new")]
+    pub corridor: Vec<Corridor>,
+    #[serde(default = "Vec::new")]
+    pub camera: Vec<Camera>,
+    #[serde(default = "Vec::new")]
+    pub commlink: Vec<Commlink>,
+    #[serde(default = "Vec::new")]
+    pub controller: Vec<Controller>,
+    #[serde(default = "Vec::new")]
+    pub dms: Vec<Dms>,
+    pub time_stamp: String,
+}
+
+/// Corridor (route and direction) with r_nodes
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Corridor {
+    #[serde(default = "Vec::new")]
+    pub r_node: Vec<RNode>,
+    pub route: String,
+    pub dir: String,
+}
+
+/// Roadway node
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct RNode {
+    #[serde(default = "Vec::new")]
+    pub detector: Vec<Detector>,
+    #[serde(default = "Vec::new")]
+    pub meter: Vec<Meter>,
+    pub name: String,
+    #[serde(default = "station_str")]
+    pub n_type: String,
+    #[serde(default = "false_str")]
+    pub pickable: String,
+    #[serde(default = "false_str")]
+    pub above: String,
+    #[serde(default = "none_str")]
+    pub transition: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub station_id: String,
+    #[serde(default = "String::new")]
+    pub label: String,
+    pub lon: String,
+    pub lat: String,
+    #[serde(default = "zero_str")]
+    pub lanes: String,
+    #[serde(default = "right_str")]
+    pub attach_side: String,
+    #[serde(default = "zero_str")]
+    pub shift: String,
+    #[serde(default = "true_str")]
+    pub active: String,
+    #[serde(default = "false_str")]
+    pub abandoned: String,
+    #[serde(default = "ff_str")]
+    pub s_limit: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub forks: String,
+}
+
+/// Vehicle detector
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Detector {
+    pub name: String,
+    #[serde(default = "future_str")]
+    pub label: String,
+    #[serde(default = "false_str")]
+    pub abandoned: String,
+    #[serde(default = "String::new")]
+    pub category: String,
+    #[serde(default = "zero_str")]
+    pub lane: String,
+    #[serde(default = "tt_str")]
+    pub field: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub controller: String,
+}
+
+/// Ramp meter
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Meter {
+    pub name: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lon: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lat: String,
+    pub storage: String,
+    #[serde(default = "tfz_str")]
+    pub max_wait: String,
+}
+
+/// Camera
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Camera {
+    pub name: String,
+    pub description: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lon: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lat: String,
+}
+
+/// Communication link
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Commlink {
+    pub name: String,
+    pub description: String,
+    pub protocol: String,
+}
+
+/// Field controller
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Controller {
+    pub name: String,
+    //active: String,  // Present in XML DTD but not the actual document
+    pub condition: String, // Present in document, but not the DTD
+    pub drop: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub commlink: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lon: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lat: String,
+    pub location: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub cabinet: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub notes: String,
+}
+
+/// Dynamic message sign
+#[derive(JsonSchema, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Dms {
+    pub name: String,
+    pub description: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lon: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub lat: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub width_pixels: String,
+    #[serde(default = "implied_str")]
+    #[serde(skip_serializing_if = "implied")]
+    pub height_pixels: String,
+}
+
+/// GeoJSON line string geometry
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct LineString {
+    #[serde(rename = "type")]
+    #[schema(value = "LineString")]
+    pub kind: String,
+    pub coordinates: Vec<[f64; 2]>,
+}
+
+/// GeoJSON properties for a corridor
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct CorridorProperties {
+    pub route: String,
+    pub dir: String,
+    /// Names of r_nodes in the line string
+    pub r_node: Vec<String>,
+}
+
+/// GeoJSON feature for a corridor
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct CorridorFeature {
+    #[serde(rename = "type")]
+    #[schema(value = "Feature")]
+    pub kind: String,
+    pub properties: CorridorProperties,
+    pub geometry: LineString,
+}
+
+/// Functions to implement defaults from the Document Type Definition (DTD)
+fn station_str() -> String {
+    "Station".to_string()
+}
+fn false_str() -> String {
+    "f".to_string()
+}
+fn true_str() -> String {
+    "t".to_string()
+}
+fn zero_str() -> String {
+    "0".to_string()
+}
+fn none_str() -> String {
+    "None".to_string()
+}
+fn right_str() -> String {
+    "right".to_string()
+}
+fn ff_str() -> String {
+    "55".to_string()
+}
+fn tt_str() -> String {
+    "22.0".to_string()
+}
+fn tfz_str() -> String {
+    "240".to_string()
+}
+fn future_str() -> String {
+    "FUTURE".to_string()
+}
+/// Used as default for #IMPLIED attributes with no default
+fn implied_str() -> String {
+    "#IMPLIED".to_string()
+}
+/// Used to check if #IMPLIED value should be left out
+pub fn implied(val: &String) -> bool {
+    val == "#IMPLIED"
+}
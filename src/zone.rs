@@ -0,0 +1,371 @@
+// zone.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Time zones for interval timestamps on daylight saving time days.
+//
+// Zones are read from the system zoneinfo database, using only the POSIX
+// `TZ` rule in the footer of each compiled (TZif) file: the current rule is
+// all that is needed for recent archives, and `chrono-tz` (which embeds the
+// whole database) is not available as a dependency.  Historical rule changes
+// (such as US rules before 2007) are not applied.
+//
+use crate::dates::DATE_FMT;
+use crate::error::Error;
+use chrono::offset::LocalResult;
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime,
+    TimeZone, Utc,
+};
+use std::fs;
+use std::path::Path;
+
+/// Directory of compiled (TZif) zone files
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Default DST rules when a POSIX zone has none (US rules)
+const DST_RULES_DEFAULT: &str = "M3.2.0,M11.1.0";
+
+/// Default time of day for DST transitions (seconds)
+const TRANSITION_DEFAULT: i32 = 2 * 3600;
+
+/// DST transition rule (`Mm.w.d[/time]`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transition {
+    /// Month (1-12)
+    month: u32,
+    /// Week of month (1-5, 5 for last)
+    week: u32,
+    /// Day of week (0 for Sunday)
+    weekday: u32,
+    /// Local time of day (seconds, may be negative or past 24 hours)
+    secs: i32,
+}
+
+/// Daylight saving time rules
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DstRule {
+    /// DST offset east of UTC (seconds)
+    offset: i32,
+    /// Start of DST (standard local time)
+    start: Transition,
+    /// End of DST (daylight local time)
+    end: Transition,
+}
+
+/// Time zone rule from a POSIX `TZ` string
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoneRule {
+    /// Standard offset east of UTC (seconds)
+    offset: i32,
+    /// Daylight saving time rules
+    dst: Option<DstRule>,
+}
+
+/// Time zone for a district
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Zone {
+    /// Local zone of the server
+    #[default]
+    Local,
+    /// Zone rule, with its name
+    Rule(String, ZoneRule),
+}
+
+/// Daylight saving flag for a sample interval
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DstFlag {
+    /// Local time occurs twice (clocks set back)
+    Repeated,
+    /// Local time does not exist (clocks set forward)
+    Missing,
+}
+
+/// Absolute time of a sample interval
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IntervalTime {
+    /// Start time, or `None` for a missing local time
+    pub time: Option<DateTime<FixedOffset>>,
+    /// Daylight saving flag
+    pub flag: Option<DstFlag>,
+}
+
+impl DstFlag {
+    /// Get the flag name
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DstFlag::Repeated => "repeated",
+            DstFlag::Missing => "missing",
+        }
+    }
+}
+
+/// Get the Unix timestamp of a date and time in UTC
+fn utc_stamp(time: NaiveDateTime) -> i64 {
+    Utc.from_utc_datetime(&time).timestamp()
+}
+
+impl IntervalTime {
+    /// Get the Unix timestamp
+    pub fn stamp(&self) -> Option<i64> {
+        self.time.map(|t| t.timestamp())
+    }
+}
+
+/// Parse a zone name (`<...>` or alphabetic)
+fn parse_name(tz: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = tz.strip_prefix('<') {
+        let end = rest.find('>')?;
+        Some((&rest[..end], &rest[end + 1..]))
+    } else {
+        let end = tz
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tz.len());
+        (end >= 3).then(|| (&tz[..end], &tz[end..]))
+    }
+}
+
+/// Parse a time (`[+-]hh[:mm[:ss]]`) as seconds
+fn parse_time(tz: &str) -> Option<(i32, &str)> {
+    let end = tz
+        .find(|c: char| !(c.is_ascii_digit() || "+-:".contains(c)))
+        .unwrap_or(tz.len());
+    let (time, rest) = tz.split_at(end);
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(t) => (-1, t),
+        None => (1, time.strip_prefix('+').unwrap_or(time)),
+    };
+    let mut secs = 0;
+    let mut parts = 0;
+    for (part, mult) in time.split(':').zip([3600, 60, 1]) {
+        secs += part.parse::<i32>().ok()? * mult;
+        parts += 1;
+    }
+    (parts > 0 && !time.is_empty()).then_some((sign * secs, rest))
+}
+
+/// Parse a transition rule (`Mm.w.d[/time]`)
+fn parse_transition(rule: &str) -> Option<Transition> {
+    let (date, time) = match rule.split_once('/') {
+        Some((date, time)) => (date, Some(time)),
+        None => (rule, None),
+    };
+    let mut fields = date.strip_prefix('M')?.split('.');
+    let month = fields.next()?.parse().ok()?;
+    let week = fields.next()?.parse().ok()?;
+    let weekday = fields.next()?.parse().ok()?;
+    let secs = match time {
+        Some(time) => match parse_time(time)? {
+            (secs, "") => secs,
+            _ => return None,
+        },
+        None => TRANSITION_DEFAULT,
+    };
+    let valid = fields.next().is_none()
+        && (1..=12).contains(&month)
+        && (1..=5).contains(&week)
+        && weekday <= 6;
+    valid.then_some(Transition {
+        month,
+        week,
+        weekday,
+        secs,
+    })
+}
+
+impl Transition {
+    /// Get local date and time of the transition in a year
+    fn local(self, year: i32) -> Option<NaiveDateTime> {
+        let first = NaiveDate::from_ymd_opt(year, self.month, 1)?;
+        let wd0 = first.weekday().num_days_from_sunday();
+        let mut day = 1 + (self.weekday + 7 - wd0) % 7 + (self.week - 1) * 7;
+        let (y, m) = if self.month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, self.month + 1)
+        };
+        let len = NaiveDate::from_ymd_opt(y, m, 1)?.pred_opt()?.day();
+        while day > len {
+            day -= 7;
+        }
+        let date = NaiveDate::from_ymd_opt(year, self.month, day)?;
+        Some(date.and_hms_opt(0, 0, 0)? + Duration::seconds(self.secs.into()))
+    }
+}
+
+impl ZoneRule {
+    /// Parse a POSIX `TZ` string (e.g. `CST6CDT,M3.2.0,M11.1.0`)
+    pub fn parse(tz: &str) -> Option<Self> {
+        let (_std, rest) = parse_name(tz)?;
+        let (std, rest) = parse_time(rest)?;
+        let offset = -std;
+        if rest.is_empty() {
+            return Some(ZoneRule { offset, dst: None });
+        }
+        let (_dst, rest) = parse_name(rest)?;
+        let (dst_offset, rest) = match parse_time(rest) {
+            Some((dst, rest)) => (-dst, rest),
+            None => (offset + 3600, rest),
+        };
+        let rules = match rest.strip_prefix(',') {
+            Some(rules) => rules,
+            None if rest.is_empty() => DST_RULES_DEFAULT,
+            None => return None,
+        };
+        let (start, end) = rules.split_once(',')?;
+        let dst = DstRule {
+            offset: dst_offset,
+            start: parse_transition(start)?,
+            end: parse_transition(end)?,
+        };
+        Some(ZoneRule {
+            offset,
+            dst: Some(dst),
+        })
+    }
+
+    /// Get the offset in effect at a Unix timestamp
+    fn offset_at(&self, stamp: i64) -> i32 {
+        let dst = match &self.dst {
+            Some(dst) => dst,
+            None => return self.offset,
+        };
+        let local = Utc
+            .timestamp_opt(stamp, 0)
+            .single()
+            .map(|t| t.naive_utc() + Duration::seconds(self.offset.into()));
+        let year = match local {
+            Some(local) => local.year(),
+            None => return self.offset,
+        };
+        let start = dst
+            .start
+            .local(year)
+            .map(|t| utc_stamp(t) - i64::from(self.offset));
+        let end = dst
+            .end
+            .local(year)
+            .map(|t| utc_stamp(t) - i64::from(dst.offset));
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                let in_dst = if start < end {
+                    start <= stamp && stamp < end
+                } else {
+                    !(end <= stamp && stamp < start)
+                };
+                if in_dst {
+                    dst.offset
+                } else {
+                    self.offset
+                }
+            }
+            _ => self.offset,
+        }
+    }
+
+    /// Get the absolute time(s) of a local time
+    fn local_times(
+        &self,
+        local: NaiveDateTime,
+    ) -> LocalResult<DateTime<FixedOffset>> {
+        let mut offsets = vec![self.offset];
+        if let Some(dst) = &self.dst {
+            offsets.push(dst.offset);
+        }
+        let mut times: Vec<DateTime<FixedOffset>> = offsets
+            .into_iter()
+            .filter_map(|off| {
+                let stamp = utc_stamp(local) - i64::from(off);
+                if self.offset_at(stamp) != off {
+                    return None;
+                }
+                FixedOffset::east_opt(off)?.timestamp_opt(stamp, 0).single()
+            })
+            .collect();
+        times.sort();
+        match times.as_slice() {
+            [] => LocalResult::None,
+            [t] => LocalResult::Single(*t),
+            [a, b, ..] => LocalResult::Ambiguous(*a, *b),
+        }
+    }
+}
+
+/// Read the POSIX `TZ` footer of a compiled (TZif version 2+) zone file
+fn read_footer(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    if !data.starts_with(b"TZif") || data.get(4).is_none_or(|v| *v < b'2') {
+        return None;
+    }
+    let data = data.strip_suffix(b"\n")?;
+    let start = data.iter().rposition(|b| *b == b'\n')? + 1;
+    String::from_utf8(data[start..].to_vec()).ok()
+}
+
+impl Zone {
+    /// Parse a zone name (e.g. `America/Chicago`) or POSIX `TZ` string
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        let bad = || Error::Config(format!("time zone: {}", name));
+        let is_path = !name.is_empty()
+            && !name.starts_with('/')
+            && name.split('/').all(|p| !p.is_empty() && p != "..");
+        let tz = if is_path {
+            read_footer(&Path::new(ZONEINFO).join(name))
+        } else {
+            None
+        };
+        let rule = match tz {
+            Some(tz) => ZoneRule::parse(&tz),
+            None => ZoneRule::parse(name),
+        };
+        Ok(Zone::Rule(name.to_string(), rule.ok_or_else(bad)?))
+    }
+
+    /// Get the absolute time(s) of a local time
+    fn local_times(
+        &self,
+        local: NaiveDateTime,
+    ) -> LocalResult<DateTime<FixedOffset>> {
+        match self {
+            Zone::Local => Local.from_local_datetime(&local).map(Into::into),
+            Zone::Rule(_, rule) => rule.local_times(local),
+        }
+    }
+
+    /// Get the times of sample intervals on a date (yyyyMMdd).
+    ///
+    /// Intervals are slots of local (wall clock) time, so on days when
+    /// clocks change some are repeated (the earlier time is used) or
+    /// missing.
+    pub fn interval_times(
+        &self,
+        date: &str,
+        period: u32,
+        n_samples: usize,
+    ) -> Option<Vec<IntervalTime>> {
+        let midnight = NaiveDate::parse_from_str(date, DATE_FMT)
+            .ok()?
+            .and_hms_opt(0, 0, 0)?;
+        let times = (0..n_samples)
+            .map(|i| {
+                let secs = i64::from(period) * i as i64;
+                let local = midnight + Duration::seconds(secs);
+                match self.local_times(local) {
+                    LocalResult::Single(t) => IntervalTime {
+                        time: Some(t),
+                        flag: None,
+                    },
+                    LocalResult::Ambiguous(t, _) => IntervalTime {
+                        time: Some(t),
+                        flag: Some(DstFlag::Repeated),
+                    },
+                    LocalResult::None => IntervalTime {
+                        time: None,
+                        flag: Some(DstFlag::Missing),
+                    },
+                }
+            })
+            .collect();
+        Some(times)
+    }
+}
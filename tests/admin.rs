@@ -0,0 +1,146 @@
+// admin.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, samples, Fixture};
+use std::time::Duration;
+use trafdat::state::{AppState, Config};
+use trafdat::watch::Watcher;
+
+/// Vehicle log
+const VLOG: &str = "250 ? 00:00:10 55\n300 2000 ? 60\n";
+
+/// Create app state with admin pages and response cache enabled
+fn admin_state(fx: &Fixture) -> web::Data<AppState> {
+    fx.add_file("tms", "20210601", "100.vlog", VLOG.as_bytes())
+        .add_file("tms", "20210602", "100.vlog", VLOG.as_bytes())
+        .add_file("d2", "20210601", "200.vlog", VLOG.as_bytes());
+    let config = Config {
+        cache_ttl: Duration::from_secs(60),
+        admin_token: Some("secret".into()),
+        ..fx.config()
+    };
+    web::Data::new(AppState::new(config))
+}
+
+/// Make an authorized admin request
+async fn admin(
+    state: &web::Data<AppState>,
+    req: TestRequest,
+) -> common::Response {
+    request(state, req.insert_header(("authorization", "Bearer secret"))).await
+}
+
+#[actix_web::test]
+async fn authentication() {
+    let fx = Fixture::new();
+    let res = get(&fx.state(), "/trafdat/admin/").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let state = admin_state(&fx);
+    let res = get(&state, "/trafdat/admin/").await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert!(res.headers.get("www-authenticate").is_some());
+    let req = TestRequest::get()
+        .uri("/trafdat/admin/status.json")
+        .insert_header(("authorization", "Bearer wrong"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    // admin:secret
+    let req = TestRequest::get()
+        .uri("/trafdat/admin/")
+        .insert_header(("authorization", "Basic YWRtaW46c2VjcmV0"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/html"));
+    assert!(res.text().contains("<h2>Response Cache</h2>"));
+}
+
+#[actix_web::test]
+async fn cache_status_and_flush() {
+    let fx = Fixture::new();
+    let state = admin_state(&fx);
+    for uri in [
+        "/trafdat/tms/20210601/100.headway.json",
+        "/trafdat/tms/20210601/100.headway.json",
+        "/trafdat/tms/20210602/100.headway.json",
+        "/trafdat/d2/20210601/200.headway.json",
+    ] {
+        assert_eq!(get(&state, uri).await.status, StatusCode::OK);
+    }
+    let req = TestRequest::get().uri("/trafdat/admin/status.json");
+    let status = admin(&state, req).await.json();
+    let cache = &status["cache"];
+    assert_eq!(cache["entries"], 3);
+    assert_eq!(cache["hits"], 1);
+    assert_eq!(cache["misses"], 3);
+    assert_eq!(cache["hit_rate"], 0.25);
+    assert_eq!(cache["scopes"][0]["district"], "d2");
+    assert_eq!(cache["scopes"][1]["date"], "20210601");
+    assert_eq!(status["breakers"]["traffic"]["state"], "closed");
+    let req = TestRequest::post()
+        .uri("/trafdat/admin/flush")
+        .set_form([("district", "tms"), ("date", "20210601")]);
+    let res = admin(&state, req).await;
+    assert_eq!(res.status, StatusCode::SEE_OTHER);
+    assert_eq!(res.headers.get("location").unwrap(), "/trafdat/admin/");
+    let req = TestRequest::get().uri("/trafdat/admin/status.json");
+    let status = admin(&state, req).await.json();
+    assert_eq!(status["cache"]["entries"], 2);
+    let req = TestRequest::post()
+        .uri("/trafdat/admin/flush")
+        .set_form([("district", "tms"), ("date", "")]);
+    admin(&state, req).await;
+    let req = TestRequest::get().uri("/trafdat/admin/status.json");
+    let status = admin(&state, req).await.json();
+    assert_eq!(status["cache"]["entries"], 1);
+    assert_eq!(status["cache"]["scopes"][0]["district"], "d2");
+    let req = TestRequest::post()
+        .uri("/trafdat/admin/flush")
+        .set_form([("date", "20210601")]);
+    let res = admin(&state, req).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let req = TestRequest::post()
+        .uri("/trafdat/admin/flush")
+        .set_form([("district", "")]);
+    admin(&state, req).await;
+    let req = TestRequest::get().uri("/trafdat/admin/status.json");
+    let status = admin(&state, req).await.json();
+    assert_eq!(status["cache"]["entries"], 0);
+}
+
+#[actix_web::test]
+async fn watcher_and_errors() {
+    let fx = Fixture::new();
+    let state = admin_state(&fx);
+    let mut watcher = Watcher::default();
+    watcher.scan(&state);
+    fx.add_archive("tms", "20210603", &[("100.v30", &samples(2880, 1, 5))])
+        .add_raw("tms/2021/20210604.traffic", b"not a zip");
+    // new archives are checked once they settle
+    assert!(watcher.scan(&state).is_empty());
+    watcher.scan(&state);
+    let res = get(&state, "/trafdat/tms/20210604/100.v30").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    let req = TestRequest::get().uri("/trafdat/admin/status.json");
+    let status = admin(&state, req).await.json();
+    let watch = &status["watcher"];
+    assert_eq!(watch["running"], false);
+    assert_eq!(watch["scans"], 3);
+    assert!(watch["last_scan"].is_string());
+    let recent = watch["recent"].as_array().unwrap();
+    assert_eq!(recent.len(), 2);
+    assert!(recent.iter().any(|ev| ev["event"] == "new_date"));
+    let error = &status["errors"][0];
+    assert_eq!(error["status"], 502);
+    assert_eq!(error["method"], "GET");
+    assert_eq!(error["path"], "/trafdat/tms/20210604/100.v30");
+    let req = TestRequest::get().uri("/trafdat/admin/");
+    let html = admin(&state, req).await.text();
+    assert!(html.contains("integrity_failure"));
+    assert!(html.contains("/trafdat/tms/20210604/100.v30"));
+}
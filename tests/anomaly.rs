@@ -0,0 +1,66 @@
+// anomaly.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{get, samples, Fixture};
+
+/// Build 30-second volume samples with a drop to zero and a spike
+fn volume() -> Vec<u8> {
+    let mut data = vec![];
+    for i in 0..2880u32 {
+        let v = match i {
+            // 07:00 - 07:10 zeros after heavy flow
+            840..=859 => 0,
+            // 08:00 - 08:01 beyond physical limit
+            960 | 961 => 40,
+            // overnight zeros are normal
+            0..=359 => 0,
+            _ => (i % 7 + 4) as u8,
+        };
+        data.push(v);
+    }
+    data
+}
+
+#[actix_web::test]
+async fn anomalies() {
+    let fx = Fixture::new();
+    let mut occ = samples(2880, 2, 0);
+    // occupancy stuck at 12.5% from 12:00 to 13:00
+    for i in 1440..1560 {
+        occ[i * 2..i * 2 + 2].copy_from_slice(&1250i16.to_be_bytes());
+    }
+    fx.add_file("tms", "20210601", "100.v30", &volume())
+        .add_file("tms", "20210601", "100.o30", &occ)
+        .add_file("tms", "20210601", "101.s30", &samples(2880, 1, 255));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.anomalies.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let json = res.json();
+    assert_eq!(json["sid"], "100");
+    assert_eq!(json["checked"], serde_json::json!(["v30", "o30"]));
+    let anomalies = json["anomalies"].as_array().unwrap();
+    assert_eq!(anomalies.len(), 3);
+    assert_eq!(anomalies[0]["ext"], "v30");
+    assert_eq!(anomalies[0]["reason"], "zero_drop");
+    assert_eq!(anomalies[0]["start"], "07:00");
+    assert_eq!(anomalies[0]["end"], "07:10");
+    assert_eq!(anomalies[0]["samples"], 20);
+    assert_eq!(anomalies[1]["reason"], "limit");
+    assert_eq!(anomalies[1]["start"], "08:00");
+    assert_eq!(anomalies[1]["samples"], 2);
+    assert_eq!(anomalies[1]["value"], 40.0);
+    assert_eq!(anomalies[2]["ext"], "o30");
+    assert_eq!(anomalies[2]["reason"], "frozen");
+    assert_eq!(anomalies[2]["start"], "12:00");
+    assert_eq!(anomalies[2]["end"], "13:00");
+    assert_eq!(anomalies[2]["value"], 12.5);
+    // all samples missing
+    let res = get(&state, "/trafdat/tms/20210601/101.anomalies.json").await;
+    assert_eq!(res.json()["anomalies"], serde_json::json!([]));
+    let res = get(&state, "/trafdat/tms/20210601/102.anomalies.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
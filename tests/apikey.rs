@@ -0,0 +1,180 @@
+// apikey.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, samples, Fixture, Response};
+use serde_json::json;
+use std::fs::write;
+use std::time::{Duration, SystemTime};
+use trafdat::apikey::ApiKeys;
+use trafdat::signing::sign;
+use trafdat::state::{AppState, Config};
+
+/// API keys file
+const KEYS: &str = r#"[
+  {"key": "k-alice", "name": "alice", "scopes": ["read:data"]},
+  {"key": "k-bob", "name": "bob", "scopes": ["read:config", "admin"]},
+  {"key": "k-carol", "name": "carol", "scopes": ["read:config"]}
+]"#;
+
+/// Build state with API keys
+fn key_state(fx: &Fixture) -> web::Data<AppState> {
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5));
+    let path = fx.traffic_path().join("keys.json");
+    write(&path, KEYS).unwrap();
+    let mut state = AppState::new(fx.config());
+    state.api_keys = ApiKeys::load(&path).unwrap();
+    web::Data::new(state)
+}
+
+/// Make a GET request with an API key
+async fn get_key(
+    state: &web::Data<AppState>,
+    uri: &str,
+    key: &str,
+) -> Response {
+    let req = TestRequest::get()
+        .uri(uri)
+        .insert_header(("x-api-key", key));
+    request(state, req).await
+}
+
+#[actix_web::test]
+async fn api_key_scopes() {
+    let fx = Fixture::new();
+    let state = key_state(&fx);
+    let uri = "/trafdat/tms/20210601/100.v30";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = get_key(&state, uri, "k-wrong").await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = get_key(&state, uri, "k-alice").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let req = TestRequest::get()
+        .uri(uri)
+        .insert_header(("authorization", "Bearer k-alice"));
+    assert_eq!(request(&state, req).await.status, StatusCode::OK);
+    let res = get_key(&state, uri, "k-bob").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    // documentation needs no key
+    let res = get(&state, "/trafdat/").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let uri = "/trafdat/metro_config/20210601.json";
+    let res = get_key(&state, uri, "k-alice").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let res = get_key(&state, uri, "k-bob").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // admin scope grants admin pages without the admin token
+    let res = get_key(&state, "/trafdat/admin/", "k-bob").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = get_key(&state, "/trafdat/admin/", "k-alice").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    // jobs need write scope
+    let req = TestRequest::post()
+        .uri("/trafdat/jobs/export")
+        .insert_header(("x-api-key", "k-alice"))
+        .set_json(json!({}));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn api_key_stats() {
+    let fx = Fixture::new();
+    let state = key_state(&fx);
+    get_key(&state, "/trafdat/tms/20210601/100.v30", "k-alice").await;
+    let res = get_key(&state, "/trafdat/admin/stats.json", "k-bob").await;
+    assert_eq!(
+        res.json(),
+        json!([{
+            "district": "tms",
+            "date": "20210601",
+            "key": "alice",
+            "requests": 1,
+            "bytes": 2880,
+        }])
+    );
+}
+
+#[actix_web::test]
+async fn api_key_file_errors() {
+    let fx = Fixture::new();
+    let path = fx.traffic_path().join("keys.json");
+    write(&path, r#"[{"key": "k", "name": "x", "scopes": ["root"]}]"#).unwrap();
+    assert!(ApiKeys::load(&path).is_err());
+    write(&path, r#"[{"key": "", "name": "x", "scopes": []}]"#).unwrap();
+    assert!(ApiKeys::load(&path).is_err());
+}
+
+#[actix_web::test]
+async fn api_key_signed_url() {
+    let fx = Fixture::new();
+    fx.add_archive("tms", "20210602", &[("200.v30", &samples(2880, 1, 7))]);
+    let path = fx.traffic_path().join("keys.json");
+    write(&path, KEYS).unwrap();
+    let config = Config {
+        signing_key: Some("secret".into()),
+        ..fx.config()
+    };
+    let mut state = AppState::new(config);
+    state.api_keys = ApiKeys::load(&path).unwrap();
+    let state = web::Data::new(state);
+    let uri = "/trafdat/tms/20210602.traffic";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let expires = SystemTime::now() + Duration::from_secs(60);
+    let query = sign(b"secret", "/tms/20210602.traffic", expires);
+    let res = get(&state, &format!("{}?{}", uri, query)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    // a signature does not grant other scopes or paths
+    let uri = "/trafdat/tms/20210602/200.v30";
+    let res = get(&state, &format!("{}?{}", uri, query)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let query = sign(b"secret", "/admin/", expires);
+    let res = get(&state, &format!("/trafdat/admin/?{}", query)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    // archive downloads still need a signature when presenting a key
+    let uri = "/trafdat/tms/20210602.traffic";
+    let res = get_key(&state, uri, "k-alice").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn api_key_cache() {
+    let fx = Fixture::new();
+    fx.add_metro_config(
+        "20210601",
+        r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0"/>
+<r_node name="rnd_2" lon="-93.0" lat="45.0"/>
+</corridor>
+</tms_config>
+"#,
+    );
+    let path = fx.traffic_path().join("keys.json");
+    write(&path, KEYS).unwrap();
+    let config = Config {
+        cache_ttl: Duration::from_secs(60),
+        ..fx.config()
+    };
+    let mut state = AppState::new(config);
+    state.api_keys = ApiKeys::load(&path).unwrap();
+    let state = web::Data::new(state);
+    let uri = "/trafdat/metro_config/20210601/I-94_EB.geojson";
+    let res = get_key(&state, uri, "k-bob").await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    let res = get_key(&state, uri, "k-bob").await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    // each key holder has separate entries
+    let res = get_key(&state, uri, "k-carol").await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    let res = get_key(&state, uri, "k-alice").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+}
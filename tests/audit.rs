@@ -0,0 +1,82 @@
+// audit.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, Fixture, Response};
+use serde_json::json;
+use std::fs::read_to_string;
+use trafdat::state::{AppState, Config};
+
+/// Make an authorized admin request
+async fn admin(state: &web::Data<AppState>, req: TestRequest) -> Response {
+    request(state, req.insert_header(("authorization", "Bearer secret"))).await
+}
+
+#[actix_web::test]
+async fn audit_log() {
+    let fx = Fixture::new();
+    let path = fx.traffic_path().join("audit.log");
+    let state = web::Data::new(AppState::new(Config {
+        admin_token: Some("secret".into()),
+        audit_path: Some(path.clone()),
+        jobs_path: Some(fx.jobs_path()),
+        job_workers: 0,
+        ..fx.config()
+    }));
+    let req = TestRequest::post()
+        .uri("/trafdat/admin/flush")
+        .set_form([("district", "tms"), ("date", "20210601")]);
+    assert_eq!(admin(&state, req).await.status, StatusCode::SEE_OTHER);
+    let req = TestRequest::post()
+        .uri("/trafdat/jobs/export")
+        .set_json(json!({
+            "district": "tms",
+            "corridor": "I-94_EB",
+            "start": "20210601",
+            "end": "20210601",
+        }));
+    let res = admin(&state, req).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let id = res.json()["id"].as_str().unwrap().to_string();
+    let uri = format!("/trafdat/jobs/{}", id);
+    let res = admin(&state, TestRequest::delete().uri(&uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    // failed operations are not logged
+    let res = admin(&state, TestRequest::delete().uri("/trafdat/jobs/1")).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(read_to_string(&path).unwrap().lines().count(), 3);
+    let res = get(&state, "/trafdat/admin/audit.json").await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let req = TestRequest::get().uri("/trafdat/admin/audit.json");
+    let entries = admin(&state, req).await.json();
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0]["principal"], "admin");
+    assert_eq!(entries[0]["action"], "admin.flush");
+    assert_eq!(entries[0]["target"], "tms/20210601");
+    assert_eq!(entries[1]["principal"], "admin");
+    assert_eq!(entries[1]["action"], "job.submit");
+    assert_eq!(entries[1]["target"], id.as_str());
+    assert!(entries[1]["time"].as_str().unwrap().ends_with('Z'));
+    assert_eq!(entries[2]["action"], "job.cancel");
+    let req = TestRequest::get().uri("/trafdat/admin/audit.json?limit=1");
+    let entries = admin(&state, req).await.json();
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["action"], "job.cancel");
+}
+
+#[actix_web::test]
+async fn audit_disabled() {
+    let fx = Fixture::new();
+    let state = web::Data::new(AppState::new(Config {
+        admin_token: Some("secret".into()),
+        ..fx.config()
+    }));
+    let req = TestRequest::get().uri("/trafdat/admin/audit.json");
+    assert_eq!(admin(&state, req).await.status, StatusCode::NOT_FOUND);
+}
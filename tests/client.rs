@@ -0,0 +1,98 @@
+// client.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use common::{samples, start_server, Fixture};
+use trafdat::client::Client;
+use trafdat::error::Error;
+
+/// Valid metro_config document
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0">
+<detector name="100" category="" lane="1"/>
+</r_node>
+<r_node name="rnd_2" lon="-93.0" lat="45.0" station_id="S2"/>
+</corridor>
+</tms_config>
+"#;
+
+#[actix_web::test]
+async fn typed_client() {
+    let fx = Fixture::new();
+    let mut v30 = samples(2880, 1, 5);
+    v30[1] = 0xFF;
+    fx.add_file("tms", "20210601", "100.v30", &v30)
+        .add_file("tms", "20210601", "100.s30", &samples(2880, 1, 60))
+        .add_file("tms", "20210601", "101.o30", &samples(2880, 2, 1))
+        .add_file("tms", "20210602", "100.v30", &samples(2880, 1, 5))
+        .add_file("d2", "20210601", "200.v30", &samples(2880, 1, 5));
+    let client = Client::new(&start_server(&fx)).unwrap();
+    let mut districts = client.list_districts().await.unwrap();
+    districts.sort();
+    assert_eq!(districts, ["d2", "tms"]);
+    let dates = client.dates("tms", "2021").await.unwrap();
+    assert_eq!(dates, ["20210601", "20210602"]);
+    let mut sensors = client.sensors("tms", "20210601").await.unwrap();
+    sensors.sort();
+    assert_eq!(sensors, ["100", "101"]);
+    let series = client
+        .get_samples("tms", "20210601", "100", "v30")
+        .await
+        .unwrap();
+    assert_eq!(series.period(), 30);
+    assert_eq!(series.values().len(), 2880);
+    assert_eq!(series.values()[..3], [Some(5), None, Some(5)]);
+    let series = client
+        .get_samples("tms", "20210601", "101", "o30")
+        .await
+        .unwrap();
+    assert_eq!(series.values()[0], Some(257));
+    let res = client.get_samples("tms", "20210601", "102", "v30").await;
+    assert!(matches!(res, Err(Error::NotFound)));
+    let res = client.get_samples("tms", "20210601", "100", "vlog").await;
+    assert!(matches!(res, Err(Error::InvalidParam(_))));
+}
+
+#[actix_web::test]
+async fn shared_types() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "100.s30", &samples(2880, 1, 60))
+        .add_file("tms", "20210601", "101.c30", &samples(1440, 2, 3))
+        .add_file("tms", "20210602", "100.v30", &samples(2880, 1, 5))
+        .add_metro_config("20210601", METRO_XML);
+    let client = Client::new(&start_server(&fx)).unwrap();
+    let years = client.years("tms").await.unwrap();
+    assert_eq!(years.len(), 1);
+    assert_eq!(years[0].year, "2021");
+    assert_eq!(years[0].dates, 2);
+    let aligned = client
+        .aligned("tms", "20210601", &["100"], &["v30", "s30"])
+        .await
+        .unwrap();
+    assert_eq!(aligned.period, 30);
+    assert_eq!(aligned.samples, 2880);
+    assert_eq!(aligned.series[1].ext, "s30");
+    assert_eq!(aligned.series[1].values[0], Some(60));
+    let res = client
+        .aligned("tms", "20210601", &["100", "101"], &["v30", "c30"])
+        .await;
+    assert!(matches!(res, Err(Error::InvalidParam(_))));
+    let corridor = client.corridor("20210601", "I-94", "EB").await.unwrap();
+    assert_eq!(corridor.route, "I-94");
+    assert_eq!(corridor.r_node.len(), 2);
+    assert_eq!(corridor.r_node[0].detector[0].lane, "1");
+    assert_eq!(corridor.r_node[0].station_id, "#IMPLIED");
+    assert_eq!(corridor.r_node[1].station_id, "S2");
+    let feature = client
+        .corridor_geojson("20210601", "I-94", "EB")
+        .await
+        .unwrap();
+    assert_eq!(feature.kind, "Feature");
+    assert_eq!(feature.properties.r_node, ["rnd_1", "rnd_2"]);
+    assert_eq!(feature.geometry.coordinates[1], [-93.0, 45.0]);
+}
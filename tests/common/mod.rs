@@ -0,0 +1,225 @@
+// common/mod.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+#![allow(dead_code)]
+
+use actix_web::http::header::HeaderMap;
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, HttpServer};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use trafdat::server::configure;
+use trafdat::state::{AppState, Config};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Temporary archive tree for tests
+pub struct Fixture {
+    /// Root directory (removed on drop)
+    dir: TempDir,
+}
+
+/// Response from a test request
+pub struct Response {
+    /// HTTP status
+    pub status: StatusCode,
+    /// Content type header
+    pub content_type: Option<String>,
+    /// All response headers
+    pub headers: HeaderMap,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Get response body as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Parse response body as JSON
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).expect("invalid JSON")
+    }
+}
+
+impl Default for Fixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fixture {
+    /// Create an empty fixture
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().expect("tempdir");
+        create_dir_all(dir.path().join("traffic")).unwrap();
+        create_dir_all(dir.path().join("metro_config")).unwrap();
+        Fixture { dir }
+    }
+
+    /// Get the traffic archive path
+    pub fn traffic_path(&self) -> PathBuf {
+        self.dir.path().join("traffic")
+    }
+
+    /// Get the metro_config path
+    pub fn metro_path(&self) -> PathBuf {
+        self.dir.path().join("metro_config")
+    }
+
+    /// Get the export job results path
+    pub fn jobs_path(&self) -> PathBuf {
+        self.dir.path().join("jobs")
+    }
+
+    /// Get a configuration using the fixture paths
+    pub fn config(&self) -> Config {
+        Config {
+            traffic_path: self.traffic_path(),
+            metro_path: self.metro_path(),
+            ..Config::default()
+        }
+    }
+
+    /// Write a file relative to the traffic archive path
+    pub fn add_raw(&self, rel: &str, data: &[u8]) -> &Self {
+        write_file(&self.traffic_path().join(rel), data);
+        self
+    }
+
+    /// Write a sample file into a date directory
+    pub fn add_file(
+        &self,
+        district: &str,
+        date: &str,
+        name: &str,
+        data: &[u8],
+    ) -> &Self {
+        let rel = format!("{}/{}/{}/{}", district, &date[..4], date, name);
+        self.add_raw(&rel, data)
+    }
+
+    /// Write a .traffic zip archive for a date
+    pub fn add_archive(
+        &self,
+        district: &str,
+        date: &str,
+        entries: &[(&str, &[u8])],
+    ) -> &Self {
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        let buf = zip.finish().unwrap().into_inner();
+        let rel = format!("{}/{}/{}.traffic", district, &date[..4], date);
+        self.add_raw(&rel, &buf)
+    }
+
+    /// Write a consolidated year archive, with `{date}/{file}` entries
+    pub fn add_year_archive(
+        &self,
+        district: &str,
+        year: &str,
+        entries: &[(&str, &[u8])],
+    ) -> &Self {
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        let buf = zip.finish().unwrap().into_inner();
+        self.add_raw(&format!("{}/{}.traffic", district, year), &buf)
+    }
+
+    /// Write a gzipped metro_config document for a date
+    pub fn add_metro_config(&self, date: &str, xml: &str) -> &Self {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(xml.as_bytes()).unwrap();
+        let path = self
+            .metro_path()
+            .join(format!("metro_config_{}.xml.gz", date));
+        write_file(&path, &enc.finish().unwrap());
+        self
+    }
+
+    /// Create application state for the fixture
+    pub fn state(&self) -> web::Data<AppState> {
+        web::Data::new(AppState::new(self.config()))
+    }
+}
+
+/// Start a server for a fixture, returning its base URL
+pub fn start_server(fx: &Fixture) -> String {
+    serve(fx.state())
+}
+
+/// Start a server with application state, returning its base URL
+pub fn serve(state: web::Data<AppState>) -> String {
+    let prefix = state.config.url_prefix.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}{}", listener.local_addr().unwrap(), prefix);
+    let server = HttpServer::new(move || {
+        let prefix = prefix.clone();
+        App::new()
+            .app_data(state.clone())
+            .configure(move |cfg| configure(cfg, &prefix))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+    url
+}
+
+/// Write a file, creating parent directories
+fn write_file(path: &Path, data: &[u8]) {
+    create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).unwrap().write_all(data).unwrap();
+}
+
+/// Make sample data with one value repeated
+pub fn samples(n_samples: usize, bytes: usize, val: u8) -> Vec<u8> {
+    vec![val; n_samples * bytes]
+}
+
+/// Make a GET request against an application with shared state
+pub async fn get(state: &web::Data<AppState>, uri: &str) -> Response {
+    request(state, test::TestRequest::get().uri(uri)).await
+}
+
+/// Make a request against an application with shared state
+pub async fn request(
+    state: &web::Data<AppState>,
+    req: test::TestRequest,
+) -> Response {
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .configure(|cfg| configure(cfg, &state.config.url_prefix)),
+    )
+    .await;
+    let res = test::call_service(&app, req.to_request()).await;
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get("content-type")
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.to_string());
+    let headers = res.headers().clone();
+    let body = test::read_body(res).await.to_vec();
+    Response {
+        status,
+        content_type,
+        headers,
+        body,
+    }
+}
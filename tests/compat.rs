@@ -0,0 +1,88 @@
+// compat.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::header::ACCEPT;
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, samples, Fixture};
+use trafdat::state::{AppState, Config, LengthCheck};
+
+/// Build legacy mode state with a typical archive tree
+fn legacy_state(fx: &Fixture) -> web::Data<AppState> {
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "100.o10", &samples(8640, 2, 1))
+        .add_file("tms", "20210601", "101.v30", &samples(100, 1, 5))
+        .add_archive("tms", "20210602", &[("200.c30", &samples(2880, 2, 1))]);
+    web::Data::new(AppState::new(Config {
+        legacy: true,
+        length_check: LengthCheck::Lenient,
+        ..fx.config()
+    }))
+}
+
+#[actix_web::test]
+async fn legacy_listings() {
+    let fx = Fixture::new();
+    let state = legacy_state(&fx);
+    let res = get(&state, "/trafdat/tms/2021").await;
+    assert_eq!(res.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(res.text(), "20210601\n20210602\n");
+    let res = get(&state, "/trafdat/tms/2021.json").await;
+    assert_eq!(res.content_type.as_deref(), Some("application/json"));
+    assert_eq!(res.text(), r#"["20210601","20210602"]"#);
+    let res = get(&state, "/trafdat/tms/20210601?sources=true").await;
+    assert_eq!(res.text(), r#"["100","101"]"#);
+    let res = get(&state, "/trafdat/tms/20210601/100.json").await;
+    assert_eq!(res.text(), r#"["o10","v30"]"#);
+    let res = get(&state, "/trafdat/tms/20210602/200.json?source=dir").await;
+    assert_eq!(res.text(), r#"["c30"]"#);
+}
+
+#[actix_web::test]
+async fn legacy_samples() {
+    let fx = Fixture::new();
+    let state = legacy_state(&fx);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("application/octet_stream")
+    );
+    assert_eq!(res.body, samples(2880, 1, 5));
+    // formatting parameters and Accept headers are ignored
+    let uri = "/trafdat/tms/20210601/100.v30.json?values=raw&decimals=2";
+    let res = get(&state, uri).await;
+    assert_eq!(res.content_type.as_deref(), Some("application/json"));
+    assert!(res.text().starts_with(r#"["5","5","#));
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210601/100.v30")
+        .insert_header((ACCEPT, "text/csv"));
+    assert_eq!(request(&state, req).await.body, samples(2880, 1, 5));
+    let res = get(&state, "/trafdat/tms/20210602/200.c30").await;
+    assert_eq!(res.body, samples(2880, 2, 1));
+}
+
+#[actix_web::test]
+async fn legacy_not_found() {
+    let fx = Fixture::new();
+    let state = legacy_state(&fx);
+    for uri in [
+        // no rebinning
+        "/trafdat/tms/20210601/100.o30",
+        // invalid length, even in lenient mode
+        "/trafdat/tms/20210601/101.v30",
+        // invalid request
+        "/trafdat/2020/20210601",
+        // newer endpoints
+        "/trafdat/tms/20210601/100.v30.csv",
+        "/trafdat/tms/20210601/100.anomalies.json",
+        "/trafdat/tms/annotations.json",
+        "/trafdat/tms/2021/checksums.json",
+    ] {
+        let res = get(&state, uri).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
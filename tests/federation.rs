@@ -0,0 +1,205 @@
+// federation.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::web;
+use common::{get, samples, Fixture};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use trafdat::diskcache::DiskCache;
+use trafdat::error::Error;
+use trafdat::state::{AppState, Config};
+use trafdat::upstream::{Upstream, UpstreamResponse};
+
+/// Spawn a fake upstream server, sending request lines to a channel
+fn spawn_upstream() -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/trafdat", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+            }
+            let path = request.split_whitespace().nth(1).unwrap().to_string();
+            let res: &[u8] = if path == "/trafdat/d2/2021" {
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n\
+                  9\r\n20210601\n\r\n9\r\n20210602\n\r\n0\r\n\r\n"
+            } else if path.starts_with("/trafdat/d2/20210601/200.v30") {
+                b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+                  Content-Length: 4\r\n\r\n\x01\x02\x03\x04"
+            } else {
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nNot Found"
+            };
+            stream.write_all(res).unwrap();
+            tx.send(path).unwrap();
+        }
+    });
+    (url, rx)
+}
+
+/// Create app state federating district `d2`
+fn federated(fx: &Fixture, url: &str) -> web::Data<AppState> {
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5));
+    let config = Config {
+        cache_ttl: Duration::from_secs(60),
+        upstreams: vec![("d2".into(), url.into())],
+        ..fx.config()
+    };
+    web::Data::new(AppState::new(config))
+}
+
+#[test]
+fn upstream_client() {
+    let (url, rx) = spawn_upstream();
+    let upstream = Upstream::new(&url).unwrap();
+    let res = upstream.get("/d2/2021").unwrap();
+    assert_eq!(res.status, 200);
+    assert_eq!(res.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(res.body, b"20210601\n20210602\n");
+    assert_eq!(rx.recv().unwrap(), "/trafdat/d2/2021");
+    let res = upstream.get("/d2/2020").unwrap();
+    assert_eq!(res.status, 404);
+    // oversized responses are not truncated
+    let upstream = upstream.with_max_response(40);
+    assert!(matches!(upstream.get("/d2/2021"), Err(Error::Upstream(_))));
+    assert!(Upstream::new("https://example.com").is_err());
+}
+
+#[actix_web::test]
+async fn proxy_districts() {
+    let (url, rx) = spawn_upstream();
+    let fx = Fixture::new();
+    let state = federated(&fx, &url);
+    let res = get(&state, "/trafdat/districts").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let mut districts: Vec<String> =
+        serde_json::from_value(res.json()).unwrap();
+    districts.sort();
+    assert_eq!(districts, ["d2", "tms"]);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body.len(), 2880);
+    let res = get(&state, "/trafdat/d2/2021").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.text(), "20210601\n20210602\n");
+    assert_eq!(rx.recv().unwrap(), "/trafdat/d2/2021");
+    let res = get(&state, "/trafdat/d2/20210601/200.v30?scale=1").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("application/octet-stream")
+    );
+    assert_eq!(res.body, [1, 2, 3, 4]);
+    assert_eq!(rx.recv().unwrap(), "/trafdat/d2/20210601/200.v30?scale=1");
+    let res = get(&state, "/trafdat/d2/20210601/200.v30?scale=1").await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    assert_eq!(res.body, [1, 2, 3, 4]);
+    let res = get(&state, "/trafdat/d2/20210601/201.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(rx.recv().unwrap(), "/trafdat/d2/20210601/201.v30");
+    // only proxied requests reached the upstream
+    assert!(rx.try_recv().is_err());
+}
+
+#[actix_web::test]
+async fn upstream_down() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/trafdat", listener.local_addr().unwrap());
+    drop(listener);
+    let fx = Fixture::new();
+    let state = federated(&fx, &url);
+    let res = get(&state, "/trafdat/d2/2021").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn proxy_mode() {
+    let (url, rx) = spawn_upstream();
+    let fx = Fixture::new();
+    let config = Config {
+        proxy_upstream: Some(url),
+        ..fx.config()
+    };
+    let mut state = AppState::new(config);
+    let dir = fx.traffic_path().join("cache");
+    let disk = DiskCache::open(&dir, 1024, Duration::from_secs(60)).unwrap();
+    state.disk_cache = Some(disk);
+    let state = web::Data::new(state);
+    let res = get(&state, "/trafdat/d2/20210601/200.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(rx.recv().unwrap(), "/trafdat/d2/20210601/200.v30");
+    let res = get(&state, "/trafdat/d2/20210601/200.v30").await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("application/octet-stream")
+    );
+    assert_eq!(res.body, [1, 2, 3, 4]);
+    let res = get(&state, "/trafdat/districts").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(rx.recv().unwrap(), "/trafdat/districts");
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(rx.try_recv().is_err());
+    // cached responses persist on disk
+    let disk = DiskCache::open(&dir, 1024, Duration::from_secs(60)).unwrap();
+    assert_eq!(disk.len(), 1);
+    assert_eq!(
+        disk.get("/trafdat/d2/20210601/200.v30 []").unwrap().body,
+        [1, 2, 3, 4]
+    );
+}
+
+#[test]
+fn disk_cache_eviction() {
+    let fx = Fixture::new();
+    let dir = fx.traffic_path().join("cache");
+    let cache = DiskCache::open(&dir, 100, Duration::from_secs(60)).unwrap();
+    let res = |n: u8| UpstreamResponse {
+        status: 200,
+        content_type: Some("text/plain".into()),
+        body: vec![n; 19],
+    };
+    for key in ["a", "b", "c"] {
+        cache.put(key, &res(key.as_bytes()[0])).unwrap();
+    }
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.total_bytes(), 90);
+    assert!(cache.get("a").is_some());
+    cache.put("d", &res(b'd')).unwrap();
+    // "b" was least recently used
+    assert_eq!(cache.len(), 3);
+    assert!(cache.get("b").is_none());
+    assert_eq!(cache.get("a").unwrap().body, vec![b'a'; 19]);
+    let missing = UpstreamResponse {
+        status: 404,
+        content_type: None,
+        body: vec![],
+    };
+    cache.put("e", &missing).unwrap();
+    assert!(cache.get("e").is_none());
+    let stale = DiskCache::open(&dir, 100, Duration::ZERO).unwrap();
+    assert_eq!(stale.len(), 3);
+    assert!(stale.get("a").is_none());
+    assert_eq!(stale.len(), 2);
+}
@@ -0,0 +1,88 @@
+// golden.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+// Golden-file tests for metro_config JSON conversion.
+//
+// Each `tests/golden/{name}.xml` document is converted through the HTTP
+// interface and compared against `{name}.json`, plus `{name}_{cor}.json` for
+// each corridor.  Set `UPDATE_GOLDEN=1` to rewrite the golden files.
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{get, Fixture};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Date used for all golden documents
+const DATE: &str = "20210601";
+
+/// Get the golden file directory
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+/// Check whether golden files should be rewritten
+fn updating() -> bool {
+    std::env::var_os("UPDATE_GOLDEN").is_some()
+}
+
+/// Compare a JSON value against a golden file
+fn check_golden(path: &Path, val: &Value) {
+    let pretty = serde_json::to_string_pretty(val).unwrap() + "\n";
+    if updating() {
+        fs::write(path, pretty).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("missing golden file {}", path.display()));
+    let expected: Value = serde_json::from_str(&golden).unwrap();
+    assert_eq!(
+        &expected,
+        val,
+        "{} differs; rerun with UPDATE_GOLDEN=1 to accept changes",
+        path.display()
+    );
+}
+
+/// Get all golden XML documents, sorted by name
+fn golden_docs() -> Vec<PathBuf> {
+    let mut docs: Vec<PathBuf> = fs::read_dir(golden_dir())
+        .unwrap()
+        .map(|ent| ent.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "xml"))
+        .collect();
+    docs.sort();
+    docs
+}
+
+#[actix_web::test]
+async fn metro_config_json() {
+    let docs = golden_docs();
+    assert!(!docs.is_empty());
+    for doc in docs {
+        let name = doc.file_stem().unwrap().to_str().unwrap().to_string();
+        let fx = Fixture::new();
+        fx.add_metro_config(DATE, &fs::read_to_string(&doc).unwrap());
+        let state = fx.state();
+        let uri = format!("/trafdat/metro_config/{}.json", DATE);
+        let res = get(&state, &uri).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", name);
+        check_golden(&golden_dir().join(format!("{}.json", name)), &res.json());
+        let uri = format!("/trafdat/metro_config/{}/corridors", DATE);
+        let res = get(&state, &uri).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", name);
+        for cor in res.json().as_array().unwrap() {
+            let cor = cor.as_str().unwrap();
+            let uri = format!("/trafdat/metro_config/{}/{}.json", DATE, cor);
+            let res = get(&state, &uri).await;
+            assert_eq!(res.status, StatusCode::OK, "{} {}", name, cor);
+            let path = golden_dir().join(format!("{}_{}.json", name, cor));
+            check_golden(&path, &res.json());
+        }
+    }
+}
@@ -0,0 +1,403 @@
+// jobs.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, samples, Fixture, Response};
+use serde_json::{json, Value};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::time::Duration;
+use trafdat::apikey::ApiKeys;
+use trafdat::jobs;
+use trafdat::state::{AppState, Config};
+
+/// Admin token for job requests
+const TOKEN: &str = "secret";
+
+/// metro_config document with one station
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" station_id="S1" lon="-93.1" lat="45.0">
+<detector name="100" category="" field="20"/>
+</r_node>
+</corridor>
+</tms_config>
+"#;
+
+/// Build config with export jobs enabled
+fn job_config(fx: &Fixture) -> Config {
+    fx.add_metro_config("20210601", METRO_XML).add_file(
+        "tms",
+        "20210601",
+        "100.v30",
+        &samples(2880, 1, 5),
+    );
+    Config {
+        admin_token: Some(TOKEN.into()),
+        jobs_path: Some(fx.jobs_path()),
+        ..fx.config()
+    }
+}
+
+/// Build state with export jobs enabled
+fn job_state(
+    fx: &Fixture,
+    workers: usize,
+    queue: usize,
+) -> web::Data<AppState> {
+    web::Data::new(AppState::new(Config {
+        job_workers: workers,
+        job_queue: queue,
+        ..job_config(fx)
+    }))
+}
+
+/// Write a saved job, with an output file
+fn add_job(fx: &Fixture, id: &str, status: &str, sql: &str) {
+    create_dir_all(fx.jobs_path()).unwrap();
+    let job = json!({
+        "id": id,
+        "status": status,
+        "spec": spec("20210601"),
+    });
+    let path = fx.jobs_path().join(format!("{}.json", id));
+    write(path, job.to_string()).unwrap();
+    write(fx.jobs_path().join(format!("{}.sql", id)), sql).unwrap();
+}
+
+/// Export job specification ending on a date
+fn spec(end: &str) -> Value {
+    json!({
+        "district": "tms",
+        "corridor": "I-94_EB",
+        "start": "20210601",
+        "end": end,
+        "ext": ["v30"],
+    })
+}
+
+/// Make a job request with the admin token
+async fn authorized(state: &web::Data<AppState>, req: TestRequest) -> Response {
+    let auth = format!("Bearer {}", TOKEN);
+    request(state, req.insert_header(("authorization", auth))).await
+}
+
+/// Make a GET job request
+async fn get_job(state: &web::Data<AppState>, uri: &str) -> Response {
+    authorized(state, TestRequest::get().uri(uri)).await
+}
+
+/// Submit an export job
+async fn submit(state: &web::Data<AppState>, spec: Value) -> Response {
+    let req = TestRequest::post()
+        .uri("/trafdat/jobs/export")
+        .set_json(spec);
+    authorized(state, req).await
+}
+
+/// Cancel an export job
+async fn cancel(state: &web::Data<AppState>, id: &str) -> Response {
+    let uri = format!("/trafdat/jobs/{}", id);
+    authorized(state, TestRequest::delete().uri(&uri)).await
+}
+
+/// Poll a job until it is no longer queued or running
+async fn wait(state: &web::Data<AppState>, id: &str) -> Value {
+    let uri = format!("/trafdat/jobs/{}", id);
+    for _ in 0..100 {
+        let job = get_job(state, &uri).await.json();
+        if job["status"] != "queued" && job["status"] != "running" {
+            return job;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("job {} not finished", id);
+}
+
+#[actix_web::test]
+async fn export_job() {
+    let fx = Fixture::new();
+    let state = job_state(&fx, 2, 16);
+    let res = submit(&state, spec("20210602")).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let job = res.json();
+    let id = job["id"].as_str().unwrap();
+    assert_eq!(
+        res.headers.get("location").unwrap(),
+        &format!("/trafdat/jobs/{}", id)
+    );
+    assert_eq!(job["spec"]["corridor"], "I-94_EB");
+    let job = wait(&state, id).await;
+    assert_eq!(job["status"], "done");
+    assert_eq!(job["rows"], 2880);
+    let uri = job["result"].as_str().unwrap();
+    assert_eq!(uri, format!("/trafdat/jobs/{}/result.sql", id));
+    let res = get_job(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("application/sql"));
+    assert!(res
+        .text()
+        .starts_with("CREATE TABLE IF NOT EXISTS metadata"));
+    let res = get_job(&state, "/trafdat/jobs/123/result.sql").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // saved in the jobs directory
+    let saved = read_to_string(fx.jobs_path().join(format!("{}.json", id)));
+    let saved: Value = serde_json::from_str(&saved.unwrap()).unwrap();
+    assert_eq!(saved["status"], "done");
+    assert_eq!(saved["dates_done"], 2);
+    let res = get_job(&state, "/trafdat/jobs").await;
+    assert_eq!(res.json()[0]["id"], id);
+    // cancelling a finished job removes its result
+    let res = cancel(&state, id).await;
+    assert_eq!(res.json()["status"], "cancelled");
+    assert!(!fx.jobs_path().join(format!("{}.sql", id)).exists());
+    let res = get_job(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn export_job_errors() {
+    let fx = Fixture::new();
+    let state = job_state(&fx, 2, 0);
+    let res = submit(&state, spec("20210531")).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let mut bad = spec("20210601");
+    bad["district"] = json!("../tms");
+    let res = submit(&state, bad).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    // queue is full
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    // jobs not enabled
+    let state = fx.state();
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get_job(&state, "/trafdat/jobs").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn cancel_queued_job() {
+    let fx = Fixture::new();
+    // no workers, so jobs stay queued
+    let state = job_state(&fx, 0, 1);
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.json()["status"], "queued");
+    let id = res.json()["id"].as_str().unwrap().to_string();
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    let res = cancel(&state, &id).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["status"], "cancelled");
+    let uri = format!("/trafdat/jobs/{}", id);
+    assert_eq!(get_job(&state, &uri).await.json()["status"], "cancelled");
+    // queue has room again
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let res = cancel(&state, "123").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn resume_jobs() {
+    let fx = Fixture::new();
+    fx.add_metro_config("20210602", METRO_XML).add_file(
+        "tms",
+        "20210602",
+        "100.v30",
+        &samples(2880, 1, 5),
+    );
+    let state = job_state(&fx, 2, 16);
+    create_dir_all(fx.jobs_path()).unwrap();
+    // interrupted after the first date, with output past that point
+    let partial = "-- first date\n";
+    let job = json!({
+        "id": "17a",
+        "status": "running",
+        "spec": spec("20210602"),
+        "dates_done": 1,
+        "part_len": partial.len(),
+        "rows": 2880,
+    });
+    write(fx.jobs_path().join("17a.json"), job.to_string()).unwrap();
+    let part = format!("{}BEGIN;\nINSERT", partial);
+    write(fx.jobs_path().join("17a.sql.part"), part).unwrap();
+    let job = json!({
+        "id": "17b",
+        "status": "done",
+        "spec": spec("20210601"),
+        "rows": 2880,
+    });
+    write(fx.jobs_path().join("17b.json"), job.to_string()).unwrap();
+    assert_eq!(jobs::resume(&state).unwrap(), 1);
+    let job = wait(&state, "17a").await;
+    assert_eq!(job["status"], "done");
+    assert_eq!(job["rows"], 2880 * 2);
+    let sql = get_job(&state, "/trafdat/jobs/17a/result.sql").await.text();
+    assert!(sql.starts_with("-- first date\nBEGIN;\n"));
+    assert!(sql.contains("('20210602','rnd_1'"));
+    assert!(!sql.contains("'20210601'"));
+    assert!(!fx.jobs_path().join("17a.sql.part").exists());
+    let res = get_job(&state, "/trafdat/jobs").await;
+    assert_eq!(res.json()[1]["id"], "17b");
+    // new jobs are listed after resumed ones
+    let res = submit(&state, spec("20210601")).await;
+    let id = res.json()["id"].as_str().unwrap().to_string();
+    let res = get_job(&state, "/trafdat/jobs").await;
+    assert_eq!(res.json()[2]["id"], id.as_str());
+}
+
+#[actix_web::test]
+async fn job_quota() {
+    let fx = Fixture::new();
+    let state = web::Data::new(AppState::new(Config {
+        job_quota: 1,
+        ..job_config(&fx)
+    }));
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let id = res.json()["id"].as_str().unwrap().to_string();
+    let job = wait(&state, &id).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["error"], "job quota exceeded");
+    assert!(!fx.jobs_path().join(format!("{}.sql.part", id)).exists());
+    // finished results over quota are removed, oldest first
+    add_job(&fx, "17a", "done", "-- 17a");
+    add_job(&fx, "17b", "done", "-- 17b");
+    let state = web::Data::new(AppState::new(Config {
+        job_quota: 6,
+        ..job_config(&fx)
+    }));
+    jobs::resume(&state).unwrap();
+    assert_eq!(jobs::cleanup(&state), 6);
+    let res = get_job(&state, "/trafdat/jobs/17a").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert!(!fx.jobs_path().join("17a.json").exists());
+    let res = get_job(&state, "/trafdat/jobs/17b").await;
+    assert_eq!(res.status, StatusCode::OK);
+    // submissions fail while at quota
+    let res = submit(&state, spec("20210601")).await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    let metrics = get(&state, "/trafdat/metrics").await.text();
+    assert!(metrics.contains("trafdat_job_evictions_total 1\n"));
+    assert!(metrics.contains("trafdat_job_output_bytes 6\n"));
+}
+
+#[actix_web::test]
+async fn job_ttl() {
+    let fx = Fixture::new();
+    add_job(&fx, "17a", "done", "-- 17a");
+    add_job(&fx, "17b", "queued", "");
+    let state = web::Data::new(AppState::new(Config {
+        job_workers: 0,
+        job_ttl: Duration::ZERO,
+        ..job_config(&fx)
+    }));
+    jobs::resume(&state).unwrap();
+    actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(jobs::cleanup(&state), 0);
+    let res = get_job(&state, "/trafdat/jobs/17a").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert!(!fx.jobs_path().join("17a.sql").exists());
+    // unfinished jobs are kept
+    let res = get_job(&state, "/trafdat/jobs/17b").await;
+    assert_eq!(res.json()["status"], "queued");
+    let metrics = get(&state, "/trafdat/metrics").await.text();
+    assert!(metrics.contains("trafdat_job_expirations_total 1\n"));
+}
+
+/// API keys file, with two job clients and an admin
+const KEYS: &str = r#"[
+  {"key": "k-alice", "name": "alice", "scopes": ["read:data", "write"]},
+  {"key": "k-carol", "name": "carol", "scopes": ["read:data", "write"]},
+  {"key": "k-ops", "name": "ops", "scopes": ["read:data", "admin"]}
+]"#;
+
+/// Make a job request with an API key
+async fn keyed(
+    state: &web::Data<AppState>,
+    req: TestRequest,
+    key: &str,
+) -> Response {
+    request(state, req.insert_header(("x-api-key", key))).await
+}
+
+#[actix_web::test]
+async fn job_owners() {
+    let fx = Fixture::new();
+    let state = job_state(&fx, 2, 16);
+    // a key or the admin token is needed, even without API keys
+    let req = TestRequest::post()
+        .uri("/trafdat/jobs/export")
+        .set_json(spec("20210601"));
+    assert_eq!(request(&state, req).await.status, StatusCode::UNAUTHORIZED);
+    let res = get(&state, "/trafdat/jobs").await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = submit(&state, spec("20210601")).await;
+    let id = res.json()["id"].as_str().unwrap().to_string();
+    assert_eq!(id.len(), 32);
+    assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    let res = get(&state, &format!("/trafdat/jobs/{}", id)).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    // jobs are only visible to the key which submitted them
+    let path = fx.traffic_path().join("keys.json");
+    write(&path, KEYS).unwrap();
+    let mut state = AppState::new(job_config(&fx));
+    state.api_keys = ApiKeys::load(&path).unwrap();
+    let state = web::Data::new(state);
+    let req = TestRequest::post()
+        .uri("/trafdat/jobs/export")
+        .set_json(spec("20210601"));
+    let res = keyed(&state, req, "k-alice").await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let id = res.json()["id"].as_str().unwrap().to_string();
+    let uri = format!("/trafdat/jobs/{}", id);
+    let req = TestRequest::get().uri(&uri);
+    let job = keyed(&state, req, "k-alice").await.json();
+    assert_eq!(job["owner"], "alice");
+    let req = TestRequest::get().uri(&uri);
+    let res = keyed(&state, req, "k-carol").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let req = TestRequest::get().uri(&uri);
+    let res = keyed(&state, req, "k-ops").await;
+    assert_eq!(res.status, StatusCode::OK);
+    for _ in 0..100 {
+        let req = TestRequest::get().uri(&uri);
+        if keyed(&state, req, "k-alice").await.json()["status"] == "done" {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    let result = format!("{}/result.sql", uri);
+    let req = TestRequest::get().uri(&result);
+    let res = keyed(&state, req, "k-alice").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let req = TestRequest::get().uri(&result);
+    let res = keyed(&state, req, "k-carol").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // listings only include owned jobs, except for admins
+    let req = TestRequest::get().uri("/trafdat/jobs");
+    let jobs = keyed(&state, req, "k-alice").await.json();
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+    let req = TestRequest::get().uri("/trafdat/jobs");
+    let jobs = keyed(&state, req, "k-carol").await.json();
+    assert!(jobs.as_array().unwrap().is_empty());
+    let req = TestRequest::get().uri("/trafdat/jobs");
+    let jobs = keyed(&state, req, "k-ops").await.json();
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+    // other clients can't cancel a job
+    let req = TestRequest::delete().uri(&uri);
+    let res = keyed(&state, req, "k-carol").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let req = TestRequest::get().uri(&uri);
+    let job = keyed(&state, req, "k-alice").await.json();
+    assert_eq!(job["status"], "done");
+    let req = TestRequest::delete().uri(&uri);
+    let job = keyed(&state, req, "k-alice").await.json();
+    assert_eq!(job["status"], "cancelled");
+}
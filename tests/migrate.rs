@@ -0,0 +1,79 @@
+// migrate.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{get, samples, Fixture};
+use serde_json::json;
+use trafdat::migrate::{migrate_date, Options};
+
+/// Migration options
+fn options(remove: bool) -> Options {
+    Options {
+        level: 3,
+        max_size: 64 * 1024,
+        remove,
+    }
+}
+
+#[actix_web::test]
+async fn zstd_files() {
+    let fx = Fixture::new();
+    let data = zstd::encode_all(&samples(2880, 1, 5)[..], 3).unwrap();
+    fx.add_file("tms", "20210601", "100.v30.zst", &data);
+    let data = zstd::encode_all(&samples(100, 1, 5)[..], 3).unwrap();
+    fx.add_file("tms", "20210601", "200.v30.zst", &data);
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(res.json(), json!(["100", "200"]));
+    let res = get(&state, "/trafdat/tms/20210601/100.json").await;
+    assert_eq!(res.json(), json!(["v30"]));
+    let res = get(&state, "/trafdat/tms/20210601/100.v30?source=dir").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, samples(2880, 1, 5));
+    // invalid length (strict mode)
+    let res = get(&state, "/trafdat/tms/20210601/200.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn migrate_archive() {
+    let fx = Fixture::new();
+    fx.add_archive(
+        "tms",
+        "20210601",
+        &[
+            ("100.v30", &samples(2880, 1, 5)),
+            ("100.vlog", b"0 0 ? 60\n"),
+            ("200.v30", &samples(2880, 1, 9)),
+        ],
+    );
+    fx.add_file("tms", "20210601", "200.v30", &samples(2880, 1, 7));
+    let dir = fx.traffic_path().join("tms/2021/20210601");
+    // originals are kept without removal
+    let m =
+        migrate_date(&fx.traffic_path(), "tms", "20210601", &options(false))
+            .unwrap();
+    assert_eq!(m.files, 3);
+    assert!(m.bytes_after < m.bytes_before);
+    assert!(dir.join("200.v30").exists());
+    assert!(dir.join("200.v30.zst").exists());
+    assert!(dir.with_extension("traffic").exists());
+    let m = migrate_date(&fx.traffic_path(), "tms", "20210601", &options(true))
+        .unwrap();
+    assert_eq!(m.files, 1);
+    assert!(!dir.join("200.v30").exists());
+    assert!(!dir.with_extension("traffic").exists());
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(res.json(), json!(["100", "200"]));
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let res = get(&state, "/trafdat/tms/20210601/100.vlog").await;
+    assert_eq!(res.body, b"0 0 ? 60\n");
+    // directory file took precedence over the zip entry
+    let res = get(&state, "/trafdat/tms/20210601/200.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 7));
+}
@@ -0,0 +1,302 @@
+// output.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::header::ACCEPT;
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use chrono::{Local, NaiveDate, TimeZone};
+use common::{get, request, samples, Fixture};
+use serde_json::json;
+use trafdat::error::Error;
+use trafdat::output::{OutputFormat, SampleData};
+use trafdat::state::{AppState, Config};
+use trafdat::zone::{DstFlag, Zone};
+
+/// Sum of decoded values (plain text)
+struct SumOutput;
+
+impl OutputFormat for SumOutput {
+    fn name(&self) -> &str {
+        "sum"
+    }
+
+    fn content_type(&self) -> &str {
+        "text/x-sum"
+    }
+
+    fn encode(&self, data: &SampleData) -> Result<Vec<u8>, Error> {
+        let series = data.series.ok_or(Error::NotFound)?;
+        Ok(series.total().to_string().into_bytes())
+    }
+}
+
+/// metro_config document with a detector lane
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0">
+<detector name="100" category="" lane="2"/>
+</r_node>
+</corridor>
+</tms_config>
+"#;
+
+fn fixture() -> Fixture {
+    let fx = Fixture::new();
+    fx.add_metro_config("20210601", METRO_XML);
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "100.o30", &samples(2880, 2, 1))
+        .add_file("tms", "20210601", "100.vlog", b"250 ? 00:00:10 55\n");
+    fx
+}
+
+#[actix_web::test]
+async fn csv_format() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.csv").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/csv"));
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2881);
+    assert_eq!(lines[0], "interval,value,time,dst");
+    assert!(lines[1].starts_with("0,5,2021-06-01T00:00:00"));
+    assert!(lines[1].ends_with(','));
+    // occupancy is scaled to percent (257 hundredths)
+    let res = get(&state, "/trafdat/tms/20210601/100.o30.csv").await;
+    let text = res.text();
+    assert!(text.lines().nth(1).unwrap().starts_with("0,2.57,"));
+    let res = get(&state, "/trafdat/tms/20210601/100.vlog.csv").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn dst_timestamps() {
+    let fx = fixture();
+    fx.add_file("tms", "20211107", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210314", "100.v30", &samples(2880, 1, 5));
+    let config = Config {
+        time_zone: Zone::parse("CST6CDT,M3.2.0,M11.1.0").unwrap(),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    // clocks set back at 02:00 CDT
+    let res = get(&state, "/trafdat/tms/20211107/100.v30.csv").await;
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[1], "0,5,2021-11-07T00:00:00-05:00,");
+    assert_eq!(lines[120], "119,5,2021-11-07T00:59:30-05:00,");
+    assert_eq!(lines[121], "120,5,2021-11-07T01:00:00-05:00,repeated");
+    assert_eq!(lines[240], "239,5,2021-11-07T01:59:30-05:00,repeated");
+    assert_eq!(lines[241], "240,5,2021-11-07T02:00:00-06:00,");
+    assert_eq!(lines[2880], "2879,5,2021-11-07T23:59:30-06:00,");
+    // clocks set forward at 02:00 CST
+    let res = get(&state, "/trafdat/tms/20210314/100.v30.csv").await;
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[240], "239,5,2021-03-14T01:59:30-06:00,");
+    assert_eq!(lines[241], "240,5,,missing");
+    assert_eq!(lines[360], "359,5,,missing");
+    assert_eq!(lines[361], "360,5,2021-03-14T03:00:00-05:00,");
+    let res = get(&state, "/trafdat/tms/20210314/100.v30.influx").await;
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2880 - 120);
+    // 2021-03-14T03:00:00-05:00
+    assert!(lines[240].ends_with(" 1615708800000000000"));
+    let res = get(&state, "/trafdat/tms/20211107/100.v30.influx").await;
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2880);
+    assert!(lines[120].contains(r#"value=5,dst="repeated" "#));
+    assert!(!lines[240].contains("dst"));
+}
+
+#[test]
+fn zone_names() {
+    for tz in [
+        "America/Chicago",
+        "UTC",
+        "EST5EDT",
+        "<+03>-3",
+        "NZST-12NZDT",
+    ] {
+        assert!(Zone::parse(tz).is_ok(), "{}", tz);
+    }
+    for tz in ["", "Nowhere/Special", "CST", "CST6CDT,J60,J300", "../etc"] {
+        assert!(Zone::parse(tz).is_err(), "{}", tz);
+    }
+    // southern hemisphere: DST spans the new year
+    let zone = Zone::parse("NZST-12NZDT,M9.5.0,M4.1.0/3").unwrap();
+    let times = zone.interval_times("20210101", 3600, 24).unwrap();
+    assert_eq!(
+        times[0].time.unwrap().to_rfc3339(),
+        "2021-01-01T00:00:00+13:00"
+    );
+    let times = zone.interval_times("20210404", 3600, 24).unwrap();
+    assert_eq!(times[2].flag, Some(DstFlag::Repeated));
+    assert_eq!(
+        times[3].time.unwrap().to_rfc3339(),
+        "2021-04-04T03:00:00+12:00"
+    );
+}
+
+#[actix_web::test]
+async fn accept_header() {
+    let fx = fixture();
+    let state = fx.state();
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210601/100.v30")
+        .insert_header((ACCEPT, "text/html, text/csv;q=0.9"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/csv"));
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210601/100.v30")
+        .insert_header((ACCEPT, "*/*"));
+    let res = request(&state, req).await;
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("application/octet_stream")
+    );
+    assert_eq!(res.body, samples(2880, 1, 5));
+}
+
+#[actix_web::test]
+async fn registered_format() {
+    let fx = fixture();
+    let mut state = AppState::new(fx.config());
+    state.formats.register(Box::new(SumOutput));
+    let state = web::Data::new(state);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.sum").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/x-sum"));
+    assert_eq!(res.text(), "14400");
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210601/100.v30")
+        .insert_header((ACCEPT, "text/x-sum"));
+    assert_eq!(request(&state, req).await.text(), "14400");
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.parquet").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn influx_format() {
+    let fx = fixture();
+    let mut o30 = samples(2880, 2, 1);
+    o30[2] = 0xFF;
+    o30[3] = 0xFF;
+    fx.add_file("tms", "20210601", "200.o30", &o30);
+    let state = fx.state();
+    let midnight = NaiveDate::from_ymd_opt(2021, 6, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let start = Local.from_local_datetime(&midnight).unwrap().timestamp()
+        * 1_000_000_000;
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.influx").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2880);
+    assert_eq!(
+        lines[0],
+        format!("volume,district=tms,sensor=100,lane=2 value=5 {}", start)
+    );
+    assert_eq!(
+        lines[1],
+        format!(
+            "volume,district=tms,sensor=100,lane=2 value=5 {}",
+            start + 30_000_000_000
+        )
+    );
+    // no lane for unknown detector; missing samples skipped
+    let res = get(&state, "/trafdat/tms/20210601/200.o30.influx").await;
+    let text = res.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2879);
+    assert_eq!(
+        lines[1],
+        format!(
+            "occupancy,district=tms,sensor=200 value=2.57 {}",
+            start + 60_000_000_000
+        )
+    );
+}
+
+#[actix_web::test]
+async fn scan_occupancy() {
+    let fx = fixture();
+    fx.add_metro_config(
+        "20210602",
+        &METRO_XML
+            .replace("20210601", "20210602")
+            .replace(r#"lane="2""#, r#"lane="2" controller="ctl_1""#),
+    );
+    fx.add_file("tms", "20210601", "100.c30", &samples(2880, 2, 1))
+        .add_file("tms", "20210602", "100.c30", &samples(2880, 2, 1));
+    let state = fx.state();
+    // 257 scans at 60 Hz in 30 seconds
+    let uri = "/trafdat/tms/20210601/100.c30.json?occupancy=true&decimals=2";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()[0], 14.28);
+    let res =
+        get(&state, "/trafdat/tms/20210601/100.c30.json?occupancy=true").await;
+    assert_eq!(res.json().as_array().unwrap().len(), 2880);
+    let res = get(&state, "/trafdat/tms/20210601/100.c30.json").await;
+    assert_eq!(res.json()[0], "1");
+    let res =
+        get(&state, "/trafdat/tms/20210601/100.c30.csv?occupancy=true").await;
+    assert!(res.text().lines().nth(1).unwrap().starts_with("0,14.27"));
+    let res =
+        get(&state, "/trafdat/tms/20210601/100.v30.csv?occupancy=true").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    // controller scan rate
+    let state = web::Data::new(AppState::new(Config {
+        controller_scan_rates: vec![("ctl_1".into(), 50)],
+        ..fx.config()
+    }));
+    let uri = "/trafdat/tms/20210602/100.c30.json?occupancy=true&decimals=2";
+    assert_eq!(get(&state, uri).await.json()[0], 17.13);
+    let uri = "/trafdat/tms/20210601/100.c30.json?occupancy=true&decimals=2";
+    assert_eq!(get(&state, uri).await.json()[0], 14.28);
+}
+
+#[actix_web::test]
+async fn signed_volume() {
+    let fx = fixture();
+    let mut v30 = samples(2880, 1, 5);
+    v30[1] = 0xFF;
+    v30[2] = 0x80;
+    v30[3] = 0x7F;
+    fx.add_file("tms", "20210601", "200.v30", &v30);
+    let state = fx.state();
+    // unsigned bytes as stored
+    let res = get(&state, "/trafdat/tms/20210601/200.v30.json").await;
+    let vals = res.json();
+    assert_eq!(vals.as_array().unwrap()[..4], ["5", "255", "128", "127"]);
+    // negative values are missing
+    let uri = "/trafdat/tms/20210601/200.v30.json?values=decoded";
+    let vals = get(&state, uri).await.json();
+    let vals = &vals.as_array().unwrap()[..4];
+    assert_eq!(vals, [json!(5), json!(null), json!(null), json!(127)]);
+    let uri = "/trafdat/tms/20210601/200.v30.json?values=raw";
+    let vals = get(&state, uri).await.json();
+    assert_eq!(vals.as_array().unwrap().len(), 2880);
+    assert_eq!(vals.as_array().unwrap()[..4], [5, -1, -128, 127]);
+    let uri = "/trafdat/tms/20210601/200.v30.csv?values=raw";
+    let text = get(&state, uri).await.text();
+    assert!(text.lines().nth(2).unwrap().starts_with("1,-1,"));
+    let text = get(&state, "/trafdat/tms/20210601/200.v30.csv")
+        .await
+        .text();
+    assert!(text.lines().nth(2).unwrap().starts_with("1,,"));
+    let uri = "/trafdat/tms/20210601/100.vlog.json?values=raw";
+    assert_eq!(get(&state, uri).await.status, StatusCode::BAD_REQUEST);
+}
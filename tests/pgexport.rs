@@ -0,0 +1,224 @@
+// pgexport.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use common::{samples, Fixture};
+use trafdat::export::date_range;
+use trafdat::pgexport::write_sql;
+use trafdat::state::AppState;
+
+#[test]
+fn pg_export() {
+    let fx = Fixture::new();
+    let mut v30 = samples(2880, 1, 5);
+    v30[1] = 0xFF;
+    fx.add_file("tms", "20210601", "100.v30", &v30)
+        .add_file("tms", "20210601", "it's.o30", &samples(2880, 2, 1))
+        .add_archive("tms", "20210602", &[("200.o30", &samples(2880, 2, 1))]);
+    let state = AppState::new(fx.config());
+    let dates = date_range("20210601", "20210603").unwrap();
+    let exts = vec!["v30".to_string(), "o30".to_string()];
+    let mut out = vec![];
+    let rows = write_sql(&state, "tms", &dates, &[], &exts, &mut out).unwrap();
+    assert_eq!(rows, 2879 + 2880 + 2880);
+    let sql = String::from_utf8(out).unwrap();
+    assert!(sql.starts_with("CREATE TABLE IF NOT EXISTS trafdat_sample"));
+    assert_eq!(sql.matches("BEGIN;").count(), 3);
+    assert_eq!(sql.matches("COMMIT;").count(), 3);
+    // batches of 1000 rows per date
+    assert_eq!(sql.matches("INSERT INTO").count(), 6 + 3);
+    assert_eq!(sql.matches("ON CONFLICT").count(), 9);
+    assert!(sql.contains("('tms','100','v30',to_timestamp("));
+    assert!(sql.contains("('tms','it''s','o30',to_timestamp("));
+    assert!(sql.contains("('tms','200','o30',to_timestamp("));
+    assert!(sql.contains("),2.57)"));
+}
+
+/// Mock PostgreSQL server, with SCRAM-SHA-256 authentication
+#[cfg(feature = "postgres")]
+mod server {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::convert::TryInto;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::JoinHandle;
+
+    /// Password of mock user
+    pub const PASSWORD: &str = "pa:ss";
+
+    /// Statements and copied data received by the server
+    #[derive(Default)]
+    pub struct Received {
+        pub queries: Vec<String>,
+        pub copied: String,
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn read_msg(s: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 5];
+        s.read_exact(&mut head).unwrap();
+        let len = i32::from_be_bytes(head[1..].try_into().unwrap()) as usize;
+        let mut body = vec![0; len - 4];
+        s.read_exact(&mut body).unwrap();
+        (head[0], body)
+    }
+
+    fn send(s: &mut TcpStream, kind: u8, body: &[u8]) {
+        s.write_all(&[kind]).unwrap();
+        s.write_all(&(body.len() as i32 + 4).to_be_bytes()).unwrap();
+        s.write_all(body).unwrap();
+    }
+
+    fn auth(s: &mut TcpStream, code: i32, data: &[u8]) {
+        let mut body = code.to_be_bytes().to_vec();
+        body.extend_from_slice(data);
+        send(s, b'R', &body);
+    }
+
+    fn ready(s: &mut TcpStream) {
+        send(s, b'Z', b"I");
+    }
+
+    /// Handle one connection
+    fn serve(mut s: TcpStream, fail: Option<&str>) -> Received {
+        let mut len = [0; 4];
+        s.read_exact(&mut len).unwrap();
+        let mut startup = vec![0; i32::from_be_bytes(len) as usize - 4];
+        s.read_exact(&mut startup).unwrap();
+        assert!(startup.windows(12).any(|w| w == b"user\0trafdat"));
+        assert!(startup.windows(17).any(|w| w == b"database\0traffic\0"));
+        auth(&mut s, 10, b"SCRAM-SHA-256\0\0");
+        let (kind, body) = read_msg(&mut s);
+        assert_eq!(kind, b'p');
+        let first = String::from_utf8(body[18..].to_vec()).unwrap();
+        let first_bare = first.strip_prefix("n,,").unwrap();
+        let nonce = format!("{}srv", first_bare.strip_prefix("n=,r=").unwrap());
+        let salt = b"mock salt";
+        let server_first =
+            format!("r={},s={},i=4096", nonce, STANDARD.encode(salt));
+        auth(&mut s, 11, server_first.as_bytes());
+        let (kind, body) = read_msg(&mut s);
+        assert_eq!(kind, b'p');
+        let last = String::from_utf8(body).unwrap();
+        let (without_proof, proof) = last.split_once(",p=").unwrap();
+        assert_eq!(without_proof, format!("c=biws,r={}", nonce));
+        // PBKDF2-HMAC-SHA-256
+        let mut u =
+            hmac(PASSWORD.as_bytes(), &[&salt[..], &[0, 0, 0, 1]].concat());
+        let mut salted = u.clone();
+        for _ in 1..4096 {
+            u = hmac(PASSWORD.as_bytes(), &u);
+            salted.iter_mut().zip(&u).for_each(|(a, b)| *a ^= b);
+        }
+        let stored_key = Sha256::digest(hmac(&salted, b"Client Key"));
+        let message =
+            format!("{},{},{}", first_bare, server_first, without_proof);
+        let signature = hmac(&stored_key, message.as_bytes());
+        let client_key: Vec<u8> = STANDARD
+            .decode(proof)
+            .unwrap()
+            .iter()
+            .zip(&signature)
+            .map(|(p, s)| p ^ s)
+            .collect();
+        assert_eq!(Sha256::digest(client_key), stored_key);
+        let server_key = hmac(&salted, b"Server Key");
+        let verifier = STANDARD.encode(hmac(&server_key, message.as_bytes()));
+        auth(&mut s, 12, format!("v={}", verifier).as_bytes());
+        auth(&mut s, 0, &[]);
+        send(&mut s, b'S', b"server_version\x0016.0\0");
+        ready(&mut s);
+        let mut received = Received::default();
+        loop {
+            let (kind, body) = read_msg(&mut s);
+            if kind == b'X' {
+                return received;
+            }
+            assert_eq!(kind, b'Q');
+            let query =
+                String::from_utf8(body[..body.len() - 1].to_vec()).unwrap();
+            received.queries.push(query.clone());
+            if fail.is_some_and(|f| query.starts_with(f)) {
+                send(&mut s, b'E', b"SERROR\0C23505\0Mduplicate key\0\0");
+            } else if query.starts_with("COPY") {
+                send(&mut s, b'G', &[0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+                loop {
+                    match read_msg(&mut s) {
+                        (b'd', data) => received
+                            .copied
+                            .push_str(std::str::from_utf8(&data).unwrap()),
+                        (b'c', _) => break,
+                        (kind, _) => panic!("unexpected {}", kind),
+                    }
+                }
+                send(&mut s, b'C', b"COPY\0");
+            } else {
+                send(&mut s, b'C', b"OK\0");
+            }
+            ready(&mut s);
+        }
+    }
+
+    /// Start a mock server, returning its URL
+    pub fn start(fail: Option<&'static str>) -> (String, JoinHandle<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "postgres://trafdat:pa%3Ass@{}/traffic",
+            listener.local_addr().unwrap()
+        );
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, fail)
+        });
+        (url, handle)
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[test]
+fn pg_load() {
+    use trafdat::pgexport::load_db;
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "it's\t.o30", &samples(2880, 2, 1))
+        .add_archive("tms", "20210602", &[("200.o30", &samples(2880, 2, 1))]);
+    let state = AppState::new(fx.config());
+    let dates = date_range("20210601", "20210602").unwrap();
+    let exts = vec!["v30".to_string(), "o30".to_string()];
+    let (url, server) = server::start(None);
+    let rows = load_db(&state, &url, "tms", &dates, &[], &exts).unwrap();
+    assert_eq!(rows, 2880 * 3);
+    let received = server.join().unwrap();
+    let queries = &received.queries;
+    assert_eq!(queries.len(), 1 + 3 * 2);
+    assert!(queries[0].starts_with("CREATE TABLE IF NOT EXISTS trafdat_sample"));
+    assert!(queries[1].contains("CREATE TEMP TABLE trafdat_load"));
+    assert!(queries[2].starts_with("COPY trafdat_load"));
+    assert!(queries[3].contains("ON CONFLICT"));
+    assert!(queries[3].ends_with("COMMIT;\n"));
+    let lines: Vec<&str> = received.copied.lines().collect();
+    assert_eq!(lines.len(), 2880 * 3);
+    assert!(lines.contains(&"tms\t100\tv30\t1622523600\t5"));
+    assert!(lines.iter().any(|l| l.starts_with("tms\tit's\\t\to30\t")));
+    assert!(lines.iter().any(|l| l.starts_with("tms\t200\to30\t")));
+    // failed dates are rolled back
+    let (url, server) = server::start(Some("INSERT"));
+    let res = load_db(&state, &url, "tms", &dates, &[], &exts);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("23505: duplicate key"));
+    let queries = server.join().unwrap().queries;
+    assert_eq!(queries.len(), 5);
+    assert_eq!(queries[4], "ROLLBACK");
+}
@@ -0,0 +1,43 @@
+// prewarm.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::web;
+use chrono::NaiveDate;
+use common::{get, samples, Fixture};
+use std::time::Duration;
+use trafdat::prewarm::{warm, HotDates};
+use trafdat::state::{AppState, Config};
+
+#[test]
+fn parse_hot_dates() {
+    assert!(HotDates::parse("tms:20210601-20210630").is_ok());
+    assert!(HotDates::parse("tms:7d").is_ok());
+    assert!(HotDates::parse("tms:20210630-20210601").is_err());
+    assert!(HotDates::parse("tms:20200101-20211231").is_err());
+    assert!(HotDates::parse("tms:0d").is_err());
+    assert!(HotDates::parse(":7d").is_err());
+    assert!(HotDates::parse("tms").is_err());
+}
+
+#[actix_web::test]
+async fn warm_hot_dates() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_raw("tms/2021/20210602.traffic", b"not a zip file")
+        .add_metro_config("20210601", "<tms_config></tms_config>\n");
+    let config = Config {
+        hot_dates: vec![
+            HotDates::parse("tms:20210601-20210603").unwrap(),
+            HotDates::parse("tms:2d").unwrap(),
+        ],
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let today = NaiveDate::from_ymd_opt(2021, 6, 2).unwrap();
+    assert_eq!(warm(&state, today, Duration::ZERO), 5);
+    let res = get(&state, "/trafdat/metrics").await;
+    assert!(res.text().contains("trafdat_corrupt_archives_total 1\n"));
+}
@@ -0,0 +1,42 @@
+// proxy.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use actix_web::test::TestRequest;
+use std::net::IpAddr;
+use trafdat::proxy::ClientInfo;
+
+/// Reverse proxy address
+const PROXY: &str = "10.0.0.1";
+
+/// Build a request from a peer with forwarding headers
+fn client(peer: &str, trusted: &[&str]) -> ClientInfo {
+    let req = TestRequest::default()
+        .peer_addr(format!("{}:4000", peer).parse().unwrap())
+        .insert_header(("host", "internal:8080"))
+        .insert_header(("x-forwarded-for", "192.0.2.7, 10.0.0.2"))
+        .insert_header(("x-forwarded-proto", "https"))
+        .insert_header(("x-forwarded-host", "data.example.org"))
+        .to_http_request();
+    let trusted: Vec<IpAddr> =
+        trusted.iter().map(|a| a.parse().unwrap()).collect();
+    ClientInfo::from_request(&req, &trusted)
+}
+
+#[test]
+fn untrusted_peer() {
+    let info = client("198.51.100.3", &[PROXY]);
+    assert_eq!(info.addr, Some("198.51.100.3".parse().unwrap()));
+    assert_eq!(info.scheme, "http");
+    assert_eq!(info.host, "internal:8080");
+}
+
+#[test]
+fn trusted_proxy() {
+    let info = client(PROXY, &[PROXY]);
+    assert_eq!(info.addr, Some("10.0.0.2".parse().unwrap()));
+    assert_eq!(info.scheme, "https");
+    assert_eq!(info.host, "data.example.org");
+    let info = client(PROXY, &[PROXY, "10.0.0.2"]);
+    assert_eq!(info.addr, Some("192.0.2.7".parse().unwrap()));
+}
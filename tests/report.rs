@@ -0,0 +1,188 @@
+// report.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use chrono::NaiveDate;
+use common::{samples, Fixture};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use trafdat::report::{render, send_mail, Credentials, ReportConfig, Schedule};
+use trafdat::state::AppState;
+
+/// Report configuration file
+const CONFIG: &str = r#"{
+    "smtp": "localhost:25",
+    "from": "trafdat@example.com",
+    "reports": [
+        {
+            "name": "TMS daily",
+            "district": "tms",
+            "schedule": "daily",
+            "recipients": ["ops@example.com"]
+        },
+        {
+            "name": "TMS weekly",
+            "district": "tms",
+            "schedule": "weekly",
+            "exts": ["v30", "c30"],
+            "recipients": ["ops@example.com", "eng@example.com"]
+        }
+    ]
+}"#;
+
+/// Load the report configuration
+fn load(fx: &Fixture) -> ReportConfig {
+    let path = fx.traffic_path().join("reports.json");
+    fx.add_raw("reports.json", CONFIG.as_bytes());
+    ReportConfig::load(&path).unwrap()
+}
+
+#[test]
+fn schedule() {
+    let fx = Fixture::new();
+    let config = load(&fx);
+    assert_eq!(config.hour, 6);
+    let daily = &config.reports[0];
+    let weekly = &config.reports[1];
+    assert_eq!(daily.schedule, Schedule::Daily);
+    assert_eq!(daily.exts, vec!["v30".to_string()]);
+    // 2021-06-07 is a Monday
+    let monday = NaiveDate::from_ymd_opt(2021, 6, 7).unwrap();
+    let tuesday = NaiveDate::from_ymd_opt(2021, 6, 8).unwrap();
+    assert!(daily.is_due(tuesday));
+    assert!(weekly.is_due(monday));
+    assert!(!weekly.is_due(tuesday));
+    assert_eq!(daily.dates(tuesday), vec!["20210607".to_string()]);
+    let dates = weekly.dates(monday);
+    assert_eq!(dates.len(), 7);
+    assert_eq!(dates[0], "20210531");
+    assert_eq!(dates[6], "20210606");
+    assert_eq!(weekly.subject(&dates), "TMS weekly: 20210531 - 20210606");
+}
+
+#[test]
+fn render_report() {
+    let fx = Fixture::new();
+    let config = load(&fx);
+    let good: Vec<u8> = (0..2880).map(|i| (i % 20) as u8).collect();
+    let mut partial = good.clone();
+    for val in partial.iter_mut().take(1000) {
+        *val = 0xFF;
+    }
+    fx.add_file("tms", "20210601", "100.v30", &good)
+        .add_file("tms", "20210601", "101.v30", &partial)
+        .add_file("tms", "20210601", "102.v30", &samples(2880, 1, 0xFF))
+        .add_file("tms", "20210601", "103.v30", &samples(2880, 1, 7));
+    let state = AppState::new(fx.config());
+    let report = render(&state, &config.reports[0], &["20210601".into()]);
+    assert!(report.starts_with("TMS daily: 20210601\n"));
+    let lines: Vec<&str> = report.lines().collect();
+    let row = lines.iter().find(|l| l.starts_with("20210601")).unwrap();
+    let cols: Vec<&str> = row.split_whitespace().collect();
+    // 2 complete sensors; valid = (3 * 2880 - 1000) / (4 * 2880)
+    assert_eq!(cols, vec!["20210601", "4", "2", "66.3%"]);
+    let issues: Vec<Vec<&str>> = lines
+        .iter()
+        .skip_while(|l| !l.starts_with("sensor"))
+        .skip(1)
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(issues.len(), 3);
+    assert_eq!(issues[0][0], "102");
+    assert_eq!(issues[0][3..5], ["no", "data"]);
+    assert_eq!(issues[1][0], "101");
+    assert_eq!(issues[1][3], "partial");
+    assert_eq!(issues[1][4], "65.3%");
+    assert_eq!(issues[2][0], "103");
+    assert_eq!(issues[2][3], "constant");
+}
+
+/// Start an SMTP server for one session, returning its address and a
+/// thread joining to the received lines
+fn smtp_server(
+    ehlo: &'static [u8],
+) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(b"220 test ESMTP\r\n").unwrap();
+        let mut lines = vec![];
+        let mut data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            let reply: &[u8] = if data {
+                if line == "." {
+                    data = false;
+                    b"250 queued\r\n"
+                } else {
+                    b""
+                }
+            } else if line == "DATA" {
+                data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                b"221 bye\r\n"
+            } else if line.starts_with("HELO") {
+                b"250-test\r\n250 OK\r\n"
+            } else if line.starts_with("EHLO") {
+                ehlo
+            } else if line.starts_with("AUTH") {
+                b"235 accepted\r\n"
+            } else {
+                b"250 OK\r\n"
+            };
+            stream.write_all(reply).unwrap();
+            lines.push(line);
+        }
+        lines
+    });
+    (addr, server)
+}
+
+#[test]
+fn smtp_send() {
+    let (addr, server) = smtp_server(b"");
+    let to = vec!["a@example.com".to_string(), "b@example.com".to_string()];
+    let body = "Report\n.hidden\nend\n";
+    send_mail(&addr, None, "t@example.com", &to, "Daily", body).unwrap();
+    let lines = server.join().unwrap();
+    assert_eq!(lines[0], "HELO trafdat");
+    assert_eq!(lines[1], "MAIL FROM:<t@example.com>");
+    assert_eq!(lines[2], "RCPT TO:<a@example.com>");
+    assert_eq!(lines[3], "RCPT TO:<b@example.com>");
+    assert_eq!(lines[4], "DATA");
+    assert!(lines.contains(&"Subject: Daily".to_string()));
+    assert!(lines.contains(&"To: a@example.com, b@example.com".to_string()));
+    assert!(lines.contains(&"..hidden".to_string()));
+    assert_eq!(lines.last().unwrap(), "QUIT");
+}
+
+#[test]
+fn smtp_auth() {
+    let auth = Credentials {
+        username: "user".into(),
+        password: "pass".into(),
+    };
+    let to = vec!["a@example.com".to_string()];
+    let (addr, server) = smtp_server(b"250-test\r\n250 AUTH LOGIN PLAIN\r\n");
+    send_mail(&addr, Some(&auth), "t@example.com", &to, "Daily", "").unwrap();
+    let lines = server.join().unwrap();
+    assert_eq!(lines[0], "EHLO trafdat");
+    // base64 of "\0user\0pass"
+    assert_eq!(lines[1], "AUTH PLAIN AHVzZXIAcGFzcw==");
+    assert_eq!(lines[2], "MAIL FROM:<t@example.com>");
+    // relay without AUTH
+    let (addr, server) = smtp_server(b"250-test\r\n250 STARTTLS\r\n");
+    let res = send_mail(&addr, Some(&auth), "t@example.com", &to, "Daily", "");
+    assert!(res.is_err());
+    drop(server);
+}
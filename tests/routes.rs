@@ -0,0 +1,2398 @@
+// routes.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::header::ACCEPT;
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use chrono::NaiveDate;
+use common::{get, request, samples, Fixture, Response};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::time::Duration;
+use trafdat::deprecation::Policy;
+use trafdat::sample::Marker;
+use trafdat::state::{normalize_prefix, AppState, Config, LengthCheck};
+
+/// Vehicle event log with three vehicles
+const VLOG: &str = "250 ? 00:00:10 55\n300 2000 ? 60\n280 4000 ? 65\n";
+
+/// Valid metro_config document
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0">
+<detector name="100" category="" lane="1"/>
+</r_node>
+</corridor>
+<corridor route="T.H.100" dir="NB">
+<r_node name="rnd_2" lon="-93.3" lat="45.1"/>
+</corridor>
+</tms_config>
+"#;
+
+/// Build a fixture with a typical archive tree
+fn fixture() -> Fixture {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "100.s30", &samples(2880, 1, 60))
+        .add_file("tms", "20210601", "100.o30", &samples(2880, 2, 0))
+        .add_file("tms", "20210601", "100.vlog", VLOG.as_bytes())
+        .add_file("tms", "20210601", "100.vs30", &samples(2880, 1, 3))
+        .add_file("tms", "20210601", "100.vl30", &samples(2880, 1, 1))
+        .add_archive(
+            "tms",
+            "20210602",
+            &[
+                ("200.v30", &samples(2880, 1, 7)),
+                ("200.c30", &samples(2880, 2, 1)),
+            ],
+        )
+        .add_file("d2", "20200101", "300.v30", &samples(2880, 1, 2))
+        .add_metro_config("20210601", METRO_XML);
+    fx
+}
+
+/// Sort a JSON array of strings
+fn sorted(val: Value) -> Vec<String> {
+    let mut list: Vec<String> = val
+        .as_array()
+        .expect("array")
+        .iter()
+        .map(|v| v.as_str().expect("string").to_string())
+        .collect();
+    list.sort();
+    list
+}
+
+#[actix_web::test]
+async fn static_pages() {
+    let fx = fixture();
+    let state = fx.state();
+    for uri in ["/trafdat/", "/trafdat/index.html"] {
+        let res = get(&state, uri).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.content_type.as_deref(), Some("text/html"));
+        assert!(res.text().contains("Trafdat"));
+    }
+    let res = get(&state, "/trafdat/trafdat.css").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/css"));
+}
+
+#[actix_web::test]
+async fn friendly_not_found() {
+    let fx = fixture();
+    let state = fx.state();
+    let browser = |uri: &str| {
+        TestRequest::get()
+            .uri(uri)
+            .insert_header((ACCEPT, "text/html,application/xhtml+xml,*/*"))
+    };
+    let res = request(&state, browser("/trafdat/tms/20210601/999.v30")).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.content_type.as_deref(), Some("text/html"));
+    let html = res.text();
+    assert!(html.contains("href=\"/trafdat/tms/years.json\""));
+    assert!(html.contains("href=\"/trafdat/tms/2021\""));
+    assert!(html.contains("href=\"/trafdat/tms/20210601\""));
+    // only existing parents are linked
+    let res = request(&state, browser("/trafdat/tms/20210605/100.json")).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let html = res.text();
+    assert!(html.contains("href=\"/trafdat/tms/2021\""));
+    assert!(!html.contains("href=\"/trafdat/tms/20210605\""));
+    let res = request(&state, browser("/trafdat/tms/2019")).await;
+    let html = res.text();
+    assert!(html.contains("href=\"/trafdat/tms/years.json\""));
+    assert!(!html.contains("href=\"/trafdat/tms/2019\""));
+    // path is escaped
+    let res = request(&state, browser("/trafdat/tms/%3Cb%3E.json")).await;
+    assert!(res.text().contains("/trafdat/tms/&lt;b&gt;"));
+    let res = get(&state, "/trafdat/tms/20210601/999.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.text(), "Not Found");
+}
+
+#[actix_web::test]
+async fn districts() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/districts").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(sorted(res.json()), ["d2", "tms"]);
+}
+
+#[actix_web::test]
+async fn dates_for_year() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/2021").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.text(), "20210601\n20210602\n");
+    let res = get(&state, "/trafdat/tms/2021").await;
+    assert_eq!(res.text(), "20210601\n20210602\n");
+    let res = get(&state, "/trafdat/tms/2021.json").await;
+    assert_eq!(sorted(res.json()), ["20210601", "20210602"]);
+    let res = get(&state, "/trafdat/d2/2020.json").await;
+    assert_eq!(sorted(res.json()), ["20200101"]);
+    let res = get(&state, "/trafdat/tms/2019.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn sensors_for_date() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!(["100"]));
+    let res = get(&state, "/trafdat/tms/20210602").await;
+    assert_eq!(res.json(), json!(["200"]));
+    let res = get(&state, "/trafdat/2021/20210601").await;
+    assert_eq!(res.json(), json!(["100"]));
+    let res = get(&state, "/trafdat/tms/2021/20210602").await;
+    assert_eq!(res.json(), json!(["200"]));
+    let res = get(&state, "/trafdat/2020/20210601").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn extensions_for_sensor() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.json(),
+        json!(["o30", "s30", "v30", "vl30", "vlog", "vs30"])
+    );
+    let res = get(&state, "/trafdat/tms/20210602/200.json").await;
+    assert_eq!(res.json(), json!(["c30", "v30"]));
+}
+
+#[actix_web::test]
+async fn deprecated_periods() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.pr60", &samples(1440, 2, 0))
+        .add_file("tms", "20210601", "100.pr30", &samples(2880, 2, 0));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/spec.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let spec = res.json();
+    assert_eq!(spec["hide_deprecated"], false);
+    assert_eq!(spec["sample_periods"][0]["seconds"], 60);
+    assert_eq!(spec["sample_periods"][0]["status"], "deprecated");
+    assert_eq!(spec["sample_periods"][0]["listed"], true);
+    assert_eq!(spec["sample_periods"][1]["status"], "canonical");
+    assert!(spec["sample_periods"][1].get("note").is_none());
+    let res = get(&state, "/trafdat/tms/20210601/100.json").await;
+    assert_eq!(res.json(), json!(["pr30", "pr60"]));
+    let state = web::Data::new(AppState::new(Config {
+        hide_deprecated: true,
+        ..fx.config()
+    }));
+    let res = get(&state, "/trafdat/spec.json").await;
+    assert_eq!(res.json()["sample_periods"][0]["listed"], false);
+    let res = get(&state, "/trafdat/tms/20210601/100.json").await;
+    assert_eq!(res.json(), json!(["pr30"]));
+    let res = get(&state, "/trafdat/tms/20210601/100.pr60").await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn sample_data() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("application/octet_stream")
+    );
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let res = get(&state, "/trafdat/tms/20210602/200.c30").await;
+    assert_eq!(res.body, samples(2880, 2, 1));
+    let res = get(&state, "/trafdat/2021/20210602/200.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 7));
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let vals = res.json();
+    assert_eq!(vals.as_array().unwrap().len(), 2880);
+    assert_eq!(vals[0], json!("5"));
+    let res = get(&state, "/trafdat/2021/20210602/200.v30.json").await;
+    assert_eq!(res.json()[0], json!("7"));
+    let res = get(&state, "/trafdat/tms/20210601/100.c30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn invalid_sample_length() {
+    let fx = fixture();
+    fx.add_file("tms", "20210601", "101.v30", &samples(100, 1, 5));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/101.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn lenient_sample_length() {
+    let fx = fixture();
+    fx.add_file("tms", "20210601", "101.v30", &samples(100, 1, 5))
+        .add_file("tms", "20210601", "101.o30", &samples(3000, 2, 1));
+    let state = web::Data::new(AppState::new(Config {
+        length_check: LengthCheck::Lenient,
+        ..fx.config()
+    }));
+    let res = get(&state, "/trafdat/tms/20210601/101.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.headers.get("x-trafdat-warning").unwrap(),
+        "101.v30: short file (100 bytes)"
+    );
+    assert_eq!(res.body.len(), 2880);
+    assert_eq!(&res.body[..100], &samples(100, 1, 5)[..]);
+    assert_eq!(res.body[100], 0xFF);
+    let res =
+        get(&state, "/trafdat/tms/20210601/101.o30.json?layout=values").await;
+    assert_eq!(res.json().as_array().unwrap().len(), 2880);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert!(res.headers.get("x-trafdat-warning").is_none());
+    let res = get(&state, "/trafdat/metrics").await;
+    assert!(res.text().contains("trafdat_length_mismatches_total 2"));
+}
+
+#[actix_web::test]
+async fn missing_markers() {
+    let fx = fixture();
+    let mut s10 = samples(8640, 1, 60);
+    for i in (0..8640).step_by(3) {
+        s10[i] = 0;
+    }
+    s10[3..6].copy_from_slice(&[0, 0, 0]);
+    fx.add_file("tms", "20210601", "300.s10", &s10);
+    let state = web::Data::new(AppState::new(Config {
+        missing_markers: vec![("s".into(), Marker::Value(0))],
+        ..fx.config()
+    }));
+    let uri = "/trafdat/tms/20210601/300.s10.json?missing=null";
+    let vals = get(&state, uri).await.json();
+    assert_eq!(vals[0], Value::Null);
+    assert_eq!(vals[1], 60.0);
+    // rebinned mean skips marked samples, and encodes missing as marker
+    let res = get(&state, "/trafdat/tms/20210601/300.s30").await;
+    assert_eq!(&res.body[..3], &[60, 0, 60]);
+    let uri = "/trafdat/tms/20210601/300.s30.json?missing=null";
+    let vals = get(&state, uri).await.json();
+    assert_eq!(vals[1], Value::Null);
+    // default marker: only negative values are missing
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/300.s30").await;
+    assert_eq!(&res.body[..3], &[40, 0, 40]);
+}
+
+#[actix_web::test]
+async fn high_resolution_bins() {
+    let fx = fixture();
+    fx.add_file("tms", "20210601", "300.v10", &samples(8640, 1, 2))
+        .add_file("tms", "20210601", "300.o1", &samples(86400, 2, 1))
+        .add_file("tms", "20210601", "300.pt10", &samples(8640, 1, 1));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/300.json").await;
+    assert_eq!(res.json(), json!(["o1", "pt10", "v10"]));
+    let res = get(&state, "/trafdat/tms/20210601/300.v10").await;
+    assert_eq!(res.body, samples(8640, 1, 2));
+    // rebinned from finer periods
+    let res = get(&state, "/trafdat/tms/20210601/300.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, samples(2880, 1, 6));
+    let res = get(&state, "/trafdat/tms/20210601/300.o30").await;
+    assert_eq!(res.body, samples(2880, 2, 1));
+    let res = get(&state, "/trafdat/tms/20210601/300.v15").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/tms/20210601/300.pt30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/tms/20210601/300.v7").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // configured sample periods
+    let state = web::Data::new(AppState::new(Config {
+        sample_periods: vec![30, 10],
+        ..fx.config()
+    }));
+    let res = get(&state, "/trafdat/tms/20210601/300.json").await;
+    assert_eq!(res.json(), json!(["pt10", "v10"]));
+    let res = get(&state, "/trafdat/tms/20210601/300.o30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/tms/20210601/300.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 6));
+}
+
+#[actix_web::test]
+async fn resample_intervals() {
+    let fx = fixture();
+    let spd: Vec<u8> = (0..2880)
+        .map(|i| if i % 2 == 0 { 40 } else { 60 })
+        .collect();
+    fx.add_file("tms", "20210601", "101.s30", &spd).add_file(
+        "tms",
+        "20210601",
+        "101.pt30",
+        &samples(2880, 1, 1),
+    );
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.v30?resample=7m30s").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, samples(192, 1, 75));
+    let uri = "/trafdat/tms/20210601/100.v30.json?resample=1h&missing=null";
+    let vals = get(&state, uri).await.json();
+    assert_eq!(vals.as_array().unwrap().len(), 24);
+    assert_eq!(vals[0], json!(600));
+    let uri = "/trafdat/tms/20210601/101.s30";
+    let res = get(&state, &format!("{}?resample=1h", uri)).await;
+    assert_eq!(res.body, samples(24, 1, 50));
+    let res = get(&state, &format!("{}?resample=1h&agg=harmonic", uri)).await;
+    assert_eq!(res.body, samples(24, 1, 48));
+    let res = get(&state, &format!("{}?resample=3600&agg=sum", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(&res.body[..2], &[127, 127]);
+    // precipitation type needs an explicit aggregation
+    let uri = "/trafdat/tms/20210601/101.pt30?resample=1h";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = get(&state, &format!("{}&agg=mean", uri)).await;
+    assert_eq!(res.body, samples(24, 1, 1));
+    for query in [
+        "resample=7m",
+        "resample=45s",
+        "resample=30s1m",
+        "resample=m",
+        "agg=sum",
+        "resample=1h&agg=median",
+    ] {
+        let uri = format!("/trafdat/tms/20210601/100.v30?{}", query);
+        let res = get(&state, &uri).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", query);
+    }
+    let uri = "/trafdat/tms/20210601/100.vlog?resample=1h";
+    assert_eq!(get(&state, uri).await.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn dir_and_zip_sources() {
+    let fx = fixture();
+    fx.add_file("tms", "20210602", "200.v30", &samples(2880, 1, 9))
+        .add_file("tms", "20210602", "200.c30", &samples(100, 2, 1))
+        .add_file("tms", "20210602", "201.v30", &samples(2880, 1, 2));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210602").await;
+    assert_eq!(res.json(), json!(["200", "201"]));
+    let res = get(&state, "/trafdat/tms/20210602?source=zip").await;
+    assert_eq!(res.json(), json!(["200"]));
+    let res = get(&state, "/trafdat/tms/20210602?sources=true").await;
+    assert_eq!(
+        res.json(),
+        json!([
+            {"name": "200", "sources": ["dir", "zip"]},
+            {"name": "201", "sources": ["dir"]},
+        ])
+    );
+    let res = get(&state, "/trafdat/tms/20210602/200.json?sources=true").await;
+    assert_eq!(
+        res.json(),
+        json!([
+            {"name": "c30", "sources": ["dir", "zip"]},
+            {"name": "v30", "sources": ["dir", "zip"]},
+        ])
+    );
+    let res = get(&state, "/trafdat/tms/20210602/201.json?source=zip").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/tms/20210602?source=tape").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    // directory takes precedence, unless its file has an invalid length
+    let res = get(&state, "/trafdat/tms/20210602/200.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 9));
+    let res = get(&state, "/trafdat/tms/20210602/200.c30").await;
+    assert_eq!(res.body, samples(2880, 2, 1));
+    let res = get(&state, "/trafdat/tms/20210602/200.v30?source=zip").await;
+    assert_eq!(res.body, samples(2880, 1, 7));
+    let res = get(&state, "/trafdat/tms/20210602/200.c30?source=dir").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn derived_data() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.classes.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let classes = res.json();
+    assert_eq!(classes["period"], json!(30));
+    assert_eq!(classes["classes"][0]["class"], json!("short"));
+    assert_eq!(classes["classes"][0]["total"], json!(3 * 2880));
+    let res = get(&state, "/trafdat/tms/20210601/100.headway.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let headway = res.json();
+    assert_eq!(headway["period"], json!(900));
+    assert_eq!(headway["intervals"][0]["vehicles"], json!(3));
+    let uri = "/trafdat/tms/20210601/100.headway.json?period=7";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/tms/20210601/100.speed_hist.json?bin=10";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let hist = res.json();
+    assert_eq!(hist["source"], json!("vlog"));
+    assert_eq!(hist["total"], json!(3));
+    let res = get(&state, "/trafdat/tms/20210602/200.speed_hist.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn oversized_headway() {
+    let fx = fixture();
+    let vlog = "250 ? 00:00:10 55\n300 4294967295 ? 60\n280 4000 ? 65\n";
+    fx.add_file("tms", "20210601", "900.vlog", vlog.as_bytes());
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/900.headway.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let headway = res.json();
+    assert_eq!(headway["intervals"][0]["vehicles"], json!(1));
+}
+
+#[actix_web::test]
+async fn metro_config() {
+    let fx = fixture();
+    fx.add_metro_config("20210602", "<tms_config><corridor></tms_config>");
+    let state = fx.state();
+    let res = get(&state, "/trafdat/metro_config/20210601.xml").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.text(), METRO_XML);
+    let res = get(&state, "/trafdat/metro_config/20210601.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["corridor"][0]["route"], json!("I-94"));
+    let res = get(&state, "/trafdat/metro_config/20210601/corridors").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!(["I-94_EB", "T.H.100_NB"]));
+    let uri = "/trafdat/metro_config/20210601/I-94_EB.xml";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.text().starts_with("<corridor route=\"I-94\""));
+    let uri = "/trafdat/metro_config/20210601/I-94_EB.json";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["r_node"][0]["name"], json!("rnd_1"));
+    let uri = "/trafdat/metro_config/20210601/I-35_SB.json";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/metro_config/20210603.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/metro_config/20210602.json").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    let res = get(&state, "/trafdat/metro_config/20210602/corridors").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+}
+
+#[actix_web::test]
+async fn corrupt_archive() {
+    let fx = fixture();
+    fx.add_raw("tms/2021/20210603.traffic", b"not a zip file");
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210603/100.v30").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    let res = get(&state, "/trafdat/metrics").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.text().contains("trafdat_corrupt_archives_total 1\n"));
+}
+
+#[actix_web::test]
+async fn sensor_renames() {
+    let fx = fixture();
+    fx.add_raw(
+        "tms/sensor_renames.json",
+        br#"[{"old":"100","new":"101","effective":"20210701"}]"#,
+    );
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(res.json(), json!(["101"]));
+    let res = get(&state, "/trafdat/tms/20210601/101.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let res = get(&state, "/trafdat/tms/20210601/101.json").await;
+    assert_eq!(sorted(res.json())[0], "o30");
+}
+
+#[actix_web::test]
+async fn invalid_renames() {
+    let fx = fixture();
+    fx.add_raw("tms/sensor_renames.json", b"[{");
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[actix_web::test]
+async fn not_found() {
+    let fx = fixture();
+    let state = fx.state();
+    for uri in [
+        "/",
+        "/other",
+        "/trafdat/nope/x/y",
+        "/trafdat/tms/20211301",
+        "/trafdat/tms/20210601/100.bogus",
+    ] {
+        let res = get(&state, uri).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn corridor_geojson() {
+    let fx = fixture();
+    fx.add_metro_config(
+        "20210602",
+        r#"<tms_config time_stamp="x"><corridor route="I-35" dir="NB">
+<r_node name="a" lon="-93.0" lat="45.0"/>
+<r_node name="b" lon="-93.0" lat="45.1"/>
+<r_node name="c" lon="-93.001" lat="45.2"/>
+<r_node name="d" lon="-93.0" lat="45.3"/>
+<r_node name="e" lon="-93.5" lat="45.4"/>
+</corridor></tms_config>"#,
+    );
+    let state = fx.state();
+    let uri = "/trafdat/metro_config/20210602/I-35_NB.geojson";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("application/geo+json"));
+    let feature = res.json();
+    assert_eq!(feature["type"], json!("Feature"));
+    assert_eq!(feature["geometry"]["type"], json!("LineString"));
+    assert_eq!(
+        feature["geometry"]["coordinates"].as_array().unwrap().len(),
+        5
+    );
+    let res = get(&state, &format!("{}?simplify=0.01", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let feature = res.json();
+    assert_eq!(feature["properties"]["r_node"], json!(["a", "d", "e"]));
+    assert_eq!(feature["geometry"]["coordinates"][1], json!([-93.0, 45.3]));
+    let res = get(&state, &format!("{}?simplify=-1", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/metro_config/20210602/I-35_SB.geojson";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn nodes_in_bbox() {
+    let fx = fixture();
+    let state = fx.state();
+    let uri = "/trafdat/metro_config/20210601/nodes.json";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["r_node"].as_array().unwrap().len(), 2);
+    let res =
+        get(&state, &format!("{}?bbox=-93.2,44.9,-93.0,45.05", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let nodes = res.json();
+    assert_eq!(nodes["r_node"].as_array().unwrap().len(), 1);
+    assert_eq!(nodes["r_node"][0]["name"], json!("rnd_1"));
+    assert_eq!(nodes["r_node"][0]["route"], json!("I-94"));
+    assert_eq!(nodes["r_node"][0]["detector"][0]["name"], json!("100"));
+    let res = get(&state, &format!("{}?bbox=1,2,3", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = get(&state, &format!("{}?bbox=3,2,1,0", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn sample_json_format() {
+    let fx = fixture();
+    let mut vol = samples(2880, 1, 5);
+    vol[0] = 0xFF;
+    let mut occ = samples(2880, 2, 0);
+    occ[2..4].copy_from_slice(&1234u16.to_be_bytes());
+    fx.add_file("tms", "20210601", "102.v30", &vol)
+        .add_file("tms", "20210601", "102.o30", &occ);
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210601/102.v30.json";
+    let res = get(&state, &format!("{}?missing=null", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let vals = res.json();
+    assert_eq!(vals.as_array().unwrap().len(), 2880);
+    assert_eq!(vals[0], Value::Null);
+    assert_eq!(vals[1], json!(5));
+    let res = get(&state, &format!("{}?missing=-1", uri)).await;
+    assert_eq!(res.json()[0], json!(-1));
+    let res = get(&state, &format!("{}?missing=omit", uri)).await;
+    assert_eq!(res.json().as_array().unwrap().len(), 2879);
+    let res =
+        get(&state, &format!("{}?missing=omit&layout=objects", uri)).await;
+    assert_eq!(res.json()[0], json!({"interval": 1, "value": 5}));
+    let res = get(&state, &format!("{}?missing=bogus", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/tms/20210601/102.o30.json?decimals=1";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(&res.text()[..12], "[0.0,12.3,0.");
+    let res =
+        get(&state, uri.replace("decimals=1", "decimals=9").as_str()).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn aligned_sensors() {
+    let fx = fixture();
+    fx.add_file("tms", "20210601", "101.v30", &samples(2880, 1, 8))
+        .add_file("tms", "20210601", "101.s60", &samples(1440, 1, 55));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210601/aligned.json?sensors=100,101&ext=v30";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let val = res.json();
+    assert_eq!(val["period"], json!(30));
+    assert_eq!(val["samples"], json!(2880));
+    assert_eq!(val["series"][1]["sid"], json!("101"));
+    assert_eq!(val["series"][1]["values"][0], json!(8));
+    let uri = "/trafdat/tms/20210601/aligned.json?sensors=101&ext=v30,s60";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    let val = res.json();
+    assert_eq!(val["error"], json!("unaligned"));
+    assert_eq!(val["series"][1]["period"], json!(60));
+    assert_eq!(val["series"][1]["samples"], json!(1440));
+    let uri = "/trafdat/tms/20210601/aligned.json?sensors=100,999&ext=v30";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    assert_eq!(res.json()["series"][1]["samples"], Value::Null);
+    let uri = "/trafdat/tms/20210601/aligned.json?sensors=&ext=v30";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn traffic_archive() {
+    let fx = fixture();
+    let state = fx.state();
+    let archive =
+        std::fs::read(fx.traffic_path().join("tms/2021/20210602.traffic"))
+            .unwrap();
+    let res = get(&state, "/trafdat/tms/20210602.traffic").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("application/zip"));
+    assert_eq!(res.body, archive);
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210602.traffic")
+        .insert_header(("range", "bytes=0-3"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, &archive[..4]);
+    let res = get(&state, "/trafdat/tms/20210601.traffic").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/tms/2021060.traffic").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn metro_config_gzip() {
+    let fx = fixture();
+    let state = fx.state();
+    let path = fx.metro_path().join("metro_config_20210601.xml.gz");
+    let stored = std::fs::read(path).unwrap();
+    let res = get(&state, "/trafdat/metro_config/20210601.xml.gz").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("application/xml"));
+    assert_eq!(res.headers.get("content-encoding").unwrap(), "gzip");
+    assert_eq!(res.body, stored);
+    let res = get(&state, "/trafdat/metro_config/20210602.xml.gz").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn url_prefix() {
+    let fx = fixture();
+    for prefix in ["", "/data/traffic"] {
+        let config = Config {
+            url_prefix: normalize_prefix(prefix).unwrap(),
+            ..fx.config()
+        };
+        let state = web::Data::new(AppState::new(config));
+        let res = get(&state, &format!("{}/districts", prefix)).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", prefix);
+        let uri = format!("{}/tms/20210601/100.v30", prefix);
+        let res = get(&state, &uri).await;
+        assert_eq!(res.body, samples(2880, 1, 5));
+        let res = get(&state, "/trafdat/districts").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND, "{}", prefix);
+    }
+    assert_eq!(normalize_prefix("/").unwrap(), "");
+    assert_eq!(normalize_prefix("/trafdat/").unwrap(), "/trafdat");
+    assert!(normalize_prefix("trafdat").is_err());
+}
+
+#[actix_web::test]
+async fn browse_page() {
+    let fx = fixture();
+    let config = Config {
+        url_prefix: "/data".into(),
+        district_default: "d\"2".into(),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let res = get(&state, "/data/browse.html").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/html"));
+    let html = res.text();
+    assert!(html.contains(r#"data-prefix="/data""#));
+    assert!(html.contains(r#"data-district="d&quot;2""#));
+    assert!(!html.contains("{{"));
+}
+
+#[actix_web::test]
+async fn static_assets() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/index.html").await;
+    let html = res.text();
+    assert_eq!(res.headers.get("cache-control").unwrap(), "no-cache");
+    let start = html.find("static/trafdat.").unwrap();
+    let end = start + html[start..].find('"').unwrap();
+    let uri = format!("/trafdat/{}", &html[start..end]);
+    let res = get(&state, &uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/css"));
+    let cache = res.headers.get("cache-control").unwrap().to_str().unwrap();
+    assert!(cache.contains("immutable"));
+    let css = get(&state, "/trafdat/trafdat.css").await;
+    assert_eq!(css.body, res.body);
+    let res = get(&state, "/trafdat/static/trafdat.0000000000000000.css").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn aligned_batch_gzip() {
+    let fx = fixture();
+    fx.add_file("tms", "20210601", "101.v30", &samples(2880, 1, 8));
+    let state = fx.state();
+    let sensors: Vec<String> = (100..2100).map(|s| s.to_string()).collect();
+    let body = json!({ "sensors": &sensors[..2], "ext": ["v30"] });
+    let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+    enc.write_all(body.to_string().as_bytes()).unwrap();
+    let req = TestRequest::post()
+        .uri("/trafdat/tms/20210601/aligned.json")
+        .insert_header(("content-type", "application/json"))
+        .insert_header(("content-encoding", "gzip"))
+        .set_payload(enc.finish().unwrap());
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["series"][1]["values"][0], json!(8));
+    let body = json!({ "sensors": sensors, "ext": ["v30", "s30", "o30"] });
+    let req = TestRequest::post()
+        .uri("/trafdat/tms/20210601/aligned.json")
+        .set_json(body);
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn server_timing() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.json").await;
+    assert!(res.headers.get("server-timing").is_none());
+    let state = web::Data::new(AppState::new(Config {
+        server_timing: true,
+        ..fx.config()
+    }));
+    let uri = "/trafdat/tms/20210602/200.v30.json?layout=values";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let timing = res.headers.get("server-timing").unwrap().to_str().unwrap();
+    let stages: Vec<&str> = timing
+        .split(", ")
+        .map(|s| s.split_once(";dur=").unwrap().0)
+        .collect();
+    assert_eq!(
+        stages,
+        [
+            "resolve",
+            "zip_open",
+            "entry_read",
+            "decode",
+            "encode",
+            "total"
+        ]
+    );
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    let timing = res.headers.get("server-timing").unwrap().to_str().unwrap();
+    assert!(!timing.contains("zip_open"));
+    let res = get(&state, "/trafdat/metrics").await;
+    let text = res.text();
+    assert!(
+        text.contains("trafdat_stage_seconds_count{stage=\"zip_open\"} 1\n")
+    );
+    assert!(text.contains("trafdat_stage_seconds_count{stage=\"encode\"} 2\n"));
+}
+
+#[actix_web::test]
+async fn deprecated_routes() {
+    let fx = fixture();
+    let state = web::Data::new(AppState::new(Config {
+        sunset: NaiveDate::from_ymd_opt(2022, 1, 1),
+        ..fx.config()
+    }));
+    let res = get(&state, "/trafdat/2021/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers.get("deprecation").unwrap(), "true");
+    assert_eq!(
+        res.headers.get("link").unwrap(),
+        "</trafdat/tms/20210601/100.v30>; rel=\"successor-version\""
+    );
+    assert_eq!(
+        res.headers.get("sunset").unwrap(),
+        "Sat, 01 Jan 2022 00:00:00 GMT"
+    );
+    let res = get(&state, "/trafdat/2021").await;
+    assert_eq!(
+        res.headers.get("link").unwrap(),
+        "</trafdat/tms/2021>; rel=\"successor-version\""
+    );
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert!(res.headers.get("deprecation").is_none());
+    let text = get(&state, "/trafdat/metrics").await.text();
+    assert!(text.contains(
+        "trafdat_deprecated_requests_total{route=\"year_sample\"} 1\n"
+    ));
+    assert!(text.contains(
+        "trafdat_deprecated_requests_total{route=\"year_dates\"} 1\n"
+    ));
+    let state = web::Data::new(AppState::new(Config {
+        deprecated_routes: Policy::Disabled,
+        ..fx.config()
+    }));
+    let res = get(&state, "/trafdat/2021/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/2021/20210601").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn aligned_batch_districts() {
+    let fx = fixture();
+    fx.add_file("d6", "20210601", "100.v30", &samples(2880, 1, 9));
+    let state = fx.state();
+    let body = json!({
+        "sensors": ["100", { "district": "d6", "sid": "100" }],
+        "ext": ["v30"],
+    });
+    let req = TestRequest::post()
+        .uri("/trafdat/tms/20210601/aligned.json")
+        .set_json(body);
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let json = res.json();
+    assert!(json["series"][0].get("district").is_none());
+    assert_eq!(json["series"][1]["district"], "d6");
+    assert_eq!(json["series"][1]["values"][0], json!(9));
+    let body = json!({
+        "sensors": [{ "district": "tms", "sid": "100" }, { "district": "d6", "sid": "101" }],
+        "ext": ["v30"],
+    });
+    let req = TestRequest::post()
+        .uri("/trafdat/tms/20210601/aligned.json")
+        .set_json(body);
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    assert_eq!(res.json()["series"][1]["district"], "d6");
+    assert_eq!(res.json()["series"][1]["period"], Value::Null);
+    let body = json!({
+        "sensors": [{ "district": "..", "sid": "100" }],
+        "ext": ["v30"],
+    });
+    let req = TestRequest::post()
+        .uri("/trafdat/tms/20210601/aligned.json")
+        .set_json(body);
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn aligned_batch_upload() {
+    let fx = fixture();
+    fx.add_file("tms", "20210601", "101.v30", &samples(2880, 1, 8))
+        .add_file("d6", "20210601", "100.v30", &samples(2880, 1, 9));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210601/aligned.json?ext=v30";
+    let req = TestRequest::post()
+        .uri(uri)
+        .insert_header(("content-type", "text/plain"))
+        .set_payload("100\n\n# comment\n101\n");
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["series"][1]["values"][0], json!(8));
+    let req = TestRequest::post()
+        .uri(uri)
+        .insert_header(("content-type", "text/csv; charset=utf-8"))
+        .set_payload("label,district,sid\nA,,101\nB,d6,\"100\"\n");
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let json = res.json();
+    assert!(json["series"][0].get("district").is_none());
+    assert_eq!(json["series"][0]["values"][0], json!(8));
+    assert_eq!(json["series"][1]["district"], "d6");
+    assert_eq!(json["series"][1]["values"][0], json!(9));
+    // first column without a header row
+    let req = TestRequest::post()
+        .uri(uri)
+        .insert_header(("content-type", "text/csv"))
+        .set_payload("101,Lane 1\n");
+    let res = request(&state, req).await;
+    assert_eq!(res.json()["series"][0]["values"][0], json!(8));
+    let req = TestRequest::post()
+        .uri("/trafdat/tms/20210601/aligned.json")
+        .insert_header(("content-type", "text/csv"))
+        .set_payload("101\n");
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let req = TestRequest::post()
+        .uri(uri)
+        .insert_header(("content-type", "application/xml"))
+        .set_payload("<sensors/>");
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn response_cache() {
+    let fx = fixture();
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210601/100.headway.json?period=900&critical=4";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.headers.get("x-cache").is_none());
+    let config = Config {
+        cache_ttl: Duration::from_secs(60),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    let headers = |res: &Response| {
+        let mut headers: Vec<_> = res
+            .headers
+            .iter()
+            .filter(|(name, _)| *name != "x-cache")
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        headers.sort_by(|a, b| a.0.cmp(&b.0));
+        headers
+    };
+    let miss = headers(&res);
+    let body = res.body;
+    let uri = "/trafdat/tms/20210601/100.headway.json?critical=4&period=900";
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    // all (end-to-end) headers are cached
+    assert_eq!(headers(&res), miss);
+    assert_eq!(res.content_type.as_deref(), Some("application/json"));
+    assert_eq!(res.body, body);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30.json").await;
+    assert!(res.headers.get("x-cache").is_none());
+    let uri = "/trafdat/metro_config/20210601/I-94_EB.geojson";
+    assert_eq!(
+        get(&state, uri).await.headers.get("x-cache").unwrap(),
+        "MISS"
+    );
+    assert_eq!(
+        get(&state, uri).await.headers.get("x-cache").unwrap(),
+        "HIT"
+    );
+}
+
+#[actix_web::test]
+async fn robots() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/robots.txt").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let text = res.text();
+    assert!(text.contains("Allow: /trafdat/index.html\n"));
+    assert!(text.contains("Disallow: /trafdat/\n"));
+    let res = get(&state, "/trafdat/index.html").await;
+    assert!(res.headers.get("x-robots-tag").is_none());
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(
+        res.headers.get("x-robots-tag").unwrap(),
+        "noindex, nofollow"
+    );
+    let config = Config {
+        robots_txt: Some("User-agent: *\nDisallow: /\n".into()),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let res = get(&state, "/robots.txt").await;
+    assert_eq!(res.text(), "User-agent: *\nDisallow: /\n");
+}
+
+#[actix_web::test]
+async fn oversize_entries() {
+    let fx = fixture();
+    fx.add_archive("tms", "20210603", &[("100.vlog", VLOG.as_bytes())]);
+    // claim a huge uncompressed size in the central directory
+    let path = fx.traffic_path().join("tms/2021/20210603.traffic");
+    let mut zip = std::fs::read(&path).unwrap();
+    let cd = zip.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+    zip[cd + 24..cd + 28].copy_from_slice(&0xFFFF_FF00u32.to_le_bytes());
+    std::fs::write(&path, &zip).unwrap();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210603/100.vlog").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        res.text(),
+        "Archive entry too large: 100.vlog (4294967040 bytes)"
+    );
+    let res = get(&state, "/trafdat/metrics").await;
+    assert!(res.text().contains("trafdat_corrupt_archives_total 1"));
+    // configured maximum
+    let state = web::Data::new(AppState::new(Config {
+        max_entry_size: 1000,
+        ..fx.config()
+    }));
+    let res = get(&state, "/trafdat/tms/20210602/200.v30").await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210602.traffic")
+        .insert_header(("accept", "multipart/mixed"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::BAD_GATEWAY);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn zip_handle_pool() {
+    let fx = fixture();
+    fx.add_archive("tms", "20210603", &[("200.v30", &samples(2880, 1, 9))]);
+    let config = Config {
+        zip_handles: 1,
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    for uri in [
+        "/trafdat/tms/20210602/200.v30",
+        "/trafdat/tms/20210602/200.c30",
+        "/trafdat/tms/20210603/200.v30",
+        "/trafdat/tms/20210603",
+    ] {
+        assert_eq!(get(&state, uri).await.status, StatusCode::OK, "{}", uri);
+    }
+    let res = get(&state, "/trafdat/metrics").await;
+    assert!(res.text().contains("trafdat_zip_opens_total 2\n"));
+    assert!(res.text().contains("trafdat_zip_evictions_total 1\n"));
+}
+
+#[actix_web::test]
+async fn storage_breaker() {
+    let fx = fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["status"], json!("ok"));
+    let config = Config {
+        traffic_path: fx.traffic_path().join("unmounted"),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    for _ in 0..5 {
+        let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    let res = get(&state, "/trafdat/metro_config/20210601.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    let val = res.json();
+    assert_eq!(val["traffic"]["state"], json!("open"));
+    assert_eq!(val["metro_config"]["state"], json!("closed"));
+}
+
+#[actix_web::test]
+async fn storage_breaker_mounted() {
+    let fx = Fixture::new();
+    let root = fx.traffic_path().join("unmounted");
+    let config = Config {
+        traffic_path: root.clone(),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(res.json()["traffic"]["failures"], json!(1));
+    // once mounted, missing files are not failures
+    let dir = root.join("tms/2021/20210601");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("100.v30"), samples(2880, 1, 4)).unwrap();
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    for _ in 0..5 {
+        let res = get(&state, "/trafdat/tms/20210601/101.v30").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(res.json()["traffic"]["failures"], json!(0));
+}
+
+#[actix_web::test]
+async fn storage_io_error() {
+    let fx = fixture();
+    let dir = fx.traffic_path().join("tms/2021/20210602");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::os::unix::fs::symlink("200.v30", dir.join("200.v30")).unwrap();
+    let state = fx.state();
+    for _ in 0..5 {
+        let res = get(&state, "/trafdat/tms/20210602/200.v30").await;
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let res = get(&state, "/trafdat/tms/20210602/200.v30").await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[actix_web::test]
+async fn archive_layouts() {
+    let fx = fixture();
+    fx.add_archive("old", "20190302", &[("200.v30", &samples(2880, 1, 8))]);
+    let dir = fx.traffic_path().join("old");
+    std::fs::rename(
+        dir.join("2019/20190302.traffic"),
+        dir.join("20190302.traffic"),
+    )
+    .unwrap();
+    std::fs::remove_dir(dir.join("2019")).unwrap();
+    fx.add_raw("old/20190301/100.v30", &samples(2880, 1, 6))
+        .add_raw("old/20200105/100.v30", &samples(2880, 1, 6));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/old/2019").await;
+    assert_eq!(res.text(), "20190301\n20190302\n");
+    let res = get(&state, "/trafdat/old/years.json").await;
+    assert_eq!(
+        res.json(),
+        json!([{"year": "2019", "dates": 2}, {"year": "2020", "dates": 1}])
+    );
+    let res = get(&state, "/trafdat/old/20190301").await;
+    assert_eq!(res.json(), json!(["100"]));
+    let res = get(&state, "/trafdat/old/20190301/100.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 6));
+    let res = get(&state, "/trafdat/old/20190302/200.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 8));
+    let res = get(&state, "/trafdat/healthz").await;
+    assert_eq!(
+        res.json()["layouts"],
+        json!({"d2": "yearly", "old": "flat", "tms": "yearly"})
+    );
+}
+
+#[actix_web::test]
+async fn year_archives() {
+    let fx = fixture();
+    fx.add_year_archive(
+        "tms",
+        "2021",
+        &[
+            ("20210601/100.v30", &samples(2880, 1, 9)),
+            ("20210601/100.c30", &samples(2880, 2, 4)),
+            ("20210603/400.v30", &samples(2880, 1, 2)),
+            ("20210603/sub/500.v30", &samples(2880, 1, 2)),
+        ],
+    )
+    .add_year_archive("yr", "2020", &[("20201231/100.v30", &[3; 2880])]);
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/2021").await;
+    assert_eq!(res.text(), "20210601\n20210602\n20210603\n");
+    let res = get(&state, "/trafdat/tms/20210603").await;
+    assert_eq!(res.json(), json!(["400"]));
+    let res = get(&state, "/trafdat/tms/20210603/400.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 2));
+    // date directory takes precedence over the year archive
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let res = get(&state, "/trafdat/tms/20210601/100.c30").await;
+    assert_eq!(res.body, samples(2880, 2, 4));
+    let res = get(&state, "/trafdat/tms/20210601/100.json?sources=true").await;
+    assert!(res
+        .json()
+        .as_array()
+        .unwrap()
+        .contains(&json!({"name": "c30", "sources": ["zip"]})));
+    let res = get(&state, "/trafdat/yr/years.json").await;
+    assert_eq!(res.json(), json!([{"year": "2020", "dates": 1}]));
+    let res = get(&state, "/trafdat/yr/20201231/100.v30").await;
+    assert_eq!(res.body, [3; 2880]);
+    let res = get(&state, "/trafdat/yr/20201230/100.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn entry_name_patterns() {
+    let fx = fixture();
+    fx.add_archive(
+        "tms",
+        "20210604",
+        &[
+            ("20210604/600.v30", &samples(2880, 1, 6)),
+            ("det/700.v30", &samples(2880, 1, 7)),
+            ("det/800.v30", &samples(2880, 1, 8)),
+            ("800.v30", &samples(2880, 1, 9)),
+        ],
+    );
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210604").await;
+    assert_eq!(res.json(), json!(["600", "700", "800"]));
+    let res = get(&state, "/trafdat/tms/20210604/600.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 6));
+    // found by base name
+    let res = get(&state, "/trafdat/tms/20210604/700.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 7));
+    // patterns take precedence over base names
+    let res = get(&state, "/trafdat/tms/20210604/800.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 9));
+    let config = Config {
+        entry_patterns: vec!["det/{sid}.{ext}".into()],
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let res = get(&state, "/trafdat/tms/20210604/800.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 8));
+}
+
+#[actix_web::test]
+async fn class_volume() {
+    let fx = fixture();
+    let mut vl = samples(2880, 1, 1);
+    vl[0] = 0xFF;
+    fx.add_file("tms", "20210601", "900.vmc30", &samples(2880, 1, 0))
+        .add_file("tms", "20210601", "900.vs30", &samples(2880, 1, 3))
+        .add_file("tms", "20210601", "900.vm30", &samples(2880, 1, 0))
+        .add_file("tms", "20210601", "900.vl30", &vl)
+        .add_file("tms", "20210601", "901.vmc30", &samples(2880, 1, 1))
+        .add_file("tms", "20210601", "901.vs30", &samples(2880, 1, 3))
+        .add_file("tms", "20210601", "901.vl30", &samples(2880, 1, 1));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601/900.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let config = Config {
+        class_volume: true,
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let res = get(&state, "/trafdat/tms/20210601/900.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let mut vol = samples(2880, 1, 4);
+    vol[0] = 0xFF;
+    assert_eq!(res.body, vol);
+    // rebinned class counts
+    let res = get(&state, "/trafdat/tms/20210601/900.v60").await;
+    let mut vol = samples(1440, 1, 8);
+    vol[0] = 0xFF;
+    assert_eq!(res.body, vol);
+    // archived volume takes precedence
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let res = get(&state, "/trafdat/tms/20210601/900.c30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // one class (vm) is not archived
+    let res = get(&state, "/trafdat/tms/20210601/901.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn year_named_district() {
+    let fx = fixture();
+    let state = fx.state();
+    // deprecated form, without a district named 2021
+    let res = get(&state, "/trafdat/2021/20210601").await;
+    assert_eq!(res.json(), json!(["100"]));
+    assert!(res.headers.contains_key("deprecation"));
+    fx.add_file("2021", "20210601", "900.v30", &samples(2880, 1, 4))
+        .add_file("2021", "20200101", "901.v30", &samples(2880, 1, 4));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/2021/20210601").await;
+    assert_eq!(res.json(), json!(["900"]));
+    assert!(!res.headers.contains_key("deprecation"));
+    let res = get(&state, "/trafdat/2021/20210601/900.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = get(&state, "/trafdat/2021/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // dates in other years
+    let res = get(&state, "/trafdat/2021/20200101").await;
+    assert_eq!(res.json(), json!(["901"]));
+    let res = get(&state, "/trafdat/2021/20200101/901.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = get(&state, "/trafdat/2021/2021/20210601").await;
+    assert_eq!(res.json(), json!(["900"]));
+    let res = get(&state, "/trafdat/2021/2021.json").await;
+    assert_eq!(res.json(), json!(["20210601"]));
+    let res = get(&state, "/trafdat/tms/2021/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn years_for_district() {
+    let fx = fixture();
+    fx.add_file("tms", "20200301", "100.v30", &samples(2880, 1, 5));
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/years.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.json(),
+        json!([{"year": "2020", "dates": 1}, {"year": "2021", "dates": 2}])
+    );
+    let res = get(&state, "/trafdat/d9/years.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn corridor_standalone_xml() {
+    let fx = fixture();
+    let xml = METRO_XML.replace(
+        "<tms_config",
+        "<!DOCTYPE tms_config [\n<!ELEMENT tms_config (corridor*)>\n]>\n<tms_config",
+    );
+    fx.add_metro_config("20210602", &xml);
+    let state = fx.state();
+    let uri = "/trafdat/metro_config/20210601/I-94_EB.xml?standalone=true";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let text = res.text();
+    assert!(text.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+    assert!(text.contains("<tms_config time_stamp=\"20210601\">\n<corridor"));
+    assert!(text.ends_with("</corridor>\n</tms_config>\n"));
+    assert!(!text.contains("DOCTYPE"));
+    let uri = "/trafdat/metro_config/20210602/I-94_EB.xml?dtd=true";
+    let text = get(&state, uri).await.text();
+    assert!(text.contains(
+        "<!DOCTYPE tms_config [\n<!ELEMENT tms_config (corridor*)>\n]>\n"
+    ));
+    assert!(!text.contains("T.H.100"));
+    let uri = "/trafdat/metro_config/20210601/I-94_EB.xml?dtd=maybe";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn roadway_graph() {
+    let fx = fixture();
+    fx.add_metro_config(
+        "20210602",
+        r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210602">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0"/>
+<r_node name="rnd_2" n_type="Exit" transition="CD" forks="rnd_4" lon="-93.0" lat="45.0"/>
+<r_node name="rnd_3" lon="-92.9" lat="45.0"/>
+</corridor>
+<corridor route="I-35" dir="NB">
+<r_node name="rnd_4" n_type="Entrance" forks="rnd_2" lon="-93.0" lat="45.1"/>
+<r_node name="rnd_5" forks="rnd_9" lon="-93.0" lat="45.2"/>
+</corridor>
+</tms_config>"#,
+    );
+    let state = fx.state();
+    let res = get(&state, "/trafdat/metro_config/20210602/graph.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let graph = res.json();
+    assert_eq!(graph["nodes"].as_array().unwrap().len(), 5);
+    assert_eq!(graph["nodes"][1]["n_type"], json!("Exit"));
+    assert_eq!(
+        graph["edges"],
+        json!([
+            {"from": "rnd_1", "to": "rnd_2", "kind": "link"},
+            {"from": "rnd_2", "to": "rnd_3", "kind": "link"},
+            {"from": "rnd_4", "to": "rnd_5", "kind": "link"},
+            {"from": "rnd_2", "to": "rnd_4", "kind": "cd"},
+        ])
+    );
+    let res = get(&state, "/trafdat/metro_config/20210603/graph.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn detector_controllers() {
+    let fx = fixture();
+    fx.add_metro_config(
+        "20210602",
+        r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210602">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0">
+<detector name="100" lane="1" controller="ctl_1"/>
+<detector name="101" category="X" controller="ctl_9"/>
+<detector name="102" category="Q" abandoned="t"/>
+</r_node>
+</corridor>
+<commlink name="cl_1" description="Fiber ring 3" protocol="NTCIP Class B"/>
+<controller name="ctl_1" condition="Active" drop="4" commlink="cl_1" location="I-94 EB @ Snelling" cabinet="334"/>
+</tms_config>"#,
+    );
+    let state = fx.state();
+    let uri = "/trafdat/metro_config/20210602/detectors.json";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let dets = res.json();
+    assert_eq!(dets.as_array().unwrap().len(), 3);
+    assert_eq!(
+        dets[0],
+        json!({
+            "name": "100",
+            "label": "FUTURE",
+            "category": "",
+            "lane": "1",
+            "abandoned": false,
+            "route": "I-94",
+            "dir": "EB",
+            "r_node": "rnd_1",
+            "controller": {
+                "name": "ctl_1",
+                "condition": "Active",
+                "drop": "4",
+                "location": "I-94 EB @ Snelling",
+                "cabinet": "334",
+                "commlink": {
+                    "name": "cl_1",
+                    "description": "Fiber ring 3",
+                    "protocol": "NTCIP Class B",
+                },
+            },
+        })
+    );
+    // unknown controller
+    assert_eq!(dets[1]["controller"], json!({"name": "ctl_9"}));
+    assert_eq!(dets[2]["controller"], Value::Null);
+    assert_eq!(dets[2]["abandoned"], json!(true));
+    let res = get(&state, &format!("{}?detectors=101,999", uri)).await;
+    let dets = res.json();
+    assert_eq!(dets.as_array().unwrap().len(), 1);
+    assert_eq!(dets[0]["name"], json!("101"));
+    let uri = "/trafdat/metro_config/20210603/detectors.json";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn detector_category() {
+    let fx = fixture();
+    fx.add_metro_config(
+        "20210602",
+        r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210602">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0">
+<detector name="100" category="" lane="1"/>
+<detector name="101" category="X"/>
+<detector name="102" category="Q"/>
+<detector name="103" category="H"/>
+</r_node>
+</corridor>
+</tms_config>"#,
+    );
+    let state = fx.state();
+    let names = |val: &Value| -> Vec<String> {
+        val.as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let uri = "/trafdat/metro_config/20210602/I-94_EB.json";
+    let res = get(&state, uri).await;
+    assert_eq!(names(&res.json()["r_node"][0]["detector"]).len(), 4);
+    let res = get(&state, &format!("{}?category=exit,entrance", uri)).await;
+    assert_eq!(names(&res.json()["r_node"][0]["detector"]), ["101", "102"]);
+    let uri = "/trafdat/metro_config/20210602/nodes.json?category=hov";
+    let res = get(&state, uri).await;
+    assert_eq!(names(&res.json()["r_node"][0]["detector"]), ["103"]);
+    let uri = "/trafdat/metro_config/20210602/nodes.json?category=mainline";
+    let res = get(&state, uri).await;
+    assert_eq!(names(&res.json()["r_node"][0]["detector"]), ["100"]);
+    let uri = "/trafdat/metro_config/20210602/I-94_EB.json?category=ramp";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+/// Corridor with two stations and ramps between them
+const CORRIDOR_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210605">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" station_id="S1" lon="-93.10" lat="45.0">
+<detector name="1" category="" lane="1"/>
+<detector name="9" category="H" lane="2"/>
+</r_node>
+<r_node name="rnd_2" n_type="Entrance" lon="-93.09" lat="45.0">
+<detector name="2" category="M"/>
+<detector name="5" category="Q"/>
+</r_node>
+<r_node name="rnd_3" n_type="Exit" lon="-93.08" lat="45.0">
+<detector name="3" category="X"/>
+</r_node>
+<r_node name="rnd_4" station_id="S2" lon="-93.07" lat="45.0">
+<detector name="4" category="" lane="1"/>
+</r_node>
+</corridor>
+</tms_config>"#;
+
+/// Build a fixture with corridor detectors on 2021-06-05
+fn corridor_fixture() -> Fixture {
+    let fx = fixture();
+    let mut down = samples(2880, 1, 12);
+    down[0] = 0xFF;
+    fx.add_metro_config("20210605", CORRIDOR_XML)
+        .add_file("tms", "20210605", "1.v30", &samples(2880, 1, 10))
+        .add_file("tms", "20210605", "2.v30", &samples(2880, 1, 3))
+        .add_file("tms", "20210605", "3.v30", &samples(2880, 1, 2))
+        .add_file("tms", "20210605", "4.v30", &down);
+    fx
+}
+
+#[actix_web::test]
+async fn ramp_balance() {
+    let fx = corridor_fixture();
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/balance.json?corridor=I-94_EB";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let val = res.json();
+    let seg = &val["segments"][0];
+    assert_eq!(val["segments"].as_array().unwrap().len(), 1);
+    assert_eq!(seg["from"], json!("S1"));
+    assert_eq!(seg["to"], json!("S2"));
+    assert_eq!(seg["intervals"], json!(2879));
+    assert_eq!(seg["upstream"], json!(10 * 2879));
+    assert_eq!(seg["entrance"], json!(3 * 2879));
+    assert_eq!(seg["exit"], json!(2 * 2879));
+    assert_eq!(seg["error"], json!(2879));
+    let miles = seg["miles"].as_f64().unwrap();
+    assert!((miles - 1.47).abs() < 0.01, "{}", miles);
+    let res = get(&state, "/trafdat/tms/20210605/balance.json").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/tms/20210605/balance.json?corridor=I-35_SB";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn bottlenecks() {
+    let fx = corridor_fixture();
+    let mut speed = samples(2880, 1, 60);
+    speed[..120].fill(25);
+    fx.add_file("tms", "20210605", "1.s30", &speed).add_file(
+        "tms",
+        "20210605",
+        "4.s30",
+        &samples(2880, 1, 55),
+    );
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/bottlenecks.json?corridor=I-94_EB";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let val = res.json();
+    assert_eq!(val["bottlenecks"].as_array().unwrap().len(), 1);
+    let bn = &val["bottlenecks"][0];
+    assert_eq!(bn["station"], json!("S2"));
+    assert_eq!(bn["upstream"], json!("S1"));
+    assert_eq!(bn["activation"], json!("00:00"));
+    assert_eq!(bn["deactivation"], json!("01:00"));
+    assert_eq!(bn["intervals"], json!(12));
+    assert_eq!(bn["discharge"], json!(1440.0));
+    let delay = bn["delay"].as_f64().unwrap();
+    assert!((delay - 41.0).abs() < 0.5, "{}", delay);
+    let res = get(&state, &format!("{}&sustain=13", uri)).await;
+    assert_eq!(res.json()["bottlenecks"], json!([]));
+    let res = get(&state, &format!("{}&speed=-1", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn corridor_vmt() {
+    let fx = corridor_fixture();
+    fx.add_file("tms", "20210605", "1.s30", &samples(2880, 1, 30))
+        .add_file("tms", "20210605", "4.s30", &samples(2880, 1, 60));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let val = res.json();
+    assert_eq!(val["stations"].as_array().unwrap().len(), 2);
+    let miles = val["stations"][0]["miles"].as_f64().unwrap();
+    let vmt = val["vmt"].as_f64().unwrap();
+    let expected = (10.0 * 2880.0 + 12.0 * 2879.0) * miles;
+    assert!((vmt - expected).abs() < 0.01, "{} {}", vmt, expected);
+    // Only the 30 mph station is delayed (vs. 60 mph)
+    let delay = val["delay"].as_f64().unwrap();
+    let expected = 10.0 * 2880.0 * miles * (1.0 / 30.0 - 1.0 / 60.0);
+    assert!((delay - expected).abs() < 0.01, "{} {}", delay, expected);
+    assert_eq!(val["stations"][1]["delay"], json!(0.0));
+    let res = get(&state, &format!("{}&free=0", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn harmonic_mean_speed() {
+    let fx = corridor_fixture();
+    let fine: Vec<u8> = (0..8640)
+        .map(|i| if i % 3 == 1 { 60 } else { 40 })
+        .collect();
+    fx.add_file("tms", "20210605", "1.s30", &samples(2880, 1, 30))
+        .add_file("tms", "20210605", "9.v30", &samples(2880, 1, 10))
+        .add_file("tms", "20210605", "9.s30", &samples(2880, 1, 60))
+        .add_file("tms", "20210605", "7.s10", &fine);
+    let state = fx.state();
+    // across lanes: arithmetic 45 mph, harmonic 40 mph
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB";
+    let val = get(&state, uri).await.json();
+    let arith = val["stations"][0]["vht"].as_f64().unwrap();
+    let val = get(&state, &format!("{}&mean=harmonic", uri)).await.json();
+    let harm = val["stations"][0]["vht"].as_f64().unwrap();
+    assert!(
+        (harm / arith - 45.0 / 40.0).abs() < 1e-9,
+        "{} {}",
+        harm,
+        arith
+    );
+    let val = get(&state, &format!("{}&mean=arithmetic", uri))
+        .await
+        .json();
+    assert_eq!(val["stations"][0]["vht"].as_f64().unwrap(), arith);
+    let res = get(&state, &format!("{}&mean=geometric", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/tms/20210605/bottlenecks.json?corridor=I-94_EB";
+    let res = get(&state, &format!("{}&mean=harmonic", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    // over time, when rebinning
+    let uri = "/trafdat/tms/20210605/7.s30";
+    let res = get(&state, uri).await;
+    assert_eq!(res.body, samples(2880, 1, 47));
+    let res = get(&state, &format!("{}?mean=harmonic", uri)).await;
+    assert_eq!(res.body, samples(2880, 1, 45));
+    let res = get(
+        &state,
+        "/trafdat/tms/20210605/7.s10?resample=1m&mean=harmonic",
+    )
+    .await;
+    assert_eq!(res.body, samples(1440, 1, 45));
+}
+
+#[actix_web::test]
+async fn flow_weighted_speed() {
+    let fx = corridor_fixture();
+    fx.add_file("tms", "20210605", "1.s30", &samples(2880, 1, 30))
+        .add_file("tms", "20210605", "9.v30", &samples(2880, 1, 30))
+        .add_file("tms", "20210605", "9.s30", &samples(2880, 1, 60));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB";
+    let vht = |val: Value| val["stations"][0]["vht"].as_f64().unwrap();
+    // flow weighted 52.5 mph (default), equal weights 45 mph
+    let flow = vht(get(&state, uri).await.json());
+    let equal = vht(get(&state, &format!("{}&weight=equal", uri)).await.json());
+    assert!(
+        (equal / flow - 52.5 / 45.0).abs() < 1e-9,
+        "{} {}",
+        equal,
+        flow
+    );
+    // flow weighted harmonic: 40 / (10 / 30 + 30 / 60) = 48 mph
+    let harm = format!("{}&mean=harmonic", uri);
+    let harm = vht(get(&state, &harm).await.json());
+    assert!(
+        (harm / flow - 52.5 / 48.0).abs() < 1e-9,
+        "{} {}",
+        harm,
+        flow
+    );
+    let res = get(&state, &format!("{}&weight=density", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/tms/20210605/bottlenecks.json?corridor=I-94_EB";
+    let res = get(&state, &format!("{}&weight=equal", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    // equal weights when a lane is missing volume
+    fx.add_file("tms", "20210605", "9.v30", &[]);
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB";
+    let missing = vht(get(&state, uri).await.json());
+    let equal = vht(get(&state, &format!("{}&weight=equal", uri)).await.json());
+    assert_eq!(missing, equal);
+}
+
+#[actix_web::test]
+async fn per_lane_breakdown() {
+    let fx = corridor_fixture();
+    fx.add_file("tms", "20210605", "1.s30", &samples(2880, 1, 30));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB";
+    let val = get(&state, uri).await.json();
+    assert!(val["stations"][0].get("lanes").is_none());
+    let val = get(&state, &format!("{}&by_lane=true", uri)).await.json();
+    let lanes = val["stations"][0]["lanes"].as_array().unwrap();
+    assert_eq!(lanes.len(), 2);
+    assert_eq!(lanes[0]["detector"], json!("1"));
+    assert_eq!(lanes[0]["lane"], json!(1));
+    assert_eq!(lanes[0]["volume"].as_array().unwrap().len(), 2880);
+    assert_eq!(lanes[0]["volume"][0], json!(10));
+    assert_eq!(lanes[0]["speed"][0], json!(30.0));
+    assert_eq!(lanes[1]["detector"], json!("9"));
+    assert_eq!(lanes[1]["lane"], json!(2));
+    assert_eq!(lanes[1]["volume"], Value::Null);
+    assert_eq!(lanes[1]["speed"], Value::Null);
+    let lanes = &val["stations"][1]["lanes"];
+    assert_eq!(lanes[0]["volume"][0], Value::Null);
+    assert!(lanes[0].get("lane").is_some());
+    let uri = format!("{}&by_lane=true&lanes=managed", uri);
+    let val = get(&state, &uri).await.json();
+    assert_eq!(val["stations"], json!([]));
+}
+
+#[actix_web::test]
+async fn duplicate_detectors() {
+    let fx = corridor_fixture();
+    let xml = CORRIDOR_XML.replace(
+        r#"<detector name="4" category="" lane="1"/>"#,
+        r#"<detector name="4" category="" lane="1"/>
+<detector name="1" category="" lane="2"/>"#,
+    );
+    fx.add_metro_config("20210605", &xml);
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/balance.json?corridor=I-94_EB";
+    let val = get(&state, uri).await.json();
+    let warning = "duplicate detector 1 in rnd_4 (kept rnd_1)";
+    assert_eq!(val["warnings"], json!([warning]));
+    // downstream station is not double-counted
+    assert_eq!(val["segments"][0]["error"], json!(2879));
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB&by_lane=true";
+    let val = get(&state, uri).await.json();
+    assert_eq!(val["warnings"], json!([warning]));
+    assert_eq!(val["stations"][1]["lanes"].as_array().unwrap().len(), 1);
+    let uri = "/trafdat/tms/20210605/bottlenecks.json?corridor=I-94_EB";
+    let val = get(&state, uri).await.json();
+    assert_eq!(val["warnings"], json!([warning]));
+    // no warnings without duplicates
+    let fx = corridor_fixture();
+    let state = fx.state();
+    let val = get(&state, uri).await.json();
+    assert!(val.get("warnings").is_none());
+}
+
+#[actix_web::test]
+async fn managed_lanes() {
+    let fx = fixture();
+    let xml = CORRIDOR_XML.replace(
+        r#"<detector name="4" category="" lane="1"/>"#,
+        r#"<detector name="4" category="" lane="1"/>
+<detector name="6" category="H" lane="2"/>"#,
+    );
+    let mut down = samples(2880, 1, 12);
+    down[0] = 0xFF;
+    fx.add_metro_config("20210605", &xml)
+        .add_file("tms", "20210605", "1.v30", &samples(2880, 1, 10))
+        .add_file("tms", "20210605", "4.v30", &down)
+        .add_file("tms", "20210605", "6.v30", &samples(2880, 1, 2))
+        .add_file("tms", "20210605", "4.s30", &samples(2880, 1, 40))
+        .add_file("tms", "20210605", "6.s30", &samples(2880, 1, 70));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB";
+    let val = get(&state, uri).await.json();
+    let station = &val["stations"][1];
+    let miles = station["miles"].as_f64().unwrap();
+    let vmt = station["vmt"].as_f64().unwrap();
+    assert!((vmt - 14.0 * 2879.0 * miles).abs() < 0.01, "{}", vmt);
+    let general = station["general"]["vmt"].as_f64().unwrap();
+    assert!(
+        (general - 12.0 * 2879.0 * miles).abs() < 0.01,
+        "{}",
+        general
+    );
+    let managed = station["managed"]["vmt"].as_f64().unwrap();
+    assert!((managed - 2.0 * 2880.0 * miles).abs() < 0.01, "{}", managed);
+    // no data for managed lanes at S1
+    assert!(val["stations"][0].get("general").is_some());
+    assert!(val["stations"][0].get("managed").is_none());
+    let val = get(&state, &format!("{}&lanes=general", uri)).await.json();
+    let station = &val["stations"][1];
+    assert!(station.get("managed").is_none());
+    let vmt = station["vmt"].as_f64().unwrap();
+    assert!((vmt - 12.0 * 2879.0 * miles).abs() < 0.01, "{}", vmt);
+    let val = get(&state, &format!("{}&lanes=managed", uri)).await.json();
+    assert_eq!(val["stations"].as_array().unwrap().len(), 1);
+    assert_eq!(val["stations"][0]["station"], json!("S2"));
+    let res = get(&state, &format!("{}&lanes=hot", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn weekday_baseline() {
+    let fx = Fixture::new();
+    let mut prev = samples(2880, 1, 6);
+    prev[1] = 0xFF;
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210525", "100.v30", &prev)
+        .add_file("tms", "20210518", "100.v30", &samples(2880, 1, 8))
+        .add_file("tms", "20210526", "100.v30", &samples(2880, 1, 50))
+        .add_file("tms", "20210504", "100.v60", &samples(1440, 1, 50))
+        .add_file("tms", "20210601", "100.o30", &samples(2880, 2, 4));
+    let state = fx.state();
+    let uri = "/trafdat/tms/20210601/100.baseline.json";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let val = res.json();
+    assert_eq!(val["ext"], json!("v30"));
+    assert_eq!(val["period"], json!(30));
+    assert_eq!(val["dates"], json!(["20210525", "20210518"]));
+    assert_eq!(val["values"][0], json!(5.0));
+    assert_eq!(val["baseline"][0], json!(7.0));
+    assert_eq!(val["baseline"][1], json!(8.0));
+    assert_eq!(val["baseline"].as_array().unwrap().len(), 2880);
+    let res = get(&state, &format!("{}?weeks=1", uri)).await;
+    assert_eq!(res.json()["dates"], json!(["20210525"]));
+    let res = get(&state, &format!("{}?ext=o30", uri)).await;
+    let val = res.json();
+    assert_eq!(val["values"][0], json!(10.28));
+    assert_eq!(val["dates"], json!([]));
+    assert_eq!(val["baseline"][0], json!(null));
+    let res = get(&state, &format!("{}?weeks=0", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = get(&state, &format!("{}?ext=vlog", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = get(&state, &format!("{}?ext=s30", uri)).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+/// Construction and workzone annotations
+const ANNOTATIONS: &str = r#"[
+{"start":"20210601","end":"20210610","corridors":["I-94_EB"],"note":"Lane closure"},
+{"start":"20210605","end":"20210605","note":"Detector maintenance"},
+{"start":"20210701","end":"20210731","corridors":["I-94_EB"],"note":"Later"},
+{"start":"20210601","end":"20210630","corridors":["I-35_SB"],"note":"Other"}
+]"#;
+
+#[actix_web::test]
+async fn annotations() {
+    let fx = corridor_fixture();
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/annotations.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!([]));
+    let uri = "/trafdat/tms/20210605/balance.json?corridor=I-94_EB";
+    assert!(get(&state, uri).await.json().get("annotations").is_none());
+    fx.add_raw("tms/annotations.json", ANNOTATIONS.as_bytes());
+    let res = get(&state, "/trafdat/tms/annotations.json").await;
+    let val = res.json();
+    assert_eq!(val.as_array().unwrap().len(), 4);
+    assert_eq!(val[1]["corridors"], json!([]));
+    for analysis in ["balance", "bottlenecks", "vmt"] {
+        let uri =
+            format!("/trafdat/tms/20210605/{}.json?corridor=I-94_EB", analysis);
+        let val = get(&state, &uri).await.json();
+        let notes: Vec<_> = val["annotations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["note"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(notes, ["Lane closure", "Detector maintenance"]);
+    }
+    let res = get(&state, "/trafdat/nowhere/annotations.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    fx.add_raw(
+        "tms/annotations.json",
+        br#"[{"start":"20210610","end":"20210601","note":"Backwards"}]"#,
+    );
+    let res = get(&state, "/trafdat/tms/annotations.json").await;
+    assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[actix_web::test]
+async fn complete_dates() {
+    let fx = fixture();
+    let mut partial = samples(2880, 1, 4);
+    partial[100] = 0xFF;
+    fx.add_file("tms", "20210601", "200.v30", &samples(2880, 1, 4))
+        .add_file("tms", "20210603", "100.v30", &samples(2880, 1, 4))
+        .add_file("tms", "20210603", "200.v30", &partial);
+    let state = fx.state();
+    let uri = "/trafdat/tms/dates.json?start=20210531&end=20210605";
+    let res = get(&state, &format!("{}&sensors=100,200", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!(["20210601"]));
+    let res = get(&state, &format!("{}&sensors=100", uri)).await;
+    assert_eq!(res.json(), json!(["20210601", "20210603"]));
+    let res = get(&state, &format!("{}&sensors=300", uri)).await;
+    assert_eq!(res.json(), json!([]));
+    let res = get(&state, &format!("{}&sensors=100&ext=s30", uri)).await;
+    assert_eq!(res.json(), json!(["20210601"]));
+    let uri = "/trafdat/tms/dates.json?sensors=100&start=20210601&end=20220701";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn complete_dates_memo() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 4))
+        .add_file("tms", "20210602", "100.v30", &samples(2880, 1, 4));
+    let state = fx.state();
+    let uri = "/trafdat/tms/dates.json?sensors=100&start=20210601&end=20210603";
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(res.json(), json!(["20210601", "20210602"]));
+    // archived days are not checked again
+    let mut partial = samples(2880, 1, 4);
+    partial[100] = 0xFF;
+    fx.add_file("tms", "20210602", "100.v30", &partial);
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    assert_eq!(res.json(), json!(["20210601", "20210602"]));
+    // invalidated by a change in the archived dates
+    fx.add_file("tms", "20210603", "100.v30", &samples(2880, 1, 4));
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(res.json(), json!(["20210601", "20210603"]));
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    // ranges including today are not memoized
+    let today = chrono::Local::now().format("%Y%m%d").to_string();
+    let uri = format!(
+        "/trafdat/tms/dates.json?sensors=100&start={}&end={}",
+        today, today
+    );
+    let res = get(&state, &uri).await;
+    assert!(res.headers.get("x-cache").is_none());
+}
+
+#[actix_web::test]
+async fn complete_dates_settled() {
+    let fx = Fixture::new();
+    let day = |n| {
+        (chrono::Local::now().date_naive() - chrono::Duration::days(n))
+            .format("%Y%m%d")
+            .to_string()
+    };
+    fx.add_file("tms", &day(1), "100.v30", &samples(2880, 1, 4))
+        .add_archive("tms", &day(2), &[("100.v30", &samples(2880, 1, 4))]);
+    let state = fx.state();
+    let dates = |start: &str, end: &str| {
+        format!(
+            "/trafdat/tms/dates.json?sensors=100&start={}&end={}",
+            start, end
+        )
+    };
+    // a date directory within the settle period may still be written
+    let uri = dates(&day(1), &day(1));
+    let res = get(&state, &uri).await;
+    assert_eq!(res.json(), json!([day(1)]));
+    assert!(res.headers.get("x-cache").is_none());
+    // finalized archives are memoized
+    let uri = dates(&day(2), &day(2));
+    let res = get(&state, &uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    let res = get(&state, &uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    assert_eq!(res.json(), json!([day(2)]));
+}
+
+#[actix_web::test]
+async fn aadt() {
+    let fx = Fixture::new();
+    let mut partial = samples(2880, 1, 4);
+    partial[100] = 0xFF;
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 4))
+        .add_file("tms", "20210603", "100.v30", &partial)
+        .add_archive("tms", "20210602", &[("100.v30", &samples(2880, 1, 6))]);
+    let state = fx.state();
+    let uri =
+        "/trafdat/tms/aadt.json?sensors=100,200&start=20210601&end=20210603";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(
+        res.json(),
+        json!([
+            { "sensor": "100", "days": 2, "aadt": 14400 },
+            { "sensor": "200", "days": 0, "aadt": null },
+        ])
+    );
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    let res = get(&state, &format!("{}&ext=s30", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn percentiles() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 4))
+        .add_file("tms", "20210602", "100.v30", &samples(2880, 1, 6));
+    let state = fx.state();
+    let uri =
+        "/trafdat/tms/percentiles.json?sensors=100&start=20210601&end=20210603";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(
+        res.json(),
+        json!([{
+            "sensor": "100",
+            "samples": 5760,
+            "percentiles": [
+                { "p": 15, "value": 4.0 },
+                { "p": 50, "value": 4.0 },
+                { "p": 85, "value": 6.0 },
+            ],
+        }])
+    );
+    let res = get(&state, uri).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "HIT");
+    // percentiles are part of the memo key
+    let res = get(&state, &format!("{}&p=100", uri)).await;
+    assert_eq!(res.headers.get("x-cache").unwrap(), "MISS");
+    assert_eq!(
+        res.json()[0]["percentiles"],
+        json!([{ "p": 100, "value": 6.0 }])
+    );
+    let res = get(&state, &format!("{}&p=101", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn sample_range() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 4))
+        .add_archive("tms", "20210603", &[("100.v30", &samples(2880, 1, 6))]);
+    let state = fx.state();
+    let uri = "/trafdat/tms/100.v30?start=20210601&end=20210603";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("application/octet-stream")
+    );
+    assert_eq!(res.headers.get("x-trafdat-missing-days").unwrap(), "1");
+    let body = &res.body;
+    assert_eq!(body.len(), 3 * 12 + 2 * 2880);
+    assert_eq!(&body[..8], b"20210601");
+    assert_eq!(&body[8..12], &2880u32.to_be_bytes());
+    assert_eq!(body[12], 4);
+    let next = 12 + 2880;
+    assert_eq!(&body[next..next + 8], b"20210602");
+    assert_eq!(&body[next + 8..next + 12], &0u32.to_be_bytes());
+    assert_eq!(&body[next + 12..next + 20], b"20210603");
+    assert_eq!(body[next + 24], 6);
+    let res =
+        get(&state, "/trafdat/tms/101.v30?start=20210601&end=20210603").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res =
+        get(&state, "/trafdat/tms/100.v30?start=20210601&end=20220701").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = get(&state, "/trafdat/tms/100.v30").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn parallel_days() {
+    let fx = Fixture::new();
+    let start = NaiveDate::from_ymd_opt(2021, 6, 1).unwrap();
+    for (i, day) in start.iter_days().take(60).enumerate() {
+        let date = day.format("%Y%m%d").to_string();
+        if i % 7 != 3 {
+            fx.add_file("tms", &date, "100.v30", &samples(2880, 1, i as u8));
+        }
+    }
+    let uri = "/trafdat/tms/100.v30?start=20210601&end=20210730";
+    let mut bodies = vec![];
+    for day_workers in [1, 4] {
+        let state = web::Data::new(AppState::new(Config {
+            day_workers,
+            ..fx.config()
+        }));
+        let res = get(&state, uri).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.headers.get("x-trafdat-missing-days").unwrap(), "9");
+        bodies.push(res.body);
+    }
+    assert_eq!(bodies[0], bodies[1]);
+    // frames are in date order
+    let body = &bodies[1];
+    assert_eq!(&body[..8], b"20210601");
+    assert_eq!(body[12], 0);
+    let last = body.len() - 12;
+    assert_eq!(&body[last..last + 8], b"20210730");
+    assert_eq!(&body[last + 8..], &0u32.to_be_bytes());
+}
+
+#[actix_web::test]
+async fn multipart_parts() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 4))
+        .add_archive(
+            "tms",
+            "20210602",
+            &[("100.v30", &samples(2880, 1, 6)), ("100.c30", b"\x01\x02")],
+        );
+    let state = fx.state();
+    let uri = "/trafdat/tms/100.v30?start=20210601&end=20210603";
+    let req = TestRequest::get()
+        .uri(uri)
+        .insert_header(("Accept", "multipart/mixed"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.content_type.as_deref(),
+        Some("multipart/mixed; boundary=trafdat-part-0")
+    );
+    assert_eq!(res.headers.get("x-trafdat-missing-days").unwrap(), "1");
+    let body = &res.body;
+    let head =
+        b"--trafdat-part-0\r\nContent-Type: application/octet-stream\r\n\
+        Content-Disposition: attachment; filename=\"20210601/100.v30\"\r\n\r\n";
+    assert!(body.starts_with(head));
+    assert_eq!(body[head.len()..head.len() + 2880], samples(2880, 1, 4)[..]);
+    assert!(body.ends_with(b"\r\n--trafdat-part-0--\r\n"));
+    let text = String::from_utf8_lossy(body);
+    assert_eq!(text.matches("--trafdat-part-0\r\n").count(), 2);
+    assert!(text.contains("filename=\"20210602/100.v30\""));
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210602.traffic")
+        .insert_header(("Accept", "multipart/mixed"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let text = String::from_utf8_lossy(&res.body);
+    assert_eq!(text.matches("--trafdat-part-0\r\n").count(), 2);
+    assert!(text.contains("filename=\"100.c30\"\r\n\r\n\x01\x02\r\n"));
+    // entries with unsafe names are skipped
+    fx.add_archive(
+        "tms",
+        "20210603",
+        &[
+            ("100.v30\"\r\nX-Part: 1", b"\x01"),
+            ("200.c30", b"\x02\x03"),
+        ],
+    );
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210603.traffic")
+        .insert_header(("Accept", "multipart/mixed"));
+    let text =
+        String::from_utf8_lossy(&request(&state, req).await.body).into_owned();
+    assert_eq!(text.matches("--trafdat-part-0\r\n").count(), 1);
+    assert!(text.contains("filename=\"200.c30\"\r\n"));
+    assert!(!text.contains("X-Part"));
+    assert!(text.contains("Content-Type: application/octet-stream\r\n"));
+    // total size of parts is limited
+    let state = web::Data::new(AppState::new(Config {
+        max_parts_size: 2880,
+        ..fx.config()
+    }));
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210602.traffic")
+        .insert_header(("Accept", "multipart/mixed"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210603.traffic")
+        .insert_header(("Accept", "multipart/mixed"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210601.traffic")
+        .insert_header(("Accept", "multipart/mixed"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn detector_locations() {
+    let fx = Fixture::new();
+    let moved = METRO_XML.replace(
+        r#"<r_node name="rnd_1" lon="-93.1" lat="45.0">
+<detector name="100" category="" lane="1"/>"#,
+        r#"<r_node name="rnd_3" station_id="S5" lon="-93.2" lat="45.0">
+<detector name="100" category="" lane="2"/>"#,
+    );
+    fx.add_file("tms", "20210610", "100.v30", &samples(2880, 1, 4))
+        .add_metro_config("20210601", METRO_XML)
+        .add_metro_config("20210612", METRO_XML)
+        .add_metro_config("20210615", &moved);
+    let state = fx.state();
+    let uri = "/trafdat/tms/100.locations.json?start=20210610&end=20210620";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let json = res.json();
+    assert_eq!(json["configs"], 2);
+    assert_eq!(
+        json["spans"],
+        json!([
+            {
+                "start": "20210610",
+                "end": "20210614",
+                "location": { "r_node": "rnd_1", "lane": "1" },
+            },
+            {
+                "start": "20210615",
+                "end": "20210620",
+                "location": { "r_node": "rnd_3", "station": "S5", "lane": "2" },
+            },
+        ])
+    );
+    assert_eq!(json["changes"][0]["date"], "20210615");
+    assert_eq!(json["changes"][0]["from"]["r_node"], "rnd_1");
+    assert_eq!(json["changes"][0]["to"]["station"], "S5");
+    let uri = "/trafdat/tms/100.v30?start=20210610&end=20210620";
+    let res = get(&state, uri).await;
+    assert_eq!(
+        res.headers.get("x-trafdat-location-changes").unwrap(),
+        "20210615"
+    );
+    // unknown before the first metro_config
+    let uri = "/trafdat/tms/101.locations.json?start=20210530&end=20210601";
+    let json = get(&state, uri).await.json();
+    assert_eq!(
+        json["spans"],
+        json!([{ "start": "20210530", "end": "20210601", "location": null }])
+    );
+    assert_eq!(json["changes"], json!([]));
+}
+
+#[actix_web::test]
+async fn detector_table_cache() {
+    let fx = Fixture::new();
+    fx.add_metro_config("20210601", METRO_XML);
+    let state = fx.state();
+    let uri = "/trafdat/tms/100.locations.json?start=20210601&end=20210601";
+    let location = |res: common::Response| {
+        res.json()["spans"][0]["location"]["r_node"].clone()
+    };
+    assert_eq!(location(get(&state, uri).await), json!("rnd_1"));
+    // rewritten without changing modified time: cached table is used
+    let path = fx.metro_path().join("metro_config_20210601.xml.gz");
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    fx.add_metro_config("20210601", &METRO_XML.replace("rnd_1", "rnd_9"));
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(mtime).unwrap();
+    assert_eq!(location(get(&state, uri).await), json!("rnd_1"));
+    file.set_modified(mtime + std::time::Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(location(get(&state, uri).await), json!("rnd_9"));
+}
+
+#[actix_web::test]
+async fn corridor_locate() {
+    let fx = Fixture::new();
+    fx.add_metro_config(
+        "20210602",
+        r#"<tms_config time_stamp="x"><corridor route="I-35" dir="NB">
+<r_node name="a" station_id="S1" lon="-93.0" lat="45.0"/>
+<r_node name="b" lon="-93.0" lat="45.1"/>
+<r_node name="c" station_id="S3" lon="-93.0" lat="45.2"/>
+</corridor></tms_config>"#,
+    );
+    let state = fx.state();
+    let uri = "/trafdat/metro_config/20210602/I-35_NB/locate.json";
+    let res = get(&state, &format!("{}?mile=8", uri)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let json = res.json();
+    assert_eq!(json["corridor"], "I-35_NB");
+    assert_eq!(json["mile"], 8.0);
+    assert_eq!(json["lon"], -93.0);
+    let lat = json["lat"].as_f64().unwrap();
+    assert!((lat - 45.1158).abs() < 0.0001, "{}", lat);
+    assert!(json.get("offset").is_none());
+    assert_eq!(json["station"]["id"], "S3");
+    assert_eq!(json["station"]["r_node"], "c");
+    let dist = json["station"]["distance"].as_f64().unwrap();
+    assert!((dist - 5.818).abs() < 0.01, "{}", dist);
+    let res = get(&state, &format!("{}?lon=-92.99&lat=45.05", uri)).await;
+    let json = res.json();
+    let mile = json["mile"].as_f64().unwrap();
+    assert!((mile - 3.454).abs() < 0.01, "{}", mile);
+    assert_eq!(json["lat"], 45.05);
+    let offset = json["offset"].as_f64().unwrap();
+    assert!((offset - 0.488).abs() < 0.01, "{}", offset);
+    assert_eq!(json["station"]["id"], "S1");
+    // clamped to corridor ends
+    let res = get(&state, &format!("{}?mile=-2", uri)).await;
+    assert_eq!(res.json()["mile"], 0.0);
+    let res = get(&state, &format!("{}?mile=1&lat=45", uri)).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let uri = "/trafdat/metro_config/20210602/I-35_SB/locate.json?mile=1";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn station_spacing() {
+    let fx = Fixture::new();
+    fx.add_metro_config(
+        "20210602",
+        r#"<tms_config time_stamp="x"><corridor route="I-35" dir="NB">
+<r_node name="a" station_id="S1" lon="-93.0" lat="45.0"/>
+<r_node name="b" n_type="Entrance" lon="-93.0" lat="45.1"/>
+<r_node name="c" station_id="S3" lon="-93.0" lat="45.2"/>
+<r_node name="d" station_id="S4" lon="-93.0" lat="45.25"/>
+</corridor></tms_config>"#,
+    );
+    let state = fx.state();
+    let uri = "/trafdat/metro_config/20210602/I-35_NB/spacing.csv";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.content_type.as_deref(), Some("text/csv"));
+    assert_eq!(
+        res.text(),
+        "from,to,from_mile,to_mile,miles\n\
+         S1,S3,0.000,13.819,13.819\n\
+         S3,S4,13.819,17.274,3.455\n"
+    );
+    let uri = "/trafdat/metro_config/20210602/I-35_SB/spacing.csv";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn weather_observations() {
+    let fx = Fixture::new();
+    fx.add_file("rwis", "20210601", "S100.at60", &samples(1440, 2, 8))
+        .add_file("rwis", "20210601", "S100.at30", &samples(2880, 2, 8))
+        .add_archive(
+            "rwis",
+            "20210601",
+            &[("S200.rh300", &samples(288, 1, 50))],
+        );
+    let state = fx.state();
+    let res = get(&state, "/trafdat/weather/rwis/20210601").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!(["S100", "S200"]));
+    let res = get(&state, "/trafdat/weather/rwis/20210601/S100.json").await;
+    assert_eq!(res.json(), json!(["at60"]));
+    // 0x0808 hundredths of a degree
+    let uri = "/trafdat/weather/rwis/20210601/S100.at60.json?decimals=2";
+    let json = get(&state, uri).await.json();
+    assert_eq!(json.as_array().unwrap().len(), 1440);
+    assert_eq!(json[0], 20.56);
+    let uri = "/trafdat/weather/rwis/20210601/S200.rh300.csv";
+    let res = get(&state, uri).await;
+    assert_eq!(res.content_type.as_deref(), Some("text/csv"));
+    assert!(res.text().lines().nth(1).unwrap().starts_with("0,50,"));
+    let res = get(&state, "/trafdat/weather/rwis/20210601/S100.at60").await;
+    assert_eq!(res.body.len(), 2880);
+    // period not configured
+    let res = get(&state, "/trafdat/weather/rwis/20210601/S100.at30").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // not served as traffic samples
+    let res = get(&state, "/trafdat/rwis/20210601/S100.at60").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
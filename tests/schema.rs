@@ -0,0 +1,359 @@
+// schema.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, samples, Fixture, Response};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::time::Duration;
+use trafdat::schema;
+use trafdat::state::{AppState, Config};
+use trafdat::watch::ArchiveEvent;
+
+/// Vehicle log
+const VLOG: &str = "250 ? 00:00:10 55\n300 2000 ? 60\n";
+
+/// metro_config document with stations, ramps, a controller and a camera
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" station_id="S1" lon="-93.10" lat="45.0">
+<detector name="1" category="" lane="1" controller="ctl_1"/>
+<detector name="9" category="H" lane="2"/>
+</r_node>
+<r_node name="rnd_2" n_type="Entrance" lon="-93.09" lat="45.0">
+<detector name="2" category="M"/>
+</r_node>
+<r_node name="rnd_3" n_type="Exit" transition="CD" forks="rnd_5" lon="-93.08" lat="45.0">
+<detector name="3" category="X"/>
+</r_node>
+<r_node name="rnd_4" station_id="S2" lon="-93.07" lat="45.0">
+<detector name="4" category="" lane="1"/>
+</r_node>
+</corridor>
+<corridor route="I-35" dir="NB">
+<r_node name="rnd_5" n_type="Entrance" lon="-93.08" lat="45.1"/>
+</corridor>
+<camera name="C1" description="Camera" lon="-93.09" lat="45.0"/>
+<commlink name="c1" description="Comm" protocol="NTCIP"/>
+<controller name="ctl_1" condition="Active" drop="1" location="Here" commlink="c1"/>
+</tms_config>
+"#;
+
+/// Construction annotation
+const ANNOTATIONS: &str = r#"[
+{"start":"20210601","end":"20210610","corridors":["I-94_EB"],"note":"Lane closure"}
+]"#;
+
+/// Admin token
+const TOKEN: &str = "secret";
+
+/// Check a JSON type name
+fn is_type(ty: &str, value: &Value) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+/// Validate a value against the subset of JSON Schema used by trafdat
+fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let fail = |msg: &str| Err(format!("{}: {}", path, msg));
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas.iter().any(|s| validate(s, value, path).is_ok()) {
+            return fail("no anyOf match");
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let errs: Vec<String> = schemas
+            .iter()
+            .filter_map(|s| validate(s, value, path).err())
+            .collect();
+        if errs.len() + 1 != schemas.len() {
+            return fail(&format!("not one oneOf match: {:?}", errs));
+        }
+    }
+    let typed = match &schema["type"] {
+        Value::String(ty) => is_type(ty, value),
+        Value::Array(tys) => {
+            tys.iter().any(|ty| is_type(ty.as_str().unwrap(), value))
+        }
+        _ => true,
+    };
+    if !typed {
+        return fail(&format!("not {}", schema["type"]));
+    }
+    if let Some(c) = schema.get("const") {
+        if c != value {
+            return fail(&format!("not {}", c));
+        }
+    }
+    if let Some(Value::Array(names)) = schema.get("enum") {
+        if !names.contains(value) {
+            return fail(&format!("{} not in enum", value));
+        }
+    }
+    if let Some(min) = schema.get("minimum") {
+        if value.is_number() && value.as_f64() < min.as_f64() {
+            return fail("below minimum");
+        }
+    }
+    if let Value::Array(items) = value {
+        if let Some(min) = schema.get("minItems") {
+            if (items.len() as u64) < min.as_u64().unwrap() {
+                return fail("too few items");
+            }
+        }
+        if let Some(max) = schema.get("maxItems") {
+            if items.len() as u64 > max.as_u64().unwrap() {
+                return fail("too many items");
+            }
+        }
+        if let Some(item) = schema.get("items") {
+            for (i, val) in items.iter().enumerate() {
+                validate(item, val, &format!("{}[{}]", path, i))?;
+            }
+        }
+    }
+    if let Value::Object(obj) = value {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required {
+                let name = name.as_str().unwrap();
+                if !obj.contains_key(name) {
+                    return fail(&format!("missing {}", name));
+                }
+            }
+        }
+        for (name, val) in obj {
+            let path = format!("{}.{}", path, name);
+            match (&schema["properties"][name], &schema["additionalProperties"])
+            {
+                (Value::Null, Value::Bool(false)) => {
+                    return Err(format!("{}: unexpected", path))
+                }
+                (Value::Null, Value::Object(_)) => {
+                    validate(&schema["additionalProperties"], val, &path)?
+                }
+                (Value::Null, _) => (),
+                (prop, _) => validate(prop, val, &path)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validate a value against a published schema
+fn check_value(name: &str, value: &Value) {
+    let schema = schema::lookup(name).unwrap();
+    if let Err(e) = validate(&schema, value, name) {
+        panic!("{}\n{}", e, value);
+    }
+}
+
+/// Validate a successful response against a published schema
+fn check_response(name: &str, uri: &str, res: Response) {
+    assert_eq!(res.status, StatusCode::OK, "{}", uri);
+    check_value(name, &res.json());
+}
+
+/// Make an authorized admin request
+async fn admin(state: &web::Data<AppState>, req: TestRequest) -> Response {
+    let auth = format!("Bearer {}", TOKEN);
+    request(state, req.insert_header(("authorization", auth))).await
+}
+
+#[actix_web::test]
+async fn schema_endpoint() {
+    let fx = Fixture::new();
+    let state = fx.state();
+    for name in schema::names() {
+        let uri = format!("/trafdat/schema/{}.json", name);
+        let res = get(&state, &uri).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(
+            res.content_type.as_deref(),
+            Some("application/schema+json")
+        );
+        let schema = res.json();
+        assert_eq!(schema["title"], name);
+        assert!(schema["$schema"].as_str().unwrap().contains("2020-12"));
+    }
+    let res = get(&state, "/trafdat/schema/bogus.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn derived_schemas() {
+    let schema = schema::lookup("years").unwrap();
+    let year = &schema["items"]["properties"]["year"];
+    assert_eq!(year["pattern"], "^[0-9]{4}$");
+    assert_eq!(year["description"], "Year (yyyy)");
+    assert_eq!(schema["items"]["required"], json!(["year", "dates"]));
+    let valid = json!([{ "year": "2021", "dates": 3 }]);
+    assert!(validate(&schema, &valid, "years").is_ok());
+    let missing = json!([{ "year": "2021" }]);
+    assert!(validate(&schema, &missing, "years").is_err());
+    let extra = json!([{ "year": "2021", "dates": 3, "extra": 1 }]);
+    assert!(validate(&schema, &extra, "years").is_err());
+    let negative = json!([{ "year": "2021", "dates": -3 }]);
+    assert!(validate(&schema, &negative, "years").is_err());
+    let schema = schema::lookup("corridor_geojson").unwrap();
+    assert_eq!(schema["properties"]["type"]["const"], "Feature");
+    let schema = schema::lookup("aligned").unwrap();
+    let series = &schema["properties"]["series"]["items"];
+    assert_eq!(series["required"], json!(["sid", "ext", "values"]));
+    let schema = schema::lookup("job").unwrap();
+    let status = &schema["properties"]["status"];
+    assert_eq!(
+        status["enum"],
+        json!(["queued", "running", "done", "failed", "cancelled"])
+    );
+    // flattened tagged enum
+    let schema = schema::lookup("admin_status").unwrap();
+    let recent = &schema["properties"]["watcher"]["properties"]["recent"];
+    let variants = recent["items"]["oneOf"].as_array().unwrap();
+    assert_eq!(variants.len(), 3);
+    assert_eq!(variants[0]["properties"]["event"]["const"], "new_date");
+    assert!(variants[0]["properties"]["time"].is_object());
+}
+
+#[actix_web::test]
+async fn responses_match_schemas() {
+    let fx = Fixture::new();
+    let mut down = samples(2880, 1, 12);
+    down[0] = 0xFF;
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "100.s30", &samples(2880, 1, 255))
+        .add_file("tms", "20210601", "100.vlog", VLOG.as_bytes())
+        .add_file("tms", "20210601", "100.vs30", &samples(2880, 1, 3))
+        .add_file("tms", "20210601", "100.vl30", &samples(2880, 1, 1))
+        .add_file("tms", "20210601", "101.c30", &samples(1440, 2, 3))
+        .add_file("tms", "20210525", "100.v30", &samples(2880, 1, 6))
+        .add_file("tms", "20210605", "1.v30", &samples(2880, 1, 10))
+        .add_file("tms", "20210605", "2.v30", &samples(2880, 1, 3))
+        .add_file("tms", "20210605", "3.v30", &samples(2880, 1, 2))
+        .add_file("tms", "20210605", "4.v30", &down)
+        .add_file("tms", "20210605", "1.s30", &samples(2880, 1, 20))
+        .add_file("tms", "20210605", "4.s30", &samples(2880, 1, 60))
+        .add_archive("tms", "20210602", &[("200.v30", &samples(2880, 1, 7))])
+        .add_file("d2", "20200101", "300.v30", &samples(2880, 1, 2))
+        .add_raw("tms/annotations.json", ANNOTATIONS.as_bytes())
+        .add_metro_config("20210601", METRO_XML)
+        .add_metro_config("20210605", &METRO_XML.replace("20210601", "x"));
+    let state = web::Data::new(AppState::new(Config {
+        admin_token: Some(TOKEN.into()),
+        audit_path: Some(fx.traffic_path().join("audit.log")),
+        jobs_path: Some(fx.jobs_path()),
+        job_workers: 0,
+        cache_ttl: Duration::from_secs(60),
+        ..fx.config()
+    }));
+    let checks = [
+        ("districts", "/trafdat/districts"),
+        ("years", "/trafdat/tms/years.json"),
+        ("listing", "/trafdat/tms/2021.json"),
+        ("listing", "/trafdat/tms/20210601"),
+        ("listing", "/trafdat/tms/20210601/100.json"),
+        ("listing", "/trafdat/tms/dates.json?sensors=100&start=20210601&end=20210605"),
+        ("aadt", "/trafdat/tms/aadt.json?sensors=100&start=20210601&end=20210605"),
+        ("percentiles", "/trafdat/tms/percentiles.json?sensors=100&start=20210601&end=20210605&ext=s30"),
+        ("listing", "/trafdat/metro_config/20210601/corridors"),
+        ("sources", "/trafdat/tms/20210602?sources=true"),
+        ("samples", "/trafdat/tms/20210601/100.v30.json"),
+        ("samples", "/trafdat/tms/20210601/100.s30.json?missing=null"),
+        ("samples", "/trafdat/tms/20210601/100.v30.json?layout=objects"),
+        ("samples", "/trafdat/tms/20210601/100.v30.json?values=raw"),
+        ("aligned", "/trafdat/tms/20210601/aligned.json?sensors=100&ext=v30,s30"),
+        ("anomalies", "/trafdat/tms/20210601/100.anomalies.json"),
+        ("baseline", "/trafdat/tms/20210601/100.baseline.json"),
+        ("classes", "/trafdat/tms/20210601/100.classes.json"),
+        ("headway", "/trafdat/tms/20210601/100.headway.json"),
+        ("speed_hist", "/trafdat/tms/20210601/100.speed_hist.json"),
+        ("speed_hist", "/trafdat/tms/20210601/100.speed_hist.json?ext=s30"),
+        ("balance", "/trafdat/tms/20210605/balance.json?corridor=I-94_EB"),
+        ("bottlenecks", "/trafdat/tms/20210605/bottlenecks.json?corridor=I-94_EB"),
+        ("vmt", "/trafdat/tms/20210605/vmt.json?corridor=I-94_EB&by_lane=true"),
+        ("annotations", "/trafdat/tms/annotations.json"),
+        ("checksums", "/trafdat/tms/2021/checksums.json"),
+        ("locations", "/trafdat/tms/1.locations.json?start=20210601&end=20210605"),
+        ("metro_config", "/trafdat/metro_config/20210601.json"),
+        ("corridor", "/trafdat/metro_config/20210601/I-94_EB.json"),
+        ("corridor_geojson", "/trafdat/metro_config/20210601/I-94_EB.geojson"),
+        ("nodes", "/trafdat/metro_config/20210601/nodes.json"),
+        ("graph", "/trafdat/metro_config/20210601/graph.json"),
+        ("detectors", "/trafdat/metro_config/20210601/detectors.json"),
+        ("locate", "/trafdat/metro_config/20210601/I-94_EB/locate.json?mile=0.5"),
+        ("locate", "/trafdat/metro_config/20210601/I-94_EB/locate.json?lon=-93.09&lat=45.01"),
+        ("spec", "/trafdat/spec.json"),
+        ("healthz", "/trafdat/healthz"),
+        ("stats", "/trafdat/admin/stats.json"),
+    ];
+    let mut checked = BTreeSet::new();
+    for (name, uri) in checks {
+        check_response(name, uri, get(&state, uri).await);
+        checked.insert(name);
+    }
+    // error responses
+    let uri = "/trafdat/tms/20210601/aligned.json?sensors=100,101&ext=v30,c30";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    check_value("mismatch", &res.json());
+    checked.insert("mismatch");
+    // jobs and admin responses
+    let req = TestRequest::post()
+        .uri("/trafdat/jobs/export")
+        .set_json(json!({
+            "district": "tms",
+            "corridor": "I-94_EB",
+            "start": "20210601",
+            "end": "20210605",
+            "ext": ["v30"],
+        }));
+    let res = admin(&state, req).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    check_value("job", &res.json());
+    let uri = format!("/trafdat/jobs/{}", res.json()["id"].as_str().unwrap());
+    for (name, uri) in [
+        ("job", uri.as_str()),
+        ("jobs", "/trafdat/jobs"),
+        ("audit", "/trafdat/admin/audit.json"),
+        ("admin_status", "/trafdat/admin/status.json"),
+    ] {
+        let res = admin(&state, TestRequest::get().uri(uri)).await;
+        check_response(name, uri, res);
+        checked.insert(name);
+    }
+    // archive events (watcher, webhooks and NATS)
+    let events = [
+        ArchiveEvent::NewDate {
+            district: "tms".into(),
+            date: "20210601".into(),
+            sensors: 3,
+        },
+        ArchiveEvent::ConfigUpdated {
+            date: "20210601".into(),
+        },
+        ArchiveEvent::IntegrityFailure {
+            district: None,
+            date: "20210601".into(),
+            error: "bad".into(),
+        },
+    ];
+    for event in events {
+        check_value("archive_event", &serde_json::to_value(event).unwrap());
+    }
+    checked.insert("archive_event");
+    let names: BTreeSet<&str> = schema::names().collect();
+    assert_eq!(checked, names);
+}
@@ -0,0 +1,60 @@
+// signing.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::web;
+use common::{get, samples, Fixture};
+use std::time::{Duration, SystemTime};
+use trafdat::signing::{sign, verify};
+use trafdat::state::{AppState, Config};
+
+/// Signing key for tests
+const KEY: &[u8] = b"secret";
+
+#[test]
+fn signed_scope() {
+    let now = SystemTime::now();
+    let query = sign(KEY, "/tms/", now + Duration::from_secs(60));
+    assert!(verify(KEY, "/tms/20210602.traffic", &query, now).is_ok());
+    assert!(verify(KEY, "/d2/20210602.traffic", &query, now).is_err());
+    assert!(verify(b"other", "/tms/20210602.traffic", &query, now).is_err());
+    let later = now + Duration::from_secs(61);
+    assert!(verify(KEY, "/tms/20210602.traffic", &query, later).is_err());
+    let forged = query.replace("scope=/tms/", "scope=/");
+    assert!(verify(KEY, "/d2/20210602.traffic", &forged, now).is_err());
+    assert!(verify(KEY, "/tms/20210602.traffic", "", now).is_err());
+    let query = sign(KEY, "/tms/2021", now + Duration::from_secs(60));
+    assert!(verify(KEY, "/tms/2021", &query, now).is_ok());
+    assert!(verify(KEY, "/tms/2021/checksums.json", &query, now).is_ok());
+    assert!(verify(KEY, "/tms/20210101.traffic", &query, now).is_err());
+    assert!(verify(KEY, "/tms/2021x/20210101", &query, now).is_err());
+}
+
+#[actix_web::test]
+async fn signed_archive() {
+    let fx = Fixture::new();
+    fx.add_archive("tms", "20210602", &[("200.v30", &samples(2880, 1, 7))]);
+    let config = Config {
+        signing_key: Some("secret".into()),
+        ..fx.config()
+    };
+    let state = web::Data::new(AppState::new(config));
+    let uri = "/trafdat/tms/20210602.traffic";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let expires = SystemTime::now() + Duration::from_secs(60);
+    let query = sign(KEY, "/tms/20210602.traffic", expires);
+    let res = get(&state, &format!("{}?{}", uri, query)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = get(&state, "/trafdat/tms/20210602/200.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let uri = "/trafdat/tms/2021/files/20210602.traffic";
+    let res = get(&state, uri).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let query = sign(KEY, "/tms/2021", expires);
+    let res = get(&state, &format!("{}?{}", uri, query)).await;
+    assert_eq!(res.status, StatusCode::OK);
+}
@@ -0,0 +1,112 @@
+// sqlite.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use common::{samples, Fixture};
+use std::process::Command;
+use trafdat::export::date_range;
+use trafdat::sqlite::Study;
+use trafdat::state::AppState;
+
+/// Query a SQLite database with the `sqlite3` shell, if installed
+fn query(db: &std::path::Path, sql: &str) -> Option<String> {
+    let out = Command::new("sqlite3").arg(db).arg(sql).output().ok()?;
+    assert!(out.status.success());
+    Some(String::from_utf8(out.stdout).unwrap().trim().to_string())
+}
+
+/// metro_config document with one station and one ramp
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" station_id="S1" lon="-93.1" lat="45.0">
+<detector name="100" category="" field="20"/>
+</r_node>
+<r_node name="rnd_2" n_type="Entrance" lon="-93.0" lat="45.0">
+<detector name="101" category="M"/>
+</r_node>
+</corridor>
+<corridor route="I-35" dir="NB">
+<r_node name="rnd_3" station_id="S3" lon="-93.3" lat="45.1">
+<detector name="300" category=""/>
+</r_node>
+</corridor>
+</tms_config>
+"#;
+
+#[test]
+fn sqlite_export() {
+    let fx = Fixture::new();
+    fx.add_metro_config("20210601", METRO_XML)
+        .add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "101.v30", &samples(2880, 1, 2))
+        .add_file("tms", "20210601", "300.v30", &samples(2880, 1, 9))
+        .add_file("tms", "20210602", "100.v30", &samples(2880, 1, 5));
+    let state = AppState::new(fx.config());
+    let study = Study {
+        district: "tms",
+        corridor: "I-94_EB",
+        dates: date_range("20210601", "20210602").unwrap(),
+        exts: vec!["v30".to_string()],
+    };
+    let mut out = vec![];
+    let rows = study.write_sql(&state, &mut out).unwrap();
+    // no metro_config on 20210602
+    assert_eq!(rows, 2880 * 2);
+    let sql = String::from_utf8(out).unwrap();
+    assert!(sql.starts_with("CREATE TABLE IF NOT EXISTS metadata"));
+    assert!(sql.contains(
+        "INSERT OR REPLACE INTO metadata VALUES ('corridor','I-94_EB');"
+    ));
+    assert!(sql.contains(
+        "INSERT OR REPLACE INTO nodes VALUES ('20210601','rnd_1','Station','S1',-93.1,45,0);"
+    ));
+    assert!(sql.contains(
+        "INSERT OR REPLACE INTO sensors VALUES ('20210601','101','rnd_2','M',22);"
+    ));
+    assert!(sql.contains("('100','v30',"));
+    assert!(!sql.contains("('300','v30',"));
+    assert_eq!(sql.matches("BEGIN;").count(), 1);
+    // batches of 500 rows
+    assert_eq!(sql.matches("INSERT OR REPLACE INTO samples").count(), 12);
+}
+
+#[test]
+fn sqlite_db() {
+    let fx = Fixture::new();
+    fx.add_metro_config("20210601", METRO_XML)
+        .add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_file("tms", "20210601", "101.v30", &samples(2880, 1, 2));
+    let state = AppState::new(fx.config());
+    let study = Study {
+        district: "tms",
+        corridor: "I-94_EB",
+        dates: date_range("20210601", "20210601").unwrap(),
+        exts: vec!["v30".to_string()],
+    };
+    let db = fx.jobs_path().with_extension("db");
+    assert_eq!(study.write_db(&state, &db).unwrap(), 2880 * 2);
+    assert!(!db.with_extension("db.part").exists());
+    let file = std::fs::read(&db).unwrap();
+    assert!(file.starts_with(b"SQLite format 3\0"));
+    let pages = u32::from_be_bytes([file[28], file[29], file[30], file[31]]);
+    assert_eq!(file.len(), pages as usize * 4096);
+    // cross-check with the `sqlite3` shell, if installed
+    let check = match query(&db, "PRAGMA integrity_check") {
+        Some(check) => check,
+        None => return,
+    };
+    assert_eq!(check, "ok");
+    let count = "SELECT count(*) FROM samples WHERE sensor = '101'";
+    assert_eq!(query(&db, count).unwrap(), "2880");
+    let meta = "SELECT value FROM metadata WHERE key = 'corridor'";
+    assert_eq!(query(&db, meta).unwrap(), "I-94_EB");
+    // existing databases are replaced
+    assert_eq!(study.write_db(&state, &db).unwrap(), 2880 * 2);
+    assert_eq!(query(&db, count).unwrap(), "2880");
+    let nodes =
+        "SELECT name, station_id, round(mile, 2) FROM nodes ORDER BY name";
+    assert_eq!(query(&db, nodes).unwrap(), "rnd_1|S1|0.0\nrnd_2||4.89");
+}
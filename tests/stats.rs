@@ -0,0 +1,42 @@
+// stats.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{get, samples, Fixture};
+use serde_json::json;
+use trafdat::stats::AccessStats;
+
+#[actix_web::test]
+async fn access_stats() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5));
+    let state = fx.state();
+    for _ in 0..2 {
+        let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+        assert_eq!(res.status, StatusCode::OK);
+    }
+    get(&state, "/trafdat/tms/20210601").await;
+    get(&state, "/trafdat/tms/20210602/100.v30").await;
+    get(&state, "/trafdat/districts").await;
+    let res = get(&state, "/trafdat/admin/stats.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.json(),
+        json!([{
+            "district": "tms",
+            "date": "20210601",
+            "requests": 3,
+            "bytes": 5760 + 7,
+        }])
+    );
+    let path = fx.traffic_path().join("stats.json");
+    state.stats.persist(&path).unwrap();
+    let stats = AccessStats::load(&path).unwrap();
+    stats.persist(&path).unwrap();
+    let loaded: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(loaded, res.json());
+}
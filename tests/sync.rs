@@ -0,0 +1,181 @@
+// sync.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::web;
+use common::{get, request, samples, serve, start_server, Fixture};
+use std::fs::{create_dir_all, read, write, File};
+use std::time::Duration;
+use trafdat::client::Client;
+use trafdat::error::Error;
+use trafdat::state::{AppState, Config};
+use trafdat::sync::{mirror, plan, FileSum, SyncReport, BLOCK_SIZE};
+
+/// Make data for a file spanning several blocks
+fn big_file() -> Vec<u8> {
+    (0..BLOCK_SIZE * 3 + 1000)
+        .map(|i| (i % 251) as u8)
+        .collect()
+}
+
+#[actix_web::test]
+async fn sync_plan() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_archive("tms", "20210602", &[("200.v30", &samples(2880, 1, 7))]);
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/2021/checksums.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let manifest: Vec<FileSum> = serde_json::from_slice(&res.body).unwrap();
+    let names: Vec<&str> = manifest.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["20210601/100.v30", "20210602.traffic"]);
+    assert_eq!(manifest[0].size, 2880);
+    assert_eq!(manifest[0].sha256.len(), 64);
+    let res = get(&state, "/trafdat/tms/2020/checksums.json").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    // Mirror has an unchanged file and a stale archive
+    let mirror = tempfile::tempdir().unwrap();
+    create_dir_all(mirror.path().join("20210601")).unwrap();
+    write(mirror.path().join("20210601/100.v30"), samples(2880, 1, 5)).unwrap();
+    write(mirror.path().join("20210602.traffic"), b"stale").unwrap();
+    write(mirror.path().join("20210604.traffic"), b"removed").unwrap();
+    let plan = plan(mirror.path(), &manifest);
+    assert_eq!(plan.unchanged, 1);
+    assert_eq!(plan.bytes_saved, 2880);
+    assert_eq!(plan.fetch, [manifest[1].clone()]);
+    assert_eq!(plan.remove, ["20210604.traffic"]);
+}
+
+#[actix_web::test]
+async fn flat_checksums() {
+    let fx = Fixture::new();
+    fx.add_raw("flat/20200105/100.v30", &samples(2880, 1, 6))
+        .add_raw("flat/20210601/100.v30", &samples(2880, 1, 5))
+        .add_raw("flat/20210602.traffic", b"archive");
+    let state = fx.state();
+    let res = get(&state, "/trafdat/flat/2021/checksums.json").await;
+    let manifest: Vec<FileSum> = serde_json::from_slice(&res.body).unwrap();
+    let names: Vec<&str> = manifest.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["20210601/100.v30", "20210602.traffic"]);
+}
+
+#[actix_web::test]
+async fn archive_files() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_raw("tms/2021/20210603.traffic", &big_file());
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/2021/checksums.json").await;
+    let manifest: Vec<FileSum> = serde_json::from_slice(&res.body).unwrap();
+    assert!(manifest[0].blocks.is_empty());
+    assert_eq!(manifest[1].blocks.len(), 4);
+    let res = get(&state, "/trafdat/tms/2021/files/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/2021/files/20210603.traffic")
+        .insert_header(("Range", "bytes=10-19"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, big_file()[10..20]);
+    for uri in [
+        "/trafdat/tms/2021/files/20210601",
+        "/trafdat/tms/2021/files/20210601/../20210601/100.v30",
+        "/trafdat/tms/2020/files/20210601/100.v30",
+    ] {
+        assert_eq!(get(&state, uri).await.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[actix_web::test]
+async fn mirror_year() {
+    let fx = Fixture::new();
+    let archive = [("200.v30", &samples(2880, 1, 7)[..])];
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5))
+        .add_archive("tms", "20210602", &archive)
+        .add_raw("tms/2021/20210603.traffic", &big_file());
+    let client = Client::new(&start_server(&fx)).unwrap();
+    // Mirror has an unchanged file, a stale archive and a changed block
+    let dir = tempfile::tempdir().unwrap();
+    create_dir_all(dir.path().join("20210601")).unwrap();
+    write(dir.path().join("20210601/100.v30"), samples(2880, 1, 5)).unwrap();
+    write(dir.path().join("20210602.traffic"), b"stale").unwrap();
+    let mut changed = big_file();
+    changed[BLOCK_SIZE + 5] ^= 0xFF;
+    write(dir.path().join("20210603.traffic"), &changed).unwrap();
+    // and a file removed upstream
+    create_dir_all(dir.path().join("20210604")).unwrap();
+    write(dir.path().join("20210604/100.v30"), samples(2880, 1, 5)).unwrap();
+    let report = mirror(&client, "tms", "2021", dir.path()).await.unwrap();
+    let archive_len = fx
+        .traffic_path()
+        .join("tms/2021/20210602.traffic")
+        .metadata()
+        .unwrap()
+        .len();
+    assert_eq!(
+        report,
+        SyncReport {
+            fetched: 2,
+            removed: 1,
+            unchanged: 1,
+            bytes_fetched: archive_len + BLOCK_SIZE as u64,
+            bytes_saved: 2880 + (BLOCK_SIZE * 2 + 1000) as u64,
+        }
+    );
+    for name in ["20210602.traffic", "20210603.traffic"] {
+        let local = read(dir.path().join(name)).unwrap();
+        let remote = read(fx.traffic_path().join("tms/2021").join(name));
+        assert_eq!(local, remote.unwrap());
+    }
+    assert!(!dir.path().join("20210604").exists());
+    let report = mirror(&client, "tms", "2021", dir.path()).await.unwrap();
+    assert_eq!(report.fetched, 0);
+    assert_eq!(report.unchanged, 3);
+}
+
+#[actix_web::test]
+async fn mirror_signed() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5));
+    let state = web::Data::new(AppState::new(Config {
+        signing_key: Some("secret".into()),
+        ..fx.config()
+    }));
+    let url = serve(state);
+    let dir = tempfile::tempdir().unwrap();
+    let client = Client::new(&url).unwrap();
+    let res = mirror(&client, "tms", "2021", dir.path()).await;
+    assert!(matches!(res, Err(Error::Forbidden)));
+    let client = client.with_signing_key("secret".into());
+    let report = mirror(&client, "tms", "2021", dir.path()).await.unwrap();
+    assert_eq!(report.fetched, 1);
+    let local = read(dir.path().join("20210601/100.v30")).unwrap();
+    assert_eq!(local, samples(2880, 1, 5));
+}
+
+#[actix_web::test]
+async fn cached_digests() {
+    let fx = Fixture::new();
+    fx.add_file("tms", "20210601", "100.v30", &samples(2880, 1, 5));
+    let state = fx.state();
+    let uri = "/trafdat/tms/2021/checksums.json";
+    let sha256 = |body: &[u8]| {
+        let manifest: Vec<FileSum> = serde_json::from_slice(body).unwrap();
+        manifest[0].sha256.clone()
+    };
+    let first = sha256(&get(&state, uri).await.body);
+    // same size and modified time: digest is not recalculated
+    let path = fx.traffic_path().join("tms/2021/20210601/100.v30");
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    write(&path, samples(2880, 1, 6)).unwrap();
+    let file = File::options().write(true).open(&path).unwrap();
+    file.set_modified(mtime).unwrap();
+    assert_eq!(sha256(&get(&state, uri).await.body), first);
+    file.set_modified(mtime + Duration::from_secs(1)).unwrap();
+    assert_ne!(sha256(&get(&state, uri).await.body), first);
+}
@@ -0,0 +1,75 @@
+// verify.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use common::{get, samples, Fixture};
+use serde_json::json;
+use trafdat::verify::{verify_archive, SkippedEntry};
+
+/// Replace bytes of an entry name in all headers of an archive
+fn patch_name(fx: &Fixture, rel: &str, from: &[u8], to: &[u8]) {
+    let path = fx.traffic_path().join(rel);
+    let mut zip = std::fs::read(&path).unwrap();
+    let mut i = 0;
+    while let Some(pos) = zip[i..].windows(from.len()).position(|w| w == from) {
+        zip[i + pos..i + pos + to.len()].copy_from_slice(to);
+        i += pos + from.len();
+    }
+    std::fs::write(&path, &zip).unwrap();
+}
+
+#[actix_web::test]
+async fn entry_names() {
+    let fx = Fixture::new();
+    fx.add_archive(
+        "tms",
+        "20210602",
+        &[
+            ("200.v30", &samples(2880, 1, 7)),
+            ("2\"1.v30", &samples(2880, 1, 7)),
+            ("2ab.v30", &samples(2880, 1, 7)),
+            ("20210602/203.v30", &samples(2880, 1, 7)),
+        ],
+    );
+    patch_name(&fx, "tms/2021/20210602.traffic", b"2ab", b"2\xFFb");
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210602").await;
+    assert_eq!(res.json(), json!(["200", "203"]));
+    let path = fx.traffic_path().join("tms/2021/20210602.traffic");
+    let report = verify_archive(&path, 64 * 1024 * 1024).unwrap();
+    assert_eq!(report.entries, 4);
+    assert_eq!(
+        report.skipped,
+        vec![
+            SkippedEntry {
+                name: "2\\\"1.v30".into(),
+                reason: "unsafe characters".into(),
+            },
+            SkippedEntry {
+                name: "2\\xffb.v30".into(),
+                reason: "invalid UTF-8".into(),
+            },
+        ]
+    );
+    let report = verify_archive(&path, 1000).unwrap();
+    assert_eq!(report.skipped.len(), 4);
+    assert_eq!(report.skipped[0].reason, "too large (2880 bytes)");
+}
+
+#[test]
+fn corrupt_entry() {
+    let fx = Fixture::new();
+    fx.add_archive("tms", "20210603", &[("300.v30", &samples(2880, 1, 7))]);
+    let path = fx.traffic_path().join("tms/2021/20210603.traffic");
+    let mut zip = std::fs::read(&path).unwrap();
+    // corrupt the CRC-32 in the central directory
+    let cd = zip.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+    zip[cd + 16] ^= 0xFF;
+    std::fs::write(&path, &zip).unwrap();
+    let report = verify_archive(&path, 64 * 1024 * 1024).unwrap();
+    assert_eq!(report.entries, 1);
+    assert_eq!(report.skipped[0].name, "300.v30");
+    assert!(report.skipped[0].reason.contains("checksum"));
+}
@@ -0,0 +1,79 @@
+// warm.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use trafdat::upstream::Upstream;
+use trafdat::warm::{popular_paths, replay};
+
+/// Access log, in the server's format and combined format
+const LOG: &str = r#"127.0.0.1 - - "GET /trafdat/tms/2021 HTTP/1.1" 200 18 0.001
+127.0.0.1 - - "GET /trafdat/tms/20210601/100.v30 HTTP/1.1" 200 2880 0.002
+10.0.0.5 - - [01/Jun/2021:10:00:00 -0500] "GET /trafdat/tms/2021 HTTP/1.1" 200 18 "-" "curl/7.68"
+127.0.0.1 - - "GET /trafdat/tms/20210601/100.v30 HTTP/1.1" 200 2880 0.002
+127.0.0.1 - - "GET /trafdat/tms/2021 HTTP/1.1" 200 18 0.001
+127.0.0.1 - - "GET /trafdat/tms/20210601/999.v30 HTTP/1.1" 404 9 0.001
+127.0.0.1 - - "POST /trafdat/jobs/export HTTP/1.1" 202 40 0.001
+127.0.0.1 - - "GET /trafdat/admin/ HTTP/1.1" 200 900 0.001
+127.0.0.1 - - "GET /trafdat/static/trafdat.css HTTP/1.1" 200 900 0.001
+127.0.0.1 - - "GET /trafdat/index.html HTTP/1.1" 200 900 0.001
+127.0.0.1 - - "GET /other/tms/2021 HTTP/1.1" 200 18 0.001
+127.0.0.1 - - "GET /trafdat/tms/20210601?sensors=1 HTTP/1.1" 200 18 0.001
+garbage
+"#;
+
+#[test]
+fn warm_popular_paths() {
+    let paths = popular_paths(LOG.as_bytes(), "/trafdat", 10).unwrap();
+    assert_eq!(
+        paths,
+        vec![
+            ("/tms/2021".to_string(), 3),
+            ("/tms/20210601/100.v30".to_string(), 2),
+            ("/tms/20210601?sensors=1".to_string(), 1),
+        ]
+    );
+    let paths = popular_paths(LOG.as_bytes(), "/trafdat", 1).unwrap();
+    assert_eq!(paths, vec![("/tms/2021".to_string(), 3)]);
+}
+
+#[test]
+fn warm_replay() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/trafdat", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+            }
+            let path = request.split_whitespace().nth(1).unwrap().to_string();
+            let res: &[u8] = if path == "/trafdat/tms/2021" {
+                b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n20210601\n"
+            } else {
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nNot Found"
+            };
+            stream.write_all(res).unwrap();
+            tx.send(path).unwrap();
+        }
+    });
+    let server = Upstream::new(&url).unwrap();
+    let paths = vec![
+        ("/tms/2021".to_string(), 3),
+        ("/tms/20210601/100.v30".to_string(), 2),
+    ];
+    assert_eq!(replay(&server, &paths), 1);
+    assert_eq!(rx.recv().unwrap(), "/trafdat/tms/2021");
+    assert_eq!(rx.recv().unwrap(), "/trafdat/tms/20210601/100.v30");
+}
@@ -0,0 +1,224 @@
+// watch.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use common::{samples, Fixture};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use trafdat::state::AppState;
+use trafdat::watch::{ArchiveEvent, EventSink, NatsSink, Watcher};
+
+/// Valid metro_config document
+const METRO_XML: &str = r#"<?xml version="1.0"?>
+<tms_config time_stamp="20210601">
+<corridor route="I-94" dir="EB">
+<r_node name="rnd_1" lon="-93.1" lat="45.0"/>
+</corridor>
+</tms_config>
+"#;
+
+#[test]
+fn new_dates() {
+    let fx = Fixture::new();
+    fx.add_archive("tms", "20210601", &[("100.v30", &samples(2880, 1, 5))]);
+    let state = AppState::new(fx.config());
+    let mut watcher = Watcher::default();
+    assert!(watcher.scan(&state).is_empty());
+    // date directories are not finalized
+    fx.add_file("tms", "20210603", "100.v30", &samples(2880, 1, 5))
+        .add_archive(
+            "tms",
+            "20210602",
+            &[
+                ("100.v30", &samples(2880, 1, 5)),
+                ("101.v30", &samples(2880, 1, 5)),
+                ("101.c30", &samples(2880, 2, 5)),
+            ],
+        )
+        .add_archive("d2", "20200101", &[("300.v30", &samples(2880, 1, 2))]);
+    // new archives may still be written
+    assert!(watcher.scan(&state).is_empty());
+    let mut events = watcher.scan(&state);
+    events.sort_by_key(|ev| format!("{:?}", ev));
+    assert_eq!(
+        events,
+        vec![
+            ArchiveEvent::NewDate {
+                district: "d2".into(),
+                date: "20200101".into(),
+                sensors: 1,
+            },
+            ArchiveEvent::NewDate {
+                district: "tms".into(),
+                date: "20210602".into(),
+                sensors: 2,
+            },
+        ]
+    );
+    assert!(watcher.scan(&state).is_empty());
+}
+
+#[test]
+fn nats_publish() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .write_all(b"INFO {\"server_id\":\"test\"}\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut connect = String::new();
+        reader.read_line(&mut connect).unwrap();
+        let mut publish = String::new();
+        reader.read_line(&mut publish).unwrap();
+        let len: usize =
+            publish.split_whitespace().nth(2).unwrap().parse().unwrap();
+        let mut payload = vec![0; len + 2];
+        reader.read_exact(&mut payload).unwrap();
+        let mut ping = String::new();
+        reader.read_line(&mut ping).unwrap();
+        stream.write_all(b"PONG\r\n").unwrap();
+        (connect, publish, String::from_utf8(payload).unwrap(), ping)
+    });
+    let sink = NatsSink::new(&format!("nats://{}", addr), "traffic.new");
+    let event = ArchiveEvent::NewDate {
+        district: "tms".into(),
+        date: "20210601".into(),
+        sensors: 3,
+    };
+    sink.publish(&event).unwrap();
+    let (connect, publish, payload, ping) = server.join().unwrap();
+    assert!(connect.starts_with("CONNECT {"));
+    assert!(publish.starts_with("PUB traffic.new "));
+    assert_eq!(
+        payload,
+        "{\"event\":\"new_date\",\"district\":\"tms\",\"date\":\"20210601\",\"sensors\":3}\r\n"
+    );
+    assert_eq!(ping, "PING\r\n");
+}
+
+#[test]
+fn config_and_integrity() {
+    let fx = Fixture::new();
+    fx.add_metro_config("20210601", METRO_XML);
+    let state = AppState::new(fx.config());
+    let mut watcher = Watcher::default();
+    assert!(watcher.scan(&state).is_empty());
+    fx.add_metro_config("20210602", METRO_XML)
+        .add_metro_config("20210603", "<tms_config>")
+        .add_raw("tms/2021/20210604.traffic", b"not a zip");
+    let mut events = watcher.scan(&state);
+    // archives are checked after they settle
+    events.extend(watcher.scan(&state));
+    events.sort_by_key(|ev| format!("{:?}", ev));
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0],
+        ArchiveEvent::ConfigUpdated {
+            date: "20210602".into()
+        }
+    );
+    assert!(matches!(
+        &events[1],
+        ArchiveEvent::IntegrityFailure { district: None, date, .. }
+            if date == "20210603"
+    ));
+    assert!(matches!(
+        &events[2],
+        ArchiveEvent::IntegrityFailure { district: Some(d), date, .. }
+            if d == "tms" && date == "20210604"
+    ));
+    assert_eq!(events[1].name(), "integrity_failure");
+    assert!(watcher.scan(&state).is_empty());
+}
+
+#[test]
+fn settling_archives() {
+    let fx = Fixture::new();
+    let state = AppState::new(fx.config());
+    let mut watcher = Watcher::default();
+    assert!(watcher.scan(&state).is_empty());
+    // an archive being written is not checked
+    fx.add_raw("tms/2021/20210601.traffic", b"PK partial");
+    assert!(watcher.scan(&state).is_empty());
+    fx.add_archive("tms", "20210601", &[("100.v30", &samples(2880, 1, 5))]);
+    assert!(watcher.scan(&state).is_empty());
+    assert_eq!(
+        watcher.scan(&state),
+        vec![ArchiveEvent::NewDate {
+            district: "tms".into(),
+            date: "20210601".into(),
+            sensors: 1,
+        }]
+    );
+}
+
+#[test]
+fn year_archives() {
+    let fx = Fixture::new();
+    let v30 = samples(2880, 1, 5);
+    fx.add_year_archive("tms", "2020", &[("20200101/100.v30", &v30)]);
+    let state = AppState::new(fx.config());
+    let mut watcher = Watcher::default();
+    assert!(watcher.scan(&state).is_empty());
+    fx.add_year_archive(
+        "tms",
+        "2020",
+        &[("20200101/100.v30", &v30), ("20200102/100.v30", &v30)],
+    )
+    .add_raw("tms/2019.traffic", b"not a zip");
+    assert!(watcher.scan(&state).is_empty());
+    let mut events = watcher.scan(&state);
+    events.sort_by_key(|ev| format!("{:?}", ev));
+    assert_eq!(events.len(), 2);
+    assert!(matches!(
+        &events[0],
+        ArchiveEvent::IntegrityFailure { district: Some(d), date, .. }
+            if d == "tms" && date == "2019"
+    ));
+    assert_eq!(
+        events[1],
+        ArchiveEvent::NewDate {
+            district: "tms".into(),
+            date: "20200102".into(),
+            sensors: 1,
+        }
+    );
+    assert!(watcher.scan(&state).is_empty());
+}
+
+#[test]
+fn nats_retry() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        // the first connection is dropped
+        drop(listener.accept().unwrap());
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"INFO {}\r\n").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            if line.ends_with("PING\r\n") {
+                stream.write_all(b"PONG\r\n").unwrap();
+                break;
+            }
+        }
+    });
+    let sink = NatsSink::new(&addr.to_string(), "traffic.new")
+        .with_retry(2, Duration::from_millis(10));
+    let event = ArchiveEvent::ConfigUpdated {
+        date: "20210601".into(),
+    };
+    sink.publish(&event).unwrap();
+    server.join().unwrap();
+    // no server
+    let sink = NatsSink::new(&addr.to_string(), "traffic.new")
+        .with_retry(2, Duration::from_millis(10));
+    assert!(sink.publish(&event).is_err());
+}
@@ -0,0 +1,87 @@
+// webhook.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use trafdat::watch::{ArchiveEvent, EventSink};
+use trafdat::webhook::{sign_body, WebhookSink};
+
+/// Accept one request, returning (headers, body) and replying with a status
+fn accept(listener: &TcpListener, status: &str) -> (Vec<String>, String) {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        headers.push(line);
+    }
+    let len: usize = headers
+        .iter()
+        .find_map(|h| h.strip_prefix("Content-Length: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).unwrap();
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+    (headers, String::from_utf8(body).unwrap())
+}
+
+#[test]
+fn webhook_retry() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let first = accept(&listener, "503 Service Unavailable");
+        let second = accept(&listener, "204 No Content");
+        (first, second)
+    });
+    let url = format!("http://{}/hooks/trafdat", addr);
+    let sink = WebhookSink::new(&url, Some("secret"))
+        .unwrap()
+        .with_retry(3, Duration::from_millis(10));
+    let event = ArchiveEvent::ConfigUpdated {
+        date: "20210601".into(),
+    };
+    sink.publish(&event).unwrap();
+    let ((headers, body), (headers2, body2)) = server.join().unwrap();
+    assert_eq!(headers[0], "POST /hooks/trafdat HTTP/1.1");
+    assert!(headers.contains(&"X-Trafdat-Event: config_updated".to_string()));
+    assert_eq!(body, r#"{"event":"config_updated","date":"20210601"}"#);
+    let sig = format!(
+        "X-Trafdat-Signature: sha256={}",
+        sign_body(b"secret", body.as_bytes())
+    );
+    assert!(headers.contains(&sig));
+    assert_eq!(headers, headers2);
+    assert_eq!(body, body2);
+}
+
+#[test]
+fn webhook_failure() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        for _ in 0..2 {
+            accept(&listener, "500 Internal Server Error");
+        }
+    });
+    let url = format!("http://{}", addr);
+    let sink = WebhookSink::new(&url, None)
+        .unwrap()
+        .with_retry(2, Duration::from_millis(10));
+    let event = ArchiveEvent::ConfigUpdated {
+        date: "20210601".into(),
+    };
+    assert!(sink.publish(&event).is_err());
+    server.join().unwrap();
+    assert!(WebhookSink::new("https://example.com/hook", None).is_err());
+}
@@ -0,0 +1,70 @@
+// zip64.rs
+//
+// Copyright (c) 2021  Minnesota Department of Transportation
+//
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use common::{get, request, samples, Fixture};
+use std::io::{Cursor, Write};
+use trafdat::verify::verify_archive;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Number of entries, beyond the classic zip limit (65,535)
+const ENTRIES: usize = 66_000;
+
+/// Write a zip64 `.traffic` archive, with zip64 extra fields on each entry
+fn add_zip64_archive(fx: &Fixture, district: &str, date: &str) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let opts = FileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+    zip.start_file("100.v30", opts).unwrap();
+    zip.write_all(&samples(2880, 1, 5)).unwrap();
+    for i in 0..ENTRIES - 1 {
+        zip.start_file(format!("{}.vlog", 1000 + i), opts).unwrap();
+        zip.write_all(b"0 0 ? 60\n").unwrap();
+    }
+    let buf = zip.finish().unwrap().into_inner();
+    // zip64 end of central directory record
+    assert!(buf.windows(4).any(|w| w == b"PK\x06\x06"));
+    let rel = format!("{}/{}/{}.traffic", district, &date[..4], date);
+    fx.add_raw(&rel, &buf);
+    buf
+}
+
+#[actix_web::test]
+async fn zip64_archive() {
+    let fx = Fixture::new();
+    add_zip64_archive(&fx, "tms", "20210601");
+    let state = fx.state();
+    let res = get(&state, "/trafdat/tms/20210601").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json().as_array().unwrap().len(), ENTRIES);
+    let res = get(&state, "/trafdat/tms/20210601/100.v30").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, samples(2880, 1, 5));
+    let uri = format!("/trafdat/tms/20210601/{}.vlog", 1000 + ENTRIES - 2);
+    let res = get(&state, &uri).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, b"0 0 ? 60\n");
+    let path = fx.traffic_path().join("tms/2021/20210601.traffic");
+    let report = verify_archive(&path, 4096).unwrap();
+    assert_eq!(report.entries, ENTRIES);
+    assert!(report.skipped.is_empty());
+}
+
+#[actix_web::test]
+async fn zip64_archive_parts() {
+    let fx = Fixture::new();
+    let buf = add_zip64_archive(&fx, "tms", "20210602");
+    let state = fx.state();
+    let req = TestRequest::get()
+        .uri("/trafdat/tms/20210602.traffic")
+        .insert_header(("range", "bytes=-22"));
+    let res = request(&state, req).await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, &buf[buf.len() - 22..]);
+}